@@ -1,10 +1,17 @@
+use std::hash::{Hash, Hasher};
+
 use anyhow::Result;
+use rustfft::num_complex::Complex;
 use tokio_util::sync::CancellationToken;
 
 use crate::analyzer::core_analyzer::Analyzer;
+use crate::error::AnalysisError;
+use crate::extractor::{ExtractedFeatures, FeatureExtractor};
 use crate::measure_time;
 use crate::utils::computing_device::ComputingDevice;
 use crate::utils::features::*;
+use crate::utils::key::Key;
+use crate::utils::loudness_meter::Loudness;
 
 #[derive(Debug, Clone, Copy)]
 pub struct AudioStat {
@@ -13,10 +20,52 @@ pub struct AudioStat {
     pub total_samples: usize,
 }
 
+/// Version of the analysis algorithms. Bump it whenever a change to the
+/// analyzer or to `utils::features` changes the results, so that results
+/// stored by an older version are analyzed again.
+pub const ANALYZER_VERSION: u32 = 1;
+
+/// Everything the results of an analysis depend on besides the track.
+/// Results analyzed with different parameters can't be compared.
 #[derive(Debug, Clone, Copy)]
 pub struct AnalysisParameter {
+    pub version: u32,
     pub window_size: usize,
     pub overlap_size: usize,
+    /// Only recorded for diagnostics, both devices give the same results.
+    /// It is left out of the comparisons.
+    pub computing_device: ComputingDevice,
+}
+
+impl AnalysisParameter {
+    /// The parameters of an analysis by the current analyzer.
+    pub fn new(window_size: usize, overlap_size: usize, computing_device: ComputingDevice) -> Self {
+        AnalysisParameter {
+            version: ANALYZER_VERSION,
+            window_size,
+            overlap_size,
+            computing_device,
+        }
+    }
+
+    /// What results are compared by.
+    fn key(&self) -> (u32, usize, usize) {
+        (self.version, self.window_size, self.overlap_size)
+    }
+}
+
+impl PartialEq for AnalysisParameter {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for AnalysisParameter {}
+
+impl Hash for AnalysisParameter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -39,42 +88,109 @@ pub struct AnalysisResult {
     pub perceptual_sharpness: f32,
     pub perceptual_loudness: [f32; 24],
     pub mfcc: [f32; 13],
+    /// BS.1770-4 integrated loudness in LUFS, negative infinity for silence.
+    pub integrated_loudness: f32,
+    /// EBU R128 loudness range in LU.
+    pub loudness_range: f32,
+    /// 4x oversampled true peak, linear with full scale at 1.0.
+    pub true_peak: f32,
+    /// Estimated tempo in beats per minute, 0 when unknown.
+    pub bpm: f32,
+    pub bpm_confidence: f32,
+    /// Time of the first beat in seconds. With the tempo, it describes the
+    /// beat grid of the track.
+    pub beat_offset: f32,
+    /// Estimated from the time-resolved chromagram, `None` for silence.
+    pub key: Option<Key>,
+    pub key_confidence: f32,
 }
 
-pub fn analyze_audio(
-    file_path: &str,
-    window_size: usize,
-    overlap_size: usize,
-    computing_device: ComputingDevice,
-    cancel_token: Option<CancellationToken>,
-) -> Result<Option<AnalysisResult>> {
-    let mut analyzer = Analyzer::new(
-        computing_device,
-        window_size,
-        overlap_size,
-        None,
-        cancel_token,
-    );
+/// Features of a stretch of a track, computed like the matching fields of
+/// `AnalysisResult` but over the windows of the segment only.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnalysisSegment {
+    /// Start of the segment in seconds.
+    pub start: f32,
+    pub duration: f32,
+    /// Number of analysis windows in the segment. The RMS, ZCR and energy
+    /// of the whole track are the means of the segments weighted by it.
+    pub window_count: u32,
+    pub rms: f32,
+    pub zcr: f32,
+    pub energy: f32,
+    pub spectral_centroid: f32,
+    pub spectral_flatness: f32,
+    pub spectral_rolloff: f32,
+    pub spectral_spread: f32,
+    pub chromagram: [f32; 12],
+    pub mfcc: [f32; 13],
+}
 
-    let audio_desc = measure_time!(
-        &format!("[{:?}] Analyzer", computing_device),
-        analyzer.process(file_path)
-    );
+/// Merges segments into one, with every value weighted by the window count
+/// of its segment. The RMS, ZCR and energy are exact; the spectral features
+/// are approximated by their weighted means, as the exact values need the
+/// segment spectra, see `merge_segments`.
+pub fn aggregate_segments(segments: &[AnalysisSegment]) -> Option<AnalysisSegment> {
+    let first = segments.first()?;
+    let last = segments.last()?;
+    let window_count: u32 = segments.iter().map(|x| x.window_count).sum();
+    if window_count == 0 {
+        return None;
+    }
 
-    if audio_desc.is_none() {
-        return Ok(None);
+    let weighted_mean = |value: &dyn Fn(&AnalysisSegment) -> f32| {
+        segments
+            .iter()
+            .map(|x| value(x) * x.window_count as f32)
+            .sum::<f32>()
+            / window_count as f32
     };
 
-    let audio_desc = audio_desc.expect("Audio desc should not be none");
+    Some(AnalysisSegment {
+        start: first.start,
+        duration: last.start + last.duration - first.start,
+        window_count,
+        rms: weighted_mean(&|x| x.rms),
+        zcr: weighted_mean(&|x| x.zcr),
+        energy: weighted_mean(&|x| x.energy),
+        spectral_centroid: weighted_mean(&|x| x.spectral_centroid),
+        spectral_flatness: weighted_mean(&|x| x.spectral_flatness),
+        spectral_rolloff: weighted_mean(&|x| x.spectral_rolloff),
+        spectral_spread: weighted_mean(&|x| x.spectral_spread),
+        chromagram: std::array::from_fn(|i| weighted_mean(&|x| x.chromagram[i])),
+        mfcc: std::array::from_fn(|i| weighted_mean(&|x| x.mfcc[i])),
+    })
+}
+
+struct SpectralFeatures {
+    spectral_centroid: f32,
+    spectral_flatness: f32,
+    spectral_flux: f32,
+    spectral_slope: f32,
+    spectral_rolloff: f32,
+    spectral_spread: f32,
+    spectral_skewness: f32,
+    spectral_kurtosis: f32,
+    chromagram: [f32; 12],
+    perceptual_spread: f32,
+    perceptual_sharpness: f32,
+    perceptual_loudness: [f32; 24],
+    mfcc: [f32; 13],
+}
 
-    let amp_spectrum = amp_spectrum(&audio_desc.spectrum, window_size);
+fn spectral_features(
+    spectrum: &[Complex<f32>],
+    sample_rate: u32,
+    window_size: usize,
+) -> Result<SpectralFeatures> {
+    let amp_spectrum = amp_spectrum(spectrum, window_size);
 
     // Calculate spectral features
     let spectral_centroid = spectral_centroid(&amp_spectrum);
     let spectral_flatness = spectral_flatness(&amp_spectrum);
     let spectral_flux = spectral_flux(&amp_spectrum, &vec![0.0; amp_spectrum.len()], window_size); // Assuming previous signal is zero
-    let spectral_slope = spectral_slope(&amp_spectrum, audio_desc.sample_rate as f32, window_size);
-    let spectral_rolloff = spectral_rolloff(&amp_spectrum, audio_desc.sample_rate as f32);
+    let spectral_slope = spectral_slope(&amp_spectrum, sample_rate as f32, window_size);
+    let spectral_rolloff = spectral_rolloff(&amp_spectrum, sample_rate as f32);
     let spectral_spread = spectral_spread(&amp_spectrum);
     let spectral_skewness = spectral_skewness(&amp_spectrum);
     let spectral_kurtosis = spectral_kurtosis(&amp_spectrum);
@@ -107,20 +223,7 @@ pub fn analyze_audio(
         .try_into()
         .expect("Expected a Vec of length 13");
 
-    // Create and return the analysis result
-    Ok(Some(AnalysisResult {
-        stat: AudioStat {
-            sample_rate: audio_desc.sample_rate,
-            duration: audio_desc.duration,
-            total_samples: audio_desc.total_samples,
-        },
-        parameters: AnalysisParameter {
-            window_size,
-            overlap_size,
-        },
-        rms: audio_desc.rms,
-        zcr: audio_desc.zcr,
-        energy: audio_desc.energy,
+    Ok(SpectralFeatures {
         spectral_centroid,
         spectral_flatness,
         spectral_flux,
@@ -130,11 +233,215 @@ pub fn analyze_audio(
         spectral_skewness,
         spectral_kurtosis,
         chromagram,
-        perceptual_loudness,
         perceptual_spread,
         perceptual_sharpness,
+        perceptual_loudness,
         mfcc,
-    }))
+    })
+}
+
+pub fn analyze_audio(
+    file_path: &str,
+    window_size: usize,
+    overlap_size: usize,
+    computing_device: ComputingDevice,
+    cancel_token: Option<CancellationToken>,
+) -> Result<AnalysisResult, AnalysisError> {
+    analyze_audio_with_extractors(
+        file_path,
+        window_size,
+        overlap_size,
+        None,
+        vec![],
+        computing_device,
+        cancel_token,
+    )
+    .map(|x| x.result)
+}
+
+/// Like `analyze_audio`, and also describes every `segment_duration`
+/// seconds of the track on its own.
+pub fn analyze_audio_segments(
+    file_path: &str,
+    window_size: usize,
+    overlap_size: usize,
+    segment_duration: f64,
+    computing_device: ComputingDevice,
+    cancel_token: Option<CancellationToken>,
+) -> Result<(AnalysisResult, Vec<AnalysisSegment>), AnalysisError> {
+    analyze_audio_with_extractors(
+        file_path,
+        window_size,
+        overlap_size,
+        Some(segment_duration),
+        vec![],
+        computing_device,
+        cancel_token,
+    )
+    .map(|x| (x.result, x.segments))
+}
+
+/// The part of a file holding a track, in seconds from its start. The whole
+/// file by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AudioRange {
+    pub start: f64,
+    /// The end of the file when unset.
+    pub end: Option<f64>,
+}
+
+/// Everything `analyze_audio_with_extractors` learns about a track.
+#[derive(Debug, Clone)]
+pub struct AnalysisOutput {
+    pub result: AnalysisResult,
+    /// Empty unless a segment duration is given.
+    pub segments: Vec<AnalysisSegment>,
+    /// The values of every extractor, in the order they were given.
+    pub features: Vec<ExtractedFeatures>,
+}
+
+/// Analyzes a track, optionally in segments, and runs the given feature
+/// extractors over it.
+pub fn analyze_audio_with_extractors(
+    file_path: &str,
+    window_size: usize,
+    overlap_size: usize,
+    segment_duration: Option<f64>,
+    extractors: Vec<Box<dyn FeatureExtractor>>,
+    computing_device: ComputingDevice,
+    cancel_token: Option<CancellationToken>,
+) -> Result<AnalysisOutput, AnalysisError> {
+    analyze_audio_range(
+        file_path,
+        AudioRange::default(),
+        AnalysisParameter::new(window_size, overlap_size, computing_device),
+        segment_duration,
+        extractors,
+        cancel_token,
+    )
+}
+
+/// Like `analyze_audio_with_extractors`, for a range of the file only, like
+/// a track of a CUE sheet. Durations and segments are those of the range.
+pub fn analyze_audio_range(
+    file_path: &str,
+    range: AudioRange,
+    parameters: AnalysisParameter,
+    segment_duration: Option<f64>,
+    extractors: Vec<Box<dyn FeatureExtractor>>,
+    cancel_token: Option<CancellationToken>,
+) -> Result<AnalysisOutput, AnalysisError> {
+    let AnalysisParameter {
+        window_size,
+        overlap_size,
+        computing_device,
+        ..
+    } = parameters;
+
+    let mut analyzer = Analyzer::new(
+        computing_device,
+        window_size,
+        overlap_size,
+        None,
+        cancel_token,
+    )
+    .with_range(range);
+    if let Some(duration) = segment_duration {
+        analyzer = analyzer.with_segments(duration);
+    }
+    for extractor in extractors {
+        analyzer = analyzer.with_extractor(extractor);
+    }
+
+    let audio_desc = measure_time!(
+        &format!("[{:?}] Analyzer", computing_device),
+        analyzer.process(file_path)
+    )?;
+
+    let (bpm, bpm_confidence, beat_offset) = match &audio_desc.tempo {
+        Some(tempo) => (
+            tempo.bpm as f32,
+            tempo.confidence as f32,
+            tempo.offset as f32,
+        ),
+        None => (0.0, 0.0, 0.0),
+    };
+    let measured_loudness = audio_desc.loudness.unwrap_or(Loudness {
+        integrated: f64::NEG_INFINITY,
+        range: 0.0,
+        true_peak: 0.0,
+    });
+
+    let features = spectral_features(&audio_desc.spectrum, audio_desc.sample_rate, window_size)?;
+
+    let segments = audio_desc
+        .segments
+        .iter()
+        .map(|segment| {
+            let features =
+                spectral_features(&segment.spectrum, audio_desc.sample_rate, window_size)?;
+
+            Ok(AnalysisSegment {
+                start: segment.start as f32,
+                duration: segment.duration as f32,
+                window_count: segment.window_count as u32,
+                rms: segment.rms,
+                zcr: segment.zcr,
+                energy: segment.energy,
+                spectral_centroid: features.spectral_centroid,
+                spectral_flatness: features.spectral_flatness,
+                spectral_rolloff: features.spectral_rolloff,
+                spectral_spread: features.spectral_spread,
+                chromagram: features.chromagram,
+                mfcc: features.mfcc,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Create and return the analysis result
+    let result = AnalysisResult {
+        stat: AudioStat {
+            sample_rate: audio_desc.sample_rate,
+            duration: audio_desc.duration,
+            total_samples: audio_desc.total_samples,
+        },
+        parameters: AnalysisParameter::new(window_size, overlap_size, computing_device),
+        rms: audio_desc.rms,
+        zcr: audio_desc.zcr,
+        energy: audio_desc.energy,
+        spectral_centroid: features.spectral_centroid,
+        spectral_flatness: features.spectral_flatness,
+        spectral_flux: features.spectral_flux,
+        spectral_slope: features.spectral_slope,
+        spectral_rolloff: features.spectral_rolloff,
+        spectral_spread: features.spectral_spread,
+        spectral_skewness: features.spectral_skewness,
+        spectral_kurtosis: features.spectral_kurtosis,
+        chromagram: features.chromagram,
+        perceptual_loudness: features.perceptual_loudness,
+        perceptual_spread: features.perceptual_spread,
+        perceptual_sharpness: features.perceptual_sharpness,
+        mfcc: features.mfcc,
+        integrated_loudness: measured_loudness.integrated as f32,
+        loudness_range: measured_loudness.range as f32,
+        true_peak: measured_loudness.true_peak as f32,
+        bpm,
+        bpm_confidence,
+        beat_offset,
+        key: audio_desc.key.map(|x| x.key),
+        key_confidence: audio_desc
+            .key
+            .map(|x| x.confidence as f32)
+            .unwrap_or_default(),
+    };
+
+    let features = analyzer.finish_extractors(&result)?;
+
+    Ok(AnalysisOutput {
+        result,
+        segments,
+        features,
+    })
 }
 
 #[derive(Debug, Clone, Copy)]
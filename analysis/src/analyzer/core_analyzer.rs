@@ -1,3 +1,4 @@
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
 
 use log::debug;
@@ -8,35 +9,65 @@ use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal};
 use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
 use symphonia::core::conv::IntoSample;
 use symphonia::core::errors::Error;
-use symphonia::core::formats::FormatReader;
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
 use symphonia::core::sample::Sample;
+use symphonia::core::units::Time;
 use symphonia::default::get_codecs;
 use tokio_util::sync::CancellationToken;
 
+use crate::analysis::{AnalysisResult, AudioRange};
 use crate::analyzer::cpu_sub_analyzer::CpuSubAnalyzer;
 use crate::analyzer::gpu_sub_analyzer::GpuSubAnalyzer;
 use crate::analyzer::sub_analyzer::SubAnalyzer;
-use crate::utils::audio_description::AudioDescription;
+use crate::error::AnalysisError;
+use crate::extractor::{ExtractedFeatures, FeatureExtractor};
+use crate::utils::audio_description::{AudioDescription, SegmentDescription};
 use crate::utils::audio_metadata_reader::*;
 use crate::utils::computing_device::ComputingDevice;
+use crate::utils::key::KeyDetector;
+use crate::utils::loudness_meter::{channel_weights, LoudnessMeter};
+use crate::utils::tempo::TempoDetector;
 
 macro_rules! check_cancellation {
     ($self:expr) => {
         if $self.is_cancelled || ($self.fn_is_cancelled)() {
             $self.is_cancelled = true;
-            return;
+            return Err(AnalysisError::Cancelled);
         }
     };
 
     ($self:expr,$func:expr) => {{
         if $self.is_cancelled || ($self.fn_is_cancelled)() {
             $self.is_cancelled = true;
-            return;
+            return Err(AnalysisError::Cancelled);
         }
         $func;
     }};
 }
 
+/// Running sums over the windows of one segment.
+struct SegmentAccumulator {
+    spectrum: Vec<Complex<f32>>,
+    spectrum_count: usize,
+    count: usize,
+    rms: f32,
+    zcr: usize,
+    energy: f32,
+}
+
+impl SegmentAccumulator {
+    fn new(window_size: usize) -> Self {
+        SegmentAccumulator {
+            spectrum: vec![Complex::new(0.0, 0.0); window_size],
+            spectrum_count: 0,
+            count: 0,
+            rms: 0.0,
+            zcr: 0,
+            energy: 0.0,
+        }
+    }
+}
+
 pub struct Analyzer {
     pub batch_size: usize,
     pub window_size: usize,
@@ -52,6 +83,7 @@ pub struct Analyzer {
     total_samples: usize,
     sample_rate: u32,
     duration_in_seconds: f64,
+    range: AudioRange,
     pub total_rms: f32,
     pub total_zcr: usize,
     pub total_energy: f32,
@@ -59,6 +91,17 @@ pub struct Analyzer {
     resample_ratio: f64,
     pub resampler: Option<FftFixedInOut<f32>>,
     pub resampler_output_buffer: Vec<Vec<f32>>,
+    // Runs on the original channels and sample rate
+    loudness_meter: Option<LoudnessMeter>,
+    frame_buffer: Vec<f32>,
+    tempo_detector: Option<TempoDetector>,
+    key_detector: Option<KeyDetector>,
+    mono_buffer: Vec<f32>,
+    // Segment mode, off unless a segment duration is set
+    segment_duration: Option<f64>,
+    segments: Vec<SegmentAccumulator>,
+    spectrum_count: usize,
+    extractors: Vec<Box<dyn FeatureExtractor>>,
     sub_analyzer: Arc<Mutex<dyn SubAnalyzer>>,
 }
 
@@ -87,7 +130,8 @@ impl Analyzer {
 
             fn_is_cancelled: Box::new(move || {
                 cancel_token
-                    .as_ref().is_some_and(|token| token.is_cancelled())
+                    .as_ref()
+                    .is_some_and(|token| token.is_cancelled())
             }),
             is_cancelled: false,
 
@@ -95,6 +139,7 @@ impl Analyzer {
             total_samples: 0,
             sample_rate: 0,
             duration_in_seconds: 0.0,
+            range: AudioRange::default(),
             total_rms: 0.0,
             total_zcr: 0,
             total_energy: 0.0,
@@ -102,6 +147,15 @@ impl Analyzer {
             resample_ratio: 0.0,
             resampler: None,
             resampler_output_buffer: vec![],
+            loudness_meter: None,
+            frame_buffer: vec![],
+            tempo_detector: None,
+            key_detector: None,
+            mono_buffer: vec![],
+            segment_duration: None,
+            segments: vec![],
+            spectrum_count: 0,
+            extractors: vec![],
 
             sub_analyzer: if computing_device == ComputingDevice::Gpu {
                 Arc::new(Mutex::new(GpuSubAnalyzer::new(window_size, batch_size)))
@@ -111,32 +165,169 @@ impl Analyzer {
         }
     }
 
-    pub fn process(&mut self, file_path: &str) -> Option<AudioDescription> {
-        let mut format = get_format(file_path).expect("no supported audio tracks");
+    /// Also describe every `duration` seconds of the audio on its own, in
+    /// `AudioDescription::segments`.
+    pub fn with_segments(mut self, duration: f64) -> Self {
+        self.segment_duration = Some(duration);
+        self
+    }
+
+    /// Only analyze a range of the file, which the results then describe.
+    pub fn with_range(mut self, range: AudioRange) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Registers a feature extractor, fed with the samples of the track.
+    /// Its values are collected with `finish_extractors`.
+    pub fn with_extractor(mut self, extractor: Box<dyn FeatureExtractor>) -> Self {
+        self.extractors.push(extractor);
+        self
+    }
+
+    /// The values of every registered extractor, once the track is
+    /// processed.
+    pub fn finish_extractors(
+        &mut self,
+        result: &AnalysisResult,
+    ) -> Result<Vec<ExtractedFeatures>, AnalysisError> {
+        self.extractors
+            .iter_mut()
+            .map(|extractor| {
+                let values = extractor.finish(result)?;
+                if values.len() != extractor.dimensions() {
+                    return Err(AnalysisError::Feature(anyhow::anyhow!(
+                        "Extractor {} returned {} values instead of {}",
+                        extractor.name(),
+                        values.len(),
+                        extractor.dimensions()
+                    )));
+                }
+
+                Ok(ExtractedFeatures {
+                    name: extractor.name(),
+                    version: extractor.version(),
+                    values,
+                })
+            })
+            .collect()
+    }
+
+    /// Segment of the window starting at the given window index.
+    fn segment_index(&self, window: usize) -> Option<usize> {
+        let duration = self.segment_duration?;
+        let hop = self.window_size - self.overlap_size;
+        // A segment holds at least one window
+        let segment_samples = ((duration * self.sample_rate as f64) as usize).max(hop);
+
+        Some(window * hop / segment_samples)
+    }
+
+    fn segment_mut(&mut self, window: usize) -> Option<&mut SegmentAccumulator> {
+        let index = self.segment_index(window)?;
+        while self.segments.len() <= index {
+            self.segments
+                .push(SegmentAccumulator::new(self.window_size));
+        }
+
+        self.segments.get_mut(index)
+    }
+
+    /// Adds the time domain statistics of the next window.
+    pub fn add_window_stats(&mut self, rms: f32, zcr: usize, energy: f32) {
+        self.total_rms += rms;
+        self.total_zcr += zcr;
+        self.total_energy += energy;
+
+        if let Some(segment) = self.segment_mut(self.count) {
+            segment.rms += rms;
+            segment.zcr += zcr;
+            segment.energy += energy;
+            segment.count += 1;
+        }
+
+        self.count += 1;
+    }
+
+    /// Adds the spectrum of the next window. Spectra may arrive later than
+    /// the statistics of their windows when the FFT is batched.
+    pub fn add_window_spectrum(&mut self, spectrum: &[Complex<f32>]) {
+        for (total, value) in self.avg_spectrum.iter_mut().zip(spectrum.iter()) {
+            *total += value;
+        }
+
+        if let Some(segment) = self.segment_mut(self.spectrum_count) {
+            for (total, value) in segment.spectrum.iter_mut().zip(spectrum.iter()) {
+                *total += value;
+            }
+            segment.spectrum_count += 1;
+        }
+
+        self.spectrum_count += 1;
+    }
+
+    fn segment_descriptions(&self) -> Vec<SegmentDescription> {
+        let Some(duration) = self.segment_duration else {
+            return vec![];
+        };
+        let total_duration = self.total_samples as f64 / self.sample_rate as f64;
+        let hop = self.window_size - self.overlap_size;
+        let segment_duration = duration.max(hop as f64 / self.sample_rate as f64);
+
+        self.segments
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.count > 0)
+            .map(|(index, x)| {
+                let start = index as f64 * segment_duration;
+                let spectrum_count = x.spectrum_count.max(1) as f32;
+
+                SegmentDescription {
+                    start,
+                    duration: segment_duration.min(total_duration - start).max(0.0),
+                    window_count: x.count,
+                    spectrum: x.spectrum.iter().map(|x| x / spectrum_count).collect(),
+                    rms: x.rms / x.count as f32,
+                    zcr: x.zcr as f32 / x.count as f32,
+                    energy: x.energy / x.count as f32,
+                }
+            })
+            .collect()
+    }
+
+    pub fn process(&mut self, file_path: &str) -> Result<AudioDescription, AnalysisError> {
+        let mut format =
+            get_format(file_path).map_err(|e| match e.downcast::<std::io::Error>() {
+                Ok(e) => AnalysisError::Io(e),
+                Err(e) => AnalysisError::UnsupportedFormat(format!("{:#}", e)),
+            })?;
         let track = format
             .tracks()
             .iter()
             .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .expect("No supported audio tracks");
+            .ok_or_else(|| AnalysisError::UnsupportedFormat("no supported audio tracks".into()))?;
 
-        let (sample_rate, duration_in_seconds) = get_codec_information(track).unwrap();
+        let (sample_rate, duration_in_seconds) = get_codec_information(track)
+            .map_err(|e| AnalysisError::UnsupportedCodec(e.to_string()))?;
         self.sample_rate = sample_rate;
-        self.duration_in_seconds = duration_in_seconds;
+        self.duration_in_seconds =
+            (self.range.end.unwrap_or(duration_in_seconds) - self.range.start).max(0.0);
+        for extractor in self.extractors.iter_mut() {
+            extractor.start(sample_rate);
+        }
 
         let dec_opts: DecoderOptions = Default::default();
         let mut decoder = get_codecs()
             .make(&track.codec_params, &dec_opts)
-            .expect("unsupported codec");
+            .map_err(|e| AnalysisError::UnsupportedCodec(e.to_string()))?;
 
         let track_id = track.id;
 
-        if self.is_cancelled || (self.fn_is_cancelled)() {
-            return None;
-        }
+        check_cancellation!(self);
 
-        self.process_audio_stream(&mut format, &mut decoder, track_id);
+        self.process_audio_stream(&mut format, &mut decoder, track_id)?;
 
-        Some(AudioDescription {
+        Ok(AudioDescription {
             sample_rate: self.sample_rate,
             duration: self.duration_in_seconds,
             total_samples: self.total_samples,
@@ -144,6 +335,10 @@ impl Analyzer {
             rms: self.total_rms / self.count as f32,
             zcr: self.total_zcr / self.count,
             energy: self.total_energy / self.count as f32,
+            loudness: self.loudness_meter.as_ref().map(|x| x.finish()),
+            tempo: self.tempo_detector.as_ref().and_then(|x| x.finish(false)),
+            key: self.key_detector.as_ref().and_then(|x| x.finish()),
+            segments: self.segment_descriptions(),
         })
     }
 
@@ -154,19 +349,35 @@ impl Analyzer {
             .process_audio_chunk(self, chunk, force);
     }
 
-    fn process_audio_buffer<T>(&mut self, buf: &AudioBuffer<T>)
+    /// Processes the given frames of a decoded buffer, the ones inside the
+    /// analyzed range.
+    fn process_audio_buffer<T>(&mut self, buf: &AudioBuffer<T>, frames: Range<usize>)
     where
         T: Sample + IntoSample<f32>,
     {
-        let frames = buf.frames();
         let num_channels = buf.spec().channels.count();
 
-        for frame_idx in 0..frames {
-            let mixed_sample: f32 = (0..num_channels)
-                .map(|ch| IntoSample::<f32>::into_sample(buf.chan(ch)[frame_idx]))
-                .sum::<f32>()
-                / num_channels as f32;
+        // Taken out while the chunks are processed, and put back afterwards
+        let mut loudness_meter = self.loudness_meter.take().unwrap_or_else(|| {
+            LoudnessMeter::new(self.sample_rate, channel_weights(buf.spec().channels))
+        });
+        // Frames of a layout other than the first one are not measured
+        let measure = loudness_meter.channels() == num_channels;
+        self.mono_buffer.clear();
+
+        for frame_idx in frames {
+            self.frame_buffer.clear();
+            self.frame_buffer.extend(
+                (0..num_channels).map(|ch| IntoSample::<f32>::into_sample(buf.chan(ch)[frame_idx])),
+            );
+
+            if measure {
+                loudness_meter.push_frame(&self.frame_buffer);
+            }
+
+            let mixed_sample: f32 = self.frame_buffer.iter().sum::<f32>() / num_channels as f32;
 
+            self.mono_buffer.push(mixed_sample);
             self.sample_buffer.push(mixed_sample);
             self.total_samples += 1;
 
@@ -177,6 +388,17 @@ impl Analyzer {
                     .drain(..(self.window_size - self.overlap_size));
             }
         }
+
+        self.loudness_meter = Some(loudness_meter);
+        self.tempo_detector
+            .get_or_insert_with(|| TempoDetector::new(self.sample_rate))
+            .push(&self.mono_buffer);
+        self.key_detector
+            .get_or_insert_with(|| KeyDetector::new(self.sample_rate))
+            .push(&self.mono_buffer);
+        for extractor in self.extractors.iter_mut() {
+            extractor.push(&self.mono_buffer);
+        }
     }
 
     fn process_audio_stream(
@@ -184,13 +406,13 @@ impl Analyzer {
         format: &mut Box<dyn FormatReader>,
         decoder: &mut Box<dyn Decoder>,
         track_id: u32,
-    ) {
+    ) -> Result<(), AnalysisError> {
         self.resample_ratio = 11025_f64 / self.sample_rate as f64;
         self.actual_data_size = ((self.window_size) as f64 / self.resample_ratio).ceil() as usize;
 
         self.resampler = Some(
             FftFixedInOut::<f32>::new(self.sample_rate as usize, 11025, self.actual_data_size, 1)
-                .unwrap(),
+                .map_err(|e| AnalysisError::UnsupportedCodec(e.to_string()))?,
         );
         self.resampler_output_buffer = self
             .resampler
@@ -200,6 +422,44 @@ impl Analyzer {
 
         self.actual_data_size = self.resampler.as_mut().unwrap().input_frames_max();
 
+        // Undecodable packets are skipped, but a file none of whose packets
+        // decode is an error
+        let mut last_decode_error: Option<Error> = None;
+
+        // Frames of the file, where packets are placed by their timestamp
+        // when the format gives one
+        let time_base = format
+            .tracks()
+            .iter()
+            .find(|x| x.id == track_id)
+            .and_then(|x| x.codec_params.time_base);
+        let sample_rate = self.sample_rate as f64;
+        let to_frame = |time: Time| ((time.seconds as f64 + time.frac) * sample_rate) as u64;
+        let start_frame = (self.range.start * sample_rate) as u64;
+        let end_frame = self
+            .range
+            .end
+            .map_or(u64::MAX, |x| (x * sample_rate) as u64);
+        let mut position: u64 = 0;
+
+        if self.range.start > 0.0 {
+            match format.seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(self.range.start),
+                    track_id: Some(track_id),
+                },
+            ) {
+                Ok(seeked) => {
+                    position =
+                        time_base.map_or(start_frame, |x| to_frame(x.calc_time(seeked.actual_ts)));
+                    decoder.reset();
+                }
+                // Decoding from the start reaches the range as well, slower
+                Err(e) => debug!("Failed to seek to the range: {}", e),
+            }
+        }
+
         // Decode loop.
         loop {
             // Check for cancellation
@@ -208,12 +468,16 @@ impl Analyzer {
             // Get the next packet from the media format.
             let packet = match format.next_packet() {
                 Ok(packet) => packet,
-                Err(Error::ResetRequired) => unimplemented!(),
+                Err(Error::ResetRequired) => {
+                    return Err(AnalysisError::Decode(
+                        "track list changed mid-stream".into(),
+                    ))
+                }
                 Err(Error::IoError(_)) => {
                     debug!("End of stream");
                     break;
                 }
-                Err(err) => panic!("{}", err),
+                Err(err) => return Err(AnalysisError::Decode(err.to_string())),
             };
             debug!("Packet received: track_id = {}", packet.track_id());
 
@@ -222,65 +486,124 @@ impl Analyzer {
                 continue;
             }
 
+            if let Some(time_base) = time_base {
+                position = to_frame(time_base.calc_time(packet.ts()));
+            }
+            if position >= end_frame {
+                debug!("End of range");
+                break;
+            }
+
             // Decode the packet into audio samples.
             let decoded = match decoder.decode(&packet) {
                 Ok(decoded) => decoded,
-                Err(Error::IoError(_)) => {
+                Err(err @ Error::IoError(_)) => {
                     debug!("IO Error while decoding");
+                    last_decode_error = Some(err);
                     continue;
                 }
-                Err(Error::DecodeError(_)) => {
+                Err(err @ Error::DecodeError(_)) => {
                     debug!("Decode Error");
+                    last_decode_error = Some(err);
                     continue;
                 }
-                Err(err) => panic!("{}", err),
+                Err(err) => return Err(AnalysisError::Decode(err.to_string())),
             };
             debug!("Packet decoded successfully");
 
+            let frame_count = decoded.frames() as u64;
+            let frames = (start_frame.saturating_sub(position).min(frame_count) as usize)
+                ..(end_frame.saturating_sub(position).min(frame_count) as usize);
+            position += frame_count;
+            if frames.is_empty() {
+                continue;
+            }
+
             match decoded {
                 AudioBufferRef::U8(buf) => {
                     debug!("Decoded buffer type: U8, length: {}", buf.frames());
-                    check_cancellation!(self, self.process_audio_buffer(buf.as_ref()));
+                    check_cancellation!(
+                        self,
+                        self.process_audio_buffer(buf.as_ref(), frames.clone())
+                    );
                 }
                 AudioBufferRef::U16(buf) => {
                     debug!("Decoded buffer type: U16, length: {}", buf.frames());
-                    check_cancellation!(self, self.process_audio_buffer(buf.as_ref()));
+                    check_cancellation!(
+                        self,
+                        self.process_audio_buffer(buf.as_ref(), frames.clone())
+                    );
                 }
                 AudioBufferRef::U24(buf) => {
                     debug!("Decoded buffer type: U24, length: {}", buf.frames());
-                    check_cancellation!(self, self.process_audio_buffer(buf.as_ref()));
+                    check_cancellation!(
+                        self,
+                        self.process_audio_buffer(buf.as_ref(), frames.clone())
+                    );
                 }
                 AudioBufferRef::U32(buf) => {
                     debug!("Decoded buffer type: U32, length: {}", buf.frames());
-                    check_cancellation!(self, self.process_audio_buffer(buf.as_ref()));
+                    check_cancellation!(
+                        self,
+                        self.process_audio_buffer(buf.as_ref(), frames.clone())
+                    );
                 }
                 AudioBufferRef::S8(buf) => {
                     debug!("Decoded buffer type: S8, length: {}", buf.frames());
-                    check_cancellation!(self, self.process_audio_buffer(buf.as_ref()));
+                    check_cancellation!(
+                        self,
+                        self.process_audio_buffer(buf.as_ref(), frames.clone())
+                    );
                 }
                 AudioBufferRef::S16(buf) => {
                     debug!("Decoded buffer type: S16, length: {}", buf.frames());
-                    check_cancellation!(self, self.process_audio_buffer(buf.as_ref()));
+                    check_cancellation!(
+                        self,
+                        self.process_audio_buffer(buf.as_ref(), frames.clone())
+                    );
                 }
                 AudioBufferRef::S24(buf) => {
                     debug!("Decoded buffer type: S24, length: {}", buf.frames());
-                    check_cancellation!(self, self.process_audio_buffer(buf.as_ref()));
+                    check_cancellation!(
+                        self,
+                        self.process_audio_buffer(buf.as_ref(), frames.clone())
+                    );
                 }
                 AudioBufferRef::S32(buf) => {
                     debug!("Decoded buffer type: S32, length: {}", buf.frames());
-                    check_cancellation!(self, self.process_audio_buffer(buf.as_ref()));
+                    check_cancellation!(
+                        self,
+                        self.process_audio_buffer(buf.as_ref(), frames.clone())
+                    );
                 }
                 AudioBufferRef::F32(buf) => {
                     debug!("Decoded buffer type: F32, length: {}", buf.frames());
-                    check_cancellation!(self, self.process_audio_buffer(buf.as_ref()));
+                    check_cancellation!(
+                        self,
+                        self.process_audio_buffer(buf.as_ref(), frames.clone())
+                    );
                 }
                 AudioBufferRef::F64(buf) => {
                     debug!("Decoded buffer type: F64, length: {}", buf.frames());
-                    check_cancellation!(self, self.process_audio_buffer(buf.as_ref()));
+                    check_cancellation!(
+                        self,
+                        self.process_audio_buffer(buf.as_ref(), frames.clone())
+                    );
                 }
             }
         }
 
+        if self.total_samples == 0 {
+            if let Some(err) = last_decode_error {
+                return Err(AnalysisError::Decode(err.to_string()));
+            }
+        }
+
+        // Less than one analysis window can not be resampled
+        if self.total_samples < self.actual_data_size {
+            return Err(AnalysisError::TooShort(self.total_samples));
+        }
+
         if !self.sample_buffer.is_empty() {
             // Pad to the nearest multiple of 1024
             let target_size = ((self.total_samples + 1023) / 1024) * 1024;
@@ -297,7 +620,7 @@ impl Analyzer {
         debug!("Total samples: {}", self.total_samples);
 
         if self.count == 0 {
-            panic!("No audio data processed");
+            return Err(AnalysisError::TooShort(self.total_samples));
         }
 
         // Calculate the final average spectrum.
@@ -305,5 +628,7 @@ impl Analyzer {
             *value /= self.count as f32;
         }
         debug!("Final average spectrum calculated");
+
+        Ok(())
     }
 }
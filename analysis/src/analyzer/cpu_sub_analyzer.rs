@@ -17,6 +17,7 @@ pub struct CpuSubAnalyzer {
     fft_input_buffer: Vec<f32>,
     batch_cache_buffer_count: usize,
     hanning_window: Vec<f32>,
+    full_spectrum: Vec<Complex<f32>>,
 }
 
 impl CpuSubAnalyzer {
@@ -33,6 +34,7 @@ impl CpuSubAnalyzer {
             fft_input_buffer,
             batch_cache_buffer_count: 0,
             hanning_window: build_hanning_window(window_size),
+            full_spectrum: vec![Complex::new(0.0, 0.0); window_size],
         }
     }
 }
@@ -48,9 +50,11 @@ impl SubAnalyzer for CpuSubAnalyzer {
 
         let resampled_chunk = &core_analyzer.resampler_output_buffer[0];
 
-        core_analyzer.total_rms += rms(resampled_chunk);
-        core_analyzer.total_zcr += zcr(resampled_chunk);
-        core_analyzer.total_energy += energy(resampled_chunk);
+        let (chunk_rms, chunk_zcr, chunk_energy) = (
+            rms(resampled_chunk),
+            zcr(resampled_chunk),
+            energy(resampled_chunk),
+        );
 
         let start_idx = self.batch_cache_buffer_count * core_analyzer.window_size;
         let buffer_slice =
@@ -60,7 +64,7 @@ impl SubAnalyzer for CpuSubAnalyzer {
         }
 
         self.batch_cache_buffer_count += 1;
-        core_analyzer.count += 1;
+        core_analyzer.add_window_stats(chunk_rms, chunk_zcr, chunk_energy);
 
         let cpu_fft = self.cpu_fft.as_ref();
 
@@ -79,14 +83,14 @@ impl SubAnalyzer for CpuSubAnalyzer {
             for batch_idx in 0..batch_count {
                 let _start = batch_idx * core_analyzer.window_size;
                 // Copy the real FFT output directly
-                for i in 0..half_window + 1 {
-                    core_analyzer.avg_spectrum[i] += self.fft_output_buffer[i];
-                }
+                self.full_spectrum[..half_window + 1]
+                    .copy_from_slice(&self.fft_output_buffer[..half_window + 1]);
                 // Reconstruct the conjugate symmetric part
                 for i in 1..half_window {
-                    core_analyzer.avg_spectrum[core_analyzer.window_size - i] +=
+                    self.full_spectrum[core_analyzer.window_size - i] =
                         self.fft_output_buffer[i].conj();
                 }
+                core_analyzer.add_window_spectrum(&self.full_spectrum);
             }
 
             self.batch_cache_buffer_count = 0;
@@ -37,9 +37,11 @@ impl SubAnalyzer for GpuSubAnalyzer {
             .process(&[chunk], None)
             .unwrap()[0];
 
-        core_analyzer.total_rms += rms(resampled_chunk);
-        core_analyzer.total_zcr += zcr(resampled_chunk);
-        core_analyzer.total_energy += energy(resampled_chunk);
+        let (chunk_rms, chunk_zcr, chunk_energy) = (
+            rms(resampled_chunk),
+            zcr(resampled_chunk),
+            energy(resampled_chunk),
+        );
 
         let start_idx = self.batch_cache_buffer_count * core_analyzer.window_size;
         let buffer_slice =
@@ -49,7 +51,7 @@ impl SubAnalyzer for GpuSubAnalyzer {
         }
 
         self.batch_cache_buffer_count += 1;
-        core_analyzer.count += 1;
+        core_analyzer.add_window_stats(chunk_rms, chunk_zcr, chunk_energy);
 
         if force || self.batch_cache_buffer_count >= core_analyzer.batch_size {
             pollster::block_on(self.gpu_fft.compute_fft(&mut self.batch_fft_buffer));
@@ -63,9 +65,9 @@ impl SubAnalyzer for GpuSubAnalyzer {
             // Accumulate spectrums for all batches
             for batch_idx in 0..batch_count {
                 let start = batch_idx * core_analyzer.window_size;
-                for i in 0..core_analyzer.window_size {
-                    core_analyzer.avg_spectrum[i] += self.batch_fft_buffer[start + i];
-                }
+                core_analyzer.add_window_spectrum(
+                    &self.batch_fft_buffer[start..start + core_analyzer.window_size],
+                );
             }
 
             self.batch_cache_buffer_count = 0;
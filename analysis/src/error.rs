@@ -0,0 +1,35 @@
+use thiserror::Error;
+
+/// Why a file could not be analyzed.
+#[derive(Error, Debug)]
+pub enum AnalysisError {
+    #[error("Failed to open media: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Unsupported format: {0}")]
+    UnsupportedFormat(String),
+    #[error("Unsupported codec: {0}")]
+    UnsupportedCodec(String),
+    #[error("Decode error: {0}")]
+    Decode(String),
+    #[error("Audio too short: {0} samples")]
+    TooShort(usize),
+    #[error("Analysis cancelled")]
+    Cancelled,
+    #[error("Feature extraction failed: {0}")]
+    Feature(#[from] anyhow::Error),
+}
+
+impl AnalysisError {
+    /// Short stable name of the error kind, suitable for storage.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AnalysisError::Io(_) => "io",
+            AnalysisError::UnsupportedFormat(_) => "unsupported_format",
+            AnalysisError::UnsupportedCodec(_) => "unsupported_codec",
+            AnalysisError::Decode(_) => "decode",
+            AnalysisError::TooShort(_) => "too_short",
+            AnalysisError::Cancelled => "cancelled",
+            AnalysisError::Feature(_) => "feature",
+        }
+    }
+}
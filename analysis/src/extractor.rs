@@ -0,0 +1,248 @@
+use anyhow::{bail, Result};
+
+use crate::analysis::{normalize_analysis_result, AnalysisResult};
+
+/// Lowest integrated loudness reported by the loudness extractor, in LUFS.
+/// Silence has no loudness, and is reported as this.
+const LOUDNESS_FLOOR: f32 = -70.0;
+
+/// A set of values describing a track, computed while the track is analyzed.
+///
+/// Extractors are registered with the `Analyzer`, which feeds them the mono
+/// samples of the track as it is decoded, and asks them for their values
+/// once the track is analyzed. The values are persisted under the name of
+/// the extractor, and the recommendation index is built from the values of
+/// the enabled extractors.
+pub trait FeatureExtractor {
+    /// Stable identifier, used as the storage key of the values.
+    fn name(&self) -> &'static str;
+    /// Bumped whenever the values change meaning, so that values stored by
+    /// an older version are not mixed with new ones.
+    fn version(&self) -> u32;
+    /// Number of values of every track.
+    fn dimensions(&self) -> usize;
+    /// Name of every value, used to weight them in the recommendation index.
+    fn labels(&self) -> Vec<String> {
+        (0..self.dimensions())
+            .map(|x| format!("{}{}", self.name(), x))
+            .collect()
+    }
+    /// Called with the sample rate of the track, before any sample.
+    fn start(&mut self, _sample_rate: u32) {}
+    /// Feed mono samples at the original sample rate.
+    fn push(&mut self, _samples: &[f32]) {}
+    /// The values of the track, `dimensions()` long.
+    fn finish(&mut self, result: &AnalysisResult) -> Result<Vec<f32>>;
+}
+
+/// Values of one extractor for one track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractedFeatures {
+    pub name: &'static str,
+    pub version: u32,
+    pub values: Vec<f32>,
+}
+
+/// The spectral, chroma, perceptual and MFCC descriptors the recommendation
+/// index was originally built from, in their original 61 value layout.
+pub struct SpectralExtractor;
+
+impl FeatureExtractor for SpectralExtractor {
+    fn name(&self) -> &'static str {
+        "spectral"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn dimensions(&self) -> usize {
+        61
+    }
+
+    fn labels(&self) -> Vec<String> {
+        [
+            "rms",
+            "zcr",
+            "energy",
+            "spectral_centroid",
+            "spectral_flatness",
+            "spectral_slope",
+            "spectral_rolloff",
+            "spectral_spread",
+            "spectral_skewness",
+            "spectral_kurtosis",
+        ]
+        .iter()
+        .map(|x| x.to_string())
+        .chain((0..12).map(|x| format!("chroma{}", x)))
+        .chain(["perceptual_spread".into(), "perceptual_sharpness".into()])
+        .chain((0..24).map(|x| format!("perceptual_loudness{}", x)))
+        .chain((0..13).map(|x| format!("mfcc{}", x)))
+        .collect()
+    }
+
+    fn finish(&mut self, result: &AnalysisResult) -> Result<Vec<f32>> {
+        let normalized = normalize_analysis_result(result);
+
+        Ok([
+            result.rms,
+            normalized.zcr,
+            normalized.energy,
+            normalized.spectral_centroid,
+            normalized.spectral_flatness,
+            normalized.spectral_slope,
+            normalized.spectral_rolloff,
+            normalized.spectral_spread,
+            normalized.spectral_skewness,
+            normalized.spectral_kurtosis,
+        ]
+        .iter()
+        .chain(&normalized.chroma)
+        .chain(&[result.perceptual_spread, result.perceptual_sharpness])
+        .chain(&result.perceptual_loudness)
+        .chain(&result.mfcc)
+        .copied()
+        .collect())
+    }
+}
+
+/// Integrated loudness, loudness range and true peak.
+pub struct LoudnessExtractor;
+
+impl FeatureExtractor for LoudnessExtractor {
+    fn name(&self) -> &'static str {
+        "loudness"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn dimensions(&self) -> usize {
+        3
+    }
+
+    fn labels(&self) -> Vec<String> {
+        vec![
+            "integrated_loudness".into(),
+            "loudness_range".into(),
+            "true_peak".into(),
+        ]
+    }
+
+    fn finish(&mut self, result: &AnalysisResult) -> Result<Vec<f32>> {
+        Ok(vec![
+            result.integrated_loudness.max(LOUDNESS_FLOOR),
+            result.loudness_range,
+            result.true_peak,
+        ])
+    }
+}
+
+/// Tempo and how confident the tempo estimate is.
+pub struct RhythmExtractor;
+
+impl FeatureExtractor for RhythmExtractor {
+    fn name(&self) -> &'static str {
+        "rhythm"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn dimensions(&self) -> usize {
+        2
+    }
+
+    fn labels(&self) -> Vec<String> {
+        vec!["bpm".into(), "bpm_confidence".into()]
+    }
+
+    fn finish(&mut self, result: &AnalysisResult) -> Result<Vec<f32>> {
+        Ok(vec![result.bpm, result.bpm_confidence])
+    }
+}
+
+type ExtractorFactory = fn() -> Box<dyn FeatureExtractor>;
+
+/// The built-in extractors, in the order their values are laid out in the
+/// recommendation index. New extractors are added at the end.
+const EXTRACTORS: &[(&str, ExtractorFactory)] = &[
+    ("spectral", || Box::new(SpectralExtractor)),
+    ("loudness", || Box::new(LoudnessExtractor)),
+    ("rhythm", || Box::new(RhythmExtractor)),
+];
+
+/// Names of every built-in extractor.
+pub fn extractor_names() -> impl Iterator<Item = &'static str> {
+    EXTRACTORS.iter().map(|(name, _)| *name)
+}
+
+/// Creates the built-in extractor with the given name.
+pub fn create_extractor(name: &str) -> Option<Box<dyn FeatureExtractor>> {
+    EXTRACTORS
+        .iter()
+        .find(|(x, _)| *x == name)
+        .map(|(_, create)| create())
+}
+
+/// A selection of the built-in extractors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExtractorSet(u32);
+
+impl ExtractorSet {
+    /// Parses a list of extractor names.
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> Result<Self> {
+        let mut bits = 0;
+        for name in names {
+            let name = name.as_ref().trim();
+            match EXTRACTORS.iter().position(|(x, _)| *x == name) {
+                Some(index) => bits |= 1 << index,
+                None => bail!("Unknown feature extractor: {}", name),
+            }
+        }
+
+        if bits == 0 {
+            bail!("No feature extractor enabled");
+        }
+
+        Ok(ExtractorSet(bits))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        EXTRACTORS
+            .iter()
+            .position(|(x, _)| *x == name)
+            .is_some_and(|index| self.0 & (1 << index) != 0)
+    }
+
+    /// Names of the extractors of the set, in layout order.
+    pub fn names(&self) -> Vec<&'static str> {
+        extractor_names().filter(|x| self.contains(x)).collect()
+    }
+
+    /// Labels of every value of the extractors of the set, in layout order.
+    pub fn labels(&self) -> Vec<String> {
+        self.create().iter().flat_map(|x| x.labels()).collect()
+    }
+
+    /// New instances of the extractors of the set, in layout order.
+    pub fn create(&self) -> Vec<Box<dyn FeatureExtractor>> {
+        EXTRACTORS
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| self.0 & (1 << index) != 0)
+            .map(|(_, (_, create))| create())
+            .collect()
+    }
+}
+
+impl Default for ExtractorSet {
+    /// Only the spectral extractor, like the index was built before
+    /// extractors could be chosen.
+    fn default() -> Self {
+        ExtractorSet(1)
+    }
+}
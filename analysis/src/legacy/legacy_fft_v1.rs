@@ -281,5 +281,9 @@ pub fn fft(
         rms: total_rms / count as f32,
         zcr: total_zcr / count,
         energy: total_energy / count as f32,
+        loudness: None,
+        tempo: None,
+        key: None,
+        segments: vec![],
     })
 }
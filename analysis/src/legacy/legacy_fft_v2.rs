@@ -165,6 +165,10 @@ impl FFTProcessor {
             rms: self.total_rms / self.count as f32,
             zcr: self.total_zcr / self.count,
             energy: self.total_energy / self.count as f32,
+            loudness: None,
+            tempo: None,
+            key: None,
+            segments: vec![],
         })
     }
 
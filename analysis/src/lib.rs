@@ -1,4 +1,6 @@
 pub mod analysis;
+pub mod error;
+pub mod extractor;
 mod tests;
 pub mod utils;
 mod wgpu_fft;
@@ -0,0 +1,169 @@
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+
+    use crate::analysis::{
+        analyze_audio_with_extractors, AnalysisParameter, AnalysisResult, ANALYZER_VERSION,
+    };
+    use crate::error::AnalysisError;
+    use crate::extractor::{create_extractor, ExtractorSet, FeatureExtractor};
+    use crate::tests::{sine, write_wav, SAMPLE_RATE};
+    use crate::utils::computing_device::ComputingDevice;
+
+    /// Two seconds of a 440 Hz tone in a WAV file.
+    fn tone_wav(name: &str) -> PathBuf {
+        write_wav(
+            &format!("extractor-{}", name),
+            &sine(440.0, 0.5, SAMPLE_RATE as usize * 2),
+            None,
+        )
+    }
+
+    /// Measures the duration of the track from the samples it is fed.
+    struct DurationExtractor {
+        sample_rate: u32,
+        samples: usize,
+        dimensions: usize,
+    }
+
+    impl FeatureExtractor for DurationExtractor {
+        fn name(&self) -> &'static str {
+            "duration"
+        }
+
+        fn version(&self) -> u32 {
+            3
+        }
+
+        fn dimensions(&self) -> usize {
+            self.dimensions
+        }
+
+        fn start(&mut self, sample_rate: u32) {
+            self.sample_rate = sample_rate;
+        }
+
+        fn push(&mut self, samples: &[f32]) {
+            self.samples += samples.len();
+        }
+
+        fn finish(&mut self, _result: &AnalysisResult) -> Result<Vec<f32>> {
+            Ok(vec![self.samples as f32 / self.sample_rate as f32])
+        }
+    }
+
+    fn duration_extractor(dimensions: usize) -> Box<dyn FeatureExtractor> {
+        Box::new(DurationExtractor {
+            sample_rate: 0,
+            samples: 0,
+            dimensions,
+        })
+    }
+
+    #[test]
+    fn test_custom_extractor() {
+        let path = tone_wav("custom");
+        let output = analyze_audio_with_extractors(
+            path.to_str().unwrap(),
+            1024,
+            512,
+            None,
+            vec![duration_extractor(1), create_extractor("spectral").unwrap()],
+            ComputingDevice::Cpu,
+            None,
+        )
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(output.features.len(), 2);
+
+        let duration = &output.features[0];
+        assert_eq!((duration.name, duration.version), ("duration", 3));
+        assert!((duration.values[0] - 2.0).abs() < 1e-3, "{:?}", duration);
+
+        let spectral = &output.features[1];
+        assert_eq!(spectral.name, "spectral");
+        assert_eq!(spectral.values.len(), 61);
+        assert_eq!(spectral.values[0], output.result.rms);
+        assert_eq!(spectral.values[60], output.result.mfcc[12]);
+    }
+
+    #[test]
+    fn test_analysis_parameters() {
+        let path = tone_wav("parameters");
+        let output = analyze_audio_with_extractors(
+            path.to_str().unwrap(),
+            2048,
+            1024,
+            None,
+            vec![],
+            ComputingDevice::Cpu,
+            None,
+        )
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let parameters = output.result.parameters;
+        assert_eq!(parameters.version, ANALYZER_VERSION);
+        assert_eq!(
+            parameters,
+            AnalysisParameter::new(2048, 1024, ComputingDevice::Cpu)
+        );
+        assert_eq!(parameters.computing_device, ComputingDevice::Cpu);
+
+        // The device is recorded, but results of both devices are comparable
+        assert_eq!(
+            parameters,
+            AnalysisParameter::new(2048, 1024, ComputingDevice::Gpu)
+        );
+        assert_ne!(
+            parameters,
+            AnalysisParameter::new(1024, 512, ComputingDevice::Cpu)
+        );
+    }
+
+    #[test]
+    fn test_dimension_mismatch() {
+        let path = tone_wav("mismatch");
+        let result = analyze_audio_with_extractors(
+            path.to_str().unwrap(),
+            1024,
+            512,
+            None,
+            vec![duration_extractor(2)],
+            ComputingDevice::Cpu,
+            None,
+        );
+        std::fs::remove_file(path).unwrap();
+
+        assert!(
+            matches!(result, Err(AnalysisError::Feature(_))),
+            "{:?}",
+            result.map(|x| x.features)
+        );
+    }
+
+    #[test]
+    fn test_extractor_set() {
+        let set = ExtractorSet::from_names(&["rhythm", " spectral"]).unwrap();
+        assert_eq!(set.names(), vec!["spectral", "rhythm"]);
+        assert!(set.contains("rhythm"));
+        assert!(!set.contains("loudness"));
+
+        let dimensions: Vec<usize> = set.create().iter().map(|x| x.dimensions()).collect();
+        assert_eq!(dimensions, vec![61, 2]);
+
+        let labels = set.labels();
+        assert_eq!(labels.len(), 63);
+        assert_eq!(labels[2], "energy");
+        assert_eq!(labels[10], "chroma0");
+        assert_eq!(labels[60], "mfcc12");
+        assert_eq!(labels[62], "bpm_confidence");
+
+        assert_eq!(ExtractorSet::default().names(), vec!["spectral"]);
+        assert!(ExtractorSet::from_names(&["spectral", "timbre"]).is_err());
+        assert!(ExtractorSet::from_names::<&str>(&[]).is_err());
+    }
+}
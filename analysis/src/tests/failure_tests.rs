@@ -0,0 +1,129 @@
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tokio_util::sync::CancellationToken;
+
+    use crate::analysis::analyze_audio;
+    use crate::error::AnalysisError;
+    use crate::tests::{sine, write_wav, SAMPLE_RATE};
+    use crate::utils::computing_device::ComputingDevice;
+
+    fn temp_path(name: &str, extension: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "rune-failure-{}-{}.{}",
+            name,
+            std::process::id(),
+            extension
+        ))
+    }
+
+    /// A 440 Hz tone of `samples` samples in a WAV file whose header claims
+    /// `declared_samples` samples, which may be more than are written.
+    fn tone_wav(name: &str, samples: usize, declared_samples: usize) -> PathBuf {
+        write_wav(
+            &format!("failure-{}", name),
+            &sine(440.0, 0.5, samples),
+            Some(declared_samples),
+        )
+    }
+
+    fn analyze(path: &Path) -> Result<(), AnalysisError> {
+        analyze_audio(
+            path.to_str().unwrap(),
+            1024,
+            512,
+            ComputingDevice::Cpu,
+            None,
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn test_header_only_file() {
+        let path = tone_wav("header", 0, SAMPLE_RATE as usize);
+        let result = analyze(&path);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(
+            matches!(
+                result,
+                Err(AnalysisError::TooShort(0)) | Err(AnalysisError::Decode(_))
+            ),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_too_short_file() {
+        let path = tone_wav("short", 100, 100);
+        let result = analyze(&path);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(
+            matches!(result, Err(AnalysisError::TooShort(100))),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        // Half a second of audio in a file claiming four seconds
+        let path = tone_wav(
+            "truncated",
+            SAMPLE_RATE as usize / 2,
+            SAMPLE_RATE as usize * 4,
+        );
+        let result = analyze(&path);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(result.is_ok(), "{:?}", result);
+    }
+
+    #[test]
+    fn test_non_audio_file() {
+        let path = temp_path("text", "mp3");
+        std::fs::write(&path, "This is not an audio file.\n".repeat(100)).unwrap();
+        let result = analyze(&path);
+        std::fs::remove_file(path).unwrap();
+
+        let error = result.unwrap_err();
+        assert!(
+            matches!(error, AnalysisError::UnsupportedFormat(_)),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.kind(), "unsupported_format");
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let result = analyze(&temp_path("missing", "flac"));
+
+        assert!(matches!(result, Err(AnalysisError::Io(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let path = tone_wav("cancelled", SAMPLE_RATE as usize, SAMPLE_RATE as usize);
+        let token = CancellationToken::new();
+        token.cancel();
+
+        let result = analyze_audio(
+            path.to_str().unwrap(),
+            1024,
+            512,
+            ComputingDevice::Cpu,
+            Some(token),
+        );
+        std::fs::remove_file(path).unwrap();
+
+        assert!(
+            matches!(result, Err(AnalysisError::Cancelled)),
+            "{:?}",
+            result.map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,122 @@
+#[cfg(test)]
+mod tests {
+    use crate::utils::key::{estimate_key, Key, KeyDetector, Mode};
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    fn frequency(midi_note: i32) -> f32 {
+        440.0 * 2f32.powf((midi_note - 69) as f32 / 12.0)
+    }
+
+    /// Plays every chord for one second, as sines with a few harmonics.
+    fn synthesize_chords(chords: &[&[i32]]) -> Vec<f32> {
+        let mut samples = vec![];
+
+        for chord in chords {
+            for i in 0..SAMPLE_RATE {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let sample: f32 = chord
+                    .iter()
+                    .flat_map(|note| {
+                        (1..=3).map(move |harmonic| {
+                            (2.0 * std::f32::consts::PI * frequency(*note) * harmonic as f32 * t)
+                                .sin()
+                                / harmonic as f32
+                        })
+                    })
+                    .sum();
+                samples.push(0.1 * sample);
+            }
+        }
+
+        samples
+    }
+
+    fn detect(samples: &[f32]) -> Option<Key> {
+        let mut detector = KeyDetector::new(SAMPLE_RATE);
+        for chunk in samples.chunks(4096) {
+            detector.push(chunk);
+        }
+        detector.finish().map(|x| x.key)
+    }
+
+    #[test]
+    fn test_detect_major_key() {
+        // I IV V I in G major
+        let samples =
+            synthesize_chords(&[&[55, 59, 62], &[60, 64, 67], &[62, 66, 69], &[55, 59, 62]]);
+        assert_eq!(detect(&samples), Some(Key::new(7, Mode::Major)));
+    }
+
+    #[test]
+    fn test_detect_minor_key() {
+        // i iv V i in A minor, the raised seventh tells it from C major
+        let samples =
+            synthesize_chords(&[&[57, 60, 64], &[62, 65, 69], &[64, 68, 71], &[57, 60, 64]]);
+        assert_eq!(detect(&samples), Some(Key::new(9, Mode::Minor)));
+    }
+
+    #[test]
+    fn test_silence() {
+        assert_eq!(detect(&vec![0.0; SAMPLE_RATE as usize * 3]), None);
+        assert_eq!(estimate_key(&[0.0; 12]), None);
+    }
+
+    #[test]
+    fn test_notations() {
+        let c_major = Key::new(0, Mode::Major);
+        let a_minor = Key::new(9, Mode::Minor);
+        let f_sharp_minor = Key::new(6, Mode::Minor);
+
+        assert_eq!(c_major.camelot(), "8B");
+        assert_eq!(c_major.open_key(), "1d");
+        assert_eq!(a_minor.camelot(), "8A");
+        assert_eq!(a_minor.open_key(), "1m");
+        assert_eq!(f_sharp_minor.camelot(), "11A");
+        assert_eq!(f_sharp_minor.open_key(), "4m");
+        assert_eq!(Key::new(4, Mode::Major).camelot(), "12B");
+        assert_eq!(Key::new(11, Mode::Major).camelot(), "1B");
+
+        assert_eq!(c_major.to_string(), "C");
+        assert_eq!(f_sharp_minor.to_string(), "F#m");
+    }
+
+    #[test]
+    fn test_parse() {
+        for code in 0..24 {
+            let key = Key::from_code(code).unwrap();
+            assert_eq!(key.code(), code);
+            assert_eq!(Key::parse(&key.camelot()), Some(key));
+            assert_eq!(Key::parse(&key.open_key()), Some(key));
+            assert_eq!(Key::parse(&key.to_string()), Some(key));
+        }
+
+        assert_eq!(Key::parse("Bb"), Some(Key::new(10, Mode::Major)));
+        assert_eq!(Key::parse("C# minor"), Some(Key::new(1, Mode::Minor)));
+        assert_eq!(Key::parse("8b"), Some(Key::new(0, Mode::Major)));
+        assert_eq!(Key::parse("13A"), None);
+        assert_eq!(Key::parse("H"), None);
+        assert_eq!(Key::from_code(24), None);
+    }
+
+    #[test]
+    fn test_harmonic_neighbours() {
+        let mut neighbours: Vec<String> = Key::parse("8A")
+            .unwrap()
+            .harmonic_neighbours()
+            .iter()
+            .map(|x| x.camelot())
+            .collect();
+        neighbours.sort();
+        assert_eq!(neighbours, vec!["7A", "8A", "8B", "9A"]);
+
+        let mut neighbours: Vec<String> = Key::parse("12B")
+            .unwrap()
+            .harmonic_neighbours()
+            .iter()
+            .map(|x| x.camelot())
+            .collect();
+        neighbours.sort();
+        assert_eq!(neighbours, vec!["11B", "12A", "12B", "1B"]);
+    }
+}
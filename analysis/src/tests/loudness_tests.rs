@@ -0,0 +1,104 @@
+#[cfg(test)]
+mod tests {
+    use crate::utils::loudness_meter::{combined_loudness, Loudness, LoudnessMeter};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Stereo sine with the same signal on both channels, as in the EBU Tech
+    /// 3341 and 3342 test signals.
+    fn sine(meter: &mut LoudnessMeter, frequency: f64, level: f64, phase: f64, duration: f64) {
+        let amplitude = 10f64.powf(level / 20.0);
+        let frames = (duration * SAMPLE_RATE as f64) as usize;
+
+        for i in 0..frames {
+            let t = i as f64 / SAMPLE_RATE as f64;
+            let sample =
+                (amplitude * (2.0 * std::f64::consts::PI * frequency * t + phase).sin()) as f32;
+            meter.push_frame(&[sample, sample]);
+        }
+    }
+
+    fn measure(segments: &[(f64, f64)]) -> Loudness {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE, vec![1.0, 1.0]);
+        for (level, duration) in segments {
+            sine(&mut meter, 1000.0, *level, 0.0, *duration);
+        }
+        meter.finish()
+    }
+
+    #[test]
+    fn test_integrated_loudness() {
+        let loudness = measure(&[(-23.0, 20.0)]);
+        assert!((loudness.integrated + 23.0).abs() < 0.1, "{:?}", loudness);
+
+        let loudness = measure(&[(-33.0, 20.0)]);
+        assert!((loudness.integrated + 33.0).abs() < 0.1, "{:?}", loudness);
+    }
+
+    #[test]
+    fn test_relative_gate() {
+        // EBU Tech 3341 case 3, the quiet parts fall below the relative gate
+        let loudness = measure(&[(-36.0, 10.0), (-23.0, 60.0), (-36.0, 10.0)]);
+        assert!((loudness.integrated + 23.0).abs() < 0.1, "{:?}", loudness);
+    }
+
+    #[test]
+    fn test_silence() {
+        let loudness = measure(&[(-120.0, 5.0)]);
+        assert_eq!(loudness.integrated, f64::NEG_INFINITY);
+        assert_eq!(loudness.range, 0.0);
+    }
+
+    #[test]
+    fn test_loudness_range() {
+        // EBU Tech 3342 cases 1 and 2
+        let loudness = measure(&[(-20.0, 20.0), (-30.0, 20.0)]);
+        assert!((loudness.range - 10.0).abs() < 1.0, "{:?}", loudness);
+
+        let loudness = measure(&[(-20.0, 20.0), (-15.0, 20.0)]);
+        assert!((loudness.range - 5.0).abs() < 1.0, "{:?}", loudness);
+    }
+
+    #[test]
+    fn test_true_peak() {
+        // A quarter of the sample rate, sampled 45 degrees away from its
+        // crests, never has a sample above -3 dBFS
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE, vec![1.0, 1.0]);
+        sine(
+            &mut meter,
+            SAMPLE_RATE as f64 / 4.0,
+            0.0,
+            std::f64::consts::FRAC_PI_4,
+            1.0,
+        );
+        let loudness = meter.finish();
+
+        assert!(loudness.true_peak > 0.95, "{:?}", loudness);
+        assert!(loudness.true_peak < 1.05, "{:?}", loudness);
+    }
+
+    #[test]
+    fn test_combined_loudness() {
+        // Measuring the tracks one by one agrees with measuring them together
+        // when no block falls under the relative gate
+        let segments = [(-20.0, 20.0), (-26.0, 40.0)];
+        let album = measure(&segments);
+        let tracks: Vec<(f64, f64)> = segments
+            .iter()
+            .map(|(level, duration)| (measure(&[(*level, *duration)]).integrated, *duration))
+            .collect();
+
+        let combined = combined_loudness(&tracks);
+        assert!(
+            (combined - album.integrated).abs() < 0.2,
+            "{} {:?}",
+            combined,
+            album
+        );
+
+        assert_eq!(combined_loudness(&[]), f64::NEG_INFINITY);
+        assert!(
+            (combined_loudness(&[(-23.0, 10.0), (f64::NEG_INFINITY, 10.0)]) + 23.0).abs() < 1e-9
+        );
+    }
+}
@@ -1,2 +1,57 @@
 pub mod analyzer_tests;
+pub mod extractor_tests;
+pub mod failure_tests;
 pub mod fft_tests;
+pub mod key_tests;
+pub mod loudness_tests;
+pub mod onset_tests;
+pub mod scaling_tests;
+pub mod segment_tests;
+pub mod tempo_tests;
+
+#[cfg(test)]
+use std::path::PathBuf;
+
+#[cfg(test)]
+pub const SAMPLE_RATE: u32 = 22050;
+
+/// A sine tone of `len` samples at [`SAMPLE_RATE`].
+#[cfg(test)]
+pub fn sine(frequency: f32, amplitude: f32, len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| {
+            amplitude
+                * (2.0 * std::f32::consts::PI * frequency * i as f32 / SAMPLE_RATE as f32).sin()
+        })
+        .collect()
+}
+
+/// Writes mono 16 bit PCM samples at [`SAMPLE_RATE`] as a WAV file in the
+/// temporary directory. The header claims `declared_samples` samples when
+/// given, which may be more than are written.
+#[cfg(test)]
+pub fn write_wav(name: &str, samples: &[f32], declared_samples: Option<usize>) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rune-{}-{}.wav", name, std::process::id()));
+    let data_size = (declared_samples.unwrap_or(samples.len()) * 2) as u32;
+
+    let mut bytes = vec![];
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    std::fs::write(&path, bytes).unwrap();
+    path
+}
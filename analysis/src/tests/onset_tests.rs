@@ -0,0 +1,44 @@
+#[cfg(test)]
+mod tests {
+    use crate::utils::onset::OnsetDetector;
+
+    fn synthesize_clicks(times: &[f64], duration: f64, sample_rate: u32) -> Vec<f32> {
+        let mut samples = vec![0.0; (duration * sample_rate as f64) as usize];
+        let burst = (0.02 * sample_rate as f64) as usize;
+
+        for time in times {
+            let start = (time * sample_rate as f64) as usize;
+            for i in 0..burst {
+                if let Some(sample) = samples.get_mut(start + i) {
+                    let t = i as f32 / sample_rate as f32;
+                    *sample = 0.5 * (2.0 * std::f32::consts::PI * 1000.0 * t).sin();
+                }
+            }
+        }
+
+        samples
+    }
+
+    #[test]
+    fn test_detect_clicks() {
+        let sample_rate = 44100;
+        let times = [0.5, 1.25, 2.0, 3.3];
+        let samples = synthesize_clicks(&times, 4.0, sample_rate);
+
+        let mut detector = OnsetDetector::new(sample_rate);
+        for chunk in samples.chunks(4096) {
+            detector.push(chunk);
+        }
+        let onsets = detector.finish();
+
+        assert_eq!(onsets.len(), times.len(), "{:?}", onsets);
+        for (onset, time) in onsets.iter().zip(times.iter()) {
+            assert!(
+                (onset.time - time).abs() < 0.02,
+                "onset at {} instead of {}",
+                onset.time,
+                time
+            );
+        }
+    }
+}
@@ -0,0 +1,189 @@
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::utils::scaling::{feature_weights, FeatureScaler, ScalingMethod};
+
+    const GENRES: usize = 8;
+    const TRACKS_PER_GENRE: usize = 40;
+    const NEIGHBOURS: usize = 10;
+
+    fn normal(rng: &mut StdRng, mean: f32, deviation: f32) -> f32 {
+        // Box-Muller transform
+        let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+        let u2: f32 = rng.gen();
+        mean + deviation * (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+    }
+
+    /// A synthetic library laid out like the spectral vectors: two large
+    /// dimensions unrelated to the genre, like energy and rolloff, followed by
+    /// twelve small ones telling genres apart, like the chroma bins. Some
+    /// tracks have an outlier in one of the small dimensions.
+    fn library(rng: &mut StdRng) -> (Vec<Vec<f32>>, Vec<usize>) {
+        let profiles: Vec<Vec<f32>> = (0..GENRES)
+            .map(|_| (0..12).map(|_| rng.gen_range(0.0..1.0)).collect())
+            .collect();
+
+        let mut vectors = vec![];
+        let mut genres = vec![];
+        for (genre, profile) in profiles.iter().enumerate() {
+            for _ in 0..TRACKS_PER_GENRE {
+                let mut vector = vec![normal(rng, 5000.0, 2000.0), normal(rng, 8000.0, 3000.0)];
+                vector.extend(profile.iter().map(|x| normal(rng, *x, 0.1)));
+                if rng.gen_bool(0.05) {
+                    vector[2 + rng.gen_range(0..12)] += 50.0;
+                }
+
+                vectors.push(vector);
+                genres.push(genre);
+            }
+        }
+
+        (vectors, genres)
+    }
+
+    /// Share of the nearest neighbours of every track that have its genre.
+    fn neighbour_precision(vectors: &[Vec<f32>], genres: &[usize]) -> f32 {
+        let distance =
+            |a: &[f32], b: &[f32]| -> f32 { a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum() };
+
+        let mut hits = 0;
+        for (i, vector) in vectors.iter().enumerate() {
+            let mut neighbours: Vec<(f32, usize)> = vectors
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, x)| (distance(vector, x), j))
+                .collect();
+            neighbours.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            hits += neighbours
+                .iter()
+                .take(NEIGHBOURS)
+                .filter(|(_, j)| genres[*j] == genres[i])
+                .count();
+        }
+
+        hits as f32 / (vectors.len() * NEIGHBOURS) as f32
+    }
+
+    fn precision_with(method: ScalingMethod, vectors: &[Vec<f32>], genres: &[usize]) -> f32 {
+        let scaler = FeatureScaler::fit(method, vectors, vec![1.0; vectors[0].len()]);
+        let scaled: Vec<Vec<f32>> = vectors.iter().map(|x| scaler.apply(x)).collect();
+        neighbour_precision(&scaled, genres)
+    }
+
+    #[test]
+    fn test_scaling_improves_neighbours() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let (vectors, genres) = library(&mut rng);
+
+        let unscaled = precision_with(ScalingMethod::Unscaled, &vectors, &genres);
+        let z_score = precision_with(ScalingMethod::ZScore, &vectors, &genres);
+        let robust = precision_with(ScalingMethod::Robust, &vectors, &genres);
+
+        // Without scaling, the neighbours are about as good as random ones
+        assert!(unscaled < 0.3, "Unscaled precision: {:.3}", unscaled);
+        assert!(
+            z_score > unscaled + 0.3,
+            "Z-score precision: {:.3}, unscaled: {:.3}",
+            z_score,
+            unscaled
+        );
+        // The outliers inflate the standard deviations of the dimensions
+        // telling genres apart, but not their interquartile ranges
+        assert!(
+            robust > z_score,
+            "Robust precision: {:.3}, z-score: {:.3}",
+            robust,
+            z_score
+        );
+    }
+
+    #[test]
+    fn test_z_score_statistics() {
+        let vectors = vec![vec![1.0, 10.0, 3.0], vec![3.0, 30.0, 3.0]];
+        let scaler = FeatureScaler::fit(ScalingMethod::ZScore, &vectors, vec![1.0, 2.0, 1.0]);
+
+        assert_eq!(scaler.center, vec![2.0, 20.0, 3.0]);
+        // The constant dimension is only centered
+        assert_eq!(scaler.scale, vec![1.0, 10.0, 1.0]);
+        assert_eq!(scaler.apply(&vectors[0]), vec![-1.0, -2.0, 0.0]);
+        assert_eq!(scaler.apply(&vectors[1]), vec![1.0, 2.0, 0.0]);
+
+        let identity = FeatureScaler::identity(3);
+        assert_eq!(identity.apply(&vectors[0]), vectors[0]);
+        assert_eq!(
+            FeatureScaler::fit(ScalingMethod::Unscaled, &vectors, vec![1.0; 3]),
+            identity
+        );
+    }
+
+    #[test]
+    fn test_drift() {
+        let scaler = FeatureScaler {
+            center: vec![0.0, 100.0],
+            scale: vec![1.0, 10.0],
+            weight: vec![1.0, 1.0],
+        };
+
+        let mut moved = scaler.clone();
+        moved.center[1] = 100.4;
+        moved.scale[0] = 1.04;
+        assert!(!moved.drifted_from(&scaler, 0.05));
+
+        moved.center[1] = 101.0;
+        assert!(moved.drifted_from(&scaler, 0.05));
+
+        let mut reweighted = scaler.clone();
+        reweighted.weight[0] = 2.0;
+        assert!(reweighted.drifted_from(&scaler, 0.05));
+        assert!(!FeatureScaler::identity(3).drifted_from(&FeatureScaler::identity(3), 0.0));
+        assert!(FeatureScaler::identity(3).drifted_from(&scaler, 0.05));
+    }
+
+    #[test]
+    fn test_robust_statistics() {
+        let vectors: Vec<Vec<f32>> = [1.0, 2.0, 3.0, 4.0, 5.0, 1000.0]
+            .iter()
+            .map(|x| vec![*x])
+            .collect();
+        let scaler = FeatureScaler::fit(ScalingMethod::Robust, &vectors, vec![1.0]);
+
+        assert_eq!(scaler.center, vec![3.5]);
+        assert!((scaler.scale[0] - 2.5 / 1.349).abs() < 1e-5, "{:?}", scaler);
+    }
+
+    #[test]
+    fn test_feature_weights() {
+        let labels: Vec<String> = ["energy", "chroma0", "chroma1", "chroma10", "mfcc0"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+
+        let weights = feature_weights(&labels, &[("chroma", 2.0), ("chroma10", 0.5)]).unwrap();
+        assert_eq!(weights, vec![1.0, 2.0, 2.0, 0.5, 1.0]);
+
+        assert!(feature_weights(&labels, &[("chroma1x", 2.0)]).is_err());
+        assert!(feature_weights(&labels, &[("mfcc", -1.0)]).is_err());
+        assert!(feature_weights(&labels, &[("ener", 1.0)]).is_err());
+    }
+
+    #[test]
+    fn test_parse_scaling_method() {
+        assert_eq!(
+            "zscore".parse::<ScalingMethod>().unwrap(),
+            ScalingMethod::ZScore
+        );
+        assert_eq!(
+            "Robust".parse::<ScalingMethod>().unwrap(),
+            ScalingMethod::Robust
+        );
+        assert_eq!(
+            "none".parse::<ScalingMethod>().unwrap(),
+            ScalingMethod::Unscaled
+        );
+        assert!("minmax".parse::<ScalingMethod>().is_err());
+    }
+}
@@ -0,0 +1,142 @@
+#[cfg(test)]
+mod tests {
+    use crate::analysis::{
+        aggregate_segments, analyze_audio, analyze_audio_range, analyze_audio_segments,
+        AnalysisParameter, AudioRange,
+    };
+    use crate::analyzer::core_analyzer::Analyzer;
+    use crate::tests::{sine, write_wav, SAMPLE_RATE};
+    use crate::utils::audio_description::merge_segments;
+    use crate::utils::computing_device::ComputingDevice;
+    use crate::utils::segment_codec::{decode_segments, encode_segments};
+
+    /// Two seconds of a quiet low tone followed by two seconds of a loud
+    /// high tone.
+    fn two_parts() -> Vec<f32> {
+        let len = SAMPLE_RATE as usize * 2;
+
+        [sine(220.0, 0.1, len), sine(1760.0, 0.8, len)].concat()
+    }
+
+    #[test]
+    fn test_segments() {
+        let path = write_wav("segments", &two_parts(), None);
+        let file_path = path.to_str().unwrap();
+
+        let (result, segments) =
+            analyze_audio_segments(file_path, 1024, 512, 1.0, ComputingDevice::Cpu, None).unwrap();
+
+        assert_eq!(segments.len(), 4, "{:?}", segments);
+        for (i, segment) in segments.iter().enumerate() {
+            assert!((segment.start - i as f32).abs() < 1e-6);
+            assert!(segment.window_count > 0);
+        }
+        assert!(segments[3].rms > segments[0].rms * 4.0);
+        assert!(segments[3].spectral_centroid > segments[0].spectral_centroid);
+
+        // The aggregate is the same as without segments, and can be derived
+        // back from the segments
+        let plain = analyze_audio(file_path, 1024, 512, ComputingDevice::Cpu, None).unwrap();
+        assert_eq!(plain.rms, result.rms);
+        assert_eq!(plain.spectral_centroid, result.spectral_centroid);
+
+        let aggregate = aggregate_segments(&segments).unwrap();
+        assert!((aggregate.rms - result.rms).abs() < 1e-4);
+        assert!((aggregate.energy - result.energy).abs() / result.energy < 1e-4);
+        assert!((aggregate.duration - 4.0).abs() < 0.01);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_range() {
+        let samples = two_parts();
+        let path = write_wav("range", &samples, None);
+        let file_path = path.to_str().unwrap();
+        let parameters = AnalysisParameter::new(1024, 512, ComputingDevice::Cpu);
+
+        let analyze = |start: f64, end: Option<f64>| {
+            analyze_audio_range(
+                file_path,
+                AudioRange { start, end },
+                parameters,
+                Some(1.0),
+                vec![],
+                None,
+            )
+            .unwrap()
+        };
+        let quiet = analyze(0.0, Some(2.0));
+        let loud = analyze(2.0, None);
+
+        for output in [&quiet, &loud] {
+            assert!((output.result.stat.duration - 2.0).abs() < 1e-6);
+            assert_eq!(output.result.stat.total_samples, SAMPLE_RATE as usize * 2);
+            assert_eq!(output.segments.len(), 2, "{:?}", output.segments);
+        }
+        assert!(loud.result.rms > quiet.result.rms * 4.0);
+
+        // A range is analyzed like a file holding only that range
+        let second_half = write_wav("range-half", &samples[SAMPLE_RATE as usize * 2..], None);
+        let plain = analyze_audio(
+            second_half.to_str().unwrap(),
+            1024,
+            512,
+            ComputingDevice::Cpu,
+            None,
+        )
+        .unwrap();
+        assert!((plain.rms - loud.result.rms).abs() < 1e-4);
+        assert!(
+            (plain.spectral_centroid - loud.result.spectral_centroid).abs()
+                < 1e-3 * plain.spectral_centroid
+        );
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(second_half).unwrap();
+    }
+
+    #[test]
+    fn test_merge_segment_spectra() {
+        let path = write_wav("merge", &two_parts(), None);
+
+        let audio_desc = Analyzer::new(ComputingDevice::Cpu, 1024, 512, None, None)
+            .with_segments(0.5)
+            .process(path.to_str().unwrap())
+            .unwrap();
+
+        let merged = merge_segments(&audio_desc.segments).unwrap();
+        assert_eq!(
+            merged.window_count,
+            audio_desc.segments.iter().map(|x| x.window_count).sum()
+        );
+        assert!((merged.rms - audio_desc.rms).abs() < 1e-4);
+        for (a, b) in merged.spectrum.iter().zip(audio_desc.spectrum.iter()) {
+            assert!((a - b).norm() < 1e-3 * (1.0 + b.norm()), "{} {}", a, b);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_segment_codec() {
+        let path = write_wav("codec", &two_parts(), None);
+        let (_, segments) = analyze_audio_segments(
+            path.to_str().unwrap(),
+            1024,
+            512,
+            3.0,
+            ComputingDevice::Cpu,
+            None,
+        )
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let bytes = encode_segments(&segments);
+        assert_eq!(decode_segments(&bytes).unwrap(), segments);
+
+        assert!(decode_segments(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_segments(b"not a segment file").is_err());
+        assert_eq!(decode_segments(&encode_segments(&[])).unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,90 @@
+#[cfg(test)]
+mod tests {
+    use crate::utils::tempo::TempoDetector;
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    /// Short decaying noise bursts, like a kick drum, on every beat.
+    fn synthesize_beats(bpm: f64, first_beat: f64, duration: f64) -> Vec<f32> {
+        let mut samples = vec![0.0; (duration * SAMPLE_RATE as f64) as usize];
+        let burst = (0.05 * SAMPLE_RATE as f64) as usize;
+        let mut seed: u32 = 1;
+
+        let mut time = first_beat;
+        while time < duration {
+            let start = (time * SAMPLE_RATE as f64) as usize;
+            for i in 0..burst {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                let noise = (seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0;
+                if let Some(sample) = samples.get_mut(start + i) {
+                    *sample = 0.8 * noise * (-(i as f32) / burst as f32 * 5.0).exp();
+                }
+            }
+            time += 60.0 / bpm;
+        }
+
+        samples
+    }
+
+    fn detect(samples: &[f32]) -> Option<crate::utils::tempo::Tempo> {
+        let mut detector = TempoDetector::new(SAMPLE_RATE);
+        for chunk in samples.chunks(4096) {
+            detector.push(chunk);
+        }
+        detector.finish(true)
+    }
+
+    #[test]
+    fn test_detect_tempo() {
+        for bpm in [90.0, 128.0, 150.0] {
+            let tempo = detect(&synthesize_beats(bpm, 0.3, 30.0)).expect("Tempo expected");
+
+            assert!(
+                (tempo.bpm - bpm).abs() < 1.0,
+                "{} instead of {}",
+                tempo.bpm,
+                bpm
+            );
+            assert!(tempo.confidence > 0.3, "{:?}", tempo.confidence);
+        }
+    }
+
+    #[test]
+    fn test_beat_positions() {
+        let bpm = 120.0;
+        let tempo = detect(&synthesize_beats(bpm, 0.3, 20.0)).expect("Tempo expected");
+
+        assert!(tempo.beats.len() >= 36, "{:?}", tempo.beats);
+        for beat in &tempo.beats {
+            // Distance to the nearest synthesized beat
+            let phase = (beat - 0.3).rem_euclid(0.5);
+            let distance = phase.min(0.5 - phase);
+            assert!(distance < 0.03, "beat at {} is off the grid", beat);
+        }
+    }
+
+    #[test]
+    fn test_beat_offset() {
+        for first_beat in [0.1, 0.3, 0.45] {
+            let mut detector = TempoDetector::new(SAMPLE_RATE);
+            for chunk in synthesize_beats(120.0, first_beat, 20.0).chunks(4096) {
+                detector.push(chunk);
+            }
+            let tempo = detector.finish(false).expect("Tempo expected");
+
+            assert!(tempo.beats.is_empty());
+            assert!(
+                (tempo.offset - first_beat).abs() < 0.03,
+                "{} instead of {}",
+                tempo.offset,
+                first_beat
+            );
+        }
+    }
+
+    #[test]
+    fn test_too_short() {
+        assert!(detect(&synthesize_beats(120.0, 0.0, 1.0)).is_none());
+        assert!(detect(&[]).is_none());
+    }
+}
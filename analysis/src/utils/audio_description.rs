@@ -1,4 +1,76 @@
 use rustfft::num_complex::Complex;
+
+use crate::utils::key::KeyEstimate;
+use crate::utils::loudness_meter::Loudness;
+use crate::utils::tempo::Tempo;
+
+/// Description of a stretch of the audio, with the same meaning as the
+/// matching fields of `AudioDescription`.
+#[derive(Clone)]
+pub struct SegmentDescription {
+    /// Start of the segment in seconds.
+    pub start: f64,
+    pub duration: f64,
+    /// Number of analysis windows the values are averaged over.
+    pub window_count: usize,
+    pub spectrum: Vec<Complex<f32>>,
+    pub rms: f32,
+    pub zcr: f32,
+    pub energy: f32,
+}
+
+impl std::fmt::Debug for SegmentDescription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SegmentDescription")
+            .field("start", &self.start)
+            .field("duration", &self.duration)
+            .field("window_count", &self.window_count)
+            .field("spectrum_len", &self.spectrum.len())
+            .field("rms", &self.rms)
+            .field("zcr", &self.zcr)
+            .field("energy", &self.energy)
+            .finish()
+    }
+}
+
+/// Merges consecutive segments into one, weighting every segment by its
+/// window count. Merging all the segments of a track gives back the
+/// aggregate spectrum, RMS, ZCR and energy of the whole track.
+pub fn merge_segments(segments: &[SegmentDescription]) -> Option<SegmentDescription> {
+    let first = segments.first()?;
+    let last = segments.last()?;
+    let window_count: usize = segments.iter().map(|x| x.window_count).sum();
+    if window_count == 0 {
+        return None;
+    }
+
+    let weighted_mean = |value: &dyn Fn(&SegmentDescription) -> f32| {
+        segments
+            .iter()
+            .map(|x| value(x) * x.window_count as f32)
+            .sum::<f32>()
+            / window_count as f32
+    };
+
+    let mut spectrum = vec![Complex::new(0.0, 0.0); first.spectrum.len()];
+    for segment in segments {
+        let weight = segment.window_count as f32 / window_count as f32;
+        for (total, value) in spectrum.iter_mut().zip(segment.spectrum.iter()) {
+            *total += value * weight;
+        }
+    }
+
+    Some(SegmentDescription {
+        start: first.start,
+        duration: last.start + last.duration - first.start,
+        window_count,
+        spectrum,
+        rms: weighted_mean(&|x| x.rms),
+        zcr: weighted_mean(&|x| x.zcr),
+        energy: weighted_mean(&|x| x.energy),
+    })
+}
+
 pub struct AudioDescription {
     pub sample_rate: u32,
     pub duration: f64,
@@ -7,6 +79,11 @@ pub struct AudioDescription {
     pub rms: f32,
     pub zcr: usize,
     pub energy: f32,
+    pub loudness: Option<Loudness>,
+    pub tempo: Option<Tempo>,
+    pub key: Option<KeyEstimate>,
+    /// Empty unless the analyzer runs in segment mode.
+    pub segments: Vec<SegmentDescription>,
 }
 
 impl std::fmt::Debug for AudioDescription {
@@ -19,6 +96,10 @@ impl std::fmt::Debug for AudioDescription {
             .field("rms", &self.rms)
             .field("zcr", &self.zcr)
             .field("energy", &self.energy)
+            .field("loudness", &self.loudness)
+            .field("tempo", &self.tempo)
+            .field("key", &self.key)
+            .field("segments", &self.segments.len())
             .finish()
     }
 }
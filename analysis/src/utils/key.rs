@@ -0,0 +1,355 @@
+use std::fmt;
+use std::sync::Arc;
+
+use realfft::{RealFftPlanner, RealToComplex};
+use rustfft::num_complex::Complex;
+
+use crate::utils::hanning_window::build_hanning_window;
+
+/// Frequency range, in Hz, the pitch classes are collected from, C2 to C7.
+const PITCH_RANGE: (f32, f32) = (65.0, 2100.0);
+
+/// Length of the analysis frames, in seconds. Long enough to tell apart
+/// semitones at the bottom of the pitch range.
+const FRAME_DURATION: f64 = 0.37;
+
+/// Frames quieter than this RMS do not vote.
+const SILENCE_THRESHOLD: f32 = 1e-4;
+
+/// Krumhansl-Kessler probe tone profiles, starting on the tonic.
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+/// A musical key, with the tonic as a pitch class where C is 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub tonic: u8,
+    pub mode: Mode,
+}
+
+impl Key {
+    pub fn new(tonic: u8, mode: Mode) -> Self {
+        Key {
+            tonic: tonic % 12,
+            mode,
+        }
+    }
+
+    /// Compact code used for storage, the tonic for major keys and the
+    /// tonic plus 12 for minor keys.
+    pub fn code(&self) -> i32 {
+        match self.mode {
+            Mode::Major => self.tonic as i32,
+            Mode::Minor => self.tonic as i32 + 12,
+        }
+    }
+
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0..=11 => Some(Key::new(code as u8, Mode::Major)),
+            12..=23 => Some(Key::new((code - 12) as u8, Mode::Minor)),
+            _ => None,
+        }
+    }
+
+    /// Position on the circle of fifths shared by Camelot and Open Key,
+    /// from 1 to 12, where relative keys share the same number.
+    fn wheel_position(&self) -> u8 {
+        let major_tonic = match self.mode {
+            Mode::Major => self.tonic,
+            Mode::Minor => (self.tonic + 3) % 12,
+        };
+        // C major is 8B in Camelot
+        ((major_tonic as u32 * 7 % 12 + 7) % 12 + 1) as u8
+    }
+
+    fn from_wheel_position(position: u8, mode: Mode) -> Option<Self> {
+        if !(1..=12).contains(&position) {
+            return None;
+        }
+
+        // Walking the circle of fifths from C major, 8B
+        let major_tonic = ((position as u32 + 4) * 7 % 12) as u8;
+        let tonic = match mode {
+            Mode::Major => major_tonic,
+            Mode::Minor => (major_tonic + 9) % 12,
+        };
+
+        Some(Key::new(tonic, mode))
+    }
+
+    /// Camelot notation, like `8B` for C major and `8A` for A minor.
+    pub fn camelot(&self) -> String {
+        let letter = match self.mode {
+            Mode::Major => 'B',
+            Mode::Minor => 'A',
+        };
+        format!("{}{}", self.wheel_position(), letter)
+    }
+
+    /// Open Key notation, like `1d` for C major and `1m` for A minor.
+    pub fn open_key(&self) -> String {
+        let letter = match self.mode {
+            Mode::Major => 'd',
+            Mode::Minor => 'm',
+        };
+        format!("{}{}", (self.wheel_position() + 4) % 12 + 1, letter)
+    }
+
+    /// Parses the Camelot and Open Key notations, and key names like `F#m`,
+    /// `Bb` or `C# minor`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let lower = text.to_lowercase();
+
+        if let Some(number) = lower.strip_suffix(['a', 'b']) {
+            if let Ok(position) = number.parse::<u8>() {
+                let mode = if lower.ends_with('a') {
+                    Mode::Minor
+                } else {
+                    Mode::Major
+                };
+                return Key::from_wheel_position(position, mode);
+            }
+        }
+
+        if let Some(number) = lower.strip_suffix(['d', 'm']) {
+            if let Ok(position) = number.parse::<u8>() {
+                if !(1..=12).contains(&position) {
+                    return None;
+                }
+                let mode = if lower.ends_with('m') {
+                    Mode::Minor
+                } else {
+                    Mode::Major
+                };
+                return Key::from_wheel_position((position + 6) % 12 + 1, mode);
+            }
+        }
+
+        let mut chars = text.chars();
+        let mut tonic: i32 = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+
+        let mut rest = chars.as_str();
+        if let Some(x) = rest.strip_prefix(['#', '♯']) {
+            tonic += 1;
+            rest = x;
+        } else if let Some(x) = rest.strip_prefix(['b', '♭']) {
+            tonic -= 1;
+            rest = x;
+        }
+
+        let mode = match rest.trim().to_lowercase().as_str() {
+            "" | "maj" | "major" => Mode::Major,
+            "m" | "min" | "minor" => Mode::Minor,
+            _ => return None,
+        };
+
+        Some(Key::new(tonic.rem_euclid(12) as u8, mode))
+    }
+
+    /// Keys that mix harmonically with this one: itself, its neighbours on
+    /// the circle of fifths and its relative key.
+    pub fn harmonic_neighbours(&self) -> Vec<Key> {
+        let position = self.wheel_position();
+        let relative_mode = match self.mode {
+            Mode::Major => Mode::Minor,
+            Mode::Minor => Mode::Major,
+        };
+
+        [
+            (position, self.mode),
+            (position % 12 + 1, self.mode),
+            ((position + 10) % 12 + 1, self.mode),
+            (position, relative_mode),
+        ]
+        .into_iter()
+        .filter_map(|(position, mode)| Key::from_wheel_position(position, mode))
+        .collect()
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            Mode::Major => write!(f, "{}", NOTE_NAMES[self.tonic as usize]),
+            Mode::Minor => write!(f, "{}m", NOTE_NAMES[self.tonic as usize]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEstimate {
+    pub key: Key,
+    /// Pearson correlation between the chroma of the track and the profile
+    /// of the key, between 0 and 1.
+    pub confidence: f64,
+}
+
+fn correlation(a: &[f64; 12], b: &[f64; 12]) -> f64 {
+    let mean_a = a.iter().sum::<f64>() / 12.0;
+    let mean_b = b.iter().sum::<f64>() / 12.0;
+
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b.iter()) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a).powi(2);
+        variance_b += (y - mean_b).powi(2);
+    }
+
+    if variance_a <= 0.0 || variance_b <= 0.0 {
+        return 0.0;
+    }
+
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+/// Krumhansl-Schmuckler key finding: the key whose rotated profile
+/// correlates best with the chroma vector.
+pub fn estimate_key(chroma: &[f64; 12]) -> Option<KeyEstimate> {
+    if chroma.iter().all(|x| *x <= 0.0) {
+        return None;
+    }
+
+    (0..12u8)
+        .flat_map(|tonic| {
+            [(MAJOR_PROFILE, Mode::Major), (MINOR_PROFILE, Mode::Minor)]
+                .into_iter()
+                .map(move |(profile, mode)| {
+                    let mut rotated = [0.0; 12];
+                    for (i, value) in profile.iter().enumerate() {
+                        rotated[(i + tonic as usize) % 12] = *value;
+                    }
+                    (Key::new(tonic, mode), correlation(chroma, &rotated))
+                })
+        })
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(key, r)| KeyEstimate {
+            key,
+            confidence: r.clamp(0.0, 1.0),
+        })
+}
+
+/// Streaming key detector. Every frame yields a chroma vector normalized to
+/// its maximum, so that loud passages do not outvote quiet ones, and the
+/// frames are summed before the key is estimated.
+pub struct KeyDetector {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window_size: usize,
+    hop_size: usize,
+    hanning_window: Vec<f32>,
+    sample_buffer: Vec<f32>,
+    fft_input_buffer: Vec<f32>,
+    fft_output_buffer: Vec<Complex<f32>>,
+    // Pitch class of every FFT bin within the pitch range
+    bin_pitch_classes: Vec<Option<usize>>,
+    chroma: [f64; 12],
+}
+
+impl KeyDetector {
+    pub fn new(sample_rate: u32) -> Self {
+        let window_size = ((sample_rate as f64 * FRAME_DURATION) as usize)
+            .next_power_of_two()
+            .max(2);
+
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(window_size);
+
+        let bin_pitch_classes = (0..=window_size / 2)
+            .map(|bin| {
+                let frequency = bin as f32 * sample_rate as f32 / window_size as f32;
+                if frequency < PITCH_RANGE.0 || frequency > PITCH_RANGE.1 {
+                    return None;
+                }
+                let semitones_from_a = (12.0 * (frequency / 440.0).log2()).round() as i32;
+                Some((semitones_from_a + 9).rem_euclid(12) as usize)
+            })
+            .collect();
+
+        KeyDetector {
+            fft_input_buffer: fft.make_input_vec(),
+            fft_output_buffer: fft.make_output_vec(),
+            fft,
+            window_size,
+            hop_size: window_size / 2,
+            hanning_window: build_hanning_window(window_size),
+            sample_buffer: Vec::with_capacity(window_size * 2),
+            bin_pitch_classes,
+            chroma: [0.0; 12],
+        }
+    }
+
+    /// Feed mono samples.
+    pub fn push(&mut self, samples: &[f32]) {
+        self.sample_buffer.extend_from_slice(samples);
+
+        while self.sample_buffer.len() >= self.window_size {
+            self.process_frame();
+            self.sample_buffer.drain(..self.hop_size);
+        }
+    }
+
+    fn process_frame(&mut self) {
+        let frame = &self.sample_buffer[..self.window_size];
+
+        let rms = (frame.iter().map(|x| x * x).sum::<f32>() / frame.len() as f32).sqrt();
+        if rms < SILENCE_THRESHOLD {
+            return;
+        }
+
+        for (i, sample) in self.fft_input_buffer.iter_mut().enumerate() {
+            *sample = frame[i] * self.hanning_window[i];
+        }
+
+        self.fft
+            .process(&mut self.fft_input_buffer, &mut self.fft_output_buffer)
+            .expect("Real FFT processing failed");
+
+        let mut chroma = [0.0f64; 12];
+        for (value, pitch_class) in self
+            .fft_output_buffer
+            .iter()
+            .zip(self.bin_pitch_classes.iter())
+        {
+            if let Some(pitch_class) = pitch_class {
+                chroma[*pitch_class] += value.norm() as f64;
+            }
+        }
+
+        let max = chroma.iter().cloned().fold(0.0, f64::max);
+        if max > 0.0 {
+            for (total, value) in self.chroma.iter_mut().zip(chroma.iter()) {
+                *total += value / max;
+            }
+        }
+    }
+
+    /// Estimate the key of everything fed so far. Returns `None` for
+    /// silence or audio shorter than a frame.
+    pub fn finish(&self) -> Option<KeyEstimate> {
+        estimate_key(&self.chroma)
+    }
+}
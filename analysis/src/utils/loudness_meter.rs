@@ -0,0 +1,335 @@
+use std::f64::consts::PI;
+
+use symphonia::core::audio::Channels;
+
+/// Length of the sub-blocks the signal energy is collected in, in seconds.
+/// Gating blocks and short-term windows are made of whole sub-blocks.
+const SUB_BLOCK_DURATION: f64 = 0.1;
+
+/// 400 ms gating blocks with 75% overlap.
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+
+/// 3 s short-term windows, used for the loudness range.
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+const LRA_RELATIVE_GATE: f64 = -20.0;
+
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// Gated integrated loudness in LUFS, negative infinity for silence.
+    pub integrated: f64,
+    /// Loudness range in LU.
+    pub range: f64,
+    /// Largest absolute sample value of the 4x oversampled signal, linear
+    /// with full scale at 1.0.
+    pub true_peak: f64,
+}
+
+/// Biquad in transposed direct form II.
+#[derive(Debug, Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad { b, a, z: [0.0; 2] }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+/// The two stage K-weighting filter of BS.1770, with the coefficients derived
+/// for any sample rate instead of the tabulated 48 kHz ones.
+#[derive(Debug, Clone)]
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let fs = sample_rate as f64;
+
+        // Stage 1, high shelf modelling the acoustic effect of the head
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / fs).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        // Stage 2, RLB high pass
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / fs).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        KWeighting { shelf, high_pass }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(x))
+    }
+}
+
+/// Polyphase interpolation filter coefficients for the true peak, a Hann
+/// windowed sinc cut at the original Nyquist frequency. Every phase is
+/// normalized to unity gain at DC.
+fn build_interpolation_filter() -> Vec<[f64; TAPS_PER_PHASE]> {
+    let taps = OVERSAMPLING * TAPS_PER_PHASE;
+    let center = (taps - 1) as f64 / 2.0;
+
+    let coefficient = |n: usize| {
+        let x = (n as f64 - center) / OVERSAMPLING as f64;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        };
+        let window = 0.5 - 0.5 * (2.0 * PI * (n as f64 + 0.5) / taps as f64).cos();
+        sinc * window
+    };
+
+    (0..OVERSAMPLING)
+        .map(|phase| {
+            let mut kernel = [0.0; TAPS_PER_PHASE];
+            for (k, value) in kernel.iter_mut().enumerate() {
+                *value = coefficient(phase + OVERSAMPLING * k);
+            }
+            let sum: f64 = kernel.iter().sum();
+            kernel.iter_mut().for_each(|x| *x /= sum);
+            kernel
+        })
+        .collect()
+}
+
+/// BS.1770 weight of a channel. The LFE channel does not count and surround
+/// channels weigh about 1.5 dB more.
+pub fn channel_weights(channels: Channels) -> Vec<f64> {
+    channels
+        .iter()
+        .map(|channel| {
+            if channel == Channels::LFE1 || channel == Channels::LFE2 {
+                0.0
+            } else if channel == Channels::SIDE_LEFT
+                || channel == Channels::SIDE_RIGHT
+                || channel == Channels::REAR_LEFT
+                || channel == Channels::REAR_RIGHT
+            {
+                1.41
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+fn power_to_loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn loudness_to_power(loudness: f64) -> f64 {
+    10f64.powf((loudness + 0.691) / 10.0)
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+/// Loudness of several tracks played back to back, like an album, from the
+/// integrated loudness and the duration of each track.
+///
+/// This is the duration weighted power mean of the tracks. Unlike measuring
+/// the album as a whole, the relative gate is not applied across tracks, so
+/// very quiet tracks weigh slightly more.
+pub fn combined_loudness(tracks: &[(f64, f64)]) -> f64 {
+    let (power, duration) = tracks
+        .iter()
+        .filter(|(loudness, duration)| loudness.is_finite() && *duration > 0.0)
+        .fold((0.0, 0.0), |(power, total), (loudness, duration)| {
+            (
+                power + loudness_to_power(*loudness) * duration,
+                total + duration,
+            )
+        });
+
+    if duration > 0.0 {
+        power_to_loudness(power / duration)
+    } else {
+        f64::NEG_INFINITY
+    }
+}
+
+/// Powers of the overlapping windows made of `size` consecutive sub-blocks.
+fn window_powers(sub_blocks: &[f64], size: usize, frames_per_sub_block: usize) -> Vec<f64> {
+    sub_blocks
+        .windows(size)
+        .map(|x| x.iter().sum::<f64>() / (size * frames_per_sub_block) as f64)
+        .collect()
+}
+
+/// Streaming ITU-R BS.1770-4 / EBU R128 loudness meter, fed with interleaved
+/// frames at the original sample rate.
+pub struct LoudnessMeter {
+    weights: Vec<f64>,
+    filters: Vec<KWeighting>,
+    interpolation: Vec<[f64; TAPS_PER_PHASE]>,
+    // Latest input samples of every channel, newest first
+    history: Vec<[f64; TAPS_PER_PHASE]>,
+    true_peak: f64,
+    frames_per_sub_block: usize,
+    sub_block_frames: usize,
+    sub_block_energy: f64,
+    // Weighted energy summed over the channels of every complete sub-block
+    sub_blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, weights: Vec<f64>) -> Self {
+        let channels = weights.len();
+
+        LoudnessMeter {
+            filters: vec![KWeighting::new(sample_rate); channels],
+            history: vec![[0.0; TAPS_PER_PHASE]; channels],
+            weights,
+            interpolation: build_interpolation_filter(),
+            true_peak: 0.0,
+            frames_per_sub_block: ((sample_rate as f64 * SUB_BLOCK_DURATION).round() as usize)
+                .max(1),
+            sub_block_frames: 0,
+            sub_block_energy: 0.0,
+            sub_blocks: vec![],
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Feed one frame, with one sample per channel.
+    pub fn push_frame(&mut self, frame: &[f32]) {
+        for (channel, sample) in frame.iter().enumerate().take(self.weights.len()) {
+            let sample = *sample as f64;
+
+            let filtered = self.filters[channel].process(sample);
+            self.sub_block_energy += self.weights[channel] * filtered * filtered;
+
+            let history = &mut self.history[channel];
+            history.copy_within(..TAPS_PER_PHASE - 1, 1);
+            history[0] = sample;
+
+            self.true_peak = self.true_peak.max(sample.abs());
+            for kernel in &self.interpolation {
+                let value: f64 = kernel.iter().zip(history.iter()).map(|(h, x)| h * x).sum();
+                self.true_peak = self.true_peak.max(value.abs());
+            }
+        }
+
+        self.sub_block_frames += 1;
+        if self.sub_block_frames == self.frames_per_sub_block {
+            self.sub_blocks.push(self.sub_block_energy);
+            self.sub_block_energy = 0.0;
+            self.sub_block_frames = 0;
+        }
+    }
+
+    /// Gated loudness over everything fed so far, following BS.1770-4 for
+    /// the integrated loudness and EBU Tech 3342 for the loudness range.
+    pub fn finish(&self) -> Loudness {
+        let absolute_gate = loudness_to_power(ABSOLUTE_GATE);
+
+        let momentary: Vec<f64> = window_powers(
+            &self.sub_blocks,
+            MOMENTARY_SUB_BLOCKS,
+            self.frames_per_sub_block,
+        )
+        .into_iter()
+        .filter(|x| *x > absolute_gate)
+        .collect();
+
+        let integrated = match mean(&momentary) {
+            Some(ungated) => {
+                let relative_gate = ungated * 10f64.powf(RELATIVE_GATE / 10.0);
+                let gated: Vec<f64> = momentary
+                    .iter()
+                    .cloned()
+                    .filter(|x| *x > relative_gate)
+                    .collect();
+                mean(&gated)
+                    .map(power_to_loudness)
+                    .unwrap_or(f64::NEG_INFINITY)
+            }
+            None => f64::NEG_INFINITY,
+        };
+
+        let short_term: Vec<f64> = window_powers(
+            &self.sub_blocks,
+            SHORT_TERM_SUB_BLOCKS,
+            self.frames_per_sub_block,
+        )
+        .into_iter()
+        .filter(|x| *x > absolute_gate)
+        .collect();
+
+        let range = match mean(&short_term) {
+            Some(ungated) => {
+                let relative_gate = ungated * 10f64.powf(LRA_RELATIVE_GATE / 10.0);
+                let mut loudness: Vec<f64> = short_term
+                    .iter()
+                    .cloned()
+                    .filter(|x| *x > relative_gate)
+                    .map(power_to_loudness)
+                    .collect();
+                loudness.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+                let percentile = |p: f64| {
+                    let index = ((loudness.len() - 1) as f64 * p).round() as usize;
+                    loudness[index]
+                };
+
+                if loudness.is_empty() {
+                    0.0
+                } else {
+                    percentile(0.95) - percentile(0.10)
+                }
+            }
+            None => 0.0,
+        };
+
+        Loudness {
+            integrated,
+            range,
+            true_peak: self.true_peak,
+        }
+    }
+}
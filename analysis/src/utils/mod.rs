@@ -3,4 +3,10 @@ pub mod audio_metadata_reader;
 pub mod computing_device;
 pub mod features;
 pub mod hanning_window;
+pub mod key;
+pub mod loudness_meter;
 pub mod measure_time_utils;
+pub mod onset;
+pub mod scaling;
+pub mod segment_codec;
+pub mod tempo;
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use realfft::{RealFftPlanner, RealToComplex};
+use rustfft::num_complex::Complex;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error;
+use symphonia::default::get_codecs;
+use tokio_util::sync::CancellationToken;
+
+use crate::utils::audio_metadata_reader::get_format;
+use crate::utils::features::{amp_spectrum, rms, spectral_flux};
+use crate::utils::hanning_window::build_hanning_window;
+
+const WINDOW_SIZE: usize = 1024;
+const HOP_SIZE: usize = 256;
+
+/// Frequency range, in Hz, where most of the singing voice energy lives.
+const VOCAL_BAND: (f32, f32) = (200.0, 4000.0);
+
+/// Minimum distance between two onsets, in seconds.
+const MIN_ONSET_DISTANCE: f64 = 0.05;
+
+/// Half width, in seconds, of the moving average used as the adaptive
+/// threshold while picking peaks.
+const THRESHOLD_RADIUS: f64 = 0.1;
+
+/// How far, on the normalized envelope, a peak must rise above the moving
+/// average to count as an onset.
+const THRESHOLD_DELTA: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Onset {
+    // Seconds from the start of the track
+    pub time: f64,
+    // Normalized between 0 and 1
+    pub strength: f32,
+}
+
+/// Streaming onset detector. The onset envelope combines the spectral flux of
+/// the vocal band with the rise of the frame RMS.
+pub struct OnsetDetector {
+    sample_rate: u32,
+    fft: Arc<dyn RealToComplex<f32>>,
+    hanning_window: Vec<f32>,
+    sample_buffer: Vec<f32>,
+    fft_input_buffer: Vec<f32>,
+    fft_output_buffer: Vec<Complex<f32>>,
+    band: (usize, usize),
+    previous_band: Vec<f32>,
+    previous_rms: f32,
+    flux: Vec<f32>,
+    rms_rise: Vec<f32>,
+}
+
+impl OnsetDetector {
+    pub fn new(sample_rate: u32) -> Self {
+        Self::with_band(sample_rate, VOCAL_BAND)
+    }
+
+    /// Detector listening to the spectral flux of a frequency range, in Hz.
+    pub fn with_band(sample_rate: u32, band: (f32, f32)) -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(WINDOW_SIZE);
+
+        let bin = |frequency: f32| {
+            ((frequency * WINDOW_SIZE as f32 / sample_rate as f32) as usize).min(WINDOW_SIZE / 2)
+        };
+        let band = (bin(band.0), bin(band.1).max(bin(band.0) + 1));
+
+        OnsetDetector {
+            sample_rate,
+            fft_input_buffer: fft.make_input_vec(),
+            fft_output_buffer: fft.make_output_vec(),
+            fft,
+            hanning_window: build_hanning_window(WINDOW_SIZE),
+            sample_buffer: Vec::with_capacity(WINDOW_SIZE * 2),
+            band,
+            previous_band: vec![0.0; band.1 - band.0],
+            previous_rms: 0.0,
+            flux: vec![],
+            rms_rise: vec![],
+        }
+    }
+
+    /// Feed mono samples.
+    pub fn push(&mut self, samples: &[f32]) {
+        self.sample_buffer.extend_from_slice(samples);
+
+        while self.sample_buffer.len() >= WINDOW_SIZE {
+            self.process_frame();
+            self.sample_buffer.drain(..HOP_SIZE);
+        }
+    }
+
+    fn process_frame(&mut self) {
+        let frame = &self.sample_buffer[..WINDOW_SIZE];
+
+        for (i, sample) in self.fft_input_buffer.iter_mut().enumerate() {
+            *sample = frame[i] * self.hanning_window[i];
+        }
+
+        self.fft
+            .process(&mut self.fft_input_buffer, &mut self.fft_output_buffer)
+            .expect("Real FFT processing failed");
+
+        let spectrum = amp_spectrum(&self.fft_output_buffer, WINDOW_SIZE);
+        let band = &spectrum[self.band.0..self.band.1];
+
+        self.flux
+            .push(spectral_flux(band, &self.previous_band, band.len() * 2));
+        self.previous_band.copy_from_slice(band);
+
+        let frame_rms = rms(frame);
+        self.rms_rise.push((frame_rms - self.previous_rms).max(0.0));
+        self.previous_rms = frame_rms;
+    }
+
+    /// Number of envelope values per second.
+    pub fn frame_rate(&self) -> f64 {
+        self.sample_rate as f64 / HOP_SIZE as f64
+    }
+
+    /// Time of an envelope value, in seconds. Frames are timed by their center.
+    pub fn frame_time(&self, frame: usize) -> f64 {
+        (frame * HOP_SIZE + WINDOW_SIZE / 2) as f64 / self.sample_rate as f64
+    }
+
+    /// The onset envelope, one value per hop, normalized between 0 and 1.
+    pub fn envelope(&self) -> Vec<f32> {
+        let normalize = |values: &[f32]| {
+            let max = values.iter().cloned().fold(0.0, f32::max);
+            values
+                .iter()
+                .map(|x| if max > 0.0 { x / max } else { 0.0 })
+                .collect::<Vec<_>>()
+        };
+
+        let flux = normalize(&self.flux);
+        let rms_rise = normalize(&self.rms_rise);
+
+        normalize(
+            &flux
+                .iter()
+                .zip(rms_rise.iter())
+                .map(|(flux, rise)| flux + rise)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Pick the peaks of the envelope.
+    pub fn finish(&self) -> Vec<Onset> {
+        let envelope = self.envelope();
+        let seconds_per_frame = HOP_SIZE as f64 / self.sample_rate as f64;
+        let radius = (THRESHOLD_RADIUS / seconds_per_frame).ceil() as usize;
+        let min_distance = MIN_ONSET_DISTANCE / seconds_per_frame;
+
+        let mut onsets: Vec<Onset> = vec![];
+        let mut last_frame: Option<usize> = None;
+
+        for i in 0..envelope.len() {
+            let from = i.saturating_sub(radius);
+            let to = (i + radius + 1).min(envelope.len());
+            let window = &envelope[from..to];
+
+            let is_peak = window.iter().all(|x| *x <= envelope[i]);
+            let average = window.iter().sum::<f32>() / window.len() as f32;
+            if !is_peak || envelope[i] <= average + THRESHOLD_DELTA {
+                continue;
+            }
+
+            let onset = Onset {
+                time: self.frame_time(i),
+                strength: envelope[i],
+            };
+
+            match last_frame {
+                Some(last) if ((i - last) as f64) < min_distance => {
+                    if let Some(previous) = onsets.last_mut() {
+                        if onset.strength > previous.strength {
+                            *previous = onset;
+                            last_frame = Some(i);
+                        }
+                    }
+                }
+                _ => {
+                    onsets.push(onset);
+                    last_frame = Some(i);
+                }
+            }
+        }
+
+        onsets
+    }
+}
+
+/// Decode an audio file and detect its onsets.
+pub fn detect_onsets(
+    file_path: &str,
+    cancel_token: Option<CancellationToken>,
+) -> Result<Option<Vec<Onset>>> {
+    let mut format = get_format(file_path)?;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .with_context(|| "no supported audio tracks")?;
+
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .with_context(|| "no sample rate found")?;
+    let track_id = track.id;
+
+    let mut decoder = get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .with_context(|| "unsupported codec")?;
+
+    let mut detector = OnsetDetector::new(sample_rate);
+    let mut sample_buffer: Option<SampleBuffer<f32>> = None;
+    let mut mono = vec![];
+
+    loop {
+        if cancel_token.as_ref().is_some_and(|x| x.is_cancelled()) {
+            return Ok(None);
+        }
+
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(_)) => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::IoError(_)) | Err(Error::DecodeError(_)) => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        let channels = decoded.spec().channels.count();
+        let buffer = sample_buffer
+            .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        buffer.copy_interleaved_ref(decoded);
+
+        mono.clear();
+        mono.extend(
+            buffer
+                .samples()
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+        detector.push(&mono);
+    }
+
+    Ok(Some(detector.finish()))
+}
@@ -0,0 +1,196 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+
+/// Scales below this are treated as constant dimensions, which are only
+/// centered.
+const MIN_SCALE: f32 = 1e-6;
+
+/// Ratio between the interquartile range and the standard deviation of a
+/// normal distribution, so that robust scales compare with z-scores.
+const IQR_TO_DEVIATION: f32 = 1.349;
+
+/// How the dimensions of feature vectors are brought to a common scale.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(i32)]
+pub enum ScalingMethod {
+    /// Subtract the mean and divide by the standard deviation.
+    #[default]
+    ZScore = 0,
+    /// Subtract the median and divide by the interquartile range, which a
+    /// few outliers barely move.
+    Robust = 1,
+    /// Keep the raw values.
+    Unscaled = 2,
+}
+
+impl From<i32> for ScalingMethod {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => ScalingMethod::Robust,
+            2 => ScalingMethod::Unscaled,
+            _ => ScalingMethod::ZScore,
+        }
+    }
+}
+
+impl FromStr for ScalingMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "zscore" | "z-score" => Ok(ScalingMethod::ZScore),
+            "robust" => Ok(ScalingMethod::Robust),
+            "none" | "unscaled" => Ok(ScalingMethod::Unscaled),
+            _ => Err(anyhow!("Unknown scaling method: {}", s)),
+        }
+    }
+}
+
+/// Per dimension statistics bringing feature vectors to a common scale,
+/// and the weight of every dimension once scaled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureScaler {
+    pub center: Vec<f32>,
+    pub scale: Vec<f32>,
+    pub weight: Vec<f32>,
+}
+
+impl FeatureScaler {
+    /// A scaler leaving vectors untouched.
+    pub fn identity(dimensions: usize) -> Self {
+        FeatureScaler {
+            center: vec![0.0; dimensions],
+            scale: vec![1.0; dimensions],
+            weight: vec![1.0; dimensions],
+        }
+    }
+
+    /// Computes the statistics of every dimension of `vectors`, which all
+    /// have `weight.len()` dimensions.
+    pub fn fit(method: ScalingMethod, vectors: &[Vec<f32>], weight: Vec<f32>) -> Self {
+        let dimensions = weight.len();
+        let mut scaler = FeatureScaler {
+            weight,
+            ..FeatureScaler::identity(dimensions)
+        };
+
+        if method == ScalingMethod::Unscaled || vectors.is_empty() {
+            return scaler;
+        }
+
+        for dimension in 0..dimensions {
+            let mut values: Vec<f32> = vectors.iter().map(|x| x[dimension]).collect();
+            let (mean, deviation) = mean_and_deviation(&values);
+
+            let (center, scale) = match method {
+                ScalingMethod::Robust => {
+                    values.sort_by(|a, b| a.total_cmp(b));
+                    let range = percentile(&values, 0.75) - percentile(&values, 0.25);
+                    // Mostly constant dimensions have no interquartile range
+                    let scale = if range > MIN_SCALE {
+                        range / IQR_TO_DEVIATION
+                    } else {
+                        deviation
+                    };
+                    (percentile(&values, 0.5), scale)
+                }
+                _ => (mean, deviation),
+            };
+
+            scaler.center[dimension] = center;
+            if scale > MIN_SCALE {
+                scaler.scale[dimension] = scale;
+            }
+        }
+
+        scaler
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.center.len()
+    }
+
+    /// Whether vectors scaled by `other` would differ noticeably from
+    /// vectors scaled by this scaler: the weights differ, or a center moved
+    /// by more than `tolerance` of the scale of `other`, or a scale changed
+    /// by more than `tolerance` of itself.
+    pub fn drifted_from(&self, other: &FeatureScaler, tolerance: f32) -> bool {
+        if self.dimensions() != other.dimensions() || self.weight != other.weight {
+            return true;
+        }
+
+        (0..self.dimensions()).any(|x| {
+            (self.center[x] - other.center[x]).abs() > tolerance * other.scale[x]
+                || (self.scale[x] / other.scale[x] - 1.0).abs() > tolerance
+        })
+    }
+
+    /// Scales and weights a vector laid out like the fitted ones.
+    pub fn apply(&self, vector: &[f32]) -> Vec<f32> {
+        vector
+            .iter()
+            .zip(&self.center)
+            .zip(&self.scale)
+            .zip(&self.weight)
+            .map(|(((value, center), scale), weight)| (value - center) / scale * weight)
+            .collect()
+    }
+}
+
+fn mean_and_deviation(values: &[f32]) -> (f32, f32) {
+    let count = values.len() as f64;
+    let mean = values.iter().map(|x| *x as f64).sum::<f64>() / count;
+    let variance = values
+        .iter()
+        .map(|x| (*x as f64 - mean).powi(2))
+        .sum::<f64>()
+        / count;
+
+    (mean as f32, variance.sqrt() as f32)
+}
+
+/// Linearly interpolated percentile of sorted values, `p` between 0 and 1.
+fn percentile(sorted: &[f32], p: f64) -> f32 {
+    let position = p * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    let fraction = (position - lower as f64) as f32;
+
+    sorted[lower] + (sorted[upper] - sorted[lower]) * fraction
+}
+
+/// The weight of every dimension, given their labels and named weights.
+///
+/// A weight applies to the dimension with the same label, or to every
+/// dimension whose label is its name followed by a number, so that `chroma`
+/// weights `chroma0` to `chroma11`. Later weights win over earlier ones, and
+/// dimensions without a weight weigh 1.
+pub fn feature_weights<S: AsRef<str>>(labels: &[String], weights: &[(S, f32)]) -> Result<Vec<f32>> {
+    let mut result = vec![1.0; labels.len()];
+
+    for (name, weight) in weights {
+        let name = name.as_ref().trim();
+        if *weight < 0.0 || !weight.is_finite() {
+            bail!("Invalid weight of {}: {}", name, weight);
+        }
+
+        let mut found = false;
+        for (label, value) in labels.iter().zip(result.iter_mut()) {
+            let matches = label == name
+                || label
+                    .strip_prefix(name)
+                    .is_some_and(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()));
+            if matches {
+                *value = *weight;
+                found = true;
+            }
+        }
+
+        if !found {
+            bail!("Unknown feature: {}", name);
+        }
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,185 @@
+use crate::utils::onset::OnsetDetector;
+
+/// Frequency range, in Hz, the rhythm is followed in.
+const RHYTHM_BAND: (f32, f32) = (30.0, 8000.0);
+
+/// Tempo range considered, in beats per minute.
+const MIN_BPM: f64 = 50.0;
+const MAX_BPM: f64 = 220.0;
+
+/// Center and width, in octaves, of the log-normal tempo prior. Without it
+/// the autocorrelation can not tell a tempo from its half or double.
+const PRIOR_BPM: f64 = 120.0;
+const PRIOR_WIDTH: f64 = 1.0;
+
+/// How strongly the beat tracker keeps beats one period apart.
+const TIGHTNESS: f64 = 100.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tempo {
+    pub bpm: f64,
+    /// Normalized autocorrelation of the onset envelope at the beat period,
+    /// between 0 and 1.
+    pub confidence: f64,
+    /// Position of the first beat in seconds, from the phase of the beat grid.
+    pub offset: f64,
+    /// Beat positions in seconds, empty unless requested.
+    pub beats: Vec<f64>,
+}
+
+/// Streaming tempo estimator working on the onset strength envelope.
+pub struct TempoDetector {
+    onsets: OnsetDetector,
+}
+
+impl TempoDetector {
+    pub fn new(sample_rate: u32) -> Self {
+        TempoDetector {
+            onsets: OnsetDetector::with_band(sample_rate, RHYTHM_BAND),
+        }
+    }
+
+    /// Feed mono samples.
+    pub fn push(&mut self, samples: &[f32]) {
+        self.onsets.push(samples);
+    }
+
+    /// Estimate the tempo and the first beat, and the beat positions if
+    /// `with_beats` is set.
+    /// Returns `None` when the audio is too short or has no onsets.
+    pub fn finish(&self, with_beats: bool) -> Option<Tempo> {
+        let envelope: Vec<f64> = self.onsets.envelope().iter().map(|x| *x as f64).collect();
+        let frame_rate = self.onsets.frame_rate();
+
+        let min_lag = (60.0 * frame_rate / MAX_BPM).floor() as usize;
+        let max_lag = (60.0 * frame_rate / MIN_BPM).ceil() as usize;
+        if min_lag < 1 || envelope.len() < max_lag * 2 {
+            return None;
+        }
+
+        let mean = envelope.iter().sum::<f64>() / envelope.len() as f64;
+        let centered: Vec<f64> = envelope.iter().map(|x| x - mean).collect();
+
+        let autocorrelation = |lag: usize| -> f64 {
+            centered
+                .iter()
+                .zip(centered[lag..].iter())
+                .map(|(a, b)| a * b)
+                .sum::<f64>()
+                / (centered.len() - lag) as f64
+        };
+
+        let energy = autocorrelation(0);
+        if energy <= 0.0 {
+            return None;
+        }
+
+        let acf: Vec<f64> = (min_lag - 1..=max_lag + 1).map(autocorrelation).collect();
+        let acf_at = |lag: usize| acf[lag + 1 - min_lag];
+
+        let prior = |lag: f64| {
+            let bpm = 60.0 * frame_rate / lag;
+            (-0.5 * ((bpm / PRIOR_BPM).log2() / PRIOR_WIDTH).powi(2)).exp()
+        };
+
+        let best_lag = (min_lag..=max_lag).max_by(|a, b| {
+            let a = acf_at(*a) * prior(*a as f64);
+            let b = acf_at(*b) * prior(*b as f64);
+            a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+        })?;
+
+        // Parabolic interpolation around the peak for a fractional period
+        let (left, center, right) = (acf_at(best_lag - 1), acf_at(best_lag), acf_at(best_lag + 1));
+        let denominator = left - 2.0 * center + right;
+        let shift = if denominator < 0.0 {
+            (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        let period = best_lag as f64 + shift;
+
+        let beats = if with_beats {
+            track_beats(&envelope, period)
+                .into_iter()
+                .map(|x| self.onsets.frame_time(x))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        Some(Tempo {
+            bpm: 60.0 * frame_rate / period,
+            confidence: (center / energy).clamp(0.0, 1.0),
+            offset: self.onsets.frame_time(beat_phase(&envelope, period)),
+            beats,
+        })
+    }
+}
+
+/// First frame of the beat grid with the given period that collects the most
+/// onset strength. Much cheaper than tracking every beat.
+fn beat_phase(envelope: &[f64], period: f64) -> usize {
+    let strength = |phase: usize| {
+        (0..)
+            .map(|k| (phase as f64 + k as f64 * period).round() as usize)
+            .take_while(|x| *x < envelope.len())
+            .map(|x| envelope[x])
+            .sum::<f64>()
+    };
+
+    (0..period.ceil() as usize)
+        .max_by(|a, b| {
+            strength(*a)
+                .partial_cmp(&strength(*b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .unwrap_or_default()
+}
+
+/// Dynamic programming beat tracker (Ellis, 2007). Every frame scores its
+/// onset strength plus the best score of a previous beat, penalized by how
+/// far the interval is from the period.
+fn track_beats(envelope: &[f64], period: f64) -> Vec<usize> {
+    let len = envelope.len();
+    let mut score = vec![0.0; len];
+    let mut backlink: Vec<Option<usize>> = vec![None; len];
+
+    let min_interval = (period / 2.0).round().max(1.0) as usize;
+    let max_interval = (period * 2.0).round() as usize;
+
+    for t in 0..len {
+        let mut best: Option<(usize, f64)> = None;
+
+        for interval in min_interval..=max_interval.min(t) {
+            let previous = t - interval;
+            let penalty = TIGHTNESS * (interval as f64 / period).ln().powi(2);
+            let candidate = score[previous] - penalty;
+
+            if best.is_none_or(|(_, x)| candidate > x) {
+                best = Some((previous, candidate));
+            }
+        }
+
+        score[t] = envelope[t] + best.map(|(_, x)| x.max(0.0)).unwrap_or(0.0);
+        backlink[t] = best.filter(|(_, x)| *x > 0.0).map(|(x, _)| x);
+    }
+
+    // The last beat is the best scoring frame within the final period
+    let tail = len.saturating_sub(period.ceil() as usize);
+    let Some(mut beat) = (tail..len).max_by(|a, b| {
+        score[*a]
+            .partial_cmp(&score[*b])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    }) else {
+        return vec![];
+    };
+
+    let mut beats = vec![beat];
+    while let Some(previous) = backlink[beat] {
+        beats.push(previous);
+        beat = previous;
+    }
+    beats.reverse();
+
+    beats
+}
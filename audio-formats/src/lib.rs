@@ -0,0 +1,336 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Number of bytes at the start of a file needed to recognize its format.
+pub const SNIFF_LENGTH: usize = 64;
+
+/// Length of the header of an ID3v2 tag, and of its footer when it has one.
+const ID3_HEADER_LENGTH: usize = 10;
+
+/// Major brands of MP4 files holding audio. Other brands are images, like
+/// `heic`, `mif1` and `avif`, or videos, like `qt  ` and `M4V `.
+const MP4_AUDIO_BRANDS: [&[u8]; 4] = [b"M4A ", b"M4B ", b"mp42", b"isom"];
+
+/// A container or codec that may be found in a library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub name: &'static str,
+    /// Lowercase extensions of the format, the usual one first.
+    pub extensions: &'static [&'static str],
+    /// Whether the decoders built into playback and analysis handle the
+    /// format. Symphonia has no Opus or WavPack decoder yet, so those files
+    /// are only recognized.
+    pub decodable: bool,
+}
+
+impl AudioFormat {
+    /// The usual extension of the format, as a hint for the decoders.
+    pub fn extension(&self) -> &'static str {
+        self.extensions[0]
+    }
+}
+
+/// Every format known to Rune. Playback enables the matching rodio and
+/// Symphonia features for every decodable format, see `playback/Cargo.toml`.
+pub const AUDIO_FORMATS: &[AudioFormat] = &[
+    AudioFormat {
+        name: "mp3",
+        extensions: &["mp3"],
+        decodable: true,
+    },
+    AudioFormat {
+        name: "flac",
+        extensions: &["flac"],
+        decodable: true,
+    },
+    AudioFormat {
+        name: "wav",
+        extensions: &["wav", "wave"],
+        decodable: true,
+    },
+    AudioFormat {
+        name: "aiff",
+        extensions: &["aiff", "aif", "aifc"],
+        decodable: true,
+    },
+    AudioFormat {
+        name: "aac",
+        extensions: &["aac"],
+        decodable: true,
+    },
+    // AAC or ALAC in an MP4 container
+    AudioFormat {
+        name: "mp4",
+        extensions: &["m4a", "mp4", "m4b"],
+        decodable: true,
+    },
+    // Vorbis or FLAC in an Ogg container
+    AudioFormat {
+        name: "ogg",
+        extensions: &["ogg", "oga"],
+        decodable: true,
+    },
+    AudioFormat {
+        name: "opus",
+        extensions: &["opus"],
+        decodable: false,
+    },
+    AudioFormat {
+        name: "wavpack",
+        extensions: &["wv"],
+        decodable: false,
+    },
+];
+
+fn format_named(name: &str) -> Option<&'static AudioFormat> {
+    AUDIO_FORMATS.iter().find(|x| x.name == name)
+}
+
+/// The format using an extension, in any case and without the dot.
+pub fn format_by_extension(extension: &str) -> Option<&'static AudioFormat> {
+    let extension = extension.to_lowercase();
+    AUDIO_FORMATS
+        .iter()
+        .find(|x| x.extensions.contains(&extension.as_str()))
+}
+
+/// The extensions of every format the decoders handle.
+pub fn decodable_extensions() -> impl Iterator<Item = &'static str> {
+    AUDIO_FORMATS
+        .iter()
+        .filter(|x| x.decodable)
+        .flat_map(|x| x.extensions.iter().copied())
+}
+
+/// Recognizes a format by the first bytes of a file, at most `SNIFF_LENGTH`
+/// of them. An ID3 tag in front of the stream is skipped when the stream
+/// starts within the header.
+pub fn sniff_format(header: &[u8]) -> Option<&'static AudioFormat> {
+    if let Some(length) = id3_tag_length(header) {
+        return sniff_format(header.get(length..)?);
+    }
+
+    let at = |offset: usize, signature: &[u8]| {
+        header
+            .get(offset..offset + signature.len())
+            .is_some_and(|x| x == signature)
+    };
+
+    let name = if at(0, b"fLaC") {
+        "flac"
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        "wav"
+    } else if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+        "aiff"
+    } else if at(0, b"OggS") {
+        // The first page of an Ogg stream holds the header of its codec
+        if header.windows(8).any(|x| x == b"OpusHead") {
+            "opus"
+        } else {
+            "ogg"
+        }
+    } else if at(4, b"ftyp") {
+        if !MP4_AUDIO_BRANDS.iter().any(|x| at(8, x)) {
+            return None;
+        }
+        "mp4"
+    } else if at(0, b"wvpk") {
+        "wavpack"
+    } else {
+        sniff_frame(header)?
+    };
+
+    format_named(name)
+}
+
+/// Length of the ID3v2 tag the header starts with, footer included. Tagged
+/// MP3, FLAC and AAC files may all start with one.
+fn id3_tag_length(header: &[u8]) -> Option<usize> {
+    let [b'I', b'D', b'3', _, _, flags, size @ ..] = header.get(..ID3_HEADER_LENGTH)? else {
+        return None;
+    };
+    // The size is stored in 7 bits per byte
+    if size.iter().any(|x| x & 0x80 != 0) {
+        return None;
+    }
+
+    let size = size.iter().fold(0, |size, x| (size << 7) | *x as usize);
+    let footer = if flags & 0x10 != 0 {
+        ID3_HEADER_LENGTH
+    } else {
+        0
+    };
+
+    Some(ID3_HEADER_LENGTH + size + footer)
+}
+
+/// Recognizes raw MPEG audio and ADTS frames, which have no signature but a
+/// sync word followed by a few fields that can't take every value.
+fn sniff_frame(header: &[u8]) -> Option<&'static str> {
+    let [first, second, third, ..] = *header else {
+        return None;
+    };
+    if first != 0xFF || second & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    let layer = (second >> 1) & 0b11;
+    if layer == 0 {
+        // ADTS has a longer sync word and 13 sample rates
+        return (second & 0xF0 == 0xF0 && (third >> 2) & 0b1111 < 13).then_some("aac");
+    }
+
+    let version = (second >> 3) & 0b11;
+    let bitrate = third >> 4;
+    let sample_rate = (third >> 2) & 0b11;
+    (version != 0b01 && bitrate != 0b1111 && sample_rate != 0b11).then_some("mp3")
+}
+
+/// Recognizes the format of a file by its content, past its ID3 tag.
+pub fn sniff_file(path: &Path) -> io::Result<Option<&'static AudioFormat>> {
+    let mut file = File::open(path)?;
+    let mut header = read_header(&mut file)?;
+
+    // Tags with cover art are much longer than the header
+    if let Some(length) = id3_tag_length(&header) {
+        file.seek(SeekFrom::Start(length as u64))?;
+        header = read_header(&mut file)?;
+    }
+
+    Ok(sniff_format(&header))
+}
+
+fn read_header(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(SNIFF_LENGTH);
+    file.take(SNIFF_LENGTH as u64).read_to_end(&mut header)?;
+
+    Ok(header)
+}
+
+/// The extension to hint the decoders with: the one of the format found in
+/// the file, or the one of the path when the content isn't recognized.
+pub fn hint_extension(path: &Path) -> Option<String> {
+    if let Ok(Some(format)) = sniff_file(path) {
+        return Some(format.extension().to_string());
+    }
+
+    path.extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sniffed(header: &[u8]) -> Option<&'static str> {
+        sniff_format(header).map(|x| x.name)
+    }
+
+    #[test]
+    fn test_extensions() {
+        assert_eq!(format_by_extension("M4B").unwrap().name, "mp4");
+        assert_eq!(format_by_extension("aif").unwrap().name, "aiff");
+        assert_eq!(format_by_extension("oga").unwrap().name, "ogg");
+        assert!(format_by_extension("jpg").is_none());
+
+        let decodable: Vec<&str> = decodable_extensions().collect();
+        assert!(decodable.contains(&"aifc"));
+        assert!(decodable.contains(&"m4b"));
+        assert!(!decodable.contains(&"opus"));
+        assert!(!decodable.contains(&"wv"));
+    }
+
+    #[test]
+    fn test_sniff_signatures() {
+        assert_eq!(sniffed(b"fLaC\0\0\0\x22"), Some("flac"));
+        assert_eq!(sniffed(b"RIFF\x24\0\0\0WAVEfmt "), Some("wav"));
+        assert_eq!(sniffed(b"FORM\0\0\0\x24AIFCFVER"), Some("aiff"));
+        assert_eq!(sniffed(b"\0\0\0\x20ftypM4A \0\0\0\0"), Some("mp4"));
+        assert_eq!(sniffed(b"wvpk\x24\0\0\0"), Some("wavpack"));
+
+        let mut page = b"OggS\0\x02".to_vec();
+        page.resize(28, 0);
+        assert_eq!(sniffed(&page), Some("ogg"));
+        page.extend_from_slice(b"OpusHead\x01\x02");
+        assert_eq!(sniffed(&page), Some("opus"));
+        page[28..32].copy_from_slice(b"\x01vor");
+        assert_eq!(sniffed(&page), Some("ogg"));
+
+        assert_eq!(sniffed(b"RIFF\x24\0\0\0AVI "), None);
+        assert_eq!(sniffed(b"fLa"), None);
+        assert_eq!(sniffed(b""), None);
+    }
+
+    #[test]
+    fn test_sniff_mp4_brands() {
+        for brand in [b"M4A ", b"M4B ", b"mp42", b"isom"] {
+            let mut header = b"\0\0\0\x20ftyp".to_vec();
+            header.extend_from_slice(brand);
+            assert_eq!(sniffed(&header), Some("mp4"), "{:?}", brand);
+        }
+
+        // Images and videos
+        for brand in [b"heic", b"mif1", b"avif", b"qt  ", b"M4V "] {
+            let mut header = b"\0\0\0\x20ftyp".to_vec();
+            header.extend_from_slice(brand);
+            assert_eq!(sniffed(&header), None, "{:?}", brand);
+        }
+    }
+
+    #[test]
+    fn test_sniff_past_id3() {
+        let tag = |size: u8| vec![b'I', b'D', b'3', 4, 0, 0, 0, 0, 0, size];
+
+        let mut header = tag(4);
+        header.extend_from_slice(b"\0\0\0\0fLaC\0\0\0\x22");
+        assert_eq!(sniffed(&header), Some("flac"));
+
+        let mut header = tag(0);
+        header.extend_from_slice(&[0xFF, 0xF1, 0x50, 0x80]);
+        assert_eq!(sniffed(&header), Some("aac"));
+
+        let mut header = tag(0);
+        header.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+        assert_eq!(sniffed(&header), Some("mp3"));
+
+        // The stream starts past the header
+        assert_eq!(sniffed(&tag(100)), None);
+    }
+
+    #[test]
+    fn test_sniff_frames() {
+        // MPEG-1 layer III, 128 kbps, 44.1 kHz
+        assert_eq!(sniffed(&[0xFF, 0xFB, 0x90, 0x64]), Some("mp3"));
+        // MPEG-4 ADTS, 44.1 kHz
+        assert_eq!(sniffed(&[0xFF, 0xF1, 0x50, 0x80]), Some("aac"));
+        // JPEG
+        assert_eq!(sniffed(&[0xFF, 0xD8, 0xFF, 0xE0]), None);
+        // Reserved version, bitrate and sample rate
+        assert_eq!(sniffed(&[0xFF, 0xEB, 0x90, 0x64]), None);
+        assert_eq!(sniffed(&[0xFF, 0xFB, 0xF0, 0x64]), None);
+        assert_eq!(sniffed(&[0xFF, 0xFB, 0x9C, 0x64]), None);
+        assert_eq!(sniffed(&[0xFF, 0xF1, 0x74, 0x80]), None);
+    }
+
+    #[test]
+    fn test_hint_extension() {
+        let path = std::env::temp_dir().join(format!("rune-formats-{}.mp3", std::process::id()));
+
+        std::fs::write(&path, b"fLaC\0\0\0\x22").unwrap();
+        assert_eq!(hint_extension(&path).as_deref(), Some("flac"));
+
+        std::fs::write(&path, b"unknown").unwrap();
+        assert_eq!(hint_extension(&path).as_deref(), Some("mp3"));
+
+        // A FLAC stream behind a tag longer than the header
+        let mut content = vec![b'I', b'D', b'3', 4, 0, 0, 0, 0, 1, 0];
+        content.resize(10 + 128, 0);
+        content.extend_from_slice(b"fLaC\0\0\0\x22");
+        std::fs::write(&path, content).unwrap();
+        assert_eq!(hint_extension(&path).as_deref(), Some("flac"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -11,15 +11,8 @@ fn main() {
     let result = analyze_audio(path, 4096, 4096 / 2, ComputingDevice::Gpu, None);
 
     let analysis_result = match result {
-        Ok(x) =>
         // Process the audio file and perform FFT using Overlap-Save method.
-        {
-            if let Some(x) = x {
-                normalize_analysis_result(&x)
-            } else {
-                panic!("Analysis canceled");
-            }
-        }
+        Ok(x) => normalize_analysis_result(&x),
         Err(e) => {
             println!("Error: {}", e);
             panic!("Unable to analysis the track");
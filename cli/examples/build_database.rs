@@ -2,14 +2,16 @@ use log::{error, info};
 use std::path::PathBuf;
 use tracing_subscriber::filter::EnvFilter;
 
+use analysis::extractor::ExtractorSet;
 use analysis::utils::computing_device::ComputingDevice;
 use database::actions::analysis::{
     analysis_audio_library, empty_progress_callback as empty_analysis_progress_callback,
+    AnalysisOptions,
 };
 use database::actions::metadata::{
     empty_progress_callback as empty_scan_progress_callback, scan_audio_library,
 };
-use database::actions::recommendation::sync_recommendation;
+use database::actions::recommendation::{sync_recommendation, IndexScaling};
 use database::connection::{connect_main_db, connect_recommendation_db};
 
 #[tokio::main]
@@ -50,6 +52,7 @@ async fn main() {
         &root_path,
         10,
         ComputingDevice::Gpu,
+        AnalysisOptions::default(),
         empty_analysis_progress_callback,
         None,
     )
@@ -58,7 +61,14 @@ async fn main() {
 
     info!("Syncing recommendation");
     let recommend_db = connect_recommendation_db(&path, None).unwrap();
-    match sync_recommendation(&main_db, &recommend_db).await {
+    match sync_recommendation(
+        &main_db,
+        &recommend_db,
+        ExtractorSet::default(),
+        &IndexScaling::default(),
+    )
+    .await
+    {
         Ok(_) => info!("OK!"),
         Err(e) => error!("Unable to sync recommendation: {}", e),
     };
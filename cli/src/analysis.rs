@@ -1,21 +1,72 @@
 use std::path::Path;
 
+use prettytable::{row, Table};
+
+use analysis::extractor::ExtractorSet;
 use analysis::utils::computing_device::ComputingDevice;
-use database::actions::analysis::{analysis_audio_library, empty_progress_callback};
-use database::actions::recommendation::sync_recommendation;
+use database::actions::analysis::{
+    analysis_audio_library, empty_progress_callback, get_analysis_failures, set_analysis_settings,
+    AnalysisOptions, AnalysisSettings,
+};
+use database::actions::recommendation::{sync_recommendation, IndexScaling};
+use database::actions::segments::load_segments;
 use database::connection::{MainDbConnection, RecommendationDbConnection};
 
+/// Parses a scaling method and `name=weight` feature weights.
+pub fn parse_index_scaling(method: &str, weights: &[String]) -> Result<IndexScaling, String> {
+    let weights = weights
+        .iter()
+        .map(|x| {
+            let (name, weight) = x
+                .split_once('=')
+                .ok_or_else(|| format!("Expected name=weight, got {}", x))?;
+            let weight: f32 = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid weight of {}: {}", name, weight))?;
+            Ok((name.trim().to_string(), weight))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(IndexScaling {
+        method: method.parse().map_err(|e| format!("{}", e))?,
+        weights,
+    })
+}
+
+pub struct AnalyzeLibraryOptions<'a> {
+    pub path: &'a Path,
+    pub computing_device: ComputingDevice,
+    pub segment_duration: Option<f64>,
+    pub extractors: ExtractorSet,
+    pub scaling: IndexScaling,
+    pub retry_failed: bool,
+}
+
 pub async fn analyze_audio_library(
-    computing_device: ComputingDevice,
     main_db: &MainDbConnection,
     analysis_db: &RecommendationDbConnection,
-    path: &Path,
+    options: AnalyzeLibraryOptions<'_>,
 ) {
+    let AnalyzeLibraryOptions {
+        path,
+        computing_device,
+        segment_duration,
+        extractors,
+        scaling,
+        retry_failed,
+    } = options;
+
     if let Err(e) = analysis_audio_library(
         main_db,
         path,
         15,
         computing_device,
+        AnalysisOptions {
+            segment_duration,
+            extractors,
+            retry_failed,
+        },
         empty_progress_callback,
         None,
     )
@@ -27,12 +78,69 @@ pub async fn analyze_audio_library(
 
     print!("Analysis finished");
 
-    if let Err(e) = sync_recommendation(main_db, analysis_db).await {
+    match get_analysis_failures(main_db).await {
+        Ok(failures) => {
+            for x in &failures {
+                eprintln!("File {} could not be analyzed: {}", x.file_id, x.detail);
+            }
+        }
+        Err(e) => eprintln!("Failed to list analysis failures: {}", e),
+    }
+
+    if let Err(e) = sync_recommendation(main_db, analysis_db, extractors, &scaling).await {
         eprintln!("Sync recommendation failed: {}", e);
         return;
     }
 
     print!("Sync finished");
 
+    let settings = AnalysisSettings {
+        computing_device,
+        segment_duration,
+        extractors,
+        scaling,
+    };
+    if let Err(e) = set_analysis_settings(main_db, &settings).await {
+        eprintln!("Failed to record analysis settings: {}", e);
+        return;
+    }
+
     println!("Audio analysis completed successfully");
 }
+
+/// Prints the energy curve of a file analyzed in segment mode.
+pub fn print_segments(lib_path: &Path, file_id: i32) {
+    let segments = match load_segments(lib_path, file_id) {
+        Ok(Some(segments)) => segments,
+        Ok(None) => {
+            println!("File {} was not analyzed in segment mode", file_id);
+            return;
+        }
+        Err(e) => {
+            eprintln!("Failed to load the segments of {}: {:#}", file_id, e);
+            return;
+        }
+    };
+
+    let mut table = Table::new();
+    table.add_row(row![
+        "Start",
+        "Duration",
+        "RMS",
+        "Energy",
+        "Spectral Centroid"
+    ]);
+
+    for x in &segments {
+        table.add_row(row![
+            format!("{:.1}", x.start),
+            format!("{:.1}", x.duration),
+            format!("{:.4}", x.rms),
+            format!("{:.4}", x.energy),
+            format!("{:.1}", x.spectral_centroid)
+        ]);
+    }
+
+    println!("Segments of file {}:", file_id);
+    table.printstd();
+}
@@ -0,0 +1,119 @@
+use std::path::Path;
+
+use prettytable::{row, Table};
+
+use database::actions::file::get_media_files;
+use database::actions::suggestions::{
+    accept_metadata_suggestion, list_files_with_metadata_suggestions, list_metadata_suggestions,
+    reject_metadata_suggestion,
+};
+use database::connection::MainDbConnection;
+use tag_editor::music_brainz::auto_tag::{auto_tag_files, AutoTagOptions};
+
+const PAGE_SIZE: usize = 1000;
+
+async fn all_file_ids(main_db: &MainDbConnection) -> Option<Vec<i32>> {
+    let mut file_ids = Vec::new();
+    let mut cursor = 0;
+
+    loop {
+        let files = match get_media_files(main_db, cursor, PAGE_SIZE).await {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Failed to list the library files: {}", e);
+                return None;
+            }
+        };
+        match files.last() {
+            Some(last) => cursor = last.id as usize,
+            None => break,
+        }
+        file_ids.extend(files.iter().map(|x| x.id));
+    }
+
+    Some(file_ids)
+}
+
+pub async fn auto_tag(main_db: &MainDbConnection, path: &Path, api_key: &str, file_ids: &[i32]) {
+    let file_ids = if file_ids.is_empty() {
+        match all_file_ids(main_db).await {
+            Some(x) => x,
+            None => return,
+        }
+    } else {
+        file_ids.to_vec()
+    };
+
+    let result = auto_tag_files(
+        main_db,
+        path,
+        &file_ids,
+        &AutoTagOptions::new(api_key),
+        |processed, total| println!("Identified {}/{} files", processed, total),
+        None,
+    )
+    .await;
+
+    match result {
+        Ok(summary) => println!(
+            "{} applied, {} waiting for review, {} unmatched, {} failed.",
+            summary.applied, summary.pending, summary.unmatched, summary.failed
+        ),
+        Err(e) => eprintln!("Failed to auto tag the library: {}", e),
+    }
+}
+
+pub async fn print_suggestions(main_db: &MainDbConnection, file_ids: &[i32]) {
+    let file_ids = if file_ids.is_empty() {
+        match list_files_with_metadata_suggestions(main_db).await {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Failed to list the pending suggestions: {}", e);
+                return;
+            }
+        }
+    } else {
+        file_ids.to_vec()
+    };
+
+    let mut table = Table::new();
+    table.add_row(row!["ID", "File ID", "Title", "Artist", "Album", "Score"]);
+
+    for file_id in file_ids {
+        let suggestions = match list_metadata_suggestions(main_db, file_id).await {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Failed to list the suggestions of {}: {}", file_id, e);
+                continue;
+            }
+        };
+
+        for x in suggestions {
+            table.add_row(row![
+                x.id,
+                x.file_id,
+                x.title,
+                x.artist,
+                x.album.unwrap_or_default(),
+                format!("{:.3}", x.score)
+            ]);
+        }
+    }
+
+    table.printstd();
+}
+
+pub async fn review_suggestion(main_db: &MainDbConnection, suggestion_id: i32, accept: bool) {
+    let result = if accept {
+        accept_metadata_suggestion(main_db, suggestion_id).await
+    } else {
+        reject_metadata_suggestion(main_db, suggestion_id).await
+    };
+
+    match result {
+        Ok(true) if accept => println!("Suggestion {} applied.", suggestion_id),
+        Ok(true) => println!("Suggestion {} rejected.", suggestion_id),
+        Ok(false) => eprintln!("Suggestion {} does not exist.", suggestion_id),
+        Err(e) => eprintln!("Failed to review suggestion {}: {}", suggestion_id, e),
+    }
+}
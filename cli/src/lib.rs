@@ -1,5 +1,7 @@
 pub mod analysis;
+pub mod auto_tag;
 pub mod index;
 pub mod mix;
 pub mod playback;
 pub mod recommend;
+pub mod replaygain;
@@ -5,6 +5,7 @@ use prettytable::{row, Table};
 use std::path::PathBuf;
 use tracing_subscriber::filter::EnvFilter;
 
+use analysis::extractor::ExtractorSet;
 use database::actions::cover_art::scan_cover_arts;
 use database::actions::metadata::{
     empty_progress_callback, get_metadata_summary_by_file_ids, scan_audio_library,
@@ -12,10 +13,12 @@ use database::actions::metadata::{
 use database::actions::search::search_for;
 use database::connection::{connect_main_db, connect_recommendation_db};
 use rune::analysis::*;
+use rune::auto_tag::{auto_tag, print_suggestions, review_suggestion};
 use rune::index::index_audio_library;
 use rune::mix::{mixes, RecommendMixOptions};
 use rune::playback::*;
 use rune::recommend::*;
+use rune::replaygain::replaygain;
 
 #[derive(Parser)]
 #[command(name = "Media Manager")]
@@ -43,6 +46,59 @@ enum Commands {
         /// The compute device to use (cpu/gpu)
         #[arg(short, long, default_value = "gpu")]
         computing_device: String,
+
+        /// Also store features for every segment of this many seconds
+        #[arg(long)]
+        segment_duration: Option<f64>,
+
+        /// Try again the files that failed to analyze before
+        #[arg(long)]
+        retry_failed: bool,
+
+        /// The feature extractors to run and build the recommendation index from
+        #[arg(long, value_delimiter = ',', default_value = "spectral")]
+        extractors: Vec<String>,
+
+        /// How the features are scaled in the recommendation index (zscore/robust/none)
+        #[arg(long, default_value = "zscore")]
+        scaling: String,
+
+        /// Weights of the scaled features, like chroma=2,energy=0.5
+        #[arg(long, value_delimiter = ',')]
+        weights: Vec<String>,
+    },
+
+    /// Compute ReplayGain 2.0 values from the analysis and write them as tags
+    Replaygain {
+        /// Only print the values, without writing any file
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Identify tracks through AcoustID and tag them with MusicBrainz metadata
+    AutoTag {
+        /// The AcoustID client API key
+        #[arg(short, long)]
+        api_key: String,
+
+        /// The IDs of the files to identify, every file in the library if empty
+        #[arg(short, long, num_args = 1..)]
+        file_ids: Vec<i32>,
+    },
+
+    /// List the metadata suggestions waiting for review
+    Suggestions {
+        /// Only list the suggestions of these files
+        #[arg(short, long, num_args = 1..)]
+        file_ids: Vec<i32>,
+
+        /// Apply the suggestion with this ID
+        #[arg(long, group = "review_group")]
+        accept: Option<i32>,
+
+        /// Discard the suggestion with this ID
+        #[arg(long, group = "review_group")]
+        reject: Option<i32>,
     },
 
     /// Show information of the track in the library
@@ -50,6 +106,10 @@ enum Commands {
         /// A list of file IDs to retrieve information for
         #[arg(short, long, num_args = 1..)]
         file_ids: Vec<i32>,
+
+        /// Also show the analysis segments of the tracks
+        #[arg(long)]
+        segments: bool,
     },
 
     /// Play audio files in the library
@@ -174,16 +234,59 @@ async fn main() {
         Commands::Index => {
             index_audio_library(&main_db).await;
         }
-        Commands::Analyze { computing_device } => {
+        Commands::Analyze {
+            computing_device,
+            segment_duration,
+            retry_failed,
+            extractors,
+            scaling,
+            weights,
+        } => {
+            let extractors = match ExtractorSet::from_names(extractors) {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
+            let scaling = match parse_index_scaling(scaling, weights) {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
+
             analyze_audio_library(
-                computing_device.as_str().into(),
                 &main_db,
                 &analysis_db,
-                &path,
+                AnalyzeLibraryOptions {
+                    path: &path,
+                    computing_device: computing_device.as_str().into(),
+                    segment_duration: *segment_duration,
+                    extractors,
+                    scaling,
+                    retry_failed: *retry_failed,
+                },
             )
             .await;
         }
-        Commands::Info { file_ids } => {
+        Commands::Replaygain { dry_run } => {
+            replaygain(&main_db, &path, *dry_run).await;
+        }
+        Commands::AutoTag { api_key, file_ids } => {
+            auto_tag(&main_db, &path, api_key, file_ids).await;
+        }
+        Commands::Suggestions {
+            file_ids,
+            accept,
+            reject,
+        } => match (accept, reject) {
+            (Some(id), _) => review_suggestion(&main_db, *id, true).await,
+            (_, Some(id)) => review_suggestion(&main_db, *id, false).await,
+            _ => print_suggestions(&main_db, file_ids).await,
+        },
+        Commands::Info { file_ids, segments } => {
             match get_metadata_summary_by_file_ids(&main_db, file_ids.to_vec()).await {
                 Ok(summaries) => {
                     let mut table = Table::new();
@@ -215,6 +318,12 @@ async fn main() {
                     error!("Failed to retrieve metadata summary: {}", e);
                 }
             }
+
+            if *segments {
+                for file_id in file_ids {
+                    print_segments(&canonicalized_path, *file_id);
+                }
+            }
         }
         // In the main function, update the match statement for Commands::Play
         Commands::Play { mode, id } => match mode.as_deref() {
@@ -9,7 +9,9 @@ use tokio::task;
 
 use database::actions::file::{get_file_by_id, get_random_files};
 use database::connection::MainDbConnection;
-use playback::player::{Playable, Player, PlayingItem};
+use database::playing_item::MediaFileHandle;
+use playback::player::{Playable, Player};
+use playback::PlaylistItem;
 
 async fn play_files(main_db: &MainDbConnection, canonicalized_path: &Path, file_ids: Vec<i32>) {
     let player = Player::new(None);
@@ -35,11 +37,19 @@ async fn play_files(main_db: &MainDbConnection, canonicalized_path: &Path, file_
         files
             .into_iter()
             .map(|file| {
-                (
-                    PlayingItem::InLibrary(file.id),
-                    canonicalize(canonicalized_path.join(file.directory).join(file.file_name))
-                        .unwrap(),
+                let path = canonicalize(
+                    canonicalized_path
+                        .join(&file.directory)
+                        .join(&file.file_name),
                 )
+                .unwrap();
+                let handle = MediaFileHandle::from(file);
+
+                PlaylistItem {
+                    item: handle.item,
+                    path,
+                    range: handle.range,
+                }
             })
             .collect(),
         playback::strategies::AddMode::AppendToEnd,
@@ -0,0 +1,73 @@
+use std::path::Path;
+
+use prettytable::{row, Table};
+
+use database::actions::replaygain::compute_replaygain;
+use database::connection::MainDbConnection;
+use tag_editor::tag_writer::replaygain::write_replaygain_tags;
+
+fn format_optional(value: Option<f64>, precision: usize) -> String {
+    value
+        .map(|x| format!("{:.*}", precision, x))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub async fn replaygain(main_db: &MainDbConnection, path: &Path, dry_run: bool) {
+    let values = match compute_replaygain(main_db).await {
+        Ok(values) => values,
+        Err(e) => {
+            eprintln!("Failed to compute ReplayGain: {}", e);
+            return;
+        }
+    };
+
+    if values.is_empty() {
+        println!("No measured tracks, analyze the library first.");
+        return;
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![
+        "ID",
+        "File",
+        "Track Gain",
+        "Track Peak",
+        "Album Gain",
+        "Album Peak"
+    ]);
+
+    for x in &values {
+        table.add_row(row![
+            x.file_id,
+            x.path.display(),
+            format!("{:.2} dB", x.track_gain),
+            format_optional(x.track_peak, 6),
+            format_optional(x.album_gain, 2),
+            format_optional(x.album_peak, 6)
+        ]);
+    }
+
+    table.printstd();
+
+    if dry_run {
+        println!("Dry run, no file was written.");
+        return;
+    }
+
+    match write_replaygain_tags(main_db, path, &values).await {
+        Ok(report) => {
+            for (file_id, error) in &report.failed {
+                eprintln!("Failed to write {}: {}", file_id, error);
+            }
+            println!(
+                "ReplayGain tags written to {} files, {} CUE tracks skipped, {} failed.",
+                report.written,
+                report.skipped,
+                report.failed.len()
+            );
+        }
+        Err(e) => {
+            eprintln!("Failed to write ReplayGain tags: {}", e);
+        }
+    }
+}
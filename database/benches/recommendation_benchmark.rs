@@ -0,0 +1,78 @@
+cfg_if::cfg_if! {
+    if #[cfg(feature = "bench")] {
+        use std::{hint::black_box, time::Duration};
+        use criterion::{criterion_group, criterion_main, Criterion};
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        use database::actions::recommendation::{rebuild_index, update_index, REBUILD_THRESHOLD};
+        use database::connection::connect_recommendation_db;
+
+        const ITEMS: u32 = 100_000;
+        const DIMENSIONS: usize = 61;
+
+        fn library(rng: &mut StdRng) -> Vec<(u32, Vec<f32>)> {
+            (0..ITEMS)
+                .map(|x| (x, (0..DIMENSIONS).map(|_| rng.gen_range(-3.0..3.0)).collect()))
+                .collect()
+        }
+
+        /// The library with one percent of the items removed, and one
+        /// percent of them analyzed again.
+        fn changed_library(vectors: &[(u32, Vec<f32>)], rng: &mut StdRng) -> Vec<(u32, Vec<f32>)> {
+            vectors
+                .iter()
+                .filter(|(item, _)| item % 100 != 0)
+                .map(|(item, vector)| match item % 100 {
+                    1 => (*item, (0..DIMENSIONS).map(|_| rng.gen_range(-3.0..3.0)).collect()),
+                    _ => (*item, vector.clone()),
+                })
+                .collect()
+        }
+
+        fn recommendation_benchmark(c: &mut Criterion) {
+            let lib_path = std::env::temp_dir().join(format!("rune-recommendation-{}", std::process::id()));
+            std::fs::create_dir_all(&lib_path).unwrap();
+            let recommend_db = connect_recommendation_db(lib_path.to_str().unwrap(), None).unwrap();
+
+            let mut rng = StdRng::seed_from_u64(42);
+            let vectors = library(&mut rng);
+            let changed = changed_library(&vectors, &mut rng);
+
+            let mut group = c.benchmark_group("recommendation index");
+            group.significance_level(0.01).sample_size(10).measurement_time(Duration::from_secs(60));
+            group.bench_function(
+                "rebuild 100k", |b| b.iter(
+                    || rebuild_index(&recommend_db, black_box(&vectors), DIMENSIONS).unwrap()
+                )
+            );
+
+            rebuild_index(&recommend_db, &vectors, DIMENSIONS).unwrap();
+            group.bench_function(
+                "update 100k unchanged", |b| b.iter(
+                    || update_index(&recommend_db, black_box(&vectors), DIMENSIONS, REBUILD_THRESHOLD).unwrap()
+                )
+            );
+
+            // Every iteration removes and updates one percent of the items,
+            // or brings them back
+            let mut toggle = false;
+            group.bench_function(
+                "update 100k with 2% changes", |b| b.iter(|| {
+                    toggle = !toggle;
+                    let target = if toggle { &changed } else { &vectors };
+                    update_index(&recommend_db, black_box(target), DIMENSIONS, REBUILD_THRESHOLD).unwrap()
+                })
+            );
+            group.finish();
+
+            std::fs::remove_dir_all(lib_path).unwrap();
+        }
+
+        criterion_group!(benches, recommendation_benchmark);
+        criterion_main!(benches);
+    } else {
+        fn main() {
+            println!("Benchmarking is disabled. Please enable the 'bench' feature to run benchmarks.");
+        }
+    }
+}
@@ -1,35 +1,193 @@
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
-use futures::future::join_all;
-use log::info;
-use paste::paste;
+use chrono::Utc;
+use log::{debug, error, info, warn};
 use rust_decimal::prelude::FromPrimitive;
 use rust_decimal::prelude::ToPrimitive;
 use sea_orm::entity::prelude::*;
-use sea_orm::{ActiveValue, QueryOrder, QuerySelect};
+use sea_orm::{ActiveValue, QueryOrder, QuerySelect, TransactionTrait};
 use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 use seq_macro::seq;
 use tokio_util::sync::CancellationToken;
 
-use analysis::analysis::{analyze_audio, normalize_analysis_result, NormalizedAnalysisResult};
+use analysis::analysis::{
+    analyze_audio_range, normalize_analysis_result, AnalysisParameter, AudioRange,
+    NormalizedAnalysisResult,
+};
+use analysis::error::AnalysisError;
+use analysis::extractor::{ExtractedFeatures, ExtractorSet};
 use analysis::utils::computing_device::ComputingDevice;
+use analysis::utils::key::{Key, KeyEstimate};
 
-use crate::entities::{media_analysis, media_files};
+use crate::actions::features::{get_files_with_features, save_features};
+use crate::actions::recommendation::IndexScaling;
+use crate::actions::segments::save_segments;
+use crate::entities::{analysis_settings, media_analysis, media_analysis_failures, media_files};
 use crate::parallel_media_files_processing;
 
 pub fn empty_progress_callback(_processed: usize, _total: usize) {}
 
+/// Optional behaviours of `analysis_audio_library`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnalysisOptions {
+    /// When set, every file is also analyzed in segments of this many seconds, stored next
+    /// to the database.
+    pub segment_duration: Option<f64>,
+    /// The feature extractors to run. Analyzed files missing the values of one of them are
+    /// analyzed again.
+    pub extractors: ExtractorSet,
+    /// Whether files that failed to analyze before are tried again. They are skipped
+    /// otherwise.
+    pub retry_failed: bool,
+}
+
+/// The settings the library was last analyzed and indexed with, so that the
+/// files changed since are analyzed and indexed the same way.
+#[derive(Debug, Clone)]
+pub struct AnalysisSettings {
+    pub computing_device: ComputingDevice,
+    pub segment_duration: Option<f64>,
+    pub extractors: ExtractorSet,
+    pub scaling: IndexScaling,
+}
+
+/// The recorded analysis settings of the library, `None` when it was never
+/// analyzed.
+pub async fn get_analysis_settings(
+    main_db: &DatabaseConnection,
+) -> Result<Option<AnalysisSettings>> {
+    let Some(row) = analysis_settings::Entity::find().one(main_db).await? else {
+        return Ok(None);
+    };
+
+    let extractors: Vec<&str> = row.extractors.split(',').collect();
+    let weights = row
+        .feature_weights
+        .split(',')
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            let (name, weight) = x
+                .split_once('=')
+                .with_context(|| format!("Invalid feature weight: {}", x))?;
+            let weight: f32 = weight
+                .parse()
+                .with_context(|| format!("Invalid feature weight: {}", x))?;
+            Ok((name.to_string(), weight))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Some(AnalysisSettings {
+        computing_device: row.computing_device.into(),
+        segment_duration: row.segment_duration,
+        extractors: ExtractorSet::from_names(&extractors)?,
+        scaling: IndexScaling {
+            method: row.scaling_method.into(),
+            weights,
+        },
+    }))
+}
+
+/// Records the settings the library was analyzed and indexed with, replacing
+/// the previous ones.
+pub async fn set_analysis_settings(
+    main_db: &DatabaseConnection,
+    settings: &AnalysisSettings,
+) -> Result<()> {
+    let txn = main_db.begin().await?;
+
+    analysis_settings::Entity::delete_many().exec(&txn).await?;
+    analysis_settings::ActiveModel {
+        computing_device: ActiveValue::Set(settings.computing_device as i32),
+        segment_duration: ActiveValue::Set(settings.segment_duration),
+        extractors: ActiveValue::Set(settings.extractors.names().join(",")),
+        scaling_method: ActiveValue::Set(settings.scaling.method as i32),
+        feature_weights: ActiveValue::Set(
+            settings
+                .scaling
+                .weights
+                .iter()
+                .map(|(name, weight)| format!("{}={}", name, weight))
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(())
+}
+
+const WINDOW_SIZE: usize = 1024;
+const OVERLAP_SIZE: usize = 512;
+
+/// The parameters the library is analyzed with on the given device.
+pub fn current_analysis_parameter(computing_device: ComputingDevice) -> AnalysisParameter {
+    AnalysisParameter::new(WINDOW_SIZE, OVERLAP_SIZE, computing_device)
+}
+
+/// The parameters a result was analyzed with, unknown for results stored
+/// before they were recorded.
+fn analysis_parameter_of(
+    version: Option<i32>,
+    window_size: Option<i32>,
+    overlap_size: Option<i32>,
+    computing_device: Option<i32>,
+) -> Option<AnalysisParameter> {
+    Some(AnalysisParameter {
+        version: version? as u32,
+        window_size: window_size? as usize,
+        overlap_size: overlap_size? as usize,
+        computing_device: computing_device?.into(),
+    })
+}
+
+/// The parameters every analyzed file was analyzed with.
+pub async fn get_analysis_parameters(
+    main_db: &DatabaseConnection,
+) -> Result<HashMap<i32, Option<AnalysisParameter>>> {
+    let rows: Vec<(i32, Option<i32>, Option<i32>, Option<i32>, Option<i32>)> =
+        media_analysis::Entity::find()
+            .select_only()
+            .column(media_analysis::Column::FileId)
+            .column(media_analysis::Column::AnalyzerVersion)
+            .column(media_analysis::Column::WindowSize)
+            .column(media_analysis::Column::OverlapSize)
+            .column(media_analysis::Column::ComputingDevice)
+            .into_tuple()
+            .all(main_db)
+            .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(
+            |(file_id, version, window_size, overlap_size, computing_device)| {
+                (
+                    file_id,
+                    analysis_parameter_of(version, window_size, overlap_size, computing_device),
+                )
+            },
+        )
+        .collect())
+}
+
 /// Analyze the audio library by reading existing files, checking if they have been analyzed,
-/// and performing audio analysis if not. The function uses cursor pagination to process files
-/// in batches for memory efficiency and utilizes multi-core parallelism for faster processing.
-/// The analysis results are normalized before being stored in the database.
+/// and performing audio analysis if not. Files analyzed by another version of the analyzer, or
+/// with other parameters, are stale and analyzed again. The function uses cursor pagination to
+/// process files in batches for memory efficiency and utilizes multi-core parallelism for
+/// faster processing. The analysis results are normalized before being stored in the database.
 ///
 /// # Arguments
 /// * `main_db` - A reference to the database connection.
 /// * `lib_path` - The root path for the audio files.
 /// * `batch_size` - The number of files to process in each batch.
+/// * `computing_device` - The device to analyze the files on.
+/// * `options` - Segments, extractors and retries, see `AnalysisOptions`.
 /// * `progress_callback` - A callback function to report progress.
 /// * `cancel_token` - An optional cancellation token to support task cancellation.
 ///
@@ -40,6 +198,7 @@ pub async fn analysis_audio_library<F>(
     lib_path: &Path,
     batch_size: usize,
     computing_device: ComputingDevice,
+    options: AnalysisOptions,
     progress_callback: F,
     cancel_token: Option<CancellationToken>,
 ) -> Result<usize>
@@ -47,19 +206,45 @@ where
     F: Fn(usize, usize) + Send + Sync + 'static,
 {
     let progress_callback = Arc::new(progress_callback);
+    let AnalysisOptions {
+        segment_duration,
+        extractors,
+        retry_failed,
+    } = options;
 
     info!(
         "Starting audio library analysis with batch size: {}",
         batch_size
     );
 
-    let existed_ids: Vec<i32> = media_analysis::Entity::find()
-        .select_only()
-        .column(media_analysis::Column::FileId)
-        .distinct()
-        .into_tuple::<i32>()
-        .all(main_db)
-        .await?;
+    let parameters = current_analysis_parameter(computing_device);
+    let analyzed_parameters = get_analysis_parameters(main_db).await?;
+    let stale_count = analyzed_parameters
+        .values()
+        .filter(|x| **x != Some(parameters))
+        .count();
+    if stale_count > 0 {
+        info!(
+            "Analyzing {} stale files again with {:?}",
+            stale_count, parameters
+        );
+    }
+
+    let mut existed_ids: Vec<i32> = get_files_with_features(main_db, extractors)
+        .await?
+        .into_iter()
+        .filter(|x| analyzed_parameters.get(x) == Some(&Some(parameters)))
+        .collect();
+
+    if !retry_failed {
+        let failed_ids: Vec<i32> = media_analysis_failures::Entity::find()
+            .select_only()
+            .column(media_analysis_failures::Column::FileId)
+            .into_tuple::<i32>()
+            .all(main_db)
+            .await?;
+        existed_ids.extend(failed_ids);
+    }
 
     let cursor_query =
         media_files::Entity::find().filter(media_files::Column::Id.is_not_in(existed_ids));
@@ -74,24 +259,120 @@ where
         cursor_query,
         lib_path,
         move |file, lib_path, cancel_token| {
-            analysis_file(file, lib_path, computing_device, cancel_token)
+            analysis_file(
+                file,
+                lib_path,
+                parameters,
+                segment_duration,
+                extractors,
+                cancel_token,
+            )
         },
-        |db,
-         file: media_files::Model,
-         analysis_result: Result<Option<NormalizedAnalysisResult>>| async move {
-            match analysis_result {
-                Ok(analysis_result) => {
-                    if let Some(x) = analysis_result {
-                        match insert_analysis_result(db, file.id, x).await {
-                            Ok(_) => debug!("Finished analysis: {}", file.id),
-                            Err(e) => error!("Failed to insert analysis result: {}", e),
-                        }
-                    };
+        move |db,
+              file: media_files::Model,
+              analysis_result: Result<(NormalizedAnalysisResult, Vec<ExtractedFeatures>)>| async move {
+            store_analysis_result(db, file.id, analysis_result, parameters).await
+        }
+    )
+}
+
+/// Analyze the given files again, whether they were analyzed before or not, like files that
+/// changed on disk. Files that fail are recorded like in `analysis_audio_library`.
+///
+/// # Arguments
+/// * `main_db` - A reference to the database connection.
+/// * `lib_path` - The root path for the audio files.
+/// * `file_ids` - The files to analyze.
+/// * `batch_size` - The number of files to process in each batch.
+/// * `computing_device` - The device to analyze the files on.
+/// * `options` - Segments and extractors, see `AnalysisOptions`.
+/// * `cancel_token` - An optional cancellation token to support task cancellation.
+pub async fn analysis_media_files(
+    main_db: &DatabaseConnection,
+    lib_path: &Path,
+    file_ids: Vec<i32>,
+    batch_size: usize,
+    computing_device: ComputingDevice,
+    options: AnalysisOptions,
+    cancel_token: Option<CancellationToken>,
+) -> Result<usize> {
+    if file_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let progress_callback = Arc::new(empty_progress_callback);
+    let AnalysisOptions {
+        segment_duration,
+        extractors,
+        ..
+    } = options;
+    let parameters = current_analysis_parameter(computing_device);
+
+    let cursor_query = media_files::Entity::find().filter(media_files::Column::Id.is_in(file_ids));
+
+    let lib_path = Arc::new(lib_path.to_path_buf());
+
+    parallel_media_files_processing!(
+        main_db,
+        batch_size,
+        progress_callback,
+        cancel_token,
+        cursor_query,
+        lib_path,
+        move |file, lib_path, cancel_token| {
+            analysis_file(
+                file,
+                lib_path,
+                parameters,
+                segment_duration,
+                extractors,
+                cancel_token,
+            )
+        },
+        move |db,
+              file: media_files::Model,
+              analysis_result: Result<(NormalizedAnalysisResult, Vec<ExtractedFeatures>)>| async move {
+            store_analysis_result(db, file.id, analysis_result, parameters).await
+        }
+    )
+}
+
+/// Stores the outcome of analyzing a file: its result, or the reason it failed.
+async fn store_analysis_result(
+    main_db: &DatabaseConnection,
+    file_id: i32,
+    analysis_result: Result<(NormalizedAnalysisResult, Vec<ExtractedFeatures>)>,
+    parameters: AnalysisParameter,
+) {
+    match analysis_result {
+        Ok((x, features)) => match insert_analysis_result(main_db, file_id, x, &features).await {
+            Ok(_) => {
+                debug!("Finished analysis: {}", file_id);
+                if let Err(e) = remove_analysis_failure(main_db, file_id).await {
+                    warn!("Failed to remove analysis failure of {}: {:?}", file_id, e);
                 }
-                Err(e) => error!("Failed to analyze track: {}", e),
+            }
+            Err(e) => error!("Failed to insert analysis result: {}", e),
+        },
+        Err(e) => {
+            let kind = match e.downcast_ref::<AnalysisError>() {
+                // Cancelled files are analyzed again next time
+                Some(AnalysisError::Cancelled) => return,
+                Some(x) => x.kind(),
+                None => "other",
+            };
+
+            warn!("Failed to analyze track {}: {:#}", file_id, e);
+            if let Err(e) =
+                record_analysis_failure(main_db, file_id, kind, &format!("{:#}", e)).await
+            {
+                error!("Failed to record analysis failure: {:?}", e);
+            }
+            if let Err(e) = remove_stale_analysis(main_db, file_id, parameters).await {
+                error!("Failed to remove stale analysis of {}: {:?}", file_id, e);
             }
         }
-    )
+    }
 }
 
 /// Process a file if it has not been analyzed yet. Perform audio analysis and store the results
@@ -101,45 +382,154 @@ where
 /// * `db` - A reference to the database connection.
 /// * `file` - A reference to the file model.
 /// * `root_path` - The root path for the audio files.
+/// * `parameters` - The parameters to analyze the file with.
+/// * `segment_duration` - The segment length in seconds, if segments are stored.
+/// * `extractors` - The feature extractors to run.
 /// * `cancel_token` - An optional cancellation token to support task cancellation.
 fn analysis_file(
     file: &media_files::Model,
     lib_path: &Path,
-    computing_device: ComputingDevice,
+    parameters: AnalysisParameter,
+    segment_duration: Option<f64>,
+    extractors: ExtractorSet,
     cancel_token: Option<CancellationToken>,
-) -> Result<Option<NormalizedAnalysisResult>> {
+) -> Result<(NormalizedAnalysisResult, Vec<ExtractedFeatures>)> {
     // Construct the full path to the file
     let file_path = lib_path.join(&file.directory).join(&file.file_name);
+    let Some(file_path) = file_path.to_str() else {
+        bail!("Unable to convert file path: {:?}", file_path);
+    };
+
+    // Tracks of a CUE sheet only cover a range of their file
+    let range = AudioRange {
+        start: file.start_time.and_then(|x| x.to_f64()).unwrap_or_default(),
+        end: file.end_time.and_then(|x| x.to_f64()),
+    };
 
     // Perform audio analysis
-    let analysis_result = analyze_audio(
-        file_path.to_str().expect("Unable to convert file path"),
-        1024, // Example window size
-        512,  // Example overlap size
-        computing_device,
+    let output = analyze_audio_range(
+        file_path,
+        range,
+        parameters,
+        segment_duration,
+        extractors.create(),
         cancel_token,
     )?;
 
-    if analysis_result.is_none() {
-        return Ok(None);
+    if segment_duration.is_some() {
+        save_segments(lib_path, file.id, &output.segments)?;
     }
 
-    let analysis_result = analysis_result.expect("Analysis result should never be none");
-
     // Normalize the analysis result
-    Ok(Some(normalize_analysis_result(&analysis_result)))
+    Ok((normalize_analysis_result(&output.result), output.features))
+}
+
+/// Remembers that a file could not be analyzed, so that it is skipped until
+/// failed files are retried.
+async fn record_analysis_failure(
+    main_db: &DatabaseConnection,
+    file_id: i32,
+    kind: &str,
+    detail: &str,
+) -> Result<()> {
+    let existing = media_analysis_failures::Entity::find()
+        .filter(media_analysis_failures::Column::FileId.eq(file_id))
+        .one(main_db)
+        .await?;
+
+    match existing {
+        Some(x) => {
+            let attempts = x.attempts + 1;
+            let mut failure: media_analysis_failures::ActiveModel = x.into();
+            failure.kind = ActiveValue::Set(kind.to_string());
+            failure.detail = ActiveValue::Set(detail.to_string());
+            failure.attempts = ActiveValue::Set(attempts);
+            failure.updated_at = ActiveValue::Set(Utc::now());
+            failure.update(main_db).await?;
+        }
+        None => {
+            media_analysis_failures::ActiveModel {
+                file_id: ActiveValue::Set(file_id),
+                kind: ActiveValue::Set(kind.to_string()),
+                detail: ActiveValue::Set(detail.to_string()),
+                attempts: ActiveValue::Set(1),
+                updated_at: ActiveValue::Set(Utc::now()),
+                ..Default::default()
+            }
+            .insert(main_db)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Drops the result of a file analyzed with other parameters, so that a file
+/// that can no longer be analyzed doesn't keep the recommendation index from
+/// being built.
+async fn remove_stale_analysis(
+    main_db: &DatabaseConnection,
+    file_id: i32,
+    parameters: AnalysisParameter,
+) -> Result<()> {
+    let stale_ids: Vec<i32> = media_analysis::Entity::find()
+        .filter(media_analysis::Column::FileId.eq(file_id))
+        .all(main_db)
+        .await?
+        .into_iter()
+        .filter(|x| {
+            analysis_parameter_of(
+                x.analyzer_version,
+                x.window_size,
+                x.overlap_size,
+                x.computing_device,
+            ) != Some(parameters)
+        })
+        .map(|x| x.id)
+        .collect();
+
+    if !stale_ids.is_empty() {
+        media_analysis::Entity::delete_many()
+            .filter(media_analysis::Column::Id.is_in(stale_ids))
+            .exec(main_db)
+            .await?;
+    }
+
+    Ok(())
 }
 
-/// Insert the normalized analysis result into the database.
+async fn remove_analysis_failure(main_db: &DatabaseConnection, file_id: i32) -> Result<()> {
+    media_analysis_failures::Entity::delete_many()
+        .filter(media_analysis_failures::Column::FileId.eq(file_id))
+        .exec(main_db)
+        .await?;
+
+    Ok(())
+}
+
+/// Files that could not be analyzed, most recent failures first.
+pub async fn get_analysis_failures(
+    main_db: &DatabaseConnection,
+) -> Result<Vec<media_analysis_failures::Model>> {
+    Ok(media_analysis_failures::Entity::find()
+        .order_by_desc(media_analysis_failures::Column::UpdatedAt)
+        .all(main_db)
+        .await?)
+}
+
+/// Insert the normalized analysis result and the extracted features into the database,
+/// replacing the previous analysis of the file.
 ///
 /// # Arguments
 /// * `main_db` - A reference to the database connection.
 /// * `file_id` - The ID of the file being analyzed.
 /// * `result` - The normalized analysis result.
+/// * `features` - The values of the feature extractors.
 async fn insert_analysis_result(
     main_db: &DatabaseConnection,
     file_id: i32,
     result: NormalizedAnalysisResult,
+    features: &[ExtractedFeatures],
 ) -> Result<()> {
     let mut new_analysis = media_analysis::ActiveModel {
         file_id: ActiveValue::Set(file_id),
@@ -155,6 +545,29 @@ async fn insert_analysis_result(
         spectral_kurtosis: ActiveValue::Set(Decimal::from_f32(result.spectral_kurtosis)),
         perceptual_spread: ActiveValue::Set(Decimal::from_f32(result.raw.perceptual_spread)),
         perceptual_sharpness: ActiveValue::Set(Decimal::from_f32(result.raw.perceptual_sharpness)),
+        // Silence has no integrated loudness and is stored as null
+        integrated_loudness: ActiveValue::Set(Decimal::from_f32(result.raw.integrated_loudness)),
+        loudness_range: ActiveValue::Set(Decimal::from_f32(result.raw.loudness_range)),
+        true_peak: ActiveValue::Set(Decimal::from_f32(result.raw.true_peak)),
+        // Tracks without a detectable tempo are stored as null
+        bpm: ActiveValue::Set(
+            Some(result.raw.bpm)
+                .filter(|x| *x > 0.0)
+                .and_then(Decimal::from_f32),
+        ),
+        bpm_confidence: ActiveValue::Set(Decimal::from_f32(result.raw.bpm_confidence)),
+        beat_offset: ActiveValue::Set(Decimal::from_f32(result.raw.beat_offset)),
+        musical_key: ActiveValue::Set(result.raw.key.map(|x| x.code())),
+        key_confidence: ActiveValue::Set(
+            result
+                .raw
+                .key
+                .and_then(|_| Decimal::from_f32(result.raw.key_confidence)),
+        ),
+        analyzer_version: ActiveValue::Set(Some(result.raw.parameters.version as i32)),
+        window_size: ActiveValue::Set(Some(result.raw.parameters.window_size as i32)),
+        overlap_size: ActiveValue::Set(Some(result.raw.parameters.overlap_size as i32)),
+        computing_device: ActiveValue::Set(Some(result.raw.parameters.computing_device as i32)),
         ..Default::default()
     };
 
@@ -170,9 +583,18 @@ async fn insert_analysis_result(
         new_analysis.mfcc~N = ActiveValue::Set(Decimal::from_f32(result.raw.mfcc[N]));
     });
 
+    let txn = main_db.begin().await?;
+
+    media_analysis::Entity::delete_many()
+        .filter(media_analysis::Column::FileId.eq(file_id))
+        .exec(&txn)
+        .await?;
     media_analysis::Entity::insert(new_analysis)
-        .exec(main_db)
+        .exec(&txn)
         .await?;
+    save_features(&txn, file_id, features).await?;
+
+    txn.commit().await?;
 
     Ok(())
 }
@@ -303,56 +725,6 @@ impl From<media_analysis::Model> for AggregatedAnalysisResult {
     }
 }
 
-/// Macro to process individual fields by updating their sum and count.
-macro_rules! process_field {
-    ($sum:expr, $count:expr, $result:expr, $field:ident) => {
-        if let Some(value) = $result.$field {
-            $sum.$field += value.to_f64().expect("Unable to convert parameter");
-            $count.$field += 1.0;
-        }
-    };
-}
-
-/// Macro to process array fields by updating their sum and count.
-macro_rules! process_array {
-    ($sum:expr, $count:expr, $result:expr, $field_prefix:ident, $size:expr) => {
-        seq!(N in 0..$size {
-            paste! {
-                if let Some(value) = $result.[<$field_prefix N>] {
-                    $sum.[<$field_prefix>][N] += value.to_f64().expect("Unable to convert parameter");
-                    $count.[<$field_prefix>][N] += 1.0;
-                }
-            }
-        });
-    };
-}
-
-/// Macro to calculate the mean of individual fields.
-macro_rules! calculate_mean {
-    ($sum:expr, $count:expr, $field:ident) => {
-        if $count.$field > 0.0 {
-            $sum.$field / $count.$field
-        } else {
-            0.0
-        }
-    };
-}
-
-/// Macro to calculate the mean of array fields.
-macro_rules! calculate_array_mean {
-    ($sum:expr, $count:expr, $field:ident, $size:expr) => {{
-        let mut result = [0.0; $size];
-        for i in 0..$size {
-            result[i] = if $count.$field[i] > 0.0 {
-                $sum.$field[i] / $count.$field[i]
-            } else {
-                0.0
-            };
-        }
-        result
-    }};
-}
-
 pub async fn if_analyze_exists(main_db: &DatabaseConnection, file_id: i32) -> Result<bool> {
     Ok(media_analysis::Entity::find()
         .filter(media_analysis::Column::FileId.eq(file_id))
@@ -365,203 +737,55 @@ pub async fn get_analyze_count(main_db: &DatabaseConnection) -> Result<u64> {
     Ok(media_analysis::Entity::find().count(main_db).await?)
 }
 
-/// Computes the centralized analysis result from the database.
-///
-/// This function retrieves analysis results based on specified file IDs,
-/// sums the parameters, and calculates averages while handling potential `None` values.
-///
-/// # Arguments
-///
-/// * `main_db` - A reference to the database connection.
-/// * `file_ids` - A vector of file IDs to filter the analysis results.
-///
-/// # Returns
-///
-/// * `AnalysisResultMean` - A struct containing the mean values of the analysis results.
-///
-/// # Example
-///
-/// ```compile_fail
-/// let main_db: DatabaseConnection = ...;
-/// let file_ids = vec![1, 2, 3];
-/// let result = get_centralized_analysis_result(&main_db, file_ids).await;
-/// println!("{:?}", result);
-/// ```
-pub async fn get_centralized_analysis_result(
+/// Loudness of analyzed files in LUFS and their linear true peak, used as a
+/// ReplayGain fallback for files without ReplayGain tags. Files analyzed
+/// before the integrated loudness was measured fall back to an estimate
+/// from their RMS, and have no true peak.
+pub async fn get_analysis_loudness(
     main_db: &DatabaseConnection,
     file_ids: Vec<i32>,
-) -> Result<AggregatedAnalysisResult> {
+) -> Result<Vec<(i32, f32, Option<f32>)>> {
     let analysis_results = media_analysis::Entity::find()
         .filter(media_analysis::Column::FileId.is_in(file_ids))
         .all(main_db)
         .await?;
 
-    let mut sum = AggregatedAnalysisResult {
-        rms: 0.0,
-        zcr: 0.0,
-        energy: 0.0,
-        spectral_centroid: 0.0,
-        spectral_flatness: 0.0,
-        spectral_slope: 0.0,
-        spectral_rolloff: 0.0,
-        spectral_spread: 0.0,
-        spectral_skewness: 0.0,
-        spectral_kurtosis: 0.0,
-        chroma: [0.0; 12],
-        perceptual_spread: 0.0,
-        perceptual_sharpness: 0.0,
-        perceptual_loudness: [0.0; 24],
-        mfcc: [0.0; 13],
-    };
-
-    let mut count = AggregatedAnalysisResult {
-        rms: 0.0,
-        zcr: 0.0,
-        energy: 0.0,
-        spectral_centroid: 0.0,
-        spectral_flatness: 0.0,
-        spectral_slope: 0.0,
-        spectral_rolloff: 0.0,
-        spectral_spread: 0.0,
-        spectral_skewness: 0.0,
-        spectral_kurtosis: 0.0,
-        chroma: [0.0; 12],
-        perceptual_spread: 0.0,
-        perceptual_sharpness: 0.0,
-        perceptual_loudness: [0.0; 24],
-        mfcc: [0.0; 13],
-    };
-
-    for result in analysis_results {
-        process_field!(sum, count, result, rms);
-        process_field!(sum, count, result, zcr);
-        process_field!(sum, count, result, energy);
-        process_field!(sum, count, result, spectral_centroid);
-        process_field!(sum, count, result, spectral_flatness);
-        process_field!(sum, count, result, spectral_slope);
-        process_field!(sum, count, result, spectral_rolloff);
-        process_field!(sum, count, result, spectral_spread);
-        process_field!(sum, count, result, spectral_skewness);
-        process_field!(sum, count, result, spectral_kurtosis);
-        process_field!(sum, count, result, perceptual_spread);
-        process_field!(sum, count, result, perceptual_sharpness);
-
-        process_array!(sum, count, result, perceptual_loudness, 24);
-        process_array!(sum, count, result, mfcc, 13);
-        process_array!(sum, count, result, chroma, 12);
-    }
+    Ok(analysis_results
+        .into_iter()
+        .filter_map(|x| {
+            let true_peak = x.true_peak.and_then(|x| x.to_f32());
+            if let Some(loudness) = x.integrated_loudness.and_then(|x| x.to_f32()) {
+                return Some((x.file_id, loudness, true_peak));
+            }
 
-    Ok(AggregatedAnalysisResult {
-        rms: calculate_mean!(sum, count, rms),
-        zcr: calculate_mean!(sum, count, zcr),
-        energy: calculate_mean!(sum, count, energy),
-        spectral_centroid: calculate_mean!(sum, count, spectral_centroid),
-        spectral_flatness: calculate_mean!(sum, count, spectral_flatness),
-        spectral_slope: calculate_mean!(sum, count, spectral_slope),
-        spectral_rolloff: calculate_mean!(sum, count, spectral_rolloff),
-        spectral_spread: calculate_mean!(sum, count, spectral_spread),
-        spectral_skewness: calculate_mean!(sum, count, spectral_skewness),
-        spectral_kurtosis: calculate_mean!(sum, count, spectral_kurtosis),
-        perceptual_spread: calculate_mean!(sum, count, perceptual_spread),
-        perceptual_sharpness: calculate_mean!(sum, count, perceptual_sharpness),
-        chroma: calculate_array_mean!(sum, count, chroma, 12),
-        perceptual_loudness: calculate_array_mean!(sum, count, perceptual_loudness, 24),
-        mfcc: calculate_array_mean!(sum, count, mfcc, 13),
-    })
+            let rms = x.rms?.to_f32()?;
+            if rms > 0.0 {
+                Some((x.file_id, 20.0 * rms.log10(), true_peak))
+            } else {
+                None
+            }
+        })
+        .collect())
 }
 
-pub async fn get_percentile(
+/// The estimated key of a file, `None` when the file is not analyzed or
+/// has no detectable key.
+pub async fn get_analysis_key(
     main_db: &DatabaseConnection,
-    n: usize,
-    column: media_analysis::Column,
-    percentile: f64,
-) -> Result<f32> {
-    // Check if values are empty
-    if n == 0 {
-        return Ok(0.0);
-    }
-
-    // Calculate the rank
-    let rank = percentile * (n as f64 - 1.0);
-    let index = rank.round() as u64;
-
-    let result = match media_analysis::Entity::find()
-        .select_only()
-        .order_by_asc(column)
-        .column(column)
-        .offset(index)
-        .limit(1)
-        .into_tuple::<f32>()
+    file_id: i32,
+) -> Result<Option<KeyEstimate>> {
+    let analysis_result = media_analysis::Entity::find()
+        .filter(media_analysis::Column::FileId.eq(file_id))
         .one(main_db)
-        .await
-    {
-        Ok(x) => x,
-        Err(_) => Some(0.0),
-    };
-    // .with_context(|| "Unable to get analysis value")?;
-
-    Ok(result.unwrap_or_default())
-}
-
-pub async fn get_percentile_analysis_result(
-    main_db: &DatabaseConnection,
-    percentile: f64,
-) -> Result<[f32; 61]> {
-    let columns: Vec<media_analysis::Column> = [
-        media_analysis::Column::Rms,
-        media_analysis::Column::Zcr,
-        media_analysis::Column::Energy,
-        media_analysis::Column::SpectralCentroid,
-        media_analysis::Column::SpectralFlatness,
-        media_analysis::Column::SpectralSlope,
-        media_analysis::Column::SpectralRolloff,
-        media_analysis::Column::SpectralSpread,
-        media_analysis::Column::SpectralSkewness,
-        media_analysis::Column::SpectralKurtosis,
-    ]
-    .into_iter()
-    .chain(seq!(N in 0..12 {[
-        #(media_analysis::Column::Chroma~N,)*
-    ]}))
-    .chain([
-        media_analysis::Column::PerceptualSpread,
-        media_analysis::Column::PerceptualSharpness,
-    ])
-    .chain(seq!(N in 0..24 {[
-        #(media_analysis::Column::PerceptualLoudness~N,)*
-    ]}))
-    .chain(seq!(N in 0..13 {[
-        #(media_analysis::Column::Mfcc~N,)*
-    ]}))
-    .collect();
-
-    let total_files = media_files::Entity::find()
-        .count(main_db)
-        .await
-        .with_context(|| "Unable to get total files")? as usize;
-
-    let futures = columns
-        .iter()
-        .map(|column| get_percentile(main_db, total_files, *column, percentile));
-
-    let percentiles = join_all(futures).await;
-
-    let mut virtual_point = Vec::new();
-    for percentile in percentiles {
-        virtual_point.push(percentile.with_context(|| "Unable to calculate percentiles")?);
-    }
-
-    if virtual_point.len() != 61 {
-        bail!(
-            "Failed to convert virtual_point to array: incorrect length (got {}, expected {})",
-            virtual_point.len(),
-            61
-        );
-    }
-
-    let virtual_point: [f32; 61] = virtual_point
-        .try_into()
-        .expect("Length checked above, this should never fail");
+        .await?;
 
-    Ok(virtual_point)
+    Ok(analysis_result.and_then(|x| {
+        Some(KeyEstimate {
+            key: Key::from_code(x.musical_key?)?,
+            confidence: x
+                .key_confidence
+                .and_then(|x| x.to_f64())
+                .unwrap_or_default(),
+        })
+    }))
 }
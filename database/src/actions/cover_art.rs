@@ -177,12 +177,13 @@ pub fn extract_cover_art_by_file_id(
     file: &media_files::Model,
     lib_path: &Path,
 ) -> Option<CoverArt> {
+    // The file may be gone since it was listed
     let file_path = canonicalize(
         Path::new(lib_path)
             .join(file.directory.clone())
             .join(file.file_name.clone()),
     )
-    .unwrap();
+    .ok()?;
 
     // If cover_art_id is empty, it means the file has not been checked before
     extract_cover_art_binary(&file_path, Some(lib_path))
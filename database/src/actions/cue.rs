@@ -0,0 +1,300 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+use rust_decimal::prelude::FromPrimitive;
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveValue, QueryOrder};
+
+use metadata::cue::{embedded_cue_tracks, CueSheetCache, VirtualTrack};
+use metadata::describe::FileDescription;
+
+use crate::actions::collection::CollectionQueryType;
+use crate::actions::metadata::{read_metadata, remove_media_file, MovedFile};
+use crate::actions::search::add_term;
+use crate::entities::{media_files, media_metadata};
+
+use super::utils::DatabaseExecutor;
+
+/// Metadata keys a CUE sheet gives each of its tracks, replacing the tags of
+/// the file they are played from.
+const TRACK_KEYS: [&str; 5] = [
+    "track_title",
+    "artist",
+    "album",
+    "album_artist",
+    "track_number",
+];
+
+fn to_decimal(seconds: f64) -> Result<Decimal> {
+    Decimal::from_f64(seconds).with_context(|| format!("Invalid track time: {}", seconds))
+}
+
+/// The tags of the file, overridden by what the sheet says about the track.
+/// Tags without a known key, among them the embedded sheet itself, are only
+/// kept by the records of whole files.
+fn track_metadata(
+    file_metadata: &[(String, String)],
+    track: &VirtualTrack,
+) -> Vec<(String, String)> {
+    let mut metadata: Vec<(String, String)> = file_metadata
+        .iter()
+        .filter(|(key, _)| !key.is_empty() && !TRACK_KEYS.contains(&key.as_str()))
+        .cloned()
+        .collect();
+
+    let file_value = |key: &str| {
+        file_metadata
+            .iter()
+            .find(|(x, _)| x == key)
+            .map(|(_, value)| value.clone())
+    };
+    let values = [
+        ("track_title", track.title.clone()),
+        (
+            "artist",
+            track.artist.clone().or_else(|| file_value("artist")),
+        ),
+        ("album", track.album.clone().or_else(|| file_value("album"))),
+        (
+            "album_artist",
+            track
+                .album_artist
+                .clone()
+                .or_else(|| file_value("album_artist")),
+        ),
+        ("track_number", Some(track.number.to_string())),
+    ];
+
+    for (key, value) in values {
+        if let Some(value) = value {
+            metadata.push((key.to_string(), value));
+        }
+    }
+
+    metadata
+}
+
+/// The records of a file split by a CUE sheet whose previous path no longer
+/// exists, and that has the content of the described file.
+async fn find_moved_tracks<E>(
+    db: &E,
+    description: &mut FileDescription,
+) -> Result<Vec<media_files::Model>>
+where
+    E: DatabaseExecutor + sea_orm::ConnectionTrait,
+{
+    let Some(root_path) = description.root_path.clone() else {
+        return Ok(vec![]);
+    };
+
+    let candidates = media_files::Entity::find()
+        .filter(media_files::Column::FileHash.eq(description.get_crc()?))
+        .filter(media_files::Column::CueTrack.is_not_null())
+        .all(db)
+        .await?;
+
+    let Some(moved) = candidates
+        .iter()
+        .find(|x| !root_path.join(&x.directory).join(&x.file_name).exists())
+    else {
+        return Ok(vec![]);
+    };
+
+    Ok(candidates
+        .iter()
+        .filter(|x| x.directory == moved.directory && x.file_name == moved.file_name)
+        .cloned()
+        .collect())
+}
+
+/// Splits an audio file into the tracks of its CUE sheet, either a `.cue`
+/// file next to it or one embedded in its tags. Each track gets its own
+/// record, holding the range of the file it is played from, so that stats,
+/// playlists and analysis apply to tracks rather than to the whole file.
+///
+/// Returns the tracks that were moved along with the file, or `None` if the
+/// file isn't split, in which case it is described like any other file. The
+/// records of tracks are kept by number, so they keep their history when the
+/// sheet is edited. Sheets are read through `cue_sheets`, and files are only
+/// read again when they or their sheet changed.
+pub async fn sync_cue_tracks<E>(
+    db: &E,
+    description: &mut FileDescription,
+    force: bool,
+    cue_sheets: &mut CueSheetCache,
+) -> Result<Option<Vec<MovedFile>>>
+where
+    E: DatabaseExecutor + sea_orm::ConnectionTrait,
+{
+    let mut existing = media_files::Entity::find()
+        .filter(media_files::Column::Directory.eq(description.directory.clone()))
+        .filter(media_files::Column::FileName.eq(description.file_name.clone()))
+        .order_by_asc(media_files::Column::Id)
+        .all(db)
+        .await?;
+    let split = existing.iter().any(|x| x.cue_track.is_some());
+
+    let (tracks, last_modified) = match cue_sheets.find_external_cue_sheet(&description.full_path) {
+        Some(sheet) => {
+            // Editing the sheet changes the tracks as much as editing the file
+            let file_modified: u64 = description.last_modified.parse().unwrap_or_default();
+            (
+                sheet.tracks,
+                file_modified.max(sheet.last_modified).to_string(),
+            )
+        }
+        None => {
+            // Reading the embedded sheet means reading the file, which is
+            // only done when it changed. Files that were split are read
+            // again, since the sheet next to them may have gone.
+            let unchanged = !force
+                && !split
+                && !existing.is_empty()
+                && existing
+                    .iter()
+                    .all(|x| x.last_modified == description.last_modified);
+            let embedded = if unchanged {
+                None
+            } else {
+                embedded_cue_tracks(&description.full_path)
+            };
+
+            match embedded {
+                Some((_, tracks)) => (tracks, description.last_modified.clone()),
+                None => {
+                    // The file is played as a whole again
+                    if let Some(root_path) = &description.root_path {
+                        for file in existing.iter().filter(|x| x.cue_track.is_some()) {
+                            remove_media_file(db, root_path, file).await?;
+                        }
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+    };
+
+    let mut moved_files = vec![];
+    if existing.is_empty() {
+        for file in find_moved_tracks(db, description).await? {
+            let from = PathBuf::from(&file.directory).join(&file.file_name);
+            info!(
+                "Track {} moved: {} -> {}",
+                file.cue_track.unwrap_or_default(),
+                from.display(),
+                description.rel_path.display()
+            );
+
+            let mut active_model: media_files::ActiveModel = file.into();
+            active_model.directory = ActiveValue::Set(description.directory.clone());
+            active_model.file_name = ActiveValue::Set(description.file_name.clone());
+            active_model.extension = ActiveValue::Set(description.extension.clone());
+            let file = active_model.update(db).await?;
+
+            moved_files.push(MovedFile {
+                id: file.id,
+                from,
+                to: description.rel_path.clone(),
+            });
+            existing.push(file);
+        }
+    }
+
+    let unchanged = existing.len() == tracks.len()
+        && existing.iter().all(|x| {
+            x.last_modified == last_modified
+                && tracks.iter().any(|track| x.cue_track == Some(track.number))
+        });
+    if unchanged && !force {
+        debug!(
+            "Tracks of the CUE sheet haven't changed, skipping: {}",
+            description.file_name
+        );
+        return Ok(Some(moved_files));
+    }
+
+    info!(
+        "Splitting {} into {} tracks",
+        description.rel_path.display(),
+        tracks.len()
+    );
+
+    let file_hash = description
+        .get_crc()
+        .with_context(|| format!("Failed to get CRC: {}", description.file_name))?;
+    let (sample_rate, duration) = description.get_codec_information()?;
+    let file_metadata = read_metadata(description)?;
+
+    for track in &tracks {
+        let previous = existing
+            .iter()
+            .position(|x| x.cue_track == Some(track.number))
+            .map(|x| existing.remove(x));
+
+        let end = track.end.unwrap_or(duration);
+        let mut active_model: media_files::ActiveModel = match &previous {
+            Some(x) => x.clone().into(),
+            None => Default::default(),
+        };
+        active_model.file_name = ActiveValue::Set(description.file_name.clone());
+        active_model.directory = ActiveValue::Set(description.directory.clone());
+        active_model.extension = ActiveValue::Set(description.extension.clone());
+        active_model.file_hash = ActiveValue::Set(file_hash.clone());
+        active_model.last_modified = ActiveValue::Set(last_modified.clone());
+        active_model.sample_rate = ActiveValue::Set(sample_rate.try_into()?);
+        active_model.duration = ActiveValue::Set(to_decimal((end - track.start).max(0.0))?);
+        active_model.file_size = ActiveValue::Set(Some(description.file_size as i64));
+        active_model.cue_track = ActiveValue::Set(Some(track.number));
+        active_model.start_time = ActiveValue::Set(Some(to_decimal(track.start)?));
+        active_model.end_time = ActiveValue::Set(track.end.map(to_decimal).transpose()?);
+
+        let file_id = match previous {
+            Some(_) => active_model.update(db).await?.id,
+            None => {
+                media_files::Entity::insert(active_model)
+                    .exec(db)
+                    .await?
+                    .last_insert_id
+            }
+        };
+
+        media_metadata::Entity::delete_many()
+            .filter(media_metadata::Column::FileId.eq(file_id))
+            .exec(db)
+            .await?;
+
+        let metadata = track_metadata(&file_metadata.metadata, track);
+        let title = metadata
+            .iter()
+            .find(|(key, _)| key == "track_title")
+            .map(|(_, value)| value.clone())
+            .unwrap_or_else(|| format!("{} ({})", description.file_name, track.number));
+        add_term(db, CollectionQueryType::Track, file_id, &title).await?;
+
+        let new_metadata: Vec<media_metadata::ActiveModel> = metadata
+            .into_iter()
+            .map(|(key, value)| media_metadata::ActiveModel {
+                file_id: ActiveValue::Set(file_id),
+                meta_key: ActiveValue::Set(key),
+                meta_value: ActiveValue::Set(value),
+                ..Default::default()
+            })
+            .collect();
+        if !new_metadata.is_empty() {
+            media_metadata::Entity::insert_many(new_metadata)
+                .exec(db)
+                .await
+                .with_context(|| format!("Failed to insert track metadata: {}", file_id))?;
+        }
+    }
+
+    // The record of the whole file, and tracks the sheet no longer has
+    if let Some(root_path) = &description.root_path {
+        for file in &existing {
+            remove_media_file(db, root_path, file).await?;
+        }
+    }
+
+    Ok(Some(moved_files))
+}
@@ -0,0 +1,162 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use sea_orm::entity::prelude::*;
+use sea_orm::ActiveValue;
+
+use analysis::extractor::{ExtractedFeatures, ExtractorSet};
+
+use crate::actions::analysis::AggregatedAnalysisResult;
+use crate::entities::{media_analysis, media_analysis_features};
+
+/// One extractor of a feature vector layout. Vectors are the values of
+/// every extractor of the layout, one after the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutEntry {
+    pub extractor: String,
+    pub version: u32,
+    pub dimensions: usize,
+}
+
+/// The layout of the vectors made of the values of the given extractors.
+pub fn layout_of(extractors: ExtractorSet) -> Vec<LayoutEntry> {
+    extractors
+        .create()
+        .iter()
+        .map(|x| LayoutEntry {
+            extractor: x.name().to_string(),
+            version: x.version(),
+            dimensions: x.dimensions(),
+        })
+        .collect()
+}
+
+pub fn layout_dimensions(layout: &[LayoutEntry]) -> usize {
+    layout.iter().map(|x| x.dimensions).sum()
+}
+
+fn encode_values(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn decode_values(data: &[u8]) -> Vec<f32> {
+    data.chunks_exact(4)
+        .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect()
+}
+
+/// Stores the values of the extractors of a file, replacing the values
+/// the same extractors stored before.
+pub async fn save_features<C>(db: &C, file_id: i32, features: &[ExtractedFeatures]) -> Result<()>
+where
+    C: ConnectionTrait,
+{
+    if features.is_empty() {
+        return Ok(());
+    }
+
+    media_analysis_features::Entity::delete_many()
+        .filter(media_analysis_features::Column::FileId.eq(file_id))
+        .filter(media_analysis_features::Column::Extractor.is_in(features.iter().map(|x| x.name)))
+        .exec(db)
+        .await?;
+
+    media_analysis_features::Entity::insert_many(features.iter().map(|x| {
+        media_analysis_features::ActiveModel {
+            file_id: ActiveValue::Set(file_id),
+            extractor: ActiveValue::Set(x.name.to_string()),
+            version: ActiveValue::Set(x.version as i32),
+            data: ActiveValue::Set(encode_values(&x.values)),
+            ..Default::default()
+        }
+    }))
+    .exec(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Values that were only kept in the columns of `media_analysis` before
+/// extractors existed.
+fn legacy_values(entry: &LayoutEntry, analysis: &media_analysis::Model) -> Option<Vec<f32>> {
+    if entry.extractor != "spectral" || entry.version != 1 {
+        return None;
+    }
+
+    let aggregated: AggregatedAnalysisResult = analysis.clone().into();
+    let vector: [f32; 61] = aggregated.into();
+    Some(vector.to_vec())
+}
+
+/// The vector of every analyzed file, or of the given files only, laid out
+/// like `layout`. Files missing the values of an extractor, or having
+/// values of another version, are left out.
+pub async fn load_feature_vectors(
+    main_db: &DatabaseConnection,
+    layout: &[LayoutEntry],
+    file_ids: Option<&[i32]>,
+) -> Result<Vec<(i32, Vec<f32>)>> {
+    let mut analysis_query = media_analysis::Entity::find();
+    let mut features_query = media_analysis_features::Entity::find().filter(
+        media_analysis_features::Column::Extractor
+            .is_in(layout.iter().map(|x| x.extractor.clone())),
+    );
+    if let Some(file_ids) = file_ids {
+        analysis_query =
+            analysis_query.filter(media_analysis::Column::FileId.is_in(file_ids.to_vec()));
+        features_query =
+            features_query.filter(media_analysis_features::Column::FileId.is_in(file_ids.to_vec()));
+    }
+
+    let mut stored: HashMap<(i32, String), (u32, Vec<f32>)> = HashMap::new();
+    for x in features_query.all(main_db).await? {
+        stored.insert(
+            (x.file_id, x.extractor),
+            (x.version as u32, decode_values(&x.data)),
+        );
+    }
+
+    let mut seen: HashSet<i32> = HashSet::new();
+    let mut vectors = vec![];
+    for analysis in analysis_query.all(main_db).await? {
+        if !seen.insert(analysis.file_id) {
+            continue;
+        }
+
+        let mut vector = Vec::with_capacity(layout_dimensions(layout));
+        for entry in layout {
+            let values = stored
+                .get(&(analysis.file_id, entry.extractor.clone()))
+                .filter(|(version, values)| {
+                    *version == entry.version && values.len() == entry.dimensions
+                })
+                .map(|(_, values)| values.clone())
+                .or_else(|| legacy_values(entry, &analysis));
+
+            match values {
+                Some(values) => vector.extend(values),
+                None => break,
+            }
+        }
+
+        if vector.len() == layout_dimensions(layout) {
+            vectors.push((analysis.file_id, vector));
+        }
+    }
+
+    vectors.sort_by_key(|(file_id, _)| *file_id);
+
+    Ok(vectors)
+}
+
+/// Files having the current values of every given extractor.
+pub async fn get_files_with_features(
+    main_db: &DatabaseConnection,
+    extractors: ExtractorSet,
+) -> Result<HashSet<i32>> {
+    Ok(load_feature_vectors(main_db, &layout_of(extractors), None)
+        .await?
+        .into_iter()
+        .map(|(file_id, _)| file_id)
+        .collect())
+}
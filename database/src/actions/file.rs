@@ -151,7 +151,19 @@ pub async fn get_file_ids_by_descriptions(
     db: &DatabaseConnection,
     descriptions: &[Option<FileDescription>],
 ) -> Result<Vec<i32>, DbErr> {
-    if descriptions.is_empty() {
+    let file_entries = get_files_by_descriptions(db, descriptions).await?;
+
+    let file_ids = file_entries.into_iter().map(|entry| entry.id).collect();
+
+    Ok(file_ids)
+}
+
+pub async fn get_files_by_descriptions(
+    db: &DatabaseConnection,
+    descriptions: &[Option<FileDescription>],
+) -> Result<Vec<media_files::Model>, DbErr> {
+    // An empty condition would match every file
+    if descriptions.iter().all(Option::is_none) {
         return Ok(vec![]);
     }
 
@@ -170,14 +182,7 @@ pub async fn get_file_ids_by_descriptions(
         }
     }
 
-    let file_entries = media_files::Entity::find()
-        .filter(conditions)
-        .all(db)
-        .await?;
-
-    let file_ids = file_entries.into_iter().map(|entry| entry.id).collect();
-
-    Ok(file_ids)
+    media_files::Entity::find().filter(conditions).all(db).await
 }
 
 pub async fn get_duration_by_file_id(
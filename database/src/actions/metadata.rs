@@ -2,25 +2,30 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
 use sea_orm::entity::prelude::*;
-use sea_orm::{ActiveValue, ColumnTrait, EntityTrait, QueryFilter};
+use sea_orm::{ActiveValue, ColumnTrait, Condition, EntityTrait, QueryFilter};
 use sea_orm::{DatabaseConnection, TransactionTrait};
 use tokio_util::sync::CancellationToken;
 
+use metadata::config::LibraryConfig;
+use metadata::cue::CueSheetCache;
 use metadata::describe::{describe_file, FileDescription};
+use metadata::ignore::IgnoreFileCache;
 use metadata::reader::get_metadata;
-use metadata::scanner::AudioScanner;
+use metadata::scanner::{AudioFileFilter, AudioScanner};
 
 use crate::actions::collection::CollectionQueryType;
 use crate::actions::cover_art::remove_cover_art_by_file_id;
+use crate::actions::cue::sync_cue_tracks;
 use crate::actions::file::get_file_ids_by_descriptions;
 use crate::actions::index::index_media_files;
 use crate::actions::logging::{insert_log, LogLevel};
 use crate::actions::search::{add_term, remove_term};
+use crate::actions::segments::remove_segments;
 use crate::entities::{albums, artists, media_file_albums, media_files};
 use crate::entities::{media_file_artists, media_metadata};
 
@@ -53,16 +58,45 @@ pub fn read_metadata(description: &FileDescription) -> Result<FileMetadata> {
     }
 }
 
+/// How far apart the durations of the two paths of a moved file may be, in
+/// seconds.
+const MOVED_DURATION_TOLERANCE: f64 = 0.01;
+
+/// A file found at a new path. Its record was moved there, keeping its
+/// stats, playlists, analysis and cover art.
+#[derive(Debug, Clone)]
+pub struct MovedFile {
+    pub id: i32,
+    /// The path it had before, relative to the library root.
+    pub from: PathBuf,
+    /// The path it has now, relative to the library root.
+    pub to: PathBuf,
+}
+
+/// Brings the records of the described files up to date, and returns the
+/// files that were found at a new path.
 pub async fn sync_file_descriptions(
     main_db: &DatabaseConnection,
     descriptions: &mut [Option<FileDescription>],
     force: bool,
-) -> Result<()> {
+) -> Result<Vec<MovedFile>> {
+    sync_file_descriptions_with(main_db, descriptions, force, &mut CueSheetCache::default()).await
+}
+
+/// Like `sync_file_descriptions`, reading CUE sheets through a cache shared
+/// by every batch of a scan.
+pub async fn sync_file_descriptions_with(
+    main_db: &DatabaseConnection,
+    descriptions: &mut [Option<FileDescription>],
+    force: bool,
+    cue_sheets: &mut CueSheetCache,
+) -> Result<Vec<MovedFile>> {
     debug!("Starting to process multiple files");
 
     // Start a transaction
     let txn = main_db.begin().await?;
     let mut search_term: Option<(i32, String)> = None;
+    let mut moved_files: Vec<MovedFile> = vec![];
 
     let mut update_search_term = |file_id: i32, metadata: &FileMetadata| {
         if let Some((_, value)) = metadata
@@ -82,6 +116,30 @@ pub async fn sync_file_descriptions(
             Some(description) => {
                 debug!("Processing file: {}", description.file_name.clone());
 
+                // Files split by a CUE sheet have a record for each track
+                match sync_cue_tracks(&txn, description, force, cue_sheets)
+                    .await
+                    .with_context(|| {
+                        format!("Failed to sync CUE tracks: {}", description.file_name)
+                    }) {
+                    Ok(Some(moved)) => {
+                        moved_files.extend(moved);
+                        continue;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        error!("{:?}", e);
+                        insert_log(
+                            &txn,
+                            LogLevel::Error,
+                            "actions::metadata::sync_file_descriptions".to_string(),
+                            format!("{:#?}", e),
+                        )
+                        .await?;
+                        continue;
+                    }
+                }
+
                 let existing_file = match media_files::Entity::find()
                     .filter(media_files::Column::Directory.eq(description.directory.clone()))
                     .filter(media_files::Column::FileName.eq(description.file_name.clone()))
@@ -265,6 +323,56 @@ pub async fn sync_file_descriptions(
                         }
                     }
                 } else {
+                    // A file that is no longer at its path may have been moved here
+                    match find_moved_file(&txn, description).await.with_context(|| {
+                        format!(
+                            "Failed to look for a moved file: {}",
+                            description.file_name.clone()
+                        )
+                    }) {
+                        Ok(Some(moved_file)) => {
+                            let from =
+                                PathBuf::from(&moved_file.directory).join(&moved_file.file_name);
+                            info!(
+                                "File moved: {} -> {}",
+                                from.display(),
+                                description.rel_path.display()
+                            );
+
+                            if let Err(e) = update_file_path(&txn, &moved_file, description)
+                                .await
+                                .with_context(|| {
+                                    format!(
+                                        "Failed to update file path: {}",
+                                        description.file_name.clone()
+                                    )
+                                })
+                            {
+                                error!("{:?}", e);
+                                insert_log(
+                                    &txn,
+                                    LogLevel::Error,
+                                    "actions::metadata::sync_file_descriptions".to_string(),
+                                    format!("{:#?}", e),
+                                )
+                                .await?;
+                                continue;
+                            }
+
+                            moved_files.push(MovedFile {
+                                id: moved_file.id,
+                                from,
+                                to: description.rel_path.clone(),
+                            });
+                            continue;
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            // Not finding the previous path only costs the history of the file
+                            warn!("{:?}", e);
+                        }
+                    }
+
                     // If the file is new, insert a new record
                     debug!(
                         "File is new, inserting new record: {}",
@@ -335,6 +443,7 @@ pub async fn sync_file_descriptions(
             format!("{:?}", e),
         )
         .await?;
+        moved_files.clear();
     }
 
     if let Some((id, name)) = search_term {
@@ -355,6 +464,76 @@ pub async fn sync_file_descriptions(
 
     debug!("Finished syncing file data");
 
+    Ok(moved_files)
+}
+
+/// The record of a file that is no longer at its path, and has the content
+/// of the described file: the same hash, size and duration. A file whose
+/// previous path still exists was copied rather than moved.
+async fn find_moved_file<E>(
+    db: &E,
+    description: &mut FileDescription,
+) -> Result<Option<media_files::Model>>
+where
+    E: DatabaseExecutor + sea_orm::ConnectionTrait,
+{
+    let Some(root_path) = description.root_path.clone() else {
+        return Ok(None);
+    };
+
+    let file_size = description.file_size as i64;
+    // Files scanned before sizes were recorded match on the rest
+    let candidates = media_files::Entity::find()
+        .filter(media_files::Column::FileHash.eq(description.get_crc()?))
+        .filter(
+            Condition::any()
+                .add(media_files::Column::FileSize.eq(file_size))
+                .add(media_files::Column::FileSize.is_null()),
+        )
+        .all(db)
+        .await?;
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+
+    let (_, duration) = description.get_codec_information()?;
+
+    Ok(candidates.into_iter().find(|x| {
+        (x.duration.to_f64().unwrap_or_default() - duration).abs() < MOVED_DURATION_TOLERANCE
+            && !root_path.join(&x.directory).join(&x.file_name).exists()
+    }))
+}
+
+/// Moves the record of a file to the described path.
+async fn update_file_path<E>(
+    db: &E,
+    existing_file: &media_files::Model,
+    description: &FileDescription,
+) -> Result<()>
+where
+    E: DatabaseExecutor + sea_orm::ConnectionTrait,
+{
+    let mut active_model: media_files::ActiveModel = existing_file.clone().into();
+    active_model.directory = ActiveValue::Set(description.directory.clone());
+    active_model.file_name = ActiveValue::Set(description.file_name.clone());
+    active_model.extension = ActiveValue::Set(description.extension.clone());
+    active_model.last_modified = ActiveValue::Set(description.last_modified.clone());
+    active_model.file_size = ActiveValue::Set(Some(description.file_size as i64));
+    active_model.update(db).await?;
+
+    // Replace the term of the old path, files without a title are searched
+    // by their file name
+    let title = media_metadata::Entity::find()
+        .filter(media_metadata::Column::FileId.eq(existing_file.id))
+        .filter(media_metadata::Column::MetaKey.eq("track_title"))
+        .one(db)
+        .await?;
+    let term = match title {
+        Some(x) => x.meta_value,
+        None => description.file_name.clone(),
+    };
+    add_term(db, CollectionQueryType::Track, existing_file.id, &term).await?;
+
     Ok(())
 }
 
@@ -526,6 +705,7 @@ where
 {
     let mut active_model: media_files::ActiveModel = existing_file.clone().into();
     active_model.last_modified = ActiveValue::Set(description.last_modified.clone());
+    active_model.file_size = ActiveValue::Set(Some(description.file_size as i64));
     active_model.update(db).await?;
     Ok(())
 }
@@ -676,6 +856,7 @@ where
     active_model.duration = ActiveValue::Set(
         Decimal::from_f64(duration_in_seconds).expect("Unable to convert track duration"),
     );
+    active_model.file_size = ActiveValue::Set(Some(description.file_size as i64));
 
     if let Err(e) = active_model
         .update(db)
@@ -725,6 +906,7 @@ where
             Decimal::from_f64(duration_in_seconds).expect("Unable to convert track duration"),
         ),
         last_modified: ActiveValue::Set(description.last_modified.clone()),
+        file_size: ActiveValue::Set(Some(description.file_size as i64)),
         ..Default::default()
     };
     let inserted_file = media_files::Entity::insert(new_file).exec(main_db).await?;
@@ -776,27 +958,54 @@ where
     Ok(())
 }
 
-async fn clean_up_database(main_db: &DatabaseConnection, root_path: &Path) -> Result<()> {
+/// Drops the files that no longer exist, or that the rules of the library
+/// now leave out of it.
+async fn clean_up_database(
+    main_db: &DatabaseConnection,
+    root_path: &Path,
+    filter: &AudioFileFilter,
+) -> Result<()> {
     let db_files = media_files::Entity::find().all(main_db).await?;
+    // Files of a directory share their ignore files
+    let mut ignore_files = IgnoreFileCache::default();
 
     for db_file in db_files {
         let full_path = root_path
             .join(PathBuf::from(&db_file.directory))
             .join(PathBuf::from(&db_file.file_name));
-        if !full_path.exists() {
+        if !full_path.exists() || filter.is_ignored_with(root_path, &full_path, &mut ignore_files) {
             info!("Cleaning {}", full_path.to_str().unwrap_or_default());
-            // Delete the file record
-            media_files::Entity::delete_by_id(db_file.id)
-                .exec(main_db)
-                .await?;
-
-            remove_term(main_db, CollectionQueryType::Track, db_file.id).await?
+            remove_media_file(main_db, root_path, &db_file).await?;
         }
     }
 
     Ok(())
 }
 
+/// Drops a file that no longer exists from the library, with its search
+/// term and analysis segments.
+pub async fn remove_media_file<E>(
+    main_db: &E,
+    root_path: &Path,
+    db_file: &media_files::Model,
+) -> Result<()>
+where
+    E: DatabaseExecutor + sea_orm::ConnectionTrait,
+{
+    // Delete the file record
+    media_files::Entity::delete_by_id(db_file.id)
+        .exec(main_db)
+        .await?;
+
+    remove_term(main_db, CollectionQueryType::Track, db_file.id).await?;
+
+    if let Err(e) = remove_segments(root_path, db_file.id) {
+        warn!("Failed to remove segments of {}: {:?}", db_file.id, e);
+    }
+
+    Ok(())
+}
+
 pub fn empty_progress_callback(_processed: usize) {}
 
 pub async fn scan_audio_library<F>(
@@ -811,7 +1020,13 @@ where
     F: Fn(usize) + Send + Sync,
 {
     let root_path_str = lib_path.to_str().expect("Invalid UTF-8 sequence in path");
-    let mut scanner = AudioScanner::new(&root_path_str);
+    let config = LibraryConfig::load(lib_path).unwrap_or_else(|e| {
+        warn!("Using the default library config: {:?}", e);
+        LibraryConfig::default()
+    });
+    let filter = AudioFileFilter::new(&config.scan);
+    let mut scanner = AudioScanner::with_filter(&root_path_str, filter.clone());
+    let mut cue_sheets = CueSheetCache::default();
 
     info!("Starting audio library scan");
 
@@ -837,11 +1052,14 @@ where
             .map(|result| result.ok())
             .collect();
 
-        match sync_file_descriptions(main_db, &mut descriptions, force)
+        match sync_file_descriptions_with(main_db, &mut descriptions, force, &mut cue_sheets)
             .await
             .with_context(|| "Unable to describe files")
         {
-            Ok(_) => {
+            Ok(moved_files) => {
+                if !moved_files.is_empty() {
+                    info!("Found {} moved files", moved_files.len());
+                }
                 debug!("Finished one batch");
             }
             Err(e) => {
@@ -868,7 +1086,7 @@ where
 
     if cleanup {
         info!("Starting cleanup process.");
-        match clean_up_database(main_db, lib_path)
+        match clean_up_database(main_db, lib_path, &filter)
             .await
             .with_context(|| "Unable to cleanup database")
         {
@@ -1048,3 +1266,135 @@ pub async fn get_parsed_file_by_id(
 
     Ok((file, artists, album))
 }
+
+pub async fn get_metadata_by_file_id(
+    db: &DatabaseConnection,
+    file_id: i32,
+) -> Result<Vec<(String, String)>> {
+    let entries = media_metadata::Entity::find()
+        .filter(media_metadata::Column::FileId.eq(file_id))
+        .all(db)
+        .await?;
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| (entry.meta_key, entry.meta_value))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::actions::file::get_file_by_path;
+    use crate::actions::search::search_for;
+    use crate::connection::{connect_main_db, MainDbConnection};
+
+    use super::*;
+
+    /// A library holding the fixture as `track.ogg`, scanned.
+    async fn scanned_library(name: &str) -> (PathBuf, MainDbConnection, media_files::Model) {
+        let lib_path = std::env::temp_dir().join(format!("rune-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&lib_path);
+        fs::create_dir_all(&lib_path).unwrap();
+        fs::copy("../assets/startup_0.ogg", lib_path.join("track.ogg")).unwrap();
+
+        let main_db = connect_main_db(lib_path.to_str().unwrap(), None)
+            .await
+            .unwrap();
+        scan_audio_library(
+            &main_db,
+            &lib_path,
+            true,
+            false,
+            empty_progress_callback,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let file = get_file_by_path(&main_db, Path::new("track.ogg"))
+            .await
+            .unwrap()
+            .expect("The fixture should be scanned");
+
+        (lib_path, main_db, file)
+    }
+
+    fn describe(lib_path: &Path, file_name: &str) -> FileDescription {
+        describe_file(&lib_path.join(file_name), &Some(lib_path.to_path_buf())).unwrap()
+    }
+
+    async fn search_tracks(main_db: &MainDbConnection, query: &str) -> Vec<i64> {
+        search_for(main_db, query, Some(vec![CollectionQueryType::Track]), 10)
+            .await
+            .unwrap()
+            .remove(&CollectionQueryType::Track)
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn test_moved_file() {
+        let (lib_path, main_db, file) = scanned_library("moved-file").await;
+        fs::rename(lib_path.join("track.ogg"), lib_path.join("renamed.ogg")).unwrap();
+
+        let mut description = describe(&lib_path, "renamed.ogg");
+        let moved_file = find_moved_file(&main_db, &mut description).await.unwrap();
+        assert_eq!(moved_file.map(|x| x.id), Some(file.id));
+
+        let moved_files = sync_file_descriptions(&main_db, &mut [Some(description)], false)
+            .await
+            .unwrap();
+        assert_eq!(moved_files.len(), 1);
+        assert_eq!(moved_files[0].id, file.id);
+        assert_eq!(moved_files[0].from.file_name().unwrap(), "track.ogg");
+        assert_eq!(moved_files[0].to, PathBuf::from("renamed.ogg"));
+
+        // The record and its search term follow the file
+        let renamed = get_file_by_path(&main_db, Path::new("renamed.ogg"))
+            .await
+            .unwrap();
+        assert_eq!(renamed.map(|x| x.id), Some(file.id));
+        assert!(get_file_by_path(&main_db, Path::new("track.ogg"))
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            search_tracks(&main_db, "renamed").await,
+            vec![file.id as i64]
+        );
+        assert!(search_tracks(&main_db, "track").await.is_empty());
+
+        fs::remove_dir_all(&lib_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_copied_file() {
+        let (lib_path, main_db, _) = scanned_library("copied-file").await;
+        fs::copy(lib_path.join("track.ogg"), lib_path.join("copy.ogg")).unwrap();
+
+        // The previous path still exists
+        let mut description = describe(&lib_path, "copy.ogg");
+        let moved_file = find_moved_file(&main_db, &mut description).await.unwrap();
+        assert!(moved_file.is_none());
+
+        fs::remove_dir_all(&lib_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_with_another_duration() {
+        let (lib_path, main_db, file) = scanned_library("another-duration").await;
+        fs::rename(lib_path.join("track.ogg"), lib_path.join("renamed.ogg")).unwrap();
+
+        let mut active_model: media_files::ActiveModel = file.clone().into();
+        active_model.duration = ActiveValue::Set(file.duration + Decimal::ONE);
+        active_model.update(&main_db).await.unwrap();
+
+        // Same hash and size, but not the same audio
+        let mut description = describe(&lib_path, "renamed.ogg");
+        let moved_file = find_moved_file(&main_db, &mut description).await.unwrap();
+        assert!(moved_file.is_none());
+
+        fs::remove_dir_all(&lib_path).unwrap();
+    }
+}
@@ -8,6 +8,7 @@ use migration::ExprTrait;
 use migration::Func;
 use migration::IntoCondition;
 use migration::SimpleExpr;
+use rust_decimal::prelude::ToPrimitive;
 use sea_orm::entity::prelude::*;
 use sea_orm::sea_query::{Condition, Expr};
 use sea_orm::{
@@ -15,8 +16,9 @@ use sea_orm::{
     QueryTrait, TransactionTrait,
 };
 
+use analysis::utils::key::Key;
+
 use crate::actions::analysis::get_analyze_count;
-use crate::actions::analysis::get_percentile_analysis_result;
 use crate::actions::cover_art::get_magic_cover_art_id;
 use crate::actions::playback_queue::list_playback_queue;
 use crate::connection::{MainDbConnection, RecommendationDbConnection};
@@ -25,12 +27,13 @@ use crate::entities::{
     media_files, mix_queries, mixes,
 };
 
-use super::analysis::get_centralized_analysis_result;
 use super::collection::CollectionQuery;
 use super::collection::CollectionQueryListMode;
 use super::collection::CollectionQueryType;
 use super::file::get_files_by_ids;
-use super::recommendation::get_recommendation_by_parameter;
+use super::recommendation::{
+    get_centralized_feature_vector, get_percentile_feature_vector, get_recommendation_by_parameter,
+};
 use super::utils::CollectionDefinition;
 
 impl CollectionDefinition for mixes::Entity {
@@ -388,9 +391,12 @@ enum QueryOperator {
     SortDuration(bool),
     SortPlayedthrough(bool),
     SortSkipped(bool),
+    SortBpm(bool),
     FilterLiked(bool),
     FilterWithCoverArt(bool),
     FilterAnalyzed(bool),
+    FilterBpm((Option<f32>, Option<f32>)),
+    FilterKey(Vec<i32>),
     PipeLimit(u64),
     PipeRecommend(i32),
     Unknown(String),
@@ -413,6 +419,104 @@ where
     }
 }
 
+/// Tempo multiples a BPM range also matches. Tempo detection often settles
+/// on half or double the felt tempo, and a running cadence of 170 steps per
+/// minute fits an 85 BPM track as well.
+const BPM_MULTIPLES: [f32; 3] = [1.0, 2.0, 0.5];
+
+/// Parses a BPM range like `120-140`, `120-` or `-140`. A single value
+/// matches tracks within 1 BPM of it.
+fn parse_bpm_range(parameter: &str, operator: &str) -> Option<(Option<f32>, Option<f32>)> {
+    let parameter = parameter.trim();
+
+    let range = match parameter.split_once('-') {
+        Some((min, max)) => {
+            let bound = |x: &str| -> Option<Option<f32>> {
+                let x = x.trim();
+                if x.is_empty() {
+                    Some(None)
+                } else {
+                    parse_parameter::<f32>(x, operator).map(Some)
+                }
+            };
+
+            (bound(min)?, bound(max)?)
+        }
+        None => {
+            let bpm = parse_parameter::<f32>(parameter, operator)?;
+            (Some(bpm - 1.0), Some(bpm + 1.0))
+        }
+    };
+
+    if range == (None, None) {
+        warn!(
+            "Unable to parse the parameter of operator: {}({})",
+            operator, parameter
+        );
+        return None;
+    }
+
+    Some(range)
+}
+
+/// Whether the tempo, or its double or half, is within the range.
+fn bpm_in_range(bpm: f32, (min, max): (Option<f32>, Option<f32>)) -> bool {
+    BPM_MULTIPLES.iter().any(|multiple| {
+        let bpm = bpm * multiple;
+        min.is_none_or(|min| bpm >= min) && max.is_none_or(|max| bpm <= max)
+    })
+}
+
+/// Condition matching the analysis rows `bpm_in_range` accepts. Tracks
+/// without a detected tempo have a null BPM and never match.
+fn bpm_range_condition((min, max): (Option<f32>, Option<f32>)) -> Condition {
+    let mut condition = Condition::any();
+
+    for multiple in BPM_MULTIPLES {
+        let mut range = Condition::all();
+        let bpm = media_analysis::Column::Bpm.into_expr().mul(multiple);
+
+        if let Some(min) = min {
+            range = range.add(bpm.clone().gte(min));
+        }
+        if let Some(max) = max {
+            range = range.add(bpm.lte(max));
+        }
+        condition = condition.add(range);
+    }
+
+    condition
+}
+
+/// Parses comma separated keys in any notation `Key::parse` understands,
+/// into their codes. With `harmonic`, the harmonic neighbours of every key
+/// are included.
+fn parse_keys(parameter: &str, operator: &str, harmonic: bool) -> Option<Vec<i32>> {
+    let mut codes = vec![];
+
+    for text in parameter.split(',').filter(|x| !x.trim().is_empty()) {
+        let Some(key) = Key::parse(text) else {
+            warn!(
+                "Unable to parse the parameter of operator: {}({})",
+                operator, parameter
+            );
+            return None;
+        };
+
+        if harmonic {
+            codes.extend(key.harmonic_neighbours().iter().map(|x| x.code()));
+        } else {
+            codes.push(key.code());
+        }
+    }
+
+    if codes.is_empty() {
+        None
+    } else {
+        Some(codes)
+    }
+}
+
 pub async fn add_item_to_mix(
     main_db: &DatabaseConnection,
     mix_id: i32,
@@ -542,12 +646,24 @@ fn parse_query(query: &(String, String)) -> QueryOperator {
         "sort::skipped" => parse_parameter::<bool>(parameter, operator)
             .map(QueryOperator::SortSkipped)
             .unwrap_or(QueryOperator::Unknown(operator.clone())),
+        "sort::bpm" => parse_parameter::<bool>(parameter, operator)
+            .map(QueryOperator::SortBpm)
+            .unwrap_or(QueryOperator::Unknown(operator.clone())),
         "filter::liked" => parse_parameter::<bool>(parameter, operator)
             .map(QueryOperator::FilterLiked)
             .unwrap_or(QueryOperator::Unknown(operator.clone())),
         "filter::analyzed" => parse_parameter::<bool>(parameter, operator)
             .map(QueryOperator::FilterAnalyzed)
             .unwrap_or(QueryOperator::Unknown(operator.clone())),
+        "filter::bpm" => parse_bpm_range(parameter, operator)
+            .map(QueryOperator::FilterBpm)
+            .unwrap_or(QueryOperator::Unknown(operator.clone())),
+        "filter::key" => parse_keys(parameter, operator, false)
+            .map(QueryOperator::FilterKey)
+            .unwrap_or(QueryOperator::Unknown(operator.clone())),
+        "filter::key.harmonic" => parse_keys(parameter, operator, true)
+            .map(QueryOperator::FilterKey)
+            .unwrap_or(QueryOperator::Unknown(operator.clone())),
         "filter::with_cover_art" => parse_parameter::<bool>(parameter, operator)
             .map(QueryOperator::FilterWithCoverArt)
             .unwrap_or(QueryOperator::Unknown(operator.clone())),
@@ -564,7 +680,7 @@ fn parse_query(query: &(String, String)) -> QueryOperator {
 fn apply_join_filter(
     query: Select<media_files::Entity>,
     filter_liked: Option<bool>,
-    filter_analyzed: Option<bool>,
+    join_analysis: bool,
     sort_track_number: Option<bool>,
     sort_playedthrough_asc: Option<bool>,
     sort_skipped_asc: Option<bool>,
@@ -581,7 +697,7 @@ fn apply_join_filter(
             .column(media_file_stats::Column::Skipped);
     }
 
-    if filter_analyzed.is_some() {
+    if join_analysis {
         _query = _query
             .join(
                 JoinType::LeftJoin,
@@ -652,6 +768,87 @@ fn sort_media_files(
     media_files
 }
 
+/// How many more neighbours are looked up when recommendations are filtered
+/// by tempo or key.
+const FILTERED_RECOMMENDATION_OVERSAMPLING: u64 = 5;
+
+async fn get_analysis_by_file_ids(
+    main_db: &DatabaseConnection,
+    file_ids: &[i32],
+) -> Result<HashMap<i32, media_analysis::Model>> {
+    Ok(media_analysis::Entity::find()
+        .filter(media_analysis::Column::FileId.is_in(file_ids.to_vec()))
+        .all(main_db)
+        .await
+        .with_context(|| "Failed to query analysis of files")?
+        .into_iter()
+        .map(|x| (x.file_id, x))
+        .collect())
+}
+
+/// Keeps the files whose BPM, or its double or half, is within the range
+/// and whose key is one of the given keys, in their original order.
+async fn filter_files_by_analysis(
+    main_db: &DatabaseConnection,
+    file_ids: Vec<i32>,
+    bpm_range: Option<(Option<f32>, Option<f32>)>,
+    keys: Option<&HashSet<i32>>,
+) -> Result<Vec<i32>> {
+    let analysis = get_analysis_by_file_ids(main_db, &file_ids).await?;
+
+    Ok(file_ids
+        .into_iter()
+        .filter(|id| {
+            let Some(x) = analysis.get(id) else {
+                return false;
+            };
+
+            let bpm_matches = bpm_range.is_none_or(|range| {
+                x.bpm
+                    .and_then(|x| x.to_f32())
+                    .is_some_and(|x| bpm_in_range(x, range))
+            });
+            let key_matches =
+                keys.is_none_or(|keys| x.musical_key.is_some_and(|x| keys.contains(&x)));
+
+            bpm_matches && key_matches
+        })
+        .collect())
+}
+
+/// Sorts files by BPM, files without a tempo go last.
+async fn sort_files_by_bpm(
+    main_db: &DatabaseConnection,
+    mut file_ids: Vec<i32>,
+    asc: bool,
+) -> Result<Vec<i32>> {
+    let bpm: HashMap<i32, f32> = get_analysis_by_file_ids(main_db, &file_ids)
+        .await?
+        .into_iter()
+        .filter_map(|(file_id, x)| Some((file_id, x.bpm?.to_f32()?)))
+        .collect();
+
+    sort_by_bpm(&mut file_ids, &bpm, asc);
+
+    Ok(file_ids)
+}
+
+fn sort_by_bpm(file_ids: &mut [i32], bpm: &HashMap<i32, f32>, asc: bool) {
+    file_ids.sort_by(|a, b| match (bpm.get(a), bpm.get(b)) {
+        (Some(a), Some(b)) => {
+            let ordering = a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal);
+            if asc {
+                ordering
+            } else {
+                ordering.reverse()
+            }
+        }
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+}
+
 pub async fn query_mix_media_files(
     main_db: &DatabaseConnection,
     recommend_db: &RecommendationDbConnection,
@@ -675,10 +872,13 @@ pub async fn query_mix_media_files(
     let mut sort_duration_asc: Option<bool> = None;
     let mut sort_playedthrough_asc: Option<bool> = None;
     let mut sort_skipped_asc: Option<bool> = None;
+    let mut sort_bpm_asc: Option<bool> = None;
 
     let mut filter_liked: Option<bool> = None;
     let mut filter_cover_art: Option<bool> = None;
     let mut filter_analyzed: Option<bool> = None;
+    let mut filter_bpm: Option<(Option<f32>, Option<f32>)> = None;
+    let mut filter_keys: Option<HashSet<i32>> = None;
     let mut pipe_limit: Option<u64> = None;
     let mut pipe_recommend: Option<i32> = None;
 
@@ -698,9 +898,12 @@ pub async fn query_mix_media_files(
             QueryOperator::SortDuration(asc) => sort_duration_asc = Some(asc),
             QueryOperator::SortPlayedthrough(asc) => sort_playedthrough_asc = Some(asc),
             QueryOperator::SortSkipped(asc) => sort_skipped_asc = Some(asc),
+            QueryOperator::SortBpm(asc) => sort_bpm_asc = Some(asc),
             QueryOperator::FilterLiked(liked) => filter_liked = Some(liked),
             QueryOperator::FilterWithCoverArt(cover_art) => filter_cover_art = Some(cover_art),
             QueryOperator::FilterAnalyzed(analyzed) => filter_analyzed = Some(analyzed),
+            QueryOperator::FilterBpm(range) => filter_bpm = Some(range),
+            QueryOperator::FilterKey(codes) => filter_keys.get_or_insert_default().extend(codes),
             QueryOperator::PipeLimit(limit) => pipe_limit = Some(limit),
             QueryOperator::PipeRecommend(recommend) => pipe_recommend = Some(recommend),
             QueryOperator::Unknown(op) => warn!("Unknown operator: {}", op),
@@ -833,8 +1036,10 @@ pub async fn query_mix_media_files(
     let has_liked = filter_liked.is_some();
     let has_cover_art = filter_cover_art.is_some();
     let has_analyzed = filter_analyzed.is_some();
+    let has_bpm = filter_bpm.is_some();
+    let has_keys = filter_keys.is_some();
 
-    if has_liked || has_cover_art || has_analyzed {
+    if has_liked || has_cover_art || has_analyzed || has_bpm || has_keys {
         let mut filter = Condition::all();
 
         if !all {
@@ -853,6 +1058,14 @@ pub async fn query_mix_media_files(
             }
         }
 
+        if let Some(range) = filter_bpm {
+            filter = filter.add(bpm_range_condition(range));
+        }
+
+        if let Some(keys) = &filter_keys {
+            filter = filter.add(media_analysis::Column::MusicalKey.is_in(keys.iter().copied()));
+        }
+
         if let Some(cover_art) = filter_cover_art {
             let magic_cover_art_id = get_magic_cover_art_id(main_db).await;
 
@@ -882,11 +1095,12 @@ pub async fn query_mix_media_files(
         query = query.filter(or_condition);
     }
 
-    // Join with media_file_stats table for sorting by playedthrough and skipped, and filtering by liked
+    // Join with media_file_stats table for sorting by playedthrough and skipped, and filtering by liked,
+    // and with media_analysis for filtering by analyzed and BPM
     query = apply_join_filter(
         query,
         filter_liked,
-        filter_analyzed,
+        has_analyzed || has_bpm || has_keys || sort_bpm_asc.is_some(),
         sort_track_number_asc,
         sort_playedthrough_asc,
         sort_skipped_asc,
@@ -937,6 +1151,13 @@ pub async fn query_mix_media_files(
             );
         }
 
+        if let Some(asc) = sort_bpm_asc {
+            query = query.order_by(
+                media_analysis::Column::Bpm,
+                if asc { Order::Asc } else { Order::Desc },
+            );
+        }
+
         if let Some(query_limit) = pipe_limit {
             query = query.limit(query_limit);
         }
@@ -954,33 +1175,48 @@ pub async fn query_mix_media_files(
             return Ok([].to_vec());
         }
 
-        let virtual_point: [f32; 61] = if recommend_group >= 0 {
-            get_percentile_analysis_result(
+        let virtual_point: Vec<f32> = if recommend_group >= 0 {
+            get_percentile_feature_vector(
                 main_db,
                 1.0 / (9 + 2) as f64 * (recommend_group + 1) as f64,
             )
             .await
             .with_context(|| "Failed to query percentile data")?
         } else {
-            get_centralized_analysis_result(main_db, candidate_file_ids)
+            get_centralized_feature_vector(main_db, &candidate_file_ids)
                 .await
                 .with_context(|| "Failed to query centralized data")?
-                .into()
         };
 
         let recommend_n = pipe_limit.unwrap_or(30);
 
-        let file_ids = match get_recommendation_by_parameter(
-            recommend_db,
-            virtual_point,
-            recommend_n as usize,
-        )
-        .with_context(|| "Failed to get recommendation by parameters")
-        {
-            Ok(x) => x.into_iter().map(|x| x.0 as i32).collect::<Vec<i32>>(),
-            Err(_) => return Ok([].to_vec()),
+        // The recommendation index knows nothing about tempo and key, so look
+        // further and keep the neighbours matching the filters
+        let search_n = if has_bpm || has_keys {
+            recommend_n * FILTERED_RECOMMENDATION_OVERSAMPLING
+        } else {
+            recommend_n
         };
 
+        let mut file_ids =
+            match get_recommendation_by_parameter(recommend_db, &virtual_point, search_n as usize)
+                .with_context(|| "Failed to get recommendation by parameters")
+            {
+                Ok(x) => x.into_iter().map(|x| x.0 as i32).collect::<Vec<i32>>(),
+                Err(_) => return Ok([].to_vec()),
+            };
+
+        if has_bpm || has_keys {
+            file_ids =
+                filter_files_by_analysis(main_db, file_ids, filter_bpm, filter_keys.as_ref())
+                    .await?;
+            file_ids.truncate(recommend_n as usize);
+        }
+
+        if let Some(asc) = sort_bpm_asc {
+            file_ids = sort_files_by_bpm(main_db, file_ids, asc).await?;
+        }
+
         let media_files = get_files_by_ids(main_db, &file_ids).await?;
 
         // Create a hash map to store files by their ID
@@ -1040,6 +1276,13 @@ pub async fn query_mix_media_files(
         );
     }
 
+    if let Some(asc) = sort_bpm_asc {
+        query = query.order_by(
+            media_analysis::Column::Bpm,
+            if asc { Order::Asc } else { Order::Desc },
+        );
+    }
+
     if let Some(limit) = pipe_limit {
         if cursor as u64 >= limit {
             return Ok(vec![]);
@@ -1063,3 +1306,80 @@ pub async fn query_mix_media_files(
 
     Ok(sorted_files)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bpm_range() {
+        assert_eq!(
+            parse_bpm_range("120-140", "filter::bpm"),
+            Some((Some(120.0), Some(140.0)))
+        );
+        assert_eq!(
+            parse_bpm_range(" 120 - 140 ", "filter::bpm"),
+            Some((Some(120.0), Some(140.0)))
+        );
+        assert_eq!(
+            parse_bpm_range("150", "filter::bpm"),
+            Some((Some(149.0), Some(151.0)))
+        );
+    }
+
+    #[test]
+    fn test_parse_open_ended_bpm_range() {
+        assert_eq!(
+            parse_bpm_range("120-", "filter::bpm"),
+            Some((Some(120.0), None))
+        );
+        assert_eq!(
+            parse_bpm_range("-140", "filter::bpm"),
+            Some((None, Some(140.0)))
+        );
+    }
+
+    #[test]
+    fn test_parse_malformed_bpm_range() {
+        for parameter in ["", "-", "fast", "120-fast", "slow-140", "120-130-140"] {
+            assert_eq!(
+                parse_bpm_range(parameter, "filter::bpm"),
+                None,
+                "{:?}",
+                parameter
+            );
+        }
+    }
+
+    #[test]
+    fn test_bpm_in_range() {
+        let range = (Some(160.0), Some(180.0));
+
+        assert!(bpm_in_range(170.0, range));
+        // Half and double tempo
+        assert!(bpm_in_range(85.0, range));
+        assert!(bpm_in_range(340.0, range));
+        assert!(!bpm_in_range(120.0, range));
+        assert!(!bpm_in_range(100.0, range));
+
+        assert!(bpm_in_range(60.0, (None, Some(100.0))));
+        assert!(bpm_in_range(150.0, (None, Some(100.0))));
+        assert!(!bpm_in_range(210.0, (None, Some(100.0))));
+        assert!(bpm_in_range(70.0, (Some(130.0), None)));
+        assert!(!bpm_in_range(60.0, (Some(130.0), None)));
+    }
+
+    #[test]
+    fn test_sort_by_bpm() {
+        let bpm = HashMap::from([(1, 128.0), (2, 90.0), (4, 174.0)]);
+
+        let mut file_ids = vec![1, 2, 3, 4];
+        sort_by_bpm(&mut file_ids, &bpm, true);
+        assert_eq!(file_ids, vec![2, 1, 4, 3]);
+
+        // Files without a tempo go last either way
+        let mut file_ids = vec![3, 1, 2, 4];
+        sort_by_bpm(&mut file_ids, &bpm, false);
+        assert_eq!(file_ids, vec![4, 1, 2, 3]);
+    }
+}
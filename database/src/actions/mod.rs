@@ -3,7 +3,9 @@ pub mod analysis;
 pub mod artists;
 pub mod collection;
 pub mod cover_art;
+pub mod cue;
 pub mod directory;
+pub mod features;
 pub mod file;
 pub mod index;
 pub mod library;
@@ -13,6 +15,10 @@ pub mod mixes;
 pub mod playback_queue;
 pub mod playlists;
 pub mod recommendation;
+pub mod replaygain;
 pub mod search;
+pub mod segments;
 pub mod stats;
+pub mod suggestions;
 pub mod utils;
+pub mod watcher;
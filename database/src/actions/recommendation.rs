@@ -1,19 +1,23 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::num::NonZeroUsize;
 
 use anyhow::{bail, Context, Result};
 use arroy::distances::Euclidean;
 use arroy::{Reader, Writer};
-use log::error;
+use log::{error, info};
 use rand::rngs::StdRng;
 use rand::SeedableRng;
 use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveValue, QueryOrder, TransactionTrait};
 
-use crate::actions::analysis::AggregatedAnalysisResult;
-use crate::connection::{MainDbConnection, RecommendationDbConnection};
-use crate::entities::{media_analysis, media_files};
+use analysis::analysis::AnalysisParameter;
+use analysis::extractor::ExtractorSet;
+use analysis::utils::scaling::{feature_weights, FeatureScaler, ScalingMethod};
 
-use super::analysis::get_percentile_analysis_result;
+use crate::actions::analysis::get_analysis_parameters;
+use crate::actions::features::{layout_dimensions, layout_of, load_feature_vectors, LayoutEntry};
+use crate::connection::{MainDbConnection, RecommendationDbConnection};
+use crate::entities::{media_files, recommendation_layout, recommendation_scaling};
 
 /// Get recommendations for a given item.
 ///
@@ -49,18 +53,19 @@ pub fn get_recommendation_by_file_id(
     Ok(results)
 }
 
-/// Get recommendations for a given item.
+/// Get recommendations for a given feature vector.
 ///
 /// # Arguments
 /// * `recommend_db` - The tuple containing the LMDB environment and the Arroy database.
-/// * `item_id` - The ID of the item for which to get recommendations.
+/// * `feature_vector` - A vector scaled like the vectors of the index, see
+///   `scale_feature_vector`.
 /// * `n` - The number of recommendations to retrieve.
 ///
 /// # Returns
 /// * `Result<Vec<(usize, f32)>>` - A vector of recommended item IDs and their distances.
 pub fn get_recommendation_by_parameter(
     recommend_db: &RecommendationDbConnection,
-    feature_vector: [f32; 61],
+    feature_vector: &[f32],
     n: usize,
 ) -> Result<Vec<(u32, f32)>> {
     let env = recommend_db.env.clone();
@@ -73,7 +78,7 @@ pub fn get_recommendation_by_parameter(
     let results = reader
         .nns(n)
         .search_k(search_k)
-        .by_vector(&rtxn, &feature_vector)
+        .by_vector(&rtxn, feature_vector)
         .with_context(|| "Failed to get recommendation by parameter");
 
     match results {
@@ -91,41 +96,236 @@ pub fn get_recommendation_by_parameter(
     }
 }
 
-/// Sync the recommendation database with the analysis data.
-///
-/// # Arguments
-/// * `main_db` - A reference to the database connection.
-/// * `recommend_db` - The tuple containing the LMDB environment and the Arroy database.
-///
-/// # Returns
-/// * `Result<()>` - A result indicating success or failure.
-pub async fn sync_recommendation(
+/// The layout of the vectors in the recommendation index. Indexes built
+/// before layouts were recorded hold the spectral values only.
+pub async fn get_index_layout(main_db: &MainDbConnection) -> Result<Vec<LayoutEntry>> {
+    let rows = recommendation_layout::Entity::find()
+        .order_by_asc(recommendation_layout::Column::Position)
+        .all(main_db)
+        .await?;
+
+    if rows.is_empty() {
+        return Ok(layout_of(ExtractorSet::default()));
+    }
+
+    Ok(rows
+        .into_iter()
+        .map(|x| LayoutEntry {
+            extractor: x.extractor,
+            version: x.version as u32,
+            dimensions: x.dimensions as usize,
+        })
+        .collect())
+}
+
+/// The statistics the vectors of the recommendation index were scaled with.
+/// Indexes built before vectors were scaled hold raw values.
+pub async fn get_index_scaler(main_db: &MainDbConnection) -> Result<Option<FeatureScaler>> {
+    let rows = recommendation_scaling::Entity::find()
+        .order_by_asc(recommendation_scaling::Column::Dimension)
+        .all(main_db)
+        .await?;
+
+    if rows.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(FeatureScaler {
+        center: rows.iter().map(|x| x.center as f32).collect(),
+        scale: rows.iter().map(|x| x.scale as f32).collect(),
+        weight: rows.iter().map(|x| x.weight as f32).collect(),
+    }))
+}
+
+/// Scales a vector laid out like the recommendation index the way the
+/// vectors of the index were scaled.
+pub async fn scale_feature_vector(
     main_db: &MainDbConnection,
-    recommend_db: &RecommendationDbConnection,
+    feature_vector: &[f32],
+) -> Result<Vec<f32>> {
+    match get_index_scaler(main_db).await? {
+        Some(scaler) => {
+            if scaler.dimensions() != feature_vector.len() {
+                bail!(
+                    "Expected a vector of {} dimensions, got {}",
+                    scaler.dimensions(),
+                    feature_vector.len()
+                );
+            }
+            Ok(scaler.apply(feature_vector))
+        }
+        None => Ok(feature_vector.to_vec()),
+    }
+}
+
+async fn set_index_description(
+    main_db: &MainDbConnection,
+    layout: &[LayoutEntry],
+    scaler: &FeatureScaler,
 ) -> Result<()> {
+    let txn = main_db.begin().await?;
+
+    recommendation_layout::Entity::delete_many()
+        .exec(&txn)
+        .await?;
+    recommendation_layout::Entity::insert_many(layout.iter().enumerate().map(|(position, x)| {
+        recommendation_layout::ActiveModel {
+            position: ActiveValue::Set(position as i32),
+            extractor: ActiveValue::Set(x.extractor.clone()),
+            version: ActiveValue::Set(x.version as i32),
+            dimensions: ActiveValue::Set(x.dimensions as i32),
+            ..Default::default()
+        }
+    }))
+    .exec(&txn)
+    .await?;
+
+    recommendation_scaling::Entity::delete_many()
+        .exec(&txn)
+        .await?;
+    recommendation_scaling::Entity::insert_many((0..scaler.dimensions()).map(|dimension| {
+        recommendation_scaling::ActiveModel {
+            dimension: ActiveValue::Set(dimension as i32),
+            center: ActiveValue::Set(scaler.center[dimension] as f64),
+            scale: ActiveValue::Set(scaler.scale[dimension] as f64),
+            weight: ActiveValue::Set(scaler.weight[dimension] as f64),
+            ..Default::default()
+        }
+    }))
+    .exec(&txn)
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(())
+}
+
+/// How the vectors of the recommendation index are scaled, so that features
+/// of large magnitudes don't dominate the distances.
+#[derive(Debug, Clone, Default)]
+pub struct IndexScaling {
+    pub method: ScalingMethod,
+    /// Weights of the scaled features by label, see
+    /// `analysis::utils::scaling::feature_weights`.
+    pub weights: Vec<(String, f32)>,
+}
+
+/// Share of the items of the recommendation index that may change before it
+/// is rebuilt from scratch rather than updated in place.
+pub const REBUILD_THRESHOLD: f32 = 0.2;
+
+/// How far the statistics of the library may drift from the ones the index
+/// was scaled with before it is rebuilt with the new ones.
+const SCALER_TOLERANCE: f32 = 0.05;
+
+/// What `update_index` did to the recommendation index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexUpdate {
+    /// The index already held the given vectors.
+    Unchanged,
+    /// Items were added, updated and removed in place.
+    Updated {
+        added: usize,
+        updated: usize,
+        removed: usize,
+    },
+    /// Too many items changed, or the index can't be updated, so nothing
+    /// was written and the index has to be rebuilt.
+    NeedsRebuild { changed: usize },
+}
+
+/// Brings the recommendation index to the given vectors in a single write
+/// transaction: missing items are added, items whose vector differs are
+/// updated, and items not in `vectors` are removed, then only the changed
+/// parts of the trees are rebuilt. When more than `rebuild_threshold` of the
+/// items would change, nothing is written.
+pub fn update_index(
+    recommend_db: &RecommendationDbConnection,
+    vectors: &[(u32, Vec<f32>)],
+    dimensions: usize,
+    rebuild_threshold: f32,
+) -> Result<IndexUpdate> {
     let env = recommend_db.env.clone();
     let arroy_db = recommend_db.db;
 
-    // Fetch all analysis data
-    let analyzes = media_analysis::Entity::find().all(main_db).await?;
+    let mut wtxn = env.write_txn()?;
+    let indexed: HashSet<u32> = match Reader::<Euclidean>::open(&wtxn, 0, arroy_db) {
+        Ok(reader) if reader.dimensions() == dimensions => reader.item_ids().iter().collect(),
+        // Never built, or built from another layout
+        _ => {
+            return Ok(IndexUpdate::NeedsRebuild {
+                changed: vectors.len(),
+            })
+        }
+    };
 
-    // Track existing IDs in the main database
-    let mut existing_ids: HashSet<i32> = HashSet::new();
-    for analysis in &analyzes {
-        existing_ids.insert(analysis.file_id);
+    let writer = Writer::<Euclidean>::new(arroy_db, 0, dimensions);
+
+    let current: HashSet<u32> = vectors.iter().map(|(item, _)| *item).collect();
+    let removed: Vec<u32> = indexed.difference(&current).copied().collect();
+
+    let mut added = 0;
+    let mut changes = vec![];
+    for (item, vector) in vectors {
+        if !indexed.contains(item) {
+            added += 1;
+            changes.push((*item, vector));
+        } else if writer
+            .item_vector(&wtxn, *item)?
+            .is_none_or(|x| x != *vector)
+        {
+            changes.push((*item, vector));
+        }
+    }
+
+    let changed = changes.len() + removed.len();
+    if changed == 0 {
+        wtxn.abort();
+        return Ok(IndexUpdate::Unchanged);
+    }
+    if changed as f32 > rebuild_threshold * indexed.len().max(1) as f32 {
+        wtxn.abort();
+        return Ok(IndexUpdate::NeedsRebuild { changed });
+    }
+
+    for item in &removed {
+        writer.del_item(&mut wtxn, *item)?;
     }
+    // Adding an existing item replaces its vector
+    for (item, vector) in &changes {
+        writer.add_item(&mut wtxn, *item, vector)?;
+    }
+
+    let mut rng = StdRng::seed_from_u64(42);
+    writer.builder(&mut rng).build(&mut wtxn)?;
+
+    wtxn.commit()?;
+
+    Ok(IndexUpdate::Updated {
+        added,
+        updated: changes.len() - added,
+        removed: removed.len(),
+    })
+}
+
+/// Replaces every item of the recommendation index with the given vectors,
+/// and builds the index from scratch.
+pub fn rebuild_index(
+    recommend_db: &RecommendationDbConnection,
+    vectors: &[(u32, Vec<f32>)],
+    dimensions: usize,
+) -> Result<()> {
+    let env = recommend_db.env.clone();
+    let arroy_db = recommend_db.db;
 
     // Open a write transaction for the recommendation database
     let mut wtxn = env.write_txn()?;
-    let writer = Writer::<Euclidean>::new(arroy_db, 0, 61);
+    let writer = Writer::<Euclidean>::new(arroy_db, 0, dimensions);
 
-    // Insert or update analysis data in the recommendation database
-    for analysis in analyzes {
-        let file_id: u32 = analysis.file_id.try_into()?;
-        let parsed_result: AggregatedAnalysisResult = analysis.into();
-        let vector: [f32; 61] = parsed_result.into();
-
-        writer.add_item(&mut wtxn, file_id, &vector)?;
+    // The layout may differ from the previous build, so nothing is kept
+    writer.clear(&mut wtxn)?;
+    for (item, vector) in vectors {
+        writer.add_item(&mut wtxn, *item, vector)?;
     }
 
     // Build the index
@@ -135,21 +335,162 @@ pub async fn sync_recommendation(
     // Commit the transaction
     wtxn.commit()?;
 
-    // Clean up the recommendation database by removing items not present in the main database
-    let rtxn = env.read_txn()?;
-    let reader = Reader::<Euclidean>::open(&rtxn, 0, arroy_db)?;
-    for id in reader.item_ids() {
-        if !existing_ids.contains(&(id as i32)) {
-            let mut wtxn = env.write_txn()?;
-            let writer = Writer::<Euclidean>::new(arroy_db, 0, 61);
-            writer.del_item(&mut wtxn, id)?;
-            wtxn.commit()?;
-        }
+    Ok(())
+}
+
+/// Fails when the files were not all analyzed with the same parameters.
+/// Files analyzed before the parameters were recorded count as a version of
+/// their own, their vectors can't be compared with newer ones either.
+fn check_analysis_parameters(
+    file_ids: &[i32],
+    analyzed_parameters: &HashMap<i32, Option<AnalysisParameter>>,
+) -> Result<()> {
+    let parameters: HashSet<_> = file_ids
+        .iter()
+        .filter_map(|file_id| analyzed_parameters.get(file_id).copied())
+        .collect();
+
+    if parameters.len() > 1 {
+        bail!(
+            "Files analyzed with different parameters can't share the recommendation index, analyze the library again: {:?}",
+            parameters
+        );
     }
 
     Ok(())
 }
 
+fn scale_vectors(
+    file_ids: &[i32],
+    vectors: &[Vec<f32>],
+    scaler: &FeatureScaler,
+) -> Result<Vec<(u32, Vec<f32>)>> {
+    file_ids
+        .iter()
+        .zip(vectors)
+        .map(|(file_id, vector)| Ok(((*file_id).try_into()?, scaler.apply(vector))))
+        .collect()
+}
+
+/// Synchronize the recommendation database with the values of the enabled
+/// extractors, scaled with statistics of the library, and record the layout
+/// and the scaling of its vectors. The index is updated in place, unless its
+/// layout changed, the statistics of the library drifted away from the ones
+/// it was scaled with, or too many files changed, in which case it is
+/// rebuilt. Fails when the files were analyzed with different parameters, as
+/// their vectors can't be compared; the stale files have to be analyzed
+/// again first.
+///
+/// # Arguments
+/// * `main_db` - A reference to the database connection.
+/// * `recommend_db` - The tuple containing the LMDB environment and the Arroy database.
+/// * `extractors` - The extractors whose values make the vectors.
+/// * `scaling` - How the values are scaled and weighted.
+///
+/// # Returns
+/// * `Result<()>` - A result indicating success or failure.
+pub async fn sync_recommendation(
+    main_db: &MainDbConnection,
+    recommend_db: &RecommendationDbConnection,
+    extractors: ExtractorSet,
+    scaling: &IndexScaling,
+) -> Result<()> {
+    let layout = layout_of(extractors);
+    let weights = feature_weights(&extractors.labels(), &scaling.weights)?;
+    let (file_ids, vectors): (Vec<i32>, Vec<Vec<f32>>) =
+        load_feature_vectors(main_db, &layout, None)
+            .await?
+            .into_iter()
+            .unzip();
+
+    check_analysis_parameters(&file_ids, &get_analysis_parameters(main_db).await?)?;
+
+    let dimensions = layout_dimensions(&layout);
+    let scaler = FeatureScaler::fit(scaling.method, &vectors, weights);
+
+    if get_index_layout(main_db).await? == layout {
+        let indexed_scaler = get_index_scaler(main_db)
+            .await?
+            .filter(|x| !scaler.drifted_from(x, SCALER_TOLERANCE));
+
+        if let Some(indexed_scaler) = indexed_scaler {
+            let scaled = scale_vectors(&file_ids, &vectors, &indexed_scaler)?;
+            match update_index(recommend_db, &scaled, dimensions, REBUILD_THRESHOLD)? {
+                IndexUpdate::NeedsRebuild { changed } => {
+                    info!(
+                        "{} files changed, rebuilding the recommendation index",
+                        changed
+                    )
+                }
+                update => {
+                    info!("Recommendation index synchronized: {:?}", update);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    info!(
+        "Building the recommendation index from {} files with {:?}",
+        vectors.len(),
+        extractors.names()
+    );
+
+    let scaled = scale_vectors(&file_ids, &vectors, &scaler)?;
+    rebuild_index(recommend_db, &scaled, dimensions)?;
+
+    set_index_description(main_db, &layout, &scaler).await
+}
+
+/// The mean vector of the given files, laid out and scaled like the
+/// recommendation index. The mean is zero before scaling when none of them is
+/// analyzed.
+pub async fn get_centralized_feature_vector(
+    main_db: &MainDbConnection,
+    file_ids: &[i32],
+) -> Result<Vec<f32>> {
+    let layout = get_index_layout(main_db).await?;
+    let vectors = load_feature_vectors(main_db, &layout, Some(file_ids)).await?;
+
+    let mut mean = vec![0.0f64; layout_dimensions(&layout)];
+    for (_, vector) in &vectors {
+        for (total, value) in mean.iter_mut().zip(vector.iter()) {
+            *total += *value as f64;
+        }
+    }
+
+    let count = vectors.len().max(1) as f64;
+    let mean: Vec<f32> = mean.into_iter().map(|x| (x / count) as f32).collect();
+
+    scale_feature_vector(main_db, &mean).await
+}
+
+/// A vector made of the given percentile of every dimension over the
+/// library, laid out and scaled like the recommendation index.
+pub async fn get_percentile_feature_vector(
+    main_db: &MainDbConnection,
+    percentile: f64,
+) -> Result<Vec<f32>> {
+    let layout = get_index_layout(main_db).await?;
+    let vectors = load_feature_vectors(main_db, &layout, None).await?;
+
+    if vectors.is_empty() {
+        return scale_feature_vector(main_db, &vec![0.0; layout_dimensions(&layout)]).await;
+    }
+
+    let index = (percentile.clamp(0.0, 1.0) * (vectors.len() - 1) as f64).round() as usize;
+
+    let vector: Vec<f32> = (0..layout_dimensions(&layout))
+        .map(|dimension| {
+            let mut values: Vec<f32> = vectors.iter().map(|(_, x)| x[dimension]).collect();
+            let (_, value, _) = values.select_nth_unstable_by(index, |a, b| a.total_cmp(b));
+            *value
+        })
+        .collect();
+
+    scale_feature_vector(main_db, &vector).await
+}
+
 pub async fn get_recommendation_by_percentile(
     main_db: &MainDbConnection,
     recommend_db: &RecommendationDbConnection,
@@ -158,11 +499,40 @@ pub async fn get_recommendation_by_percentile(
 ) -> Result<Vec<(u32, f32)>> {
     let p = 1.0 / (total_groups + 2) as f64 * (group_index + 1) as f64;
 
-    let virtual_point = get_percentile_analysis_result(main_db, p).await?;
+    let virtual_point = get_percentile_feature_vector(main_db, p).await?;
     let total_files = media_files::Entity::find()
         .count(main_db)
         .await
         .with_context(|| "Unable to get total files")? as usize;
 
-    get_recommendation_by_parameter(recommend_db, virtual_point, total_files / total_groups)
+    get_recommendation_by_parameter(recommend_db, &virtual_point, total_files / total_groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use analysis::utils::computing_device::ComputingDevice;
+
+    use super::*;
+
+    #[test]
+    fn test_check_analysis_parameters() {
+        let current = AnalysisParameter::new(1024, 512, ComputingDevice::Cpu);
+        let other_device = AnalysisParameter::new(1024, 512, ComputingDevice::Gpu);
+        let other_window = AnalysisParameter::new(2048, 1024, ComputingDevice::Cpu);
+
+        let analyzed_parameters = HashMap::from([
+            (1, None),
+            (2, Some(current)),
+            (3, Some(other_device)),
+            (4, None),
+            (5, Some(other_window)),
+        ]);
+
+        // The computing device gives the same results
+        assert!(check_analysis_parameters(&[2, 3], &analyzed_parameters).is_ok());
+        assert!(check_analysis_parameters(&[1, 4], &analyzed_parameters).is_ok());
+        assert!(check_analysis_parameters(&[2, 5], &analyzed_parameters).is_err());
+        // A legacy row can't be compared with a new one
+        assert!(check_analysis_parameters(&[1, 2], &analyzed_parameters).is_err());
+    }
 }
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use rust_decimal::prelude::ToPrimitive;
+use sea_orm::entity::prelude::*;
+
+use analysis::utils::loudness_meter::combined_loudness;
+
+use crate::entities::{media_analysis, media_file_albums, media_files};
+
+/// Loudness ReplayGain 2.0 normalizes to, in LUFS.
+pub const REPLAYGAIN_REFERENCE_LOUDNESS: f64 = -18.0;
+
+/// ReplayGain 2.0 values of a track. Gains are in dB and peaks are linear
+/// true peaks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayGainValues {
+    pub file_id: i32,
+    /// Relative to the library root
+    pub path: PathBuf,
+    pub album_id: Option<i32>,
+    /// Set for the tracks of a CUE sheet, which share their file with the
+    /// other tracks of the sheet.
+    pub cue_track: Option<i32>,
+    pub track_gain: f64,
+    /// Missing when the analysis has no true peak for the track.
+    pub track_peak: Option<f64>,
+    /// Missing when the track has no album, or when some tracks of its album
+    /// have not been analyzed yet.
+    pub album_gain: Option<f64>,
+    /// Missing as well when the true peak of a track of the album is.
+    pub album_peak: Option<f64>,
+}
+
+struct MeasuredTrack {
+    /// Negative infinity for silent tracks, whose loudness is stored as NULL
+    loudness: f64,
+    peak: Option<f64>,
+    duration: f64,
+}
+
+/// Computes ReplayGain 2.0 track and album values for every file whose
+/// loudness was measured by the analysis. Albums are grouped by
+/// `media_file_albums`. Silent tracks get no values of their own, and add
+/// nothing to the loudness of their album.
+pub async fn compute_replaygain(main_db: &DatabaseConnection) -> Result<Vec<ReplayGainValues>> {
+    let analysis = media_analysis::Entity::find().all(main_db).await?;
+
+    let files: HashMap<i32, media_files::Model> = media_files::Entity::find()
+        .all(main_db)
+        .await?
+        .into_iter()
+        .map(|x| (x.id, x))
+        .collect();
+
+    let mut measured: HashMap<i32, MeasuredTrack> = HashMap::new();
+    for row in analysis {
+        let Some(file) = files.get(&row.file_id) else {
+            continue;
+        };
+        let loudness = row
+            .integrated_loudness
+            .and_then(|x| x.to_f64())
+            .unwrap_or(f64::NEG_INFINITY);
+
+        measured.entry(row.file_id).or_insert(MeasuredTrack {
+            loudness,
+            peak: row.true_peak.and_then(|x| x.to_f64()),
+            duration: file.duration.to_f64().unwrap_or_default(),
+        });
+    }
+
+    let album_of_file: HashMap<i32, i32> = media_file_albums::Entity::find()
+        .all(main_db)
+        .await?
+        .into_iter()
+        .map(|x| (x.media_file_id, x.album_id))
+        .collect();
+
+    let mut album_files: HashMap<i32, Vec<i32>> = HashMap::new();
+    for (file_id, album_id) in &album_of_file {
+        album_files.entry(*album_id).or_default().push(*file_id);
+    }
+
+    // An album is only measured once all of its tracks are analyzed
+    let album_values: HashMap<i32, (f64, Option<f64>)> = album_files
+        .into_iter()
+        .filter_map(|(album_id, file_ids)| {
+            let tracks = file_ids
+                .iter()
+                .map(|x| measured.get(x))
+                .collect::<Option<Vec<_>>>()?;
+
+            let loudness = combined_loudness(
+                &tracks
+                    .iter()
+                    .map(|x| (x.loudness, x.duration))
+                    .collect::<Vec<_>>(),
+            );
+            // Silent tracks have no peak to speak of
+            let peak = tracks
+                .iter()
+                .filter(|x| x.loudness.is_finite())
+                .map(|x| x.peak)
+                .try_fold(0.0, |peak, x| x.map(|x| f64::max(peak, x)));
+
+            Some((album_id, (REPLAYGAIN_REFERENCE_LOUDNESS - loudness, peak)))
+        })
+        .collect();
+
+    let mut result: Vec<ReplayGainValues> = measured
+        .into_iter()
+        .filter(|(_, track)| track.loudness.is_finite())
+        .filter_map(|(file_id, track)| {
+            let file = files.get(&file_id)?;
+            let album_id = album_of_file.get(&file_id).copied();
+            let album = album_id.and_then(|x| album_values.get(&x));
+
+            Some(ReplayGainValues {
+                file_id,
+                path: PathBuf::from(&file.directory).join(&file.file_name),
+                album_id,
+                cue_track: file.cue_track,
+                track_gain: REPLAYGAIN_REFERENCE_LOUDNESS - track.loudness,
+                track_peak: track.peak,
+                album_gain: album.map(|x| x.0).filter(|x| x.is_finite()),
+                album_peak: album.and_then(|x| x.1),
+            })
+        })
+        .collect();
+
+    result.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(result)
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use analysis::analysis::AnalysisSegment;
+use analysis::utils::segment_codec::{decode_segments, encode_segments};
+
+/// Segment analysis is kept out of SQLite, in one small binary file per
+/// media file under `.rune/segments`.
+pub fn segments_path(lib_path: &Path, file_id: i32) -> PathBuf {
+    lib_path
+        .join(".rune")
+        .join("segments")
+        .join(format!("{}.seg", file_id))
+}
+
+pub fn save_segments(lib_path: &Path, file_id: i32, segments: &[AnalysisSegment]) -> Result<()> {
+    let path = segments_path(lib_path, file_id);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create segment directory: {:?}", parent))?;
+    }
+
+    // Written aside and renamed, so readers never see a partial file
+    let temporary_path = path.with_extension("seg.tmp");
+    fs::write(&temporary_path, encode_segments(segments))
+        .with_context(|| format!("Failed to write segments: {:?}", temporary_path))?;
+    fs::rename(&temporary_path, &path)
+        .with_context(|| format!("Failed to move segments into place: {:?}", path))?;
+
+    Ok(())
+}
+
+/// The segments of a file, `None` when it was not analyzed in segment mode.
+pub fn load_segments(lib_path: &Path, file_id: i32) -> Result<Option<Vec<AnalysisSegment>>> {
+    let path = segments_path(lib_path, file_id);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let bytes = fs::read(&path).with_context(|| format!("Failed to read segments: {:?}", path))?;
+    let segments = decode_segments(&bytes)
+        .with_context(|| format!("Failed to decode segments: {:?}", path))?;
+
+    Ok(Some(segments))
+}
+
+pub fn remove_segments(lib_path: &Path, file_id: i32) -> Result<()> {
+    let path = segments_path(lib_path, file_id);
+    if path.exists() {
+        fs::remove_file(&path).with_context(|| format!("Failed to remove segments: {:?}", path))?;
+    }
+
+    Ok(())
+}
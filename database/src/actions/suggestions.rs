@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sea_orm::prelude::*;
+use sea_orm::{ActiveValue, QueryOrder, TransactionTrait};
+
+use crate::actions::index::index_media_files;
+use crate::entities::{media_metadata, media_metadata_suggestions};
+
+/// A metadata match for a media file, typically produced by AcoustID
+/// identification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataCandidate {
+    pub acoustid_id: Option<String>,
+    pub recording_id: String,
+    pub release_id: Option<String>,
+    pub track_id: Option<String>,
+    pub artist_ids: Vec<String>,
+    pub title: String,
+    pub artist: String,
+    pub album: Option<String>,
+    pub score: f64,
+}
+
+impl MetadataCandidate {
+    /// The `media_metadata` entries this candidate stands for, keyed by the
+    /// `metadata::reader` standard key names.
+    pub fn to_metadata(&self) -> Vec<(String, String)> {
+        let mut metadata = vec![
+            ("track_title".to_string(), self.title.clone()),
+            ("artist".to_string(), self.artist.clone()),
+            (
+                "musicbrainz_recording_id".to_string(),
+                self.recording_id.clone(),
+            ),
+        ];
+
+        if let Some(album) = &self.album {
+            metadata.push(("album".to_string(), album.clone()));
+        }
+        if let Some(acoustid_id) = &self.acoustid_id {
+            metadata.push(("acoustid_id".to_string(), acoustid_id.clone()));
+        }
+        if let Some(release_id) = &self.release_id {
+            metadata.push(("musicbrainz_album_id".to_string(), release_id.clone()));
+        }
+        if let Some(track_id) = &self.track_id {
+            metadata.push(("musicbrainz_release_track_id".to_string(), track_id.clone()));
+        }
+        for artist_id in &self.artist_ids {
+            metadata.push(("musicbrainz_artist_id".to_string(), artist_id.clone()));
+        }
+
+        metadata
+    }
+}
+
+impl From<media_metadata_suggestions::Model> for MetadataCandidate {
+    fn from(model: media_metadata_suggestions::Model) -> Self {
+        MetadataCandidate {
+            acoustid_id: model.acoustid_id,
+            recording_id: model.recording_id,
+            release_id: model.release_id,
+            track_id: model.track_id,
+            artist_ids: model
+                .artist_ids
+                .split(';')
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_string())
+                .collect(),
+            title: model.title,
+            artist: model.artist,
+            album: model.album,
+            score: model.score,
+        }
+    }
+}
+
+/// Write a candidate into `media_metadata`, replacing the keys it covers, and
+/// drop every pending suggestion of the file.
+///
+/// # Arguments
+/// * `main_db` - A reference to the database connection.
+/// * `file_id` - The ID of the media file to update.
+/// * `candidate` - The metadata to apply.
+pub async fn apply_metadata_candidate(
+    main_db: &DatabaseConnection,
+    file_id: i32,
+    candidate: &MetadataCandidate,
+) -> Result<()> {
+    let metadata = candidate.to_metadata();
+    let keys: Vec<String> = metadata.iter().map(|(key, _)| key.clone()).collect();
+
+    let txn = main_db.begin().await?;
+
+    media_metadata::Entity::delete_many()
+        .filter(media_metadata::Column::FileId.eq(file_id))
+        .filter(media_metadata::Column::MetaKey.is_in(keys))
+        .exec(&txn)
+        .await
+        .with_context(|| format!("Failed to delete existing metadata: {}", file_id))?;
+
+    let new_metadata: Vec<media_metadata::ActiveModel> = metadata
+        .into_iter()
+        .map(|(key, value)| media_metadata::ActiveModel {
+            file_id: ActiveValue::Set(file_id),
+            meta_key: ActiveValue::Set(key),
+            meta_value: ActiveValue::Set(value),
+            ..Default::default()
+        })
+        .collect();
+
+    media_metadata::Entity::insert_many(new_metadata)
+        .exec(&txn)
+        .await
+        .with_context(|| format!("Failed to insert metadata: {}", file_id))?;
+
+    media_metadata_suggestions::Entity::delete_many()
+        .filter(media_metadata_suggestions::Column::FileId.eq(file_id))
+        .exec(&txn)
+        .await?;
+
+    txn.commit().await?;
+
+    // Artist and album links are derived from the metadata
+    index_media_files(main_db, vec![file_id], None).await?;
+
+    Ok(())
+}
+
+/// Replace the pending suggestions of a media file.
+///
+/// # Arguments
+/// * `main_db` - A reference to the database connection.
+/// * `file_id` - The ID of the media file.
+/// * `candidates` - The candidates to keep for later review.
+pub async fn replace_metadata_suggestions(
+    main_db: &DatabaseConnection,
+    file_id: i32,
+    candidates: &[MetadataCandidate],
+) -> Result<()> {
+    let txn = main_db.begin().await?;
+
+    media_metadata_suggestions::Entity::delete_many()
+        .filter(media_metadata_suggestions::Column::FileId.eq(file_id))
+        .exec(&txn)
+        .await?;
+
+    let now = Utc::now();
+    let suggestions: Vec<media_metadata_suggestions::ActiveModel> = candidates
+        .iter()
+        .map(|candidate| media_metadata_suggestions::ActiveModel {
+            file_id: ActiveValue::Set(file_id),
+            acoustid_id: ActiveValue::Set(candidate.acoustid_id.clone()),
+            recording_id: ActiveValue::Set(candidate.recording_id.clone()),
+            release_id: ActiveValue::Set(candidate.release_id.clone()),
+            track_id: ActiveValue::Set(candidate.track_id.clone()),
+            artist_ids: ActiveValue::Set(candidate.artist_ids.join(";")),
+            title: ActiveValue::Set(candidate.title.clone()),
+            artist: ActiveValue::Set(candidate.artist.clone()),
+            album: ActiveValue::Set(candidate.album.clone()),
+            score: ActiveValue::Set(candidate.score),
+            created_at: ActiveValue::Set(now),
+            ..Default::default()
+        })
+        .collect();
+
+    if !suggestions.is_empty() {
+        media_metadata_suggestions::Entity::insert_many(suggestions)
+            .exec(&txn)
+            .await?;
+    }
+
+    txn.commit().await?;
+
+    Ok(())
+}
+
+/// List the pending suggestions of a media file, best match first.
+pub async fn list_metadata_suggestions(
+    main_db: &DatabaseConnection,
+    file_id: i32,
+) -> Result<Vec<media_metadata_suggestions::Model>> {
+    let suggestions = media_metadata_suggestions::Entity::find()
+        .filter(media_metadata_suggestions::Column::FileId.eq(file_id))
+        .order_by_desc(media_metadata_suggestions::Column::Score)
+        .all(main_db)
+        .await?;
+
+    Ok(suggestions)
+}
+
+/// List the IDs of every media file with pending suggestions.
+pub async fn list_files_with_metadata_suggestions(
+    main_db: &DatabaseConnection,
+) -> Result<Vec<i32>> {
+    let mut file_ids: Vec<i32> = media_metadata_suggestions::Entity::find()
+        .all(main_db)
+        .await?
+        .into_iter()
+        .map(|x| x.file_id)
+        .collect();
+
+    file_ids.sort_unstable();
+    file_ids.dedup();
+
+    Ok(file_ids)
+}
+
+/// Accept a pending suggestion, applying it to the media file.
+///
+/// # Returns
+/// * `Result<bool>` - `false` if the suggestion no longer exists.
+pub async fn accept_metadata_suggestion(
+    main_db: &DatabaseConnection,
+    suggestion_id: i32,
+) -> Result<bool> {
+    let suggestion = media_metadata_suggestions::Entity::find_by_id(suggestion_id)
+        .one(main_db)
+        .await?;
+
+    match suggestion {
+        Some(suggestion) => {
+            let file_id = suggestion.file_id;
+            apply_metadata_candidate(main_db, file_id, &suggestion.into()).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Reject a pending suggestion.
+///
+/// # Returns
+/// * `Result<bool>` - `false` if the suggestion no longer exists.
+pub async fn reject_metadata_suggestion(
+    main_db: &DatabaseConnection,
+    suggestion_id: i32,
+) -> Result<bool> {
+    let result = media_metadata_suggestions::Entity::delete_by_id(suggestion_id)
+        .exec(main_db)
+        .await?;
+
+    Ok(result.rows_affected > 0)
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use sea_orm::entity::prelude::*;
+use sea_orm::{Condition, DatabaseConnection};
+use tokio_util::sync::CancellationToken;
+
+use analysis::utils::computing_device::ComputingDevice;
+use metadata::describe::{describe_file, FileDescription};
+use metadata::watcher::LibraryChanges;
+
+use crate::actions::analysis::{analysis_media_files, get_analysis_settings, AnalysisOptions};
+use crate::actions::collection::CollectionQueryType;
+use crate::actions::cover_art::{
+    ensure_magic_cover_art_id, extract_cover_art_by_file_id, insert_extract_result,
+    remove_cover_art_by_file_id,
+};
+use crate::actions::file::get_files_by_descriptions;
+use crate::actions::index::index_media_files;
+use crate::actions::metadata::{
+    empty_progress_callback, get_metadata_summary_by_file_ids, remove_media_file,
+    scan_audio_library, sync_file_descriptions,
+};
+use crate::actions::recommendation::sync_recommendation;
+use crate::actions::search::add_term;
+use crate::connection::RecommendationDbConnection;
+use crate::entities::media_files;
+
+/// How the files reported by the library watcher are processed.
+#[derive(Debug, Clone, Copy)]
+pub struct WatchOptions {
+    /// The number of files analyzed at the same time.
+    pub batch_size: usize,
+}
+
+/// The files of the library touched by a batch of changes.
+#[derive(Debug, Clone, Default)]
+pub struct LibraryUpdate {
+    /// Files that were added, or whose content changed.
+    pub changed_ids: Vec<i32>,
+    /// Files that were moved or renamed, and kept their content.
+    pub moved_ids: Vec<i32>,
+    /// Files that no longer exist.
+    pub removed_ids: Vec<i32>,
+    /// Whether the whole library was scanned again, because the watcher lost
+    /// events.
+    pub rescanned: bool,
+}
+
+impl LibraryUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.changed_ids.is_empty()
+            && self.moved_ids.is_empty()
+            && self.removed_ids.is_empty()
+            && !self.rescanned
+    }
+}
+
+/// Brings the database up to date with a batch of changes reported by
+/// `metadata::watcher::LibraryWatcher`: moved files keep their records,
+/// removed files are dropped, and changed files are described, indexed,
+/// searched, given their cover art and analyzed, like a scan followed by an
+/// analysis would do. Changed files are analyzed with the recorded settings
+/// of the library, and the recommendation index follows once it was built.
+pub async fn sync_library_changes(
+    main_db: &DatabaseConnection,
+    recommend_db: &RecommendationDbConnection,
+    lib_path: &Path,
+    changes: &LibraryChanges,
+    options: WatchOptions,
+    cancel_token: Option<CancellationToken>,
+) -> Result<LibraryUpdate> {
+    let mut update = LibraryUpdate::default();
+
+    if changes.rescan {
+        info!("Library watcher lost events, scanning the library again");
+        scan_audio_library(
+            main_db,
+            lib_path,
+            true,
+            false,
+            empty_progress_callback,
+            cancel_token.clone(),
+        )
+        .await?;
+        update.rescanned = true;
+    }
+
+    let mut descriptions: Vec<Option<FileDescription>> = changes
+        .changed
+        .iter()
+        .map(|x| describe_file(x, &Some(lib_path.to_path_buf())).ok())
+        .collect();
+
+    // Files keeping their content were only touched, and keep their analysis
+    let previous_contents: HashMap<i32, FileContent> =
+        get_files_by_descriptions(main_db, &descriptions)
+            .await?
+            .iter()
+            .map(|x| (x.id, file_content(x)))
+            .collect();
+
+    // Moved files are synced before removed paths are dropped, so that the
+    // previous path of a moved file doesn't take its record along
+    let moved_files = sync_file_descriptions(main_db, &mut descriptions, false)
+        .await
+        .with_context(|| "Unable to describe changed files")?;
+    update.moved_ids = moved_files.iter().map(|x| x.id).collect();
+
+    for path in &changes.removed {
+        let Ok(relative_path) = path.strip_prefix(lib_path) else {
+            warn!("Removed path is outside of the library: {}", path.display());
+            continue;
+        };
+
+        for file in get_files_under_path(main_db, relative_path).await? {
+            // The path may have been removed and created again since
+            if lib_path
+                .join(&file.directory)
+                .join(&file.file_name)
+                .exists()
+            {
+                continue;
+            }
+
+            remove_media_file(main_db, lib_path, &file).await?;
+            update.removed_ids.push(file.id);
+        }
+    }
+
+    let files = get_files_by_descriptions(main_db, &descriptions).await?;
+    update.changed_ids = files
+        .iter()
+        .filter(|x| !update.moved_ids.contains(&x.id))
+        .filter(|x| previous_contents.get(&x.id) != Some(&file_content(x)))
+        .map(|x| x.id)
+        .collect();
+
+    // Splitting a file by a CUE sheet replaces its records
+    update.removed_ids.extend(
+        previous_contents
+            .keys()
+            .filter(|id| files.iter().all(|x| x.id != **id)),
+    );
+
+    let magic_cover_art_id = ensure_magic_cover_art_id(main_db).await?;
+    for file in &files {
+        if update.changed_ids.contains(&file.id) {
+            // The cover art of the previous content may not apply anymore
+            remove_cover_art_by_file_id(main_db, file.id).await?;
+        } else if file.cover_art_id.is_some() {
+            continue;
+        }
+
+        let cover_art = extract_cover_art_by_file_id(file, lib_path);
+        insert_extract_result(main_db, file, magic_cover_art_id, cover_art).await?;
+    }
+
+    if update.changed_ids.is_empty() && update.removed_ids.is_empty() {
+        return Ok(update);
+    }
+
+    let settings = get_analysis_settings(main_db).await?;
+
+    if !update.changed_ids.is_empty() {
+        index_media_files(main_db, update.changed_ids.clone(), cancel_token.as_ref())
+            .await
+            .with_context(|| "Unable to index changed files")?;
+
+        for summary in get_metadata_summary_by_file_ids(main_db, update.changed_ids.clone()).await?
+        {
+            let name = if summary.title.is_empty() {
+                &summary.file_name
+            } else {
+                &summary.title
+            };
+            add_term(main_db, CollectionQueryType::Track, summary.id, name).await?;
+        }
+
+        // Libraries that were never analyzed get the default settings
+        let (computing_device, analysis) = match &settings {
+            Some(x) => (
+                x.computing_device,
+                AnalysisOptions {
+                    segment_duration: x.segment_duration,
+                    extractors: x.extractors,
+                    retry_failed: false,
+                },
+            ),
+            None => (ComputingDevice::Cpu, AnalysisOptions::default()),
+        };
+
+        analysis_media_files(
+            main_db,
+            lib_path,
+            update.changed_ids.clone(),
+            options.batch_size,
+            computing_device,
+            analysis,
+            cancel_token,
+        )
+        .await
+        .with_context(|| "Unable to analyze changed files")?;
+    }
+
+    if let Some(settings) = settings {
+        sync_recommendation(
+            main_db,
+            recommend_db,
+            settings.extractors,
+            &settings.scaling,
+        )
+        .await
+        .with_context(|| "Unable to sync the recommendation index")?;
+    }
+
+    Ok(update)
+}
+
+/// What a record plays: the content of its file and, for the tracks of a
+/// CUE sheet, the range of the file and the sheet they were last read from.
+type FileContent = (String, Option<Decimal>, Option<Decimal>, Option<String>);
+
+fn file_content(file: &media_files::Model) -> FileContent {
+    (
+        file.file_hash.clone(),
+        file.start_time,
+        file.end_time,
+        file.cue_track.map(|_| file.last_modified.clone()),
+    )
+}
+
+/// The files at a relative path of the library, or in the directory at that
+/// path and its subdirectories.
+async fn get_files_under_path(
+    main_db: &DatabaseConnection,
+    relative_path: &Path,
+) -> Result<Vec<media_files::Model>> {
+    let Some(path) = relative_path.to_str().map(|x| x.replace('\\', "/")) else {
+        return Ok(vec![]);
+    };
+    let (directory, file_name) = match path.rsplit_once('/') {
+        Some((directory, file_name)) => (directory.to_string(), file_name.to_string()),
+        None => (String::new(), path.clone()),
+    };
+
+    Ok(media_files::Entity::find()
+        .filter(
+            Condition::any()
+                .add(
+                    media_files::Column::Directory
+                        .eq(directory)
+                        .and(media_files::Column::FileName.eq(file_name)),
+                )
+                .add(media_files::Column::Directory.eq(path.clone()))
+                .add(media_files::Column::Directory.starts_with(format!("{}/", path))),
+        )
+        .all(main_db)
+        .await?)
+}
@@ -69,6 +69,20 @@ pub struct Model {
     pub mfcc10: Option<Decimal>,
     pub mfcc11: Option<Decimal>,
     pub mfcc12: Option<Decimal>,
+    pub integrated_loudness: Option<Decimal>,
+    pub loudness_range: Option<Decimal>,
+    pub true_peak: Option<Decimal>,
+    pub bpm: Option<Decimal>,
+    pub bpm_confidence: Option<Decimal>,
+    pub beat_offset: Option<Decimal>,
+    /// Code of the estimated key, see `analysis::utils::key::Key::code`
+    pub musical_key: Option<i32>,
+    pub key_confidence: Option<Decimal>,
+    /// Parameters of the analysis, see `analysis::analysis::AnalysisParameter`
+    pub analyzer_version: Option<i32>,
+    pub window_size: Option<i32>,
+    pub overlap_size: Option<i32>,
+    pub computing_device: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
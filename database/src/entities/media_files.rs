@@ -20,12 +20,20 @@ pub struct Model {
     pub cover_art_id: Option<i32>,
     pub sample_rate: i32,
     pub duration: Decimal,
+    pub file_size: Option<i64>,
+    pub cue_track: Option<i32>,
+    pub start_time: Option<Decimal>,
+    pub end_time: Option<Decimal>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(has_many = "super::media_analysis::Entity")]
     MediaAnalysis,
+    #[sea_orm(has_one = "super::media_analysis_failures::Entity")]
+    MediaAnalysisFailures,
+    #[sea_orm(has_many = "super::media_analysis_features::Entity")]
+    MediaAnalysisFeatures,
     #[sea_orm(
         belongs_to = "super::media_cover_art::Entity",
         from = "Column::CoverArtId",
@@ -44,6 +52,8 @@ pub enum Relation {
     MediaFileStats,
     #[sea_orm(has_many = "super::media_metadata::Entity")]
     MediaMetadata,
+    #[sea_orm(has_many = "super::media_metadata_suggestions::Entity")]
+    MediaMetadataSuggestions,
 }
 
 impl Related<super::media_analysis::Entity> for Entity {
@@ -52,6 +62,18 @@ impl Related<super::media_analysis::Entity> for Entity {
     }
 }
 
+impl Related<super::media_analysis_failures::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MediaAnalysisFailures.def()
+    }
+}
+
+impl Related<super::media_analysis_features::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MediaAnalysisFeatures.def()
+    }
+}
+
 impl Related<super::media_cover_art::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::MediaCoverArt.def()
@@ -88,4 +110,10 @@ impl Related<super::media_metadata::Entity> for Entity {
     }
 }
 
+impl Related<super::media_metadata_suggestions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MediaMetadataSuggestions.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
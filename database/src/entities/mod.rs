@@ -3,9 +3,12 @@
 pub mod prelude;
 
 pub mod albums;
+pub mod analysis_settings;
 pub mod artists;
 pub mod log;
 pub mod media_analysis;
+pub mod media_analysis_failures;
+pub mod media_analysis_features;
 pub mod media_cover_art;
 pub mod media_file_albums;
 pub mod media_file_artists;
@@ -13,8 +16,11 @@ pub mod media_file_playlists;
 pub mod media_file_stats;
 pub mod media_files;
 pub mod media_metadata;
+pub mod media_metadata_suggestions;
 pub mod mix_queries;
 pub mod mixes;
 pub mod playback_queue;
 pub mod playlists;
+pub mod recommendation_layout;
+pub mod recommendation_scaling;
 pub mod search_index;
@@ -1,9 +1,12 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.0
 
 pub use super::albums::Entity as Albums;
+pub use super::analysis_settings::Entity as AnalysisSettings;
 pub use super::artists::Entity as Artists;
 pub use super::log::Entity as Log;
 pub use super::media_analysis::Entity as MediaAnalysis;
+pub use super::media_analysis_failures::Entity as MediaAnalysisFailures;
+pub use super::media_analysis_features::Entity as MediaAnalysisFeatures;
 pub use super::media_cover_art::Entity as MediaCoverArt;
 pub use super::media_file_albums::Entity as MediaFileAlbums;
 pub use super::media_file_artists::Entity as MediaFileArtists;
@@ -11,8 +14,11 @@ pub use super::media_file_playlists::Entity as MediaFilePlaylists;
 pub use super::media_file_stats::Entity as MediaFileStats;
 pub use super::media_files::Entity as MediaFiles;
 pub use super::media_metadata::Entity as MediaMetadata;
+pub use super::media_metadata_suggestions::Entity as MediaMetadataSuggestions;
 pub use super::mix_queries::Entity as MixQueries;
 pub use super::mixes::Entity as Mixes;
 pub use super::playback_queue::Entity as PlaybackQueue;
 pub use super::playlists::Entity as Playlists;
+pub use super::recommendation_layout::Entity as RecommendationLayout;
+pub use super::recommendation_scaling::Entity as RecommendationScaling;
 pub use super::search_index::Entity as SearchIndex;
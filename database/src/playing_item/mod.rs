@@ -6,14 +6,18 @@ use std::{
     collections::HashMap,
     fmt,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use anyhow::Result;
 use async_trait::async_trait;
+use rust_decimal::prelude::ToPrimitive;
+use sea_orm::prelude::Decimal;
 use sea_orm::DatabaseConnection;
 
 use metadata::describe::FileDescription;
 use playback::player::PlayingItem;
+use playback::range::TrackRange;
 
 use crate::{actions::metadata::MetadataSummary, entities::media_files};
 
@@ -24,16 +28,25 @@ pub struct MediaFileHandle {
     pub directory: String,
     pub extension: String,
     pub last_modified: String,
+    /// The part of the file to play, for the tracks of a CUE sheet.
+    pub range: Option<TrackRange>,
 }
 
 impl From<media_files::Model> for MediaFileHandle {
     fn from(x: media_files::Model) -> Self {
+        let seconds = |x: Decimal| Duration::from_secs_f64(x.to_f64().unwrap_or_default().max(0.0));
+        let range = x.start_time.map(|start| TrackRange {
+            start: seconds(start),
+            end: x.end_time.map(seconds),
+        });
+
         MediaFileHandle {
             item: PlayingItem::InLibrary(x.id),
             file_name: x.file_name,
             directory: x.directory,
             extension: x.extension,
             last_modified: x.last_modified,
+            range,
         }
     }
 }
@@ -46,6 +59,7 @@ impl From<FileDescription> for MediaFileHandle {
             directory: x.directory,
             extension: x.extension,
             last_modified: x.last_modified,
+            range: None,
         }
     }
 }
@@ -0,0 +1,228 @@
+use std::path::Path;
+
+use analysis::utils::onset::{detect_onsets, Onset};
+use anyhow::Result;
+
+use crate::types::LyricFile;
+
+const COARSE_OFFSET_STEP: f64 = 50.0;
+const FINE_OFFSET_STEP: f64 = 5.0;
+const COARSE_DRIFT_STEP: f64 = 0.001;
+const FINE_DRIFT_STEP: f64 = 0.0001;
+
+#[derive(Debug, Clone)]
+pub struct AlignmentOptions {
+    // Largest global offset to consider, in milliseconds
+    pub max_offset: f64,
+    // Largest relative drift to consider, eg. 0.02 for 2%
+    pub max_drift: f64,
+    // How close, in milliseconds, a line has to start to an onset to match it
+    pub tolerance: f64,
+}
+
+impl Default for AlignmentOptions {
+    fn default() -> Self {
+        AlignmentOptions {
+            max_offset: 10_000.0,
+            max_drift: 0.02,
+            tolerance: 60.0,
+        }
+    }
+}
+
+/// Timestamps of the corrected lyrics are `time * (1 + drift) + offset`.
+#[derive(Debug, Clone)]
+pub struct AlignmentResult {
+    pub lyric: LyricFile,
+    // Milliseconds
+    pub offset: f64,
+    pub drift: f64,
+    // Between 0 and 1
+    pub confidence: f64,
+}
+
+fn steps(max: f64, step: f64) -> impl Iterator<Item = f64> {
+    let count = (max / step).round() as i64;
+    (-count..=count).map(move |i| i as f64 * step)
+}
+
+/// How well the lines land on onsets: each line scores the strength of the
+/// closest onset, weighted by a Gaussian of the distance to it.
+fn score(lines: &[f64], onsets: &[(f64, f64)], sigma: f64, offset: f64, drift: f64) -> f64 {
+    lines
+        .iter()
+        .map(|time| {
+            let target = time * (1.0 + drift) + offset;
+            let from = onsets.partition_point(|(onset, _)| *onset < target - 3.0 * sigma);
+
+            onsets[from..]
+                .iter()
+                .take_while(|(onset, _)| *onset <= target + 3.0 * sigma)
+                .map(|(onset, strength)| {
+                    strength * (-(onset - target).powi(2) / (2.0 * sigma * sigma)).exp()
+                })
+                .fold(0.0, f64::max)
+        })
+        .sum()
+}
+
+/// Estimate the global offset and linear drift that best fit the lines of a
+/// lyric file to the given onsets.
+///
+/// The confidence compares the best fit with the average fit over every
+/// candidate, so a track full of onsets matching any timing scores low.
+pub fn align_to_onsets(
+    lyric: &LyricFile,
+    onsets: &[Onset],
+    options: &AlignmentOptions,
+) -> AlignmentResult {
+    // Empty lines usually mark instrumental breaks rather than vocals
+    let mut lines: Vec<f64> = lyric
+        .lyrics
+        .iter()
+        .filter(|x| !x.text.trim().is_empty())
+        .map(|x| i32::from(x.start_time.clone()) as f64)
+        .collect();
+    lines.dedup();
+
+    let mut onsets: Vec<(f64, f64)> = onsets
+        .iter()
+        .map(|x| (x.time * 1000.0, x.strength as f64))
+        .collect();
+    onsets.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    if lines.is_empty() || onsets.is_empty() {
+        return AlignmentResult {
+            lyric: lyric.clone(),
+            offset: 0.0,
+            drift: 0.0,
+            confidence: 0.0,
+        };
+    }
+
+    let sigma = options.tolerance / 2.0;
+
+    let mut best = (0.0, 0.0, f64::MIN);
+    let mut total = 0.0;
+    let mut count = 0;
+
+    for drift in steps(options.max_drift, COARSE_DRIFT_STEP) {
+        for offset in steps(options.max_offset, COARSE_OFFSET_STEP) {
+            let score = score(&lines, &onsets, sigma, offset, drift);
+            total += score;
+            count += 1;
+
+            if score > best.2 {
+                best = (offset, drift, score);
+            }
+        }
+    }
+
+    let mean = total / count as f64;
+
+    let (coarse_offset, coarse_drift, _) = best;
+    for drift in steps(COARSE_DRIFT_STEP, FINE_DRIFT_STEP) {
+        for offset in steps(COARSE_OFFSET_STEP, FINE_OFFSET_STEP) {
+            let offset = coarse_offset + offset;
+            let drift = coarse_drift + drift;
+            let score = score(&lines, &onsets, sigma, offset, drift);
+
+            if score > best.2 {
+                best = (offset, drift, score);
+            }
+        }
+    }
+
+    let (offset, drift, best_score) = best;
+
+    // Every line sitting on a full strength onset
+    let max_score = lines.len() as f64;
+    let confidence = if max_score > mean {
+        ((best_score - mean) / (max_score - mean)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    let mut corrected = lyric.clone();
+    corrected.map_times(|time| (time as f64 * (1.0 + drift) + offset).round().max(0.0) as u32);
+
+    AlignmentResult {
+        lyric: corrected,
+        offset,
+        drift,
+        confidence,
+    }
+}
+
+/// Decode a track and align a lyric file against its vocal onsets.
+pub fn align_lyrics(
+    lyric: &LyricFile,
+    file_path: &Path,
+    options: &AlignmentOptions,
+) -> Result<AlignmentResult> {
+    let onsets = detect_onsets(&file_path.to_string_lossy(), None)?.unwrap_or_default();
+
+    Ok(align_to_onsets(lyric, &onsets, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lrc::parse_lrc;
+
+    /// Full strength onsets, which is what the detector reports for clicks.
+    fn onsets_at(times: &[f64]) -> Vec<Onset> {
+        times
+            .iter()
+            .map(|&time| Onset {
+                time,
+                strength: 1.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_align_offset_and_drift() -> Result<()> {
+        let lyric = parse_lrc(
+            "[00:02.000]One\n[00:05.300]Two\n[00:09.100]Three\n[00:14.000]Four\n[00:18.700]Five\n[00:23.200]Six\n[00:24.000]",
+        )?;
+
+        // The track runs 1% slower and starts 400ms later than the lyrics
+        let clicks: Vec<f64> = [2.0, 5.3, 9.1, 14.0, 18.7, 23.2]
+            .iter()
+            .map(|x| x * 1.01 + 0.4)
+            .collect();
+        let onsets = onsets_at(&clicks);
+
+        let result = align_to_onsets(&lyric, &onsets, &AlignmentOptions::default());
+
+        assert!((result.offset - 400.0).abs() < 30.0, "{:?}", result);
+        assert!((result.drift - 0.01).abs() < 0.002, "{:?}", result);
+        assert!(result.confidence > 0.8, "{:?}", result);
+
+        for (line, click) in result.lyric.lyrics.iter().zip(clicks.iter()) {
+            let start = i32::from(line.start_time.clone()) as f64;
+            assert!(
+                (start - click * 1000.0).abs() < 40.0,
+                "{} vs {}",
+                start,
+                click
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_align_without_onsets() -> Result<()> {
+        let lyric = parse_lrc("[00:02.000]One\n[00:05.300]Two")?;
+        let onsets = onsets_at(&[]);
+
+        let result = align_to_onsets(&lyric, &onsets, &AlignmentOptions::default());
+
+        assert_eq!(result.confidence, 0.0);
+        assert_eq!(result.lyric.lyrics, lyric.lyrics);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+use std::{fs::File, path::Path};
+
+use anyhow::Result;
+use lofty::{
+    config::ParseOptions,
+    file::{AudioFile, FileType, TaggedFileExt},
+    id3::v2::{Frame, FrameFlags, SynchronizedTextFrame, TimestampFormat},
+    mpeg::{Layer, MpegFile, MpegVersion},
+    tag::ItemKey,
+};
+use log::warn;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::{
+    lrc::parse_lrc,
+    types::{LyricFile, LyricLine, LyricSource, TimeTag, VoiceType},
+};
+
+static LRC_LINE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\s*\[\d+:\d+").unwrap());
+
+/// Samples per MPEG audio frame, used to convert SYLT frames with MPEG frame
+/// timestamps. Layer III frames of MPEG-2 and MPEG-2.5 are half as long as
+/// the MPEG-1 ones.
+fn mpeg_frame_samples(version: MpegVersion, layer: Layer) -> u64 {
+    match layer {
+        Layer::Layer1 => 384,
+        Layer::Layer3 if version != MpegVersion::V1 => 576,
+        _ => 1152,
+    }
+}
+
+fn end_of_track() -> TimeTag {
+    TimeTag {
+        minutes: 9999,
+        seconds: 0,
+        milliseconds: 0,
+    }
+}
+
+pub fn parse_embedded_lyrics(path: &Path) -> Option<(LyricSource, Result<LyricFile>)> {
+    let tagged_file = match lofty::read_from_path(path) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("Unable to read tags for lyrics: {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    // Synchronised lyrics only exist in ID3v2
+    if tagged_file.file_type() == FileType::Mpeg {
+        if let Some(lyric) = read_synchronized_lyrics(path) {
+            return Some((LyricSource::EmbeddedSynchronized, lyric));
+        }
+    }
+
+    let text = tagged_file
+        .tags()
+        .iter()
+        .find_map(|tag| tag.get_string(&ItemKey::Lyrics))
+        .filter(|x| !x.trim().is_empty())?;
+
+    Some((LyricSource::Embedded, parse_unsynchronized_text(text)))
+}
+
+fn read_synchronized_lyrics(path: &Path) -> Option<Result<LyricFile>> {
+    let mut file = File::open(path).ok()?;
+    let mpeg_file = MpegFile::read_from(&mut file, ParseOptions::new()).ok()?;
+    let id3v2 = mpeg_file.id3v2()?;
+    let properties = mpeg_file.properties();
+    let sample_rate = properties.sample_rate();
+    let frame_samples = mpeg_frame_samples(properties.version(), properties.layer());
+
+    for frame in id3v2 {
+        let data = match frame {
+            Frame::Binary(binary) if frame.id_str() == "SYLT" => &binary.data,
+            _ => continue,
+        };
+
+        let sylt = match SynchronizedTextFrame::parse(data, FrameFlags::default()) {
+            Ok(x) => x,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        let content: Vec<(u32, String)> = match sylt.timestamp_format {
+            TimestampFormat::MS => sylt.content,
+            TimestampFormat::MPEG => {
+                if sample_rate == 0 {
+                    continue;
+                }
+
+                sylt.content
+                    .into_iter()
+                    .map(|(frames, text)| {
+                        let ms = frames as u64 * frame_samples * 1000 / sample_rate as u64;
+                        (ms as u32, text)
+                    })
+                    .collect()
+            }
+        };
+
+        if !content.is_empty() {
+            return Some(Ok(parse_synchronized_text(&content)));
+        }
+    }
+
+    None
+}
+
+/// Unsynchronised lyrics frequently carry a whole LRC document, in which case
+/// it is parsed as such. Otherwise every line is kept without timing.
+pub fn parse_unsynchronized_text(text: &str) -> Result<LyricFile> {
+    if LRC_LINE_RE.is_match(text) {
+        return parse_lrc(text);
+    }
+
+    let mut lyric = LyricFile::new();
+    let zero = TimeTag::from_milliseconds(0);
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        lyric.lyrics.push(LyricLine {
+            start_time: zero.clone(),
+            end_time: zero.clone(),
+            voice_type: VoiceType::Default,
+            text: line.to_string(),
+            word_time_tags: vec![(zero.clone(), zero.clone(), line.to_string())],
+        });
+    }
+
+    Ok(lyric)
+}
+
+/// Build lines from SYLT entries. Taggers either store one entry per line, or
+/// one entry per syllable with a leading line break marking a new line.
+pub fn parse_synchronized_text(content: &[(u32, String)]) -> LyricFile {
+    let mut lyric = LyricFile::new();
+
+    let per_syllable = content
+        .iter()
+        .skip(1)
+        .any(|(_, text)| text.starts_with(['\n', '\r']));
+
+    let mut lines: Vec<Vec<(TimeTag, String)>> = Vec::new();
+    for (index, (time, text)) in content.iter().enumerate() {
+        let starts_line = index == 0 || !per_syllable || text.starts_with(['\n', '\r']);
+        let text = text.trim_start_matches(['\n', '\r']);
+
+        if starts_line {
+            lines.push(Vec::new());
+        }
+        if let Some(line) = lines.last_mut() {
+            line.push((TimeTag::from_milliseconds(*time), text.to_string()));
+        }
+    }
+
+    for (index, words) in lines.iter().enumerate() {
+        let start_time = words[0].0.clone();
+        let end_time = lines
+            .get(index + 1)
+            .map(|next| next[0].0.clone())
+            .unwrap_or_else(end_of_track);
+
+        let word_time_tags = words
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, word))| !word.trim().is_empty())
+            .map(|(word_index, (time, word))| {
+                let word_end = words
+                    .get(word_index + 1)
+                    .map(|next| next.0.clone())
+                    .unwrap_or_else(|| end_time.clone());
+                (time.clone(), word_end, word.clone())
+            })
+            .collect();
+
+        let text: String = words.iter().map(|(_, word)| word.as_str()).collect();
+
+        lyric.lyrics.push(LyricLine {
+            start_time,
+            end_time,
+            voice_type: VoiceType::Default,
+            text: text.trim().to_string(),
+            word_time_tags,
+        });
+    }
+
+    lyric
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synchronized_lines() {
+        let content = vec![
+            (1000, "First line".to_string()),
+            (3500, "Second line".to_string()),
+        ];
+
+        let lyric = parse_synchronized_text(&content);
+
+        assert_eq!(lyric.lyrics.len(), 2);
+        assert_eq!(lyric.lyrics[0].text, "First line");
+        assert_eq!(lyric.lyrics[0].start_time.to_string(), "[00:01.000]");
+        assert_eq!(lyric.lyrics[0].end_time.to_string(), "[00:03.500]");
+        assert_eq!(lyric.lyrics[1].end_time, end_of_track());
+    }
+
+    #[test]
+    fn test_synchronized_syllables() {
+        let content = vec![
+            (1000, "Word ".to_string()),
+            (1500, "by ".to_string()),
+            (2000, "word".to_string()),
+            (4000, "\nNext".to_string()),
+        ];
+
+        let lyric = parse_synchronized_text(&content);
+
+        assert_eq!(lyric.lyrics.len(), 2);
+
+        let first = &lyric.lyrics[0];
+        assert_eq!(first.text, "Word by word");
+        assert_eq!(first.word_time_tags.len(), 3);
+        assert_eq!(first.word_time_tags[1].0.to_string(), "[00:01.500]");
+        assert_eq!(first.word_time_tags[2].1.to_string(), "[00:04.000]");
+
+        assert_eq!(lyric.lyrics[1].text, "Next");
+    }
+
+    #[test]
+    fn test_mpeg_frame_samples() {
+        assert_eq!(mpeg_frame_samples(MpegVersion::V1, Layer::Layer3), 1152);
+        assert_eq!(mpeg_frame_samples(MpegVersion::V2, Layer::Layer3), 576);
+        assert_eq!(mpeg_frame_samples(MpegVersion::V2_5, Layer::Layer3), 576);
+        assert_eq!(mpeg_frame_samples(MpegVersion::V2, Layer::Layer2), 1152);
+        assert_eq!(mpeg_frame_samples(MpegVersion::V1, Layer::Layer1), 384);
+    }
+
+    #[test]
+    fn test_unsynchronized_text() -> Result<()> {
+        let lyric = parse_unsynchronized_text("First line\r\n\r\nSecond line\n")?;
+
+        assert_eq!(lyric.lyrics.len(), 2);
+        assert_eq!(lyric.lyrics[1].text, "Second line");
+        assert_eq!(i32::from(lyric.lyrics[1].start_time.clone()), 0);
+
+        let lyric = parse_unsynchronized_text("[ar:Someone]\n[00:12.00]Timed line")?;
+
+        assert_eq!(lyric.lyrics.len(), 1);
+        assert_eq!(lyric.lyrics[0].start_time.to_string(), "[00:12.000]");
+
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+pub mod align;
+pub mod embedded;
 pub mod lrc;
 pub mod parser;
 pub mod srt;
@@ -2,10 +2,15 @@ use std::str::FromStr;
 
 use anyhow::Result;
 
-use crate::types::{LyricFile, LyricLine, TimeTag, VoiceType};
+use crate::{
+    types::{LyricFile, LyricLine, LyricTrack, LyricTrackKind, TimeTag, VoiceType},
+    utils::{has_word_timing, plain_text, voice_marker},
+};
 
 pub fn parse_lrc(content: &str) -> Result<LyricFile> {
     let mut lrc = LyricFile::new();
+    // How many lines in a row have shared the current timestamp
+    let mut duplicates = 0;
 
     for line in content.lines() {
         let line = line.trim();
@@ -36,15 +41,6 @@ pub fn parse_lrc(content: &str) -> Result<LyricFile> {
                 let time_str = &line[first_bracket..=first_bracket + close_bracket];
                 let start_time = TimeTag::from_str(time_str)?;
 
-                // Update previous line's end_time
-                if let Some(last_line) = lrc.lyrics.last_mut() {
-                    last_line.end_time = start_time.clone();
-
-                    if let Some(last_detail) = last_line.word_time_tags.last_mut() {
-                        last_detail.1 = start_time.clone();
-                    }
-                }
-
                 let remaining_content = &line[first_bracket + close_bracket + 1..];
 
                 // Extract lyric text and voice type
@@ -76,7 +72,7 @@ pub fn parse_lrc(content: &str) -> Result<LyricFile> {
                     )]
                 };
 
-                lrc.lyrics.push(LyricLine {
+                let lyric_line = LyricLine {
                     start_time: start_time.clone(),
                     end_time: TimeTag {
                         minutes: 9999,
@@ -86,14 +82,67 @@ pub fn parse_lrc(content: &str) -> Result<LyricFile> {
                     voice_type,
                     text,
                     word_time_tags,
-                });
+                };
+
+                // Lines repeating the timestamp of the previous line translate it
+                if lrc.lyrics.last().map(|x| &x.start_time) == Some(&start_time) {
+                    duplicates += 1;
+
+                    while lrc.tracks.len() < duplicates {
+                        lrc.tracks
+                            .push(LyricTrack::new(LyricTrackKind::Translation, None));
+                    }
+                    lrc.tracks[duplicates - 1].lyrics.push(lyric_line);
+                    continue;
+                }
+                duplicates = 0;
+
+                // Update previous line's end_time
+                if let Some(last_line) = lrc.lyrics.last_mut() {
+                    last_line.end_time = start_time.clone();
+
+                    if let Some(last_detail) = last_line.word_time_tags.last_mut() {
+                        last_detail.1 = start_time.clone();
+                    }
+                }
+
+                lrc.lyrics.push(lyric_line);
             }
         }
     }
 
+    close_track_lines(&mut lrc);
+
+    // A positive offset makes the lyrics appear sooner
+    let offset = lrc
+        .metadata
+        .get("offset")
+        .and_then(|x| x.trim().parse::<i64>().ok());
+    if let Some(offset) = offset {
+        lrc.map_times(|time| (time as i64 - offset).max(0) as u32);
+        lrc.metadata.remove("offset");
+    }
+
     Ok(lrc)
 }
 
+/// Translated lines end together with the line they translate.
+fn close_track_lines(lrc: &mut LyricFile) {
+    for track in &mut lrc.tracks {
+        for line in &mut track.lyrics {
+            let original = lrc.lyrics.iter().find(|x| x.start_time == line.start_time);
+
+            if let Some(original) = original {
+                line.end_time = original.end_time.clone();
+
+                if let Some(last_detail) = line.word_time_tags.last_mut() {
+                    last_detail.1 = original.end_time.clone();
+                }
+            }
+        }
+    }
+}
+
 fn parse_enhanced_lrc(content: &str) -> Result<Vec<(TimeTag, TimeTag, String)>> {
     let mut word_time_tags: Vec<(TimeTag, TimeTag, String)> = Vec::new();
     let mut current_pos = 0;
@@ -136,6 +185,67 @@ fn parse_enhanced_lrc(content: &str) -> Result<Vec<(TimeTag, TimeTag, String)>>
     Ok(word_time_tags)
 }
 
+/// Write a lyric file as line-synced LRC. Word timing is dropped.
+pub fn write_lrc(lyric: &LyricFile) -> String {
+    write_lrc_lines(lyric, false)
+}
+
+/// Write a lyric file as enhanced LRC, keeping word timing as `<mm:ss.xx>`
+/// tags.
+pub fn write_enhanced_lrc(lyric: &LyricFile) -> String {
+    write_lrc_lines(lyric, true)
+}
+
+fn write_lrc_lines(lyric: &LyricFile, enhanced: bool) -> String {
+    let mut output = String::new();
+
+    let mut keys: Vec<&String> = lyric.metadata.keys().collect();
+    keys.sort();
+    for key in keys {
+        output.push_str(&format!("[{}:{}]\n", key, lyric.metadata[key]));
+    }
+
+    for line in &lyric.lyrics {
+        write_lrc_line(&mut output, line, enhanced);
+
+        // Translations repeat the timestamp of the original line
+        for track in &lyric.tracks {
+            let translation = track
+                .lyrics
+                .iter()
+                .find(|x| x.start_time == line.start_time);
+            if let Some(translation) = translation {
+                write_lrc_line(&mut output, translation, enhanced);
+            }
+        }
+    }
+
+    output
+}
+
+fn write_lrc_line(output: &mut String, line: &LyricLine, enhanced: bool) {
+    output.push_str(&normalize(&line.start_time).to_string());
+
+    if let Some(marker) = voice_marker(&line.voice_type) {
+        output.push_str(&format!("{}: ", marker));
+    }
+
+    if enhanced && has_word_timing(line) {
+        for (start, _, word) in &line.word_time_tags {
+            let tag = normalize(start).to_string();
+            output.push_str(&format!("<{}>{}", &tag[1..tag.len() - 1], word));
+        }
+    } else {
+        output.push_str(&plain_text(line));
+    }
+
+    output.push('\n');
+}
+
+fn normalize(time: &TimeTag) -> TimeTag {
+    TimeTag::from_milliseconds(i32::from(time.clone()) as u32)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -160,19 +270,19 @@ mod tests {
 
         // Test first line
         let first = &lrc_file.lyrics[0];
-        assert_eq!(first.start_time.to_string(), "[00:12.00]");
+        assert_eq!(first.start_time.to_string(), "[00:12.000]");
         assert_eq!(first.text, "First line");
         assert_eq!(first.voice_type, VoiceType::Default);
 
         // Test female line
         let female = &lrc_file.lyrics[1];
-        assert_eq!(female.start_time.to_string(), "[00:15.30]");
+        assert_eq!(female.start_time.to_string(), "[00:15.300]");
         assert_eq!(female.text, "Female line");
         assert_eq!(female.voice_type, VoiceType::Female);
 
         // Test male line
         let male = &lrc_file.lyrics[2];
-        assert_eq!(male.start_time.to_string(), "[00:21.10]");
+        assert_eq!(male.start_time.to_string(), "[00:21.100]");
         assert_eq!(male.text, "<00:21.10>Male <00:23.10>line");
         assert_eq!(male.voice_type, VoiceType::Male);
 
@@ -183,9 +293,95 @@ mod tests {
         assert_eq!(word_time.word_time_tags[1].2, "by ");
         assert_eq!(word_time.word_time_tags[2].2, "word");
 
-        assert_eq!(word_time.word_time_tags[0].0.to_string(), "[00:24.00]");
-        assert_eq!(word_time.word_time_tags[1].0.to_string(), "[00:24.50]");
-        assert_eq!(word_time.word_time_tags[2].0.to_string(), "[00:25.00]");
+        assert_eq!(word_time.word_time_tags[0].0.to_string(), "[00:24.000]");
+        assert_eq!(word_time.word_time_tags[1].0.to_string(), "[00:24.500]");
+        assert_eq!(word_time.word_time_tags[2].0.to_string(), "[00:25.000]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lrc_round_trip() -> Result<()> {
+        let lrc_content = r#"[ar:Chubby Checker]
+[ti:Let's Twist Again]
+[00:12.000]First line
+[00:15.300]F: Female line
+[00:21.100]M: <00:21.100>Male <00:23.100>line
+[00:24.000]D: <00:24.000>Word <00:24.500>by <00:25.000>word
+[00:28.000]<00:28.000>Alone
+"#;
+
+        let lrc_file = parse_lrc(lrc_content)?;
+
+        assert_eq!(write_enhanced_lrc(&lrc_file), lrc_content);
+
+        let reparsed = parse_lrc(&write_enhanced_lrc(&lrc_file))?;
+        assert_eq!(reparsed.metadata, lrc_file.metadata);
+        assert_eq!(reparsed.lyrics, lrc_file.lyrics);
+
+        let plain = write_lrc(&lrc_file);
+        assert!(plain.contains("[00:21.100]M: Male line\n"));
+        assert!(plain.contains("[00:24.000]D: Word by word\n"));
+
+        let reparsed = parse_lrc(&plain)?;
+        assert_eq!(reparsed.lyrics.len(), lrc_file.lyrics.len());
+        assert_eq!(reparsed.lyrics[1], lrc_file.lyrics[1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lrc_milliseconds_and_offset() -> Result<()> {
+        let lrc_content = r#"[offset:+500]
+[00:12.345]Milliseconds
+[00:15]No fraction
+[01:00:01.5]<01:00:01.5>With <01:00:02.25>hours"#;
+
+        let lrc_file = parse_lrc(lrc_content)?;
+
+        assert!(!lrc_file.metadata.contains_key("offset"));
+
+        assert_eq!(i32::from(lrc_file.lyrics[0].start_time.clone()), 11_845);
+        assert_eq!(i32::from(lrc_file.lyrics[0].end_time.clone()), 14_500);
+        assert_eq!(i32::from(lrc_file.lyrics[1].start_time.clone()), 14_500);
+        assert_eq!(i32::from(lrc_file.lyrics[2].start_time.clone()), 3_601_000);
+        assert_eq!(
+            lrc_file.lyrics[2].word_time_tags[1].0.to_string(),
+            "[60:01.750]"
+        );
+        assert_eq!(lrc_file.lyrics[2].end_time.minutes, 9999);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lrc_translation_tracks() -> Result<()> {
+        let lrc_content = r#"[00:01.000]君の名は
+[00:01.000]Your name
+[00:01.000]你的名字
+[00:04.000]二行目
+[00:04.000]Second line
+[00:08.000]未訳"#;
+
+        let lrc_file = parse_lrc(lrc_content)?;
+
+        assert_eq!(lrc_file.lyrics.len(), 3);
+        assert_eq!(i32::from(lrc_file.lyrics[0].end_time.clone()), 4000);
+
+        assert_eq!(lrc_file.tracks.len(), 2);
+        assert_eq!(lrc_file.tracks[0].kind, LyricTrackKind::Translation);
+
+        let translation = &lrc_file.tracks[0].lyrics;
+        assert_eq!(translation.len(), 2);
+        assert_eq!(translation[0].text, "Your name");
+        assert_eq!(i32::from(translation[0].end_time.clone()), 4000);
+        assert_eq!(i32::from(translation[0].word_time_tags[0].1.clone()), 4000);
+        assert_eq!(translation[1].text, "Second line");
+        assert_eq!(lrc_file.tracks[1].lyrics[0].text, "你的名字");
+
+        let reparsed = parse_lrc(&write_lrc(&lrc_file))?;
+        assert_eq!(reparsed.lyrics, lrc_file.lyrics);
+        assert_eq!(reparsed.tracks, lrc_file.tracks);
 
         Ok(())
     }
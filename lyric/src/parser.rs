@@ -6,31 +6,52 @@ use std::{
 
 use anyhow::Result;
 
-use crate::{lrc::parse_lrc, srt::parse_srt, ttml::parse_ttml, types::LyricFile, vtt::parse_vtt};
+use crate::{
+    embedded::parse_embedded_lyrics,
+    lrc::parse_lrc,
+    srt::parse_srt,
+    ttml::parse_ttml,
+    types::{LyricFile, LyricSource},
+    vtt::parse_vtt,
+};
 
 pub fn parse_audio_lyrics(path: PathBuf) -> Option<Result<LyricFile>> {
+    parse_audio_lyrics_with_source(path).map(|(_, lyric)| lyric)
+}
+
+/// Find the lyrics of a track and report where they came from. Sidecar files
+/// take precedence over lyrics embedded in the tags.
+pub fn parse_audio_lyrics_with_source(path: PathBuf) -> Option<(LyricSource, Result<LyricFile>)> {
+    if let Some(lyric) = parse_sidecar_lyrics(&path) {
+        return Some((LyricSource::Sidecar, lyric));
+    }
+
+    parse_embedded_lyrics(&path)
+}
+
+fn parse_sidecar_lyrics(path: &Path) -> Option<Result<LyricFile>> {
     // Try to find and parse the .ttml file
-    if let Some(lyric) = parse_lyrics_with_extension(&path, "ttml", parse_ttml) {
+    if let Some(lyric) = parse_lyrics_with_extension(path, "ttml", parse_ttml) {
         return Some(lyric);
     }
 
     // Try to find and parse the .lrc file
-    if let Some(lyric) = parse_lyrics_with_extension(&path, "lrc", parse_lrc) {
+    if let Some(lyric) = parse_lyrics_with_extension(path, "lrc", parse_lrc) {
         return Some(lyric);
     }
 
     // Try to find and parse the .lrc file
-    if let Some(lyric) = parse_lyrics_with_extension(&path, "lrcx", parse_lrc) {
+    if let Some(lyric) = parse_lyrics_with_extension(path, "lrcx", parse_lrc) {
         return Some(lyric);
     }
 
     // Try to find and parse the .vtt file
-    if let Some(lyric) = parse_lyrics_with_extension(&path, "vtt", parse_vtt) {
+    if let Some(lyric) = parse_lyrics_with_extension(path, "vtt", parse_vtt) {
         return Some(lyric);
     }
 
     // Try to find and parse the .srt file
-    if let Some(lyric) = parse_lyrics_with_extension(&path, "srt", parse_srt) {
+    if let Some(lyric) = parse_lyrics_with_extension(path, "srt", parse_srt) {
         return Some(lyric);
     }
 
@@ -1,9 +1,11 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
 use subtp::srt::{SrtTimestamp, SubRip};
 
 use crate::{
     types::{LyricFile, LyricLine, TimeTag, VoiceType},
-    utils::parse_word_time_tags,
+    utils::{format_timestamp, inline_word_time_tags, parse_word_time_tags, voice_marker},
 };
 
 impl From<SrtTimestamp> for TimeTag {
@@ -20,9 +22,46 @@ impl From<SrtTimestamp> for TimeTag {
     }
 }
 
+/// ID tags, kept in `# key: value` comment lines before the first subtitle.
+/// Returns them with the rest of the content.
+fn parse_metadata(content: &str) -> (HashMap<String, String>, &str) {
+    let mut metadata = HashMap::new();
+    let mut offset = 0;
+
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            if let Some((key, value)) = comment.split_once(':') {
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                metadata.insert(key.trim().to_string(), value.to_string());
+            }
+        } else if !trimmed.is_empty() {
+            break;
+        }
+        offset += line.len();
+    }
+
+    (metadata, &content[offset..])
+}
+
+/// Voices are written as a `M:`, `F:` or `D:` speaker prefix, like in LRC.
+fn parse_voice_type(payload: &str) -> (VoiceType, &str) {
+    for voice_type in [VoiceType::Male, VoiceType::Female, VoiceType::Duet] {
+        let prefix = format!("{}:", voice_marker(&voice_type).unwrap_or_default());
+        if let Some(stripped) = payload.strip_prefix(prefix.as_str()) {
+            return (voice_type, stripped.trim_start());
+        }
+    }
+
+    (VoiceType::Default, payload)
+}
+
 pub fn parse_srt(content: &str) -> Result<LyricFile> {
     let mut srt = LyricFile::new();
 
+    let (metadata, content) = parse_metadata(content);
+    srt.metadata = metadata;
+
     if content.trim().is_empty() {
         return Ok(srt);
     }
@@ -39,13 +78,14 @@ pub fn parse_srt(content: &str) -> Result<LyricFile> {
         let start_time: TimeTag = block.start.into();
         let end_time: TimeTag = block.end.into();
 
-        let (text, word_time_tags) =
-            parse_word_time_tags(&block.text.join("\n"), &start_time, &end_time);
+        let payload = block.text.join("\n");
+        let (voice_type, payload) = parse_voice_type(&payload);
+        let (text, word_time_tags) = parse_word_time_tags(payload, &start_time, &end_time);
 
         let lyric_line = LyricLine {
             start_time,
             end_time,
-            voice_type: VoiceType::Default,
+            voice_type,
             text,
             word_time_tags,
         };
@@ -56,6 +96,39 @@ pub fn parse_srt(content: &str) -> Result<LyricFile> {
     Ok(srt)
 }
 
+/// Write a lyric file as SubRip. Word timing is kept as inline
+/// `<hh:mm:ss,mmm>` tags, voices as speaker prefixes, and ID tags as
+/// `# key: value` comment lines before the first subtitle.
+pub fn write_srt(lyric: &LyricFile) -> String {
+    let mut output = String::new();
+
+    let mut keys: Vec<&String> = lyric.metadata.keys().collect();
+    keys.sort();
+    for key in &keys {
+        output.push_str(&format!("# {}: {}\n", key, lyric.metadata[*key]));
+    }
+    if !keys.is_empty() {
+        output.push('\n');
+    }
+
+    for (index, line) in lyric.lyrics.iter().enumerate() {
+        let voice = voice_marker(&line.voice_type)
+            .map(|marker| format!("{}: ", marker))
+            .unwrap_or_default();
+
+        output.push_str(&format!(
+            "{}\n{} --> {}\n{}{}\n\n",
+            index + 1,
+            format_timestamp(&line.start_time, ','),
+            format_timestamp(&line.end_time, ','),
+            voice,
+            inline_word_time_tags(line, ',')
+        ));
+    }
+
+    output
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -210,4 +283,78 @@ Text spanning over an hour"#;
             }
         );
     }
+
+    #[test]
+    fn test_srt_round_trip() {
+        let srt_content = r#"1
+00:00:01,000 --> 00:00:05,000
+Hello world <00:00:02,500>this is <00:00:03,500>a test
+
+2
+01:30:06,000 --> 01:30:10,000
+Line one
+Line two
+"#;
+
+        let result = parse_srt(srt_content).unwrap();
+        let written = write_srt(&result);
+
+        assert!(written.starts_with("1\n00:00:01,000 --> 00:00:05,000\n"));
+        assert!(written.contains("2\n01:30:06,000 --> 01:30:10,000\nLine one\nLine two\n"));
+
+        let reparsed = parse_srt(&written).unwrap();
+        assert_eq!(reparsed.lyrics, result.lyrics);
+    }
+
+    #[test]
+    fn test_srt_round_trip_voices_and_metadata() {
+        let srt_content = r#"# ar: Someone
+# ti: Title: Subtitle
+
+1
+00:00:01,000 --> 00:00:05,000
+F: Hello <00:00:02,500>world
+
+2
+00:00:06,000 --> 00:00:10,000
+D: Together
+
+3
+00:00:11,000 --> 00:00:12,000
+Nobody in particular
+"#;
+
+        let result = parse_srt(srt_content).unwrap();
+        assert_eq!(result.metadata.len(), 2);
+        assert_eq!(result.metadata["ar"], "Someone");
+        assert_eq!(result.metadata["ti"], "Title: Subtitle");
+        assert_eq!(result.lyrics[0].voice_type, VoiceType::Female);
+        assert_eq!(result.lyrics[0].word_time_tags[0].2, "Hello");
+        assert_eq!(result.lyrics[0].word_time_tags[1].2, "world");
+        assert_eq!(result.lyrics[1].voice_type, VoiceType::Duet);
+        assert_eq!(result.lyrics[2].voice_type, VoiceType::Default);
+
+        let written = write_srt(&result);
+        assert!(written.starts_with("# ar: Someone\n# ti: Title: Subtitle\n\n1\n"));
+        assert!(written.contains("\nD: Together\n"));
+
+        let reparsed = parse_srt(&written).unwrap();
+        assert_eq!(reparsed.metadata, result.metadata);
+        assert_eq!(reparsed.lyrics, result.lyrics);
+    }
+
+    #[test]
+    fn test_srt_round_trip_special_characters() {
+        let srt_content = r#"1
+00:00:01,000 --> 00:00:05,000
+a < b & <00:00:02,500>b > c
+"#;
+
+        let result = parse_srt(srt_content).unwrap();
+        assert_eq!(result.lyrics[0].word_time_tags[0].2, "a < b &");
+        assert_eq!(result.lyrics[0].word_time_tags[1].2, "b > c");
+
+        let reparsed = parse_srt(&write_srt(&result)).unwrap();
+        assert_eq!(reparsed.lyrics, result.lyrics);
+    }
 }
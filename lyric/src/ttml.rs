@@ -1,7 +1,10 @@
 use anyhow::Result;
 use xmltree::Element;
 
-use crate::types::{LyricFile, LyricLine, TimeTag, VoiceType};
+use crate::{
+    types::{parse_fraction, LyricFile, LyricLine, LyricTrackKind, TimeTag, VoiceType},
+    utils::{escape_markup, format_timestamp},
+};
 
 fn parse_timestamp(s: &str) -> TimeTag {
     let parts: Vec<&str> = s.split(':').collect();
@@ -13,11 +16,11 @@ fn parse_timestamp(s: &str) -> TimeTag {
                 .first()
                 .and_then(|s| s.parse::<u32>().ok())
                 .unwrap_or_default();
-            let centiseconds = sec_parts
+            let milliseconds = sec_parts
                 .get(1)
-                .and_then(|s| s.parse::<u32>().ok())
+                .and_then(|s| parse_fraction(s).ok())
                 .unwrap_or_default();
-            (0, seconds, centiseconds * 10)
+            (0, seconds, milliseconds)
         }
         2 => {
             let sec_parts: Vec<&str> = parts[1].split('.').collect();
@@ -29,11 +32,11 @@ fn parse_timestamp(s: &str) -> TimeTag {
                 .first()
                 .and_then(|s| s.parse::<u32>().ok())
                 .unwrap_or_default();
-            let centiseconds = sec_parts
+            let milliseconds = sec_parts
                 .get(1)
-                .and_then(|s| s.parse::<u32>().ok())
+                .and_then(|s| parse_fraction(s).ok())
                 .unwrap_or_default();
-            (minutes, seconds, centiseconds * 10)
+            (minutes, seconds, milliseconds)
         }
         3 => {
             let sec_parts: Vec<&str> = parts[2].split('.').collect();
@@ -49,11 +52,11 @@ fn parse_timestamp(s: &str) -> TimeTag {
                 .first()
                 .and_then(|s| s.parse::<u32>().ok())
                 .unwrap_or_default();
-            let centiseconds = sec_parts
+            let milliseconds = sec_parts
                 .get(1)
-                .and_then(|s| s.parse::<u32>().ok())
+                .and_then(|s| parse_fraction(s).ok())
                 .unwrap_or_default();
-            (hours * 60 + minutes, seconds, centiseconds * 10)
+            (hours * 60 + minutes, seconds, milliseconds)
         }
         _ => (0, 0, 0),
     };
@@ -65,13 +68,57 @@ fn parse_timestamp(s: &str) -> TimeTag {
     }
 }
 
+const AGENT_TYPES: [&str; 5] = ["person", "character", "group", "organization", "other"];
+
+fn voice_agent(voice_type: &VoiceType) -> Option<(&'static str, &'static str)> {
+    match voice_type {
+        VoiceType::Male => Some(("male", "person")),
+        VoiceType::Female => Some(("female", "person")),
+        VoiceType::Duet => Some(("duet", "group")),
+        VoiceType::Default => None,
+    }
+}
+
+fn parse_voice_type(agent: &str) -> VoiceType {
+    match agent {
+        "male" => VoiceType::Male,
+        "female" => VoiceType::Female,
+        "duet" => VoiceType::Duet,
+        _ => VoiceType::Default,
+    }
+}
+
+fn escape(text: &str) -> String {
+    escape_markup(text).replace('"', "&quot;")
+}
+
+/// Look up an attribute by its qualified name, falling back to the local
+/// name since the namespace prefix is not always kept.
+fn attribute<'a>(element: &'a Element, name: &str) -> Option<&'a String> {
+    element.attributes.get(name).or_else(|| {
+        name.split_once(':')
+            .and_then(|(_, local_name)| element.attributes.get(local_name))
+    })
+}
+
+fn track_kind(element: &Element) -> Option<LyricTrackKind> {
+    match attribute(element, "ttm:role").map(|x| x.as_str()) {
+        Some("x-translation") => Some(LyricTrackKind::Translation),
+        Some("x-roman") => Some(LyricTrackKind::Romanization),
+        _ => None,
+    }
+}
+
 fn extract_text(element: &Element) -> String {
     let mut text = String::new();
     for child in &element.children {
         if let Some(text_content) = child.as_text() {
             text.push_str(text_content);
         } else if let Some(child_element) = child.as_element() {
-            text.push_str(&extract_text(child_element));
+            // Translations and romanizations go to their own tracks
+            if track_kind(child_element).is_none() {
+                text.push_str(&extract_text(child_element));
+            }
         }
     }
     text
@@ -87,7 +134,7 @@ pub fn parse_ttml(content: &str) -> Result<LyricFile> {
             for child in &metadata.children {
                 if let Some(element) = child.as_element() {
                     if element.name == "agent" {
-                        if let Some(id) = element.attributes.get("xml:id") {
+                        if let Some(id) = attribute(element, "xml:id") {
                             if let Some(agent_type) = element.attributes.get("type") {
                                 lyric_file
                                     .metadata
@@ -100,12 +147,18 @@ pub fn parse_ttml(content: &str) -> Result<LyricFile> {
         }
     }
 
+    let root_language = attribute(&root, "xml:lang");
+    // Lines in another language than the document are translations
+    let mut main_language = root_language.cloned();
+
     if let Some(body) = root.get_child("body") {
         for div in body.children.iter().filter_map(|c| c.as_element()) {
             if div.name != "div" {
                 continue;
             }
 
+            let div_language = attribute(div, "xml:lang").or(root_language);
+
             for p in div.children.iter().filter_map(|c| c.as_element()) {
                 if p.name != "p" {
                     continue;
@@ -120,7 +173,14 @@ pub fn parse_ttml(content: &str) -> Result<LyricFile> {
                     None => continue,
                 };
 
-                let voice_type = VoiceType::Default;
+                let voice_type = attribute(p, "ttm:agent")
+                    .map(|agent| parse_voice_type(agent))
+                    .unwrap_or(VoiceType::Default);
+
+                let language = attribute(p, "xml:lang").or(div_language);
+                if main_language.is_none() {
+                    main_language = language.cloned();
+                }
 
                 let mut text = String::new();
                 let mut word_time_tags = Vec::new();
@@ -144,21 +204,45 @@ pub fn parse_ttml(content: &str) -> Result<LyricFile> {
                                 text.push_str(&word_text);
                                 word_time_tags.push((word_start_time, word_end_time, word_text));
                             }
-                        } else if let Some(translation) = span.attributes.get("ttm:role") {
-                            if translation == "x-translation" {
-                                text.push_str(&extract_text(span));
-                            }
+                        } else if let Some(kind) = track_kind(span) {
+                            let span_language = attribute(span, "xml:lang").map(|x| x.as_str());
+
+                            lyric_file
+                                .track_mut(kind, span_language)
+                                .lyrics
+                                .push(LyricLine {
+                                    start_time: start_time.clone(),
+                                    end_time: end_time.clone(),
+                                    voice_type: voice_type.clone(),
+                                    text: extract_text(span).trim().to_string(),
+                                    word_time_tags: vec![],
+                                });
                         }
                     }
+
+                    // Untimed spans only style the text
+                    if word_time_tags.is_empty() {
+                        text.push_str(&extract_text(p));
+                    }
                 }
 
-                lyric_file.lyrics.push(LyricLine {
+                let lyric_line = LyricLine {
                     start_time,
                     end_time,
                     voice_type,
                     text,
                     word_time_tags,
-                });
+                };
+
+                if language.is_some() && language != main_language.as_ref() {
+                    let language = language.map(|x| x.as_str());
+                    lyric_file
+                        .track_mut(LyricTrackKind::Translation, language)
+                        .lyrics
+                        .push(lyric_line);
+                } else {
+                    lyric_file.lyrics.push(lyric_line);
+                }
             }
         }
     }
@@ -166,6 +250,102 @@ pub fn parse_ttml(content: &str) -> Result<LyricFile> {
     Ok(lyric_file)
 }
 
+/// Write a lyric file as TTML. Metadata entries naming an agent type are
+/// written as `ttm:agent` declarations, voices as agents on each line, and
+/// translation or romanization tracks as role spans inside the line they
+/// accompany.
+pub fn write_ttml(lyric: &LyricFile) -> String {
+    let mut agents: Vec<(String, String)> = lyric
+        .metadata
+        .iter()
+        .filter(|(_, agent_type)| AGENT_TYPES.contains(&agent_type.as_str()))
+        .map(|(id, agent_type)| (id.clone(), agent_type.clone()))
+        .collect();
+
+    for line in &lyric.lyrics {
+        if let Some((id, agent_type)) = voice_agent(&line.voice_type) {
+            if !agents.iter().any(|(x, _)| x == id) {
+                agents.push((id.to_string(), agent_type.to_string()));
+            }
+        }
+    }
+    agents.sort();
+
+    let mut output = String::from(
+        "<tt xmlns=\"http://www.w3.org/ns/ttml\" xmlns:ttm=\"http://www.w3.org/ns/ttml#metadata\">\n",
+    );
+
+    if !agents.is_empty() {
+        output.push_str("  <head>\n    <metadata>\n");
+        for (id, agent_type) in &agents {
+            output.push_str(&format!(
+                "      <ttm:agent type=\"{}\" xml:id=\"{}\"/>\n",
+                escape(agent_type),
+                escape(id)
+            ));
+        }
+        output.push_str("    </metadata>\n  </head>\n");
+    }
+
+    output.push_str("  <body>\n    <div>\n");
+    for line in &lyric.lyrics {
+        let agent = voice_agent(&line.voice_type)
+            .map(|(id, _)| format!(" ttm:agent=\"{}\"", id))
+            .unwrap_or_default();
+
+        output.push_str(&format!(
+            "      <p begin=\"{}\" end=\"{}\"{}>",
+            format_timestamp(&line.start_time, '.'),
+            format_timestamp(&line.end_time, '.'),
+            agent
+        ));
+
+        if line.word_time_tags.is_empty() {
+            output.push_str(&escape(&line.text));
+        } else {
+            for (start, end, word) in &line.word_time_tags {
+                output.push_str(&format!(
+                    "<span begin=\"{}\" end=\"{}\">{}</span>",
+                    format_timestamp(start, '.'),
+                    format_timestamp(end, '.'),
+                    escape(word)
+                ));
+            }
+        }
+
+        for track in &lyric.tracks {
+            let track_line = track
+                .lyrics
+                .iter()
+                .find(|x| x.start_time == line.start_time);
+
+            if let Some(track_line) = track_line {
+                let role = match track.kind {
+                    LyricTrackKind::Translation => "x-translation",
+                    LyricTrackKind::Romanization => "x-roman",
+                };
+                let language = track
+                    .language
+                    .as_ref()
+                    .map(|x| format!(" xml:lang=\"{}\"", escape(x)))
+                    .unwrap_or_default();
+
+                output.push_str(&format!(
+                    "<span ttm:role=\"{}\"{}>{}</span>",
+                    role,
+                    language,
+                    escape(&track_line.text)
+                ));
+            }
+        }
+
+        output.push_str("</p>\n");
+    }
+    output.push_str("    </div>\n  </body>\n</tt>\n");
+
+    output
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -193,7 +373,7 @@ mod tests {
             end_time: TimeTag {
                 minutes: 0,
                 seconds: 5,
-                milliseconds: 2120,
+                milliseconds: 212,
             },
             voice_type: VoiceType::Default,
             text: "Hello, world!".to_string(),
@@ -332,4 +512,81 @@ mod tests {
 
         assert_eq!(result.lyrics, expected);
     }
+
+    #[test]
+    fn test_ttml_round_trip() {
+        let ttml_content = r#"
+            <tt xmlns="http://www.w3.org/ns/ttml" xmlns:ttm="http://www.w3.org/ns/ttml#metadata">
+                <head>
+                    <metadata>
+                        <ttm:agent type="person" xml:id="female"/>
+                        <ttm:agent type="group" xml:id="v2"/>
+                    </metadata>
+                </head>
+                <body>
+                    <div>
+                        <p begin="0:01.00" end="0:02.50">Rock &amp; roll</p>
+                        <p begin="0:03.00" end="1:04.00" ttm:agent="female"><span begin="0:03.00" end="0:03.50">Word </span><span begin="0:03.50" end="1:04.00">by word</span></p>
+                    </div>
+                </body>
+            </tt>
+        "#;
+
+        let result = parse_ttml(ttml_content).unwrap();
+        assert_eq!(result.lyrics[0].text, "Rock & roll");
+        assert_eq!(result.lyrics[1].voice_type, VoiceType::Female);
+        assert_eq!(result.lyrics[1].text, "Word by word");
+
+        let reparsed = parse_ttml(&write_ttml(&result)).unwrap();
+        assert_eq!(reparsed.metadata, result.metadata);
+        assert_eq!(reparsed.lyrics, result.lyrics);
+    }
+
+    #[test]
+    fn test_parse_translation_tracks() {
+        let ttml_content = r#"
+            <tt xmlns="http://www.w3.org/ns/ttml" xmlns:ttm="http://www.w3.org/ns/ttml#metadata" xml:lang="ja">
+                <body>
+                    <div>
+                        <p begin="0:01.00" end="0:02.00">
+                            <span begin="0:01.00" end="0:01.50">君の</span><span begin="0:01.50" end="0:02.00">名は</span>
+                            <span ttm:role="x-translation" xml:lang="en">Your name</span>
+                            <span ttm:role="x-roman">Kimi no na wa</span>
+                        </p>
+                        <p begin="0:03.00" end="0:04.00">二行目<span ttm:role="x-translation" xml:lang="en">Second line</span></p>
+                    </div>
+                    <div xml:lang="zh">
+                        <p begin="0:01.00" end="0:02.00">你的名字</p>
+                    </div>
+                </body>
+            </tt>
+        "#;
+
+        let result = parse_ttml(ttml_content).unwrap();
+
+        assert_eq!(result.lyrics.len(), 2);
+        assert_eq!(result.lyrics[0].text, "君の名は");
+        assert_eq!(result.lyrics[1].text, "二行目");
+        assert_eq!(result.tracks.len(), 3);
+
+        let english = &result.tracks[0];
+        assert_eq!(english.kind, LyricTrackKind::Translation);
+        assert_eq!(english.language.as_deref(), Some("en"));
+        assert_eq!(english.lyrics.len(), 2);
+        assert_eq!(english.lyrics[0].text, "Your name");
+        assert_eq!(english.lyrics[1].start_time, result.lyrics[1].start_time);
+
+        let romanization = &result.tracks[1];
+        assert_eq!(romanization.kind, LyricTrackKind::Romanization);
+        assert_eq!(romanization.lyrics[0].text, "Kimi no na wa");
+
+        let chinese = &result.tracks[2];
+        assert_eq!(chinese.kind, LyricTrackKind::Translation);
+        assert_eq!(chinese.language.as_deref(), Some("zh"));
+        assert_eq!(chinese.lyrics[0].text, "你的名字");
+
+        let reparsed = parse_ttml(&write_ttml(&result)).unwrap();
+        assert_eq!(reparsed.lyrics, result.lyrics);
+        assert_eq!(reparsed.tracks, result.tracks);
+    }
 }
@@ -9,6 +9,16 @@ pub struct TimeTag {
     pub milliseconds: u32,
 }
 
+impl TimeTag {
+    pub fn from_milliseconds(milliseconds: u32) -> Self {
+        TimeTag {
+            minutes: milliseconds / 60000,
+            seconds: (milliseconds / 1000) % 60,
+            milliseconds: milliseconds % 1000,
+        }
+    }
+}
+
 impl From<TimeTag> for i32 {
     fn from(val: TimeTag) -> Self {
         (val.minutes * 60 * 1000 + val.seconds * 1000 + val.milliseconds) as i32
@@ -19,10 +29,8 @@ impl fmt::Display for TimeTag {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "[{:02}:{:02}.{:02}]",
-            self.minutes,
-            self.seconds,
-            self.milliseconds / 10
+            "[{:02}:{:02}.{:03}]",
+            self.minutes, self.seconds, self.milliseconds
         )
     }
 }
@@ -30,34 +38,62 @@ impl fmt::Display for TimeTag {
 impl FromStr for TimeTag {
     type Err = anyhow::Error;
 
+    /// Accepts `mm:ss`, `mm:ss.x`, `mm:ss.xx`, `mm:ss.xxx` and the same forms
+    /// with a leading hour, with either `.` or `,` before the fraction.
     fn from_str(s: &str) -> Result<Self> {
         // Remove [] or <> brackets
         let s = s
             .trim_start_matches(['[', '<'])
             .trim_end_matches([']', '>']);
 
-        let parts: Vec<&str> = s.split(':').collect();
-        if parts.len() != 2 {
-            bail!("Invalid time format");
-        }
+        let (clock, fraction) = match s.split_once(['.', ',']) {
+            Some((clock, fraction)) => (clock, Some(fraction)),
+            None => (s, None),
+        };
+
+        let parts = clock
+            .split(':')
+            .map(|x| x.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let minutes = parts[0].parse::<u32>()?;
-        let second_parts: Vec<&str> = parts[1].split('.').collect();
-        if second_parts.len() != 2 {
+        let (hours, minutes, seconds) = match parts.as_slice() {
+            [minutes, seconds] => (0, *minutes, *seconds),
+            [hours, minutes, seconds] => (*hours, *minutes, *seconds),
+            _ => bail!("Invalid time format"),
+        };
+
+        if seconds >= 60 {
             bail!("Invalid seconds format");
         }
 
-        let seconds = second_parts[0].parse::<u32>()?;
-        let centiseconds = second_parts[1].parse::<u32>()?;
+        let milliseconds = match fraction {
+            Some(fraction) => parse_fraction(fraction)?,
+            None => 0,
+        };
 
         Ok(TimeTag {
-            minutes,
+            minutes: hours * 60 + minutes,
             seconds,
-            milliseconds: centiseconds * 10,
+            milliseconds,
         })
     }
 }
 
+/// Convert the digits after the decimal point into milliseconds, so `5`,
+/// `50` and `500` all read as half a second.
+pub fn parse_fraction(fraction: &str) -> Result<u32> {
+    let digits: String = fraction.trim().chars().take(3).collect();
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    if !digits.chars().all(|x| x.is_ascii_digit()) {
+        bail!("Invalid fraction format");
+    }
+
+    let value = digits.parse::<u32>()?;
+    Ok(value * 10u32.pow(3 - digits.len() as u32))
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum VoiceType {
     Male,
@@ -75,12 +111,39 @@ pub struct LyricLine {
     pub word_time_tags: Vec<(TimeTag, TimeTag, String)>, // Start and end time tags for each word
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LyricTrackKind {
+    Translation,
+    Romanization,
+}
+
+/// Lines accompanying the original lyrics, such as a translation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LyricTrack {
+    pub kind: LyricTrackKind,
+    // Language code, when the file names one
+    pub language: Option<String>,
+    pub lyrics: Vec<LyricLine>,
+}
+
+impl LyricTrack {
+    pub fn new(kind: LyricTrackKind, language: Option<String>) -> Self {
+        Self {
+            kind,
+            language,
+            lyrics: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct LyricFile {
     // ID tags
     pub metadata: HashMap<String, String>,
     // Lyrics content
     pub lyrics: Vec<LyricLine>,
+    // Translation and romanization tracks
+    pub tracks: Vec<LyricTrack>,
 }
 
 impl LyricFile {
@@ -88,6 +151,96 @@ impl LyricFile {
         Self {
             metadata: HashMap::new(),
             lyrics: Vec::new(),
+            tracks: Vec::new(),
+        }
+    }
+
+    /// Rewrite every timestamp, in milliseconds, of every track. The open end
+    /// of a last line is left alone.
+    pub fn map_times<F>(&mut self, f: F)
+    where
+        F: Fn(u32) -> u32,
+    {
+        let map = |time: &mut TimeTag| {
+            if time.minutes >= 9999 {
+                return;
+            }
+
+            *time = TimeTag::from_milliseconds(f(i32::from(time.clone()) as u32));
+        };
+
+        let tracks = self.tracks.iter_mut().map(|x| &mut x.lyrics);
+        for line in std::iter::once(&mut self.lyrics).chain(tracks).flatten() {
+            map(&mut line.start_time);
+            map(&mut line.end_time);
+
+            for (start, end, _) in &mut line.word_time_tags {
+                map(start);
+                map(end);
+            }
+        }
+    }
+
+    /// Find the track of the given kind and language, creating it if needed.
+    pub fn track_mut(&mut self, kind: LyricTrackKind, language: Option<&str>) -> &mut LyricTrack {
+        let index = self
+            .tracks
+            .iter()
+            .position(|x| x.kind == kind && x.language.as_deref() == language);
+
+        match index {
+            Some(index) => &mut self.tracks[index],
+            None => {
+                self.tracks
+                    .push(LyricTrack::new(kind, language.map(|x| x.to_string())));
+                self.tracks.last_mut().unwrap()
+            }
         }
     }
 }
+
+/// Where the lyrics of a track were found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LyricSource {
+    /// A .ttml/.lrc/.lrcx/.vtt/.srt file next to the track
+    Sidecar,
+    /// Unsynchronised lyrics in the tags (ID3 USLT, Vorbis LYRICS, MP4 ©lyr)
+    Embedded,
+    /// Synchronised lyrics in the tags (ID3 SYLT)
+    EmbeddedSynchronized,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_tag_from_str() -> Result<()> {
+        let cases = [
+            ("[01:02.3]", 62_300),
+            ("[01:02.34]", 62_340),
+            ("[01:02.345]", 62_345),
+            ("[01:02]", 62_000),
+            ("<01:02:03.456>", 3_723_456),
+            ("00:00:01,500", 1_500),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(i32::from(TimeTag::from_str(input)?), expected, "{}", input);
+        }
+
+        assert!(TimeTag::from_str("[ar:Someone]").is_err());
+        assert!(TimeTag::from_str("[01:75.00]").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_tag_display() {
+        assert_eq!(
+            TimeTag::from_milliseconds(3_723_456).to_string(),
+            "[62:03.456]"
+        );
+        assert_eq!(TimeTag::from_milliseconds(1_050).to_string(), "[00:01.050]");
+    }
+}
@@ -1,7 +1,7 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
 
-use crate::types::TimeTag;
+use crate::types::{LyricLine, TimeTag, VoiceType};
 
 static STYLE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"</?([a-zA-Z][^>]*)>").unwrap());
 pub static TIME_TAG_RE: Lazy<Regex> =
@@ -93,3 +93,94 @@ pub fn parse_word_time_tags(
 
     (cleaned_text.trim().to_string(), word_time_tags)
 }
+
+/// Format a time tag as `hh:mm:ss.mmm`, the form used by SRT, WebVTT and
+/// TTML.
+pub fn format_timestamp(time: &TimeTag, separator: char) -> String {
+    let total = i32::from(time.clone()) as u32;
+
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        total / 3_600_000,
+        (total / 60_000) % 60,
+        (total / 1000) % 60,
+        separator,
+        total % 1000
+    )
+}
+
+/// Whether the word time tags of a line carry more than the line itself, ie.
+/// anything but a single entry spanning the whole line.
+pub fn has_word_timing(line: &LyricLine) -> bool {
+    match line.word_time_tags.as_slice() {
+        [] => false,
+        [(start, end, word)] => {
+            start != &line.start_time || end != &line.end_time || word != &line.text
+        }
+        _ => true,
+    }
+}
+
+/// The text of a line without any inline time tags.
+pub fn plain_text(line: &LyricLine) -> String {
+    if !has_word_timing(line) {
+        return line.text.clone();
+    }
+
+    let mut text = String::new();
+    for (_, _, word) in &line.word_time_tags {
+        let needs_space = !text.is_empty()
+            && !text.ends_with(char::is_whitespace)
+            && !word.starts_with(char::is_whitespace);
+        if needs_space {
+            text.push(' ');
+        }
+        text.push_str(word);
+    }
+
+    text.trim().to_string()
+}
+
+/// Write the words of a line with an inline time tag before every word but
+/// the first, the layout read back by `parse_word_time_tags`.
+pub fn inline_word_time_tags(line: &LyricLine, separator: char) -> String {
+    if !has_word_timing(line) {
+        return line.text.clone();
+    }
+
+    let mut text = String::new();
+    for (index, (start, _, word)) in line.word_time_tags.iter().enumerate() {
+        if index > 0 {
+            text.push_str(&format!(" <{}>", format_timestamp(start, separator)));
+        }
+        text.push_str(word.trim());
+    }
+
+    text
+}
+
+/// Escape the characters that would otherwise be read as markup, for WebVTT
+/// cue text and TTML.
+pub fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The reverse of `escape_markup`.
+pub fn unescape_markup(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// The single letter voice marker shared by LRC and SubRip (`M:`) and WebVTT
+/// (`<v M>`).
+pub fn voice_marker(voice_type: &VoiceType) -> Option<&'static str> {
+    match voice_type {
+        VoiceType::Male => Some("M"),
+        VoiceType::Female => Some("F"),
+        VoiceType::Duet => Some("D"),
+        VoiceType::Default => None,
+    }
+}
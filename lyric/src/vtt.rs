@@ -1,14 +1,70 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use subtp::vtt::{VttBlock, VttCue, WebVtt};
 
 use crate::{
     types::{LyricFile, LyricLine, TimeTag, VoiceType},
-    utils::parse_word_time_tags,
+    utils::{
+        escape_markup, format_timestamp, inline_word_time_tags, parse_word_time_tags,
+        unescape_markup, voice_marker,
+    },
 };
 
+static VOICE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^<v(?:\.[^ >]*)? ([^>]+)>").unwrap());
+
+fn parse_voice_type(payload: &str) -> VoiceType {
+    let voice = VOICE_RE
+        .captures(payload.trim_start())
+        .map(|cap| cap[1].trim().to_string());
+
+    match voice.as_deref() {
+        Some("M") => VoiceType::Male,
+        Some("F") => VoiceType::Female,
+        Some("D") => VoiceType::Duet,
+        _ => VoiceType::Default,
+    }
+}
+
+/// ID tags, kept in `NOTE` blocks before the first cue with one `key: value`
+/// per line. Other comments are left alone.
+fn parse_metadata(content: &str) -> HashMap<String, String> {
+    let mut metadata = HashMap::new();
+    let mut blocks: Vec<Vec<&str>> = vec![vec![]];
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() {
+            blocks.push(vec![]);
+        } else if let Some(block) = blocks.last_mut() {
+            block.push(line);
+        }
+    }
+
+    for block in blocks.iter().filter(|x| !x.is_empty()).skip(1) {
+        if block.iter().any(|x| x.contains("-->")) {
+            break;
+        }
+        if block[0] != "NOTE" {
+            continue;
+        }
+
+        let tags: Option<Vec<(&str, &str)>> =
+            block[1..].iter().map(|x| x.split_once(':')).collect();
+        for (key, value) in tags.unwrap_or_default() {
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            metadata.insert(key.trim().to_string(), unescape_markup(value));
+        }
+    }
+
+    metadata
+}
+
 pub fn parse_vtt(content: &str) -> Result<LyricFile> {
     let mut vtt = LyricFile::new();
     let webvtt = WebVtt::parse(content.trim_start())?;
+    vtt.metadata = parse_metadata(content);
 
     for block in webvtt.blocks {
         if let VttBlock::Que(VttCue {
@@ -27,13 +83,19 @@ pub fn parse_vtt(content: &str) -> Result<LyricFile> {
                 milliseconds: timings.end.milliseconds as u32,
             };
 
-            let (text, word_time_tags) =
-                parse_word_time_tags(&payload.join("\n"), &start_time, &end_time);
+            let payload = payload.join("\n");
+            let voice_type = parse_voice_type(&payload);
+            let (text, word_time_tags) = parse_word_time_tags(&payload, &start_time, &end_time);
+            let text = unescape_markup(&text);
+            let word_time_tags = word_time_tags
+                .into_iter()
+                .map(|(start, end, word)| (start, end, unescape_markup(&word)))
+                .collect();
 
             let lyric_line = LyricLine {
                 start_time,
                 end_time,
-                voice_type: VoiceType::Default,
+                voice_type,
                 text,
                 word_time_tags,
             };
@@ -45,6 +107,51 @@ pub fn parse_vtt(content: &str) -> Result<LyricFile> {
     Ok(vtt)
 }
 
+/// Write a lyric file as WebVTT. Word timing is kept as inline timestamp tags,
+/// voices as `<v M>`, `<v F>` or `<v D>` spans, and ID tags in a `NOTE` block
+/// after the header.
+pub fn write_vtt(lyric: &LyricFile) -> String {
+    let mut output = String::from("WEBVTT\n\n");
+
+    if !lyric.metadata.is_empty() {
+        output.push_str("NOTE\n");
+
+        let mut keys: Vec<&String> = lyric.metadata.keys().collect();
+        keys.sort();
+        for key in keys {
+            output.push_str(&format!(
+                "{}: {}\n",
+                key,
+                escape_markup(&lyric.metadata[key])
+            ));
+        }
+        output.push('\n');
+    }
+
+    for line in &lyric.lyrics {
+        // Cue text is markup, the words may not be
+        let mut line = line.clone();
+        line.text = escape_markup(&line.text);
+        for (_, _, word) in &mut line.word_time_tags {
+            *word = escape_markup(word);
+        }
+
+        let voice = voice_marker(&line.voice_type)
+            .map(|marker| format!("<v {}>", marker))
+            .unwrap_or_default();
+
+        output.push_str(&format!(
+            "{} --> {}\n{}{}\n\n",
+            format_timestamp(&line.start_time, '.'),
+            format_timestamp(&line.end_time, '.'),
+            voice,
+            inline_word_time_tags(&line, '.')
+        ));
+    }
+
+    output
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -246,4 +353,61 @@ This is a <b>test</b>
         );
         assert_eq!(second_line.word_time_tags[1].2, "time.");
     }
+
+    #[test]
+    fn test_vtt_round_trip() {
+        let vtt_content = r#"WEBVTT
+
+00:00:01.000 --> 00:00:05.000
+<v F>Hello world <00:00:02.500>this is <00:00:03.500>a test
+
+00:00:06.000 --> 00:00:10.000
+Plain line
+"#;
+
+        let result = parse_vtt(vtt_content).unwrap();
+        assert_eq!(result.lyrics[0].voice_type, VoiceType::Female);
+        assert_eq!(result.lyrics[1].voice_type, VoiceType::Default);
+
+        let written = write_vtt(&result);
+        assert_eq!(written, vtt_content.to_string() + "\n");
+
+        let reparsed = parse_vtt(&written).unwrap();
+        assert_eq!(reparsed.lyrics, result.lyrics);
+    }
+
+    #[test]
+    fn test_vtt_round_trip_metadata_and_markup() {
+        let vtt_content = r#"WEBVTT
+
+NOTE
+ar: Tom & Jerry
+ti: &lt;Untitled&gt; --&gt; Remix
+
+NOTE This is a regular comment
+
+00:00:01.000 --> 00:00:05.000
+<v M>a &lt; b <00:00:02.500>&amp; b &gt; c
+
+00:00:06.000 --> 00:00:10.000
+Fish &amp; chips
+"#;
+
+        let result = parse_vtt(vtt_content).unwrap();
+        assert_eq!(result.metadata.len(), 2);
+        assert_eq!(result.metadata["ar"], "Tom & Jerry");
+        assert_eq!(result.metadata["ti"], "<Untitled> --> Remix");
+        assert_eq!(result.lyrics[0].text, "a < b <00:00:02.500>& b > c");
+        assert_eq!(result.lyrics[0].word_time_tags[0].2, "a < b");
+        assert_eq!(result.lyrics[0].word_time_tags[1].2, "& b > c");
+        assert_eq!(result.lyrics[1].text, "Fish & chips");
+
+        let written = write_vtt(&result);
+        assert!(written.contains("ti: &lt;Untitled&gt; --&gt; Remix\n"));
+        assert!(written.contains("\nFish &amp; chips\n"));
+
+        let reparsed = parse_vtt(&written).unwrap();
+        assert_eq!(reparsed.metadata, result.metadata);
+        assert_eq!(reparsed.lyrics, result.lyrics);
+    }
 }
@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// File holding the settings of a library, in its `.rune` directory so that
+/// they move along with the library.
+const CONFIG_FILE: &str = "library.toml";
+
+/// Settings of a library, shared by every client scanning or watching it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LibraryConfig {
+    pub scan: ScanConfig,
+}
+
+/// Directories left out of every library unless `default_exclude` is off:
+/// hidden ones, like the database and the trash of desktops, thumbnails of
+/// Synology NAS, and the system directories of Windows drives.
+const DEFAULT_EXCLUDE: [&str; 4] = [
+    ".*/",
+    "@eaDir/",
+    "$RECYCLE.BIN/",
+    "System Volume Information/",
+];
+
+/// Which files of a library are taken for audio files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScanConfig {
+    /// Extensions of audio files, without the dot. Defaults to every format
+    /// the decoders handle.
+    pub extensions: Vec<String>,
+    /// Whether files with other extensions are recognized by their content,
+    /// for files whose extension is wrong or missing. Off by default, as it
+    /// reads every other file of the library.
+    pub sniff: bool,
+    /// Patterns of the paths taken into the library, in the syntax of
+    /// `.gitignore` and relative to its root. Everything is taken when
+    /// empty.
+    pub include: Vec<String>,
+    /// Patterns of the paths left out of the library, in the same syntax.
+    /// They come after the built-in ones, and can take their paths back with
+    /// `!`. `.runeignore` files add patterns relative to their directory.
+    pub exclude: Vec<String>,
+    /// Whether hidden and system directories are left out before `exclude`
+    /// is applied.
+    pub default_exclude: bool,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            extensions: audio_formats::decodable_extensions()
+                .map(|x| x.to_string())
+                .collect(),
+            sniff: false,
+            include: vec![],
+            exclude: vec![],
+            default_exclude: true,
+        }
+    }
+}
+
+impl ScanConfig {
+    /// The patterns of the paths left out of the library, the built-in ones
+    /// first.
+    pub fn exclude_patterns(&self) -> Vec<&str> {
+        let defaults: &[&str] = if self.default_exclude {
+            &DEFAULT_EXCLUDE
+        } else {
+            &[]
+        };
+
+        defaults
+            .iter()
+            .copied()
+            .chain(self.exclude.iter().map(|x| x.as_str()))
+            .collect()
+    }
+}
+
+impl LibraryConfig {
+    pub fn path(lib_path: &Path) -> PathBuf {
+        lib_path.join(".rune").join(CONFIG_FILE)
+    }
+
+    /// Reads the settings of a library, which are the defaults until the
+    /// file exists.
+    pub fn load(lib_path: &Path) -> Result<Self> {
+        let path = Self::path(lib_path);
+        if !path.exists() {
+            return Ok(LibraryConfig::default());
+        }
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read library config: {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Invalid library config: {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_config() {
+        let config: LibraryConfig = toml::from_str("[scan]\nsniff = true\n").unwrap();
+        assert!(config.scan.sniff);
+        assert_eq!(config.scan.extensions, ScanConfig::default().extensions);
+
+        let config: LibraryConfig = toml::from_str("[scan]\nextensions = [\"opus\"]\n").unwrap();
+        assert_eq!(config.scan.extensions, vec!["opus"]);
+        assert!(!config.scan.sniff);
+        assert!(config.scan.include.is_empty());
+        assert!(config.scan.exclude.is_empty());
+        assert!(config.scan.default_exclude);
+    }
+
+    #[test]
+    fn test_exclude_patterns() {
+        let config: LibraryConfig = toml::from_str("[scan]\nexclude = [\"Podcasts/\"]\n").unwrap();
+        assert_eq!(
+            config.scan.exclude_patterns(),
+            vec![
+                ".*/",
+                "@eaDir/",
+                "$RECYCLE.BIN/",
+                "System Volume Information/",
+                "Podcasts/"
+            ]
+        );
+
+        let config: LibraryConfig =
+            toml::from_str("[scan]\nexclude = [\"Podcasts/\"]\ndefault_exclude = false\n").unwrap();
+        assert_eq!(config.scan.exclude_patterns(), vec!["Podcasts/"]);
+    }
+}
@@ -0,0 +1,575 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context, Result};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, Value};
+use symphonia::core::probe::Hint;
+
+/// Frames per second of the timestamps of a CUE sheet.
+const FRAMES_PER_SECOND: f64 = 75.0;
+
+/// Tag holding a CUE sheet inside the audio file it describes.
+const CUESHEET_TAG: &str = "cuesheet";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub files: Vec<CueFile>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CueFile {
+    pub name: String,
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CueTrack {
+    pub number: i32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Where the track starts in its file, in seconds, given by `INDEX 01`.
+    pub start: f64,
+}
+
+/// A track of a CUE sheet, played from a range of a larger audio file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualTrack {
+    pub number: i32,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    /// In seconds from the start of the file.
+    pub start: f64,
+    /// In seconds from the start of the file, the end of the file for the
+    /// last track.
+    pub end: Option<f64>,
+}
+
+/// The tracks a CUE sheet next to an audio file finds in it.
+#[derive(Debug, Clone)]
+pub struct ExternalCueSheet {
+    pub path: PathBuf,
+    /// Seconds since the epoch, like `FileDescription::last_modified`.
+    pub last_modified: u64,
+    pub tracks: Vec<VirtualTrack>,
+}
+
+/// Splits a line into its command and arguments, keeping quoted arguments
+/// together.
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = line.trim().chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let mut token = String::new();
+        if c == '"' {
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                token.push(c);
+            }
+        } else {
+            token.push(c);
+            while let Some(c) = chars.next_if(|x| !x.is_whitespace()) {
+                token.push(c);
+            }
+        }
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+/// Parses a `mm:ss:ff` timestamp into seconds.
+fn parse_timestamp(value: &str) -> Result<f64> {
+    let parts: Vec<&str> = value.split(':').collect();
+    let [minutes, seconds, frames] = parts.as_slice() else {
+        bail!("Invalid timestamp: {}", value);
+    };
+
+    let parse = |x: &str| {
+        x.parse::<u32>()
+            .with_context(|| format!("Invalid timestamp: {}", value))
+    };
+    Ok(parse(minutes)? as f64 * 60.0
+        + parse(seconds)? as f64
+        + parse(frames)? as f64 / FRAMES_PER_SECOND)
+}
+
+/// Parses the text of a CUE sheet. Commands that don't describe audio
+/// tracks are ignored, and tracks without an `INDEX 01` are skipped.
+pub fn parse_cue_sheet(content: &str) -> Result<CueSheet> {
+    let mut sheet = CueSheet::default();
+    // The track being read, and whether it holds audio
+    let mut track: Option<(CueTrack, bool, bool)> = None;
+
+    fn finish(sheet: &mut CueSheet, track: &mut Option<(CueTrack, bool, bool)>) {
+        if let Some((track, audio, indexed)) = track.take() {
+            if let (true, true, Some(file)) = (audio, indexed, sheet.files.last_mut()) {
+                file.tracks.push(track);
+            }
+        }
+    }
+
+    for line in content.lines() {
+        let tokens = tokenize(line);
+        let Some(command) = tokens.first() else {
+            continue;
+        };
+        let argument = tokens.get(1).cloned();
+
+        match command.to_uppercase().as_str() {
+            "FILE" => {
+                finish(&mut sheet, &mut track);
+                let Some(name) = argument else {
+                    bail!("FILE without a name");
+                };
+                sheet.files.push(CueFile {
+                    name,
+                    tracks: vec![],
+                });
+            }
+            "TRACK" => {
+                finish(&mut sheet, &mut track);
+                let Some(number) = argument.and_then(|x| x.parse::<i32>().ok()) else {
+                    bail!("Invalid TRACK: {}", line.trim());
+                };
+                let audio = tokens
+                    .get(2)
+                    .is_some_and(|x| x.eq_ignore_ascii_case("AUDIO"));
+                track = Some((
+                    CueTrack {
+                        number,
+                        ..Default::default()
+                    },
+                    audio,
+                    false,
+                ));
+            }
+            "TITLE" | "PERFORMER" => {
+                let field = match &mut track {
+                    Some((track, _, _)) if command.eq_ignore_ascii_case("TITLE") => {
+                        &mut track.title
+                    }
+                    Some((track, _, _)) => &mut track.performer,
+                    None if command.eq_ignore_ascii_case("TITLE") => &mut sheet.title,
+                    None => &mut sheet.performer,
+                };
+                *field = argument.filter(|x| !x.is_empty());
+            }
+            "INDEX" => {
+                if let (Some((track, _, indexed)), Some("01"), Some(timestamp)) =
+                    (&mut track, argument.as_deref(), tokens.get(2))
+                {
+                    track.start = parse_timestamp(timestamp)?;
+                    *indexed = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    finish(&mut sheet, &mut track);
+
+    Ok(sheet)
+}
+
+/// Reads the text of a CUE sheet file. Sheets are often written in a legacy
+/// encoding, whose bytes are taken for Latin-1.
+pub fn read_cue_sheet(path: &Path) -> Result<CueSheet> {
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read CUE sheet: {}", path.display()))?;
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+
+    let content = match std::str::from_utf8(bytes) {
+        Ok(x) => x.to_string(),
+        Err(_) => bytes.iter().map(|x| *x as char).collect(),
+    };
+
+    parse_cue_sheet(&content).with_context(|| format!("Invalid CUE sheet: {}", path.display()))
+}
+
+fn file_stem(name: &str) -> &str {
+    Path::new(name)
+        .file_stem()
+        .and_then(|x| x.to_str())
+        .unwrap_or(name)
+}
+
+impl CueSheet {
+    /// The entry of the sheet describing a file. Sheets often name the file
+    /// they were ripped to, like a WAV file that was compressed afterwards,
+    /// so files with the same stem match too.
+    pub fn file_named(&self, file_name: &str) -> Option<&CueFile> {
+        self.files
+            .iter()
+            .find(|x| x.name.eq_ignore_ascii_case(file_name))
+            .or_else(|| {
+                self.files
+                    .iter()
+                    .find(|x| file_stem(&x.name).eq_ignore_ascii_case(file_stem(file_name)))
+            })
+    }
+
+    /// The tracks of an entry of the sheet, ending where the next one starts.
+    pub fn virtual_tracks(&self, file: &CueFile) -> Vec<VirtualTrack> {
+        let mut tracks: Vec<&CueTrack> = file.tracks.iter().collect();
+        tracks.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+        tracks
+            .iter()
+            .enumerate()
+            .map(|(index, track)| VirtualTrack {
+                number: track.number,
+                title: track.title.clone(),
+                artist: track.performer.clone().or_else(|| self.performer.clone()),
+                album: self.title.clone(),
+                album_artist: self.performer.clone(),
+                start: track.start,
+                end: tracks.get(index + 1).map(|x| x.start),
+            })
+            .collect()
+    }
+}
+
+fn modified_seconds(path: &Path) -> Result<u64> {
+    Ok(fs::metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_secs())
+}
+
+pub fn is_cue_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| x.eq_ignore_ascii_case("cue"))
+}
+
+fn cue_sheets_in(directory: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return vec![];
+    };
+
+    let mut sheets: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|x| x.path())
+        .filter(|x| x.is_file() && is_cue_path(x))
+        .collect();
+    sheets.sort();
+    sheets
+}
+
+/// A CUE sheet of a directory, as it was when the directory was read.
+#[derive(Debug, Clone)]
+struct CachedCueSheet {
+    path: PathBuf,
+    last_modified: u64,
+    sheet: CueSheet,
+}
+
+/// The CUE sheets read so far, by directory, so that scanning a library
+/// reads and parses each sheet once rather than once per audio file next to
+/// it.
+#[derive(Debug, Default)]
+pub struct CueSheetCache {
+    directories: HashMap<PathBuf, Vec<CachedCueSheet>>,
+}
+
+impl CueSheetCache {
+    /// The sheets of a directory, read the first time it is asked for.
+    fn sheets_in(&mut self, directory: &Path) -> &[CachedCueSheet] {
+        self.directories
+            .entry(directory.to_path_buf())
+            .or_insert_with(|| {
+                cue_sheets_in(directory)
+                    .into_iter()
+                    .filter_map(|path| match read_cue_sheet(&path) {
+                        Ok(sheet) => Some(CachedCueSheet {
+                            last_modified: modified_seconds(&path).unwrap_or_default(),
+                            path,
+                            sheet,
+                        }),
+                        Err(e) => {
+                            log::warn!("{:?}", e);
+                            None
+                        }
+                    })
+                    .collect()
+            })
+    }
+
+    /// Looks for a CUE sheet next to an audio file that splits it into more
+    /// than one track. A sheet with the stem of the file may describe it
+    /// under any name, as long as it describes a single file.
+    pub fn find_external_cue_sheet(&mut self, audio_path: &Path) -> Option<ExternalCueSheet> {
+        let directory = audio_path.parent()?;
+        let file_name = audio_path.file_name()?.to_str()?;
+
+        self.sheets_in(directory).iter().find_map(|x| {
+            let same_stem = x
+                .path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .is_some_and(|x| x.eq_ignore_ascii_case(file_stem(file_name)));
+            let file = x
+                .sheet
+                .file_named(file_name)
+                .or(match x.sheet.files.as_slice() {
+                    [file] if same_stem => Some(file),
+                    _ => None,
+                })?;
+
+            let tracks = x.sheet.virtual_tracks(file);
+            (tracks.len() >= 2).then(|| ExternalCueSheet {
+                path: x.path.clone(),
+                last_modified: x.last_modified,
+                tracks,
+            })
+        })
+    }
+}
+
+/// Looks for a CUE sheet next to an audio file, see
+/// `CueSheetCache::find_external_cue_sheet`.
+pub fn find_external_cue_sheet(audio_path: &Path) -> Option<ExternalCueSheet> {
+    CueSheetCache::default().find_external_cue_sheet(audio_path)
+}
+
+/// The audio files a CUE sheet describes, among the files next to it.
+pub fn cue_sheet_audio_files(cue_path: &Path) -> Vec<PathBuf> {
+    let Some(directory) = cue_path.parent() else {
+        return vec![];
+    };
+    let Ok(sheet) = read_cue_sheet(cue_path) else {
+        return vec![];
+    };
+    let Ok(entries) = fs::read_dir(directory) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|x| x.path())
+        .filter(|x| x.is_file() && x.as_path() != cue_path)
+        .filter(|x| {
+            x.file_name()
+                .and_then(|x| x.to_str())
+                .is_some_and(|x| sheet.file_named(x).is_some())
+        })
+        .collect()
+}
+
+fn find_cue_sheet_tag(revision: &MetadataRevision) -> Option<String> {
+    revision
+        .tags()
+        .iter()
+        .find(|x| x.key.eq_ignore_ascii_case(CUESHEET_TAG))
+        .and_then(|x| match &x.value {
+            Value::String(x) => Some(x.clone()),
+            _ => None,
+        })
+}
+
+/// The CUE sheet embedded in the tags of an audio file, like the
+/// `CUESHEET` Vorbis comment of FLAC files.
+pub fn read_embedded_cue_sheet(audio_path: &Path) -> Option<String> {
+    let file = File::open(audio_path).ok()?;
+    let mut hint = Hint::new();
+    if let Some(extension) = audio_formats::hint_extension(audio_path) {
+        hint.with_extension(&extension);
+    }
+
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?;
+
+    if let Some(sheet) = probed
+        .format
+        .metadata()
+        .current()
+        .and_then(find_cue_sheet_tag)
+    {
+        return Some(sheet);
+    }
+    probed
+        .metadata
+        .get()
+        .as_ref()
+        .and_then(|x| x.current())
+        .and_then(find_cue_sheet_tag)
+}
+
+/// The tracks of the CUE sheet embedded in an audio file, and the text of
+/// the sheet. An embedded sheet always describes its own file, whatever
+/// name it gives it.
+pub fn embedded_cue_tracks(audio_path: &Path) -> Option<(String, Vec<VirtualTrack>)> {
+    let content = read_embedded_cue_sheet(audio_path)?;
+    let sheet = match parse_cue_sheet(&content) {
+        Ok(x) => x,
+        Err(e) => {
+            log::warn!(
+                "Invalid embedded CUE sheet in {}: {:?}",
+                audio_path.display(),
+                e
+            );
+            return None;
+        }
+    };
+
+    let file = match sheet.files.as_slice() {
+        [file] => file,
+        _ => sheet.file_named(audio_path.file_name()?.to_str()?)?,
+    };
+    let tracks = sheet.virtual_tracks(file);
+
+    (tracks.len() > 1).then_some((content, tracks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHEET: &str = r#"REM GENRE "Progressive Rock"
+PERFORMER "The Band"
+TITLE "The Album"
+FILE "The Album.wav" WAVE
+  TRACK 01 AUDIO
+    TITLE "Opening"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Second Song"
+    PERFORMER "The Band feat. Guest"
+    INDEX 00 03:58:10
+    INDEX 01 04:00:37
+  TRACK 03 DATA
+    INDEX 01 08:00:00
+  TRACK 04 AUDIO
+    TITLE ""
+    INDEX 01 10:30:00
+"#;
+
+    #[test]
+    fn test_parse_cue_sheet() {
+        let sheet = parse_cue_sheet(SHEET).unwrap();
+        assert_eq!(sheet.title.as_deref(), Some("The Album"));
+        assert_eq!(sheet.performer.as_deref(), Some("The Band"));
+        assert_eq!(sheet.files.len(), 1);
+
+        let file = &sheet.files[0];
+        assert_eq!(file.name, "The Album.wav");
+        // The data track is skipped
+        assert_eq!(
+            file.tracks.iter().map(|x| x.number).collect::<Vec<_>>(),
+            vec![1, 2, 4]
+        );
+        assert_eq!(file.tracks[1].start, 240.0 + 37.0 / 75.0);
+        assert_eq!(file.tracks[2].title, None);
+
+        assert!(parse_cue_sheet("FILE\n").is_err());
+        assert!(parse_cue_sheet("FILE a.wav WAVE\nTRACK 01 AUDIO\nINDEX 01 1:2\n").is_err());
+    }
+
+    #[test]
+    fn test_virtual_tracks() {
+        let sheet = parse_cue_sheet(SHEET).unwrap();
+        // Compressed after ripping
+        let file = sheet.file_named("the album.flac").unwrap();
+        let tracks = sheet.virtual_tracks(file);
+
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].start, 0.0);
+        assert_eq!(tracks[0].end, Some(tracks[1].start));
+        assert_eq!(tracks[0].artist.as_deref(), Some("The Band"));
+        assert_eq!(tracks[1].artist.as_deref(), Some("The Band feat. Guest"));
+        assert_eq!(tracks[1].album_artist.as_deref(), Some("The Band"));
+        assert_eq!(tracks[1].album.as_deref(), Some("The Album"));
+        assert_eq!(tracks[1].end, Some(630.0));
+        assert_eq!(tracks[2].end, None);
+
+        assert!(sheet.file_named("Other.flac").is_none());
+    }
+
+    #[test]
+    fn test_find_external_cue_sheet() {
+        let directory = std::env::temp_dir().join(format!("rune-cue-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let audio = directory.join("The Album.flac");
+        fs::write(&audio, b"audio").unwrap();
+        fs::write(directory.join("Single.flac"), b"audio").unwrap();
+        // Latin-1, with a name the audio file doesn't have
+        let sheet = SHEET
+            .replace("The Album.wav", "Rip.wav")
+            .replace("Opening", "Ouverture \u{e9}");
+        let sheet: Vec<u8> = sheet.chars().map(|x| x as u8).collect();
+        fs::write(directory.join("The Album.cue"), sheet).unwrap();
+
+        let found = find_external_cue_sheet(&audio).unwrap();
+        assert_eq!(found.path, directory.join("The Album.cue"));
+        assert_eq!(found.tracks.len(), 3);
+        assert_eq!(found.tracks[0].title.as_deref(), Some("Ouverture \u{e9}"));
+        assert!(found.last_modified > 0);
+
+        assert!(find_external_cue_sheet(&directory.join("Single.flac")).is_none());
+        assert!(cue_sheet_audio_files(&directory.join("The Album.cue")).is_empty());
+
+        fs::write(directory.join("The Album.cue"), SHEET).unwrap();
+        assert_eq!(
+            cue_sheet_audio_files(&directory.join("The Album.cue")),
+            vec![audio]
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_cue_sheet_cache() {
+        let directory = std::env::temp_dir().join(format!("rune-cue-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let audio = directory.join("The Album.wav");
+        fs::write(&audio, b"audio").unwrap();
+        fs::write(directory.join("Single.wav"), b"audio").unwrap();
+        fs::write(directory.join("The Album.cue"), SHEET).unwrap();
+
+        let mut cache = CueSheetCache::default();
+        assert_eq!(
+            cache.find_external_cue_sheet(&audio).unwrap().tracks.len(),
+            3
+        );
+        assert!(cache
+            .find_external_cue_sheet(&directory.join("Single.wav"))
+            .is_none());
+
+        // The directory is only read once
+        fs::remove_file(directory.join("The Album.cue")).unwrap();
+        assert_eq!(
+            cache.find_external_cue_sheet(&audio).unwrap().tracks.len(),
+            3
+        );
+        assert!(CueSheetCache::default()
+            .find_external_cue_sheet(&audio)
+            .is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -26,6 +26,7 @@ pub struct FileDescription {
     pub directory: String,
     pub extension: String,
     pub file_hash: Option<String>,
+    pub file_size: u64,
     pub last_modified: String,
 }
 
@@ -139,6 +140,7 @@ pub fn describe_file(file_path: &PathBuf, lib_path: &Option<PathBuf>) -> Result<
         directory,
         extension,
         file_hash: None,
+        file_size: metadata.len(),
         last_modified,
     })
 }
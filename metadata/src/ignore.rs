@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+use log::warn;
+
+/// File listing paths a directory leaves out of the library, in the syntax
+/// of `.gitignore`.
+pub const IGNORE_FILE: &str = ".runeignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// One line of a gitignore-style list.
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    /// Whether the line starts with `!`, taking back what earlier lines left
+    /// out.
+    negated: bool,
+    /// Whether the line ends with `/`, matching directories only.
+    directory_only: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(x) => (true, x),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (directory_only, line) = match line.strip_suffix('/') {
+            Some(x) => (true, x),
+            None => (false, line),
+        };
+
+        // Patterns with a slash are relative to the directory of the list,
+        // others match a name at any depth
+        let pattern = if line.contains('/') {
+            line.trim_start_matches('/').to_string()
+        } else {
+            format!("**/{}", line)
+        };
+
+        match Pattern::new(&pattern) {
+            Ok(pattern) => Some(Rule {
+                pattern,
+                negated,
+                directory_only,
+            }),
+            Err(e) => {
+                warn!("Invalid ignore pattern {}: {}", line, e);
+                None
+            }
+        }
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        (is_dir || !self.directory_only) && self.pattern.matches_with(path, MATCH_OPTIONS)
+    }
+}
+
+/// The paths `path` is made of, joined by `/` whatever the platform.
+fn to_pattern_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|x| match x {
+            Component::Normal(x) => x.to_str(),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// A gitignore-style list of patterns, where later lines override earlier
+/// ones.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    pub fn parse<I, S>(lines: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        IgnoreRules {
+            rules: lines
+                .into_iter()
+                .filter_map(|x| Rule::parse(x.as_ref()))
+                .collect(),
+        }
+    }
+
+    /// Reads the ignore file of a directory, if it has one.
+    pub fn read(directory: &Path) -> Self {
+        let path = directory.join(IGNORE_FILE);
+        match fs::read_to_string(&path) {
+            Ok(content) => Self::parse(content.lines()),
+            Err(_) => IgnoreRules::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether the last line matching a path, relative to the directory of
+    /// the list, leaves it out (`Some(true)`) or takes it back
+    /// (`Some(false)`). `None` if no line matches.
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let path = to_pattern_path(path);
+        self.rules
+            .iter()
+            .rev()
+            .find(|x| x.matches(&path, is_dir))
+            .map(|x| !x.negated)
+    }
+}
+
+/// The ignore files read so far, so that checking many paths of a library
+/// reads each of them once.
+#[derive(Debug, Default)]
+pub struct IgnoreFileCache {
+    files: HashMap<PathBuf, IgnoreRules>,
+}
+
+impl IgnoreFileCache {
+    /// The ignore file of a directory, read the first time it is asked for.
+    pub fn get(&mut self, directory: &Path) -> &IgnoreRules {
+        self.files
+            .entry(directory.to_path_buf())
+            .or_insert_with(|| IgnoreRules::read(directory))
+    }
+}
+
+pub fn is_ignore_path(path: &Path) -> bool {
+    path.file_name().is_some_and(|x| x == IGNORE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let rules = IgnoreRules::parse([
+            "# Comments and blank lines are skipped",
+            "",
+            "*.tmp",
+            "/Scans",
+            "live/",
+            "Singles/**/demo*",
+            "*.wav",
+            "!keep.wav",
+            "\\!bang.flac",
+        ]);
+
+        let matched = |path: &str, is_dir| rules.matched(Path::new(path), is_dir);
+
+        // Names match at any depth
+        assert_eq!(matched("a.tmp", false), Some(true));
+        assert_eq!(matched("album/disc1/a.tmp", false), Some(true));
+        // Anchored patterns only match from the directory of the list
+        assert_eq!(matched("Scans", true), Some(true));
+        assert_eq!(matched("album/Scans", true), None);
+        // Patterns ending with a slash only match directories
+        assert_eq!(matched("album/live", true), Some(true));
+        assert_eq!(matched("album/live", false), None);
+        assert_eq!(matched("Singles/2024/demo.flac", false), Some(true));
+        assert_eq!(matched("Singles/demo.flac", false), Some(true));
+        assert_eq!(matched("Albums/demo.flac", false), None);
+        // Later lines override earlier ones
+        assert_eq!(matched("album/b.wav", false), Some(true));
+        assert_eq!(matched("album/keep.wav", false), Some(false));
+        assert_eq!(matched("!bang.flac", false), Some(true));
+        assert_eq!(matched("album/a.flac", false), None);
+    }
+
+    #[test]
+    fn test_read() {
+        let directory = std::env::temp_dir().join(format!("rune-ignore-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        assert!(IgnoreRules::read(&directory).is_empty());
+
+        fs::write(directory.join(IGNORE_FILE), "Bonus/\r\n*.m4a\r\n").unwrap();
+        let rules = IgnoreRules::read(&directory);
+        assert_eq!(rules.matched(Path::new("Bonus"), true), Some(true));
+        assert_eq!(rules.matched(Path::new("a/b.m4a"), false), Some(true));
+        assert!(is_ignore_path(&directory.join(IGNORE_FILE)));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
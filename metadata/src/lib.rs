@@ -1,6 +1,35 @@
 pub mod artist;
+pub mod config;
 pub mod cover_art;
 pub mod crc;
+pub mod cue;
 pub mod describe;
+pub mod ignore;
 pub mod reader;
 pub mod scanner;
+pub mod watcher;
+
+#[cfg(test)]
+mod test_utils {
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Creates a library in the temporary directory holding the given files,
+    /// by their paths relative to its root. The canonical path of the root is
+    /// returned, as the watcher reports events under it.
+    pub fn temp_library(name: &str, files: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rune-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        for file in files {
+            let path = root.join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).unwrap();
+            }
+            fs::write(path, b"audio").unwrap();
+        }
+
+        fs::canonicalize(root).unwrap()
+    }
+}
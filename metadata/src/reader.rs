@@ -3,6 +3,7 @@ use std::fs::File;
 use std::path::Path;
 
 use anyhow::{bail, Result};
+use audio_formats::hint_extension;
 use symphonia::core::formats::FormatOptions;
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, Value};
@@ -225,10 +226,11 @@ pub fn get_metadata(
     // Create the media source stream.
     let mss = MediaSourceStream::new(Box::new(src), Default::default());
 
-    // Create a probe hint using the file's extension.
+    // Create a probe hint using the format of the file, or its extension.
     let mut hint = Hint::new();
-    let ext = file_path.split('.').last().unwrap_or_default();
-    hint.with_extension(ext);
+    if let Some(ext) = hint_extension(Path::new(file_path)) {
+        hint.with_extension(&ext);
+    }
 
     // Use the default options for metadata and format readers.
     let fmt_opts: FormatOptions = Default::default();
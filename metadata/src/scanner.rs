@@ -1,22 +1,189 @@
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+
+use audio_formats::sniff_file;
 use walkdir::{DirEntry, WalkDir};
 
-fn is_audio_file(entry: &DirEntry) -> bool {
-    if let Some(ext) = entry.path().extension() {
-        matches!(
-            ext.to_str().unwrap_or("").to_lowercase().as_str(),
-            "mp3" | "flac" | "wav" | "aac" | "ogg" | "m4a"
-        )
-    } else {
-        false
+use crate::config::ScanConfig;
+use crate::ignore::{IgnoreFileCache, IgnoreRules};
+
+/// Decides which files of a library are audio files, by their extension or,
+/// when sniffing is enabled, by their content, and which paths are left out
+/// of the library.
+#[derive(Debug, Clone)]
+pub struct AudioFileFilter {
+    extensions: HashSet<String>,
+    sniff: bool,
+    include: IgnoreRules,
+    exclude: IgnoreRules,
+}
+
+impl Default for AudioFileFilter {
+    fn default() -> Self {
+        AudioFileFilter::new(&ScanConfig::default())
+    }
+}
+
+impl AudioFileFilter {
+    pub fn new(config: &ScanConfig) -> Self {
+        AudioFileFilter {
+            extensions: config
+                .extensions
+                .iter()
+                .map(|x| x.trim_start_matches('.').to_lowercase())
+                .collect(),
+            sniff: config.sniff,
+            include: IgnoreRules::parse(&config.include),
+            exclude: IgnoreRules::parse(config.exclude_patterns()),
+        }
+    }
+
+    pub fn is_audio_path(&self, path: &Path) -> bool {
+        let extension = path
+            .extension()
+            .and_then(|x| x.to_str())
+            .map(|x| x.to_lowercase());
+        if extension.is_some_and(|x| self.extensions.contains(&x)) {
+            return true;
+        }
+
+        // Only formats the library accepts count, whatever their extension
+        self.sniff
+            && matches!(
+                sniff_file(path),
+                Ok(Some(format)) if format.extensions.iter().any(|x| self.extensions.contains(*x))
+            )
+    }
+
+    /// Whether a path under the root is left out by the patterns of the
+    /// library or by the ignore files of the directories above it, each
+    /// taking precedence over the ones above.
+    fn is_excluded(
+        &self,
+        root: &Path,
+        path: &Path,
+        is_dir: bool,
+        ignore_files: &[(PathBuf, IgnoreRules)],
+    ) -> bool {
+        let Ok(relative) = path.strip_prefix(root) else {
+            return false;
+        };
+
+        let mut excluded = self.exclude.matched(relative, is_dir);
+        for (directory, rules) in ignore_files {
+            if let Ok(x) = path.strip_prefix(directory) {
+                excluded = rules.matched(x, is_dir).or(excluded);
+            }
+        }
+
+        excluded == Some(true)
     }
+
+    /// Whether a file under the root is taken by the include patterns, either
+    /// by itself or by one of the directories above it.
+    fn is_included(&self, root: &Path, path: &Path) -> bool {
+        if self.include.is_empty() {
+            return true;
+        }
+        let Ok(relative) = path.strip_prefix(root) else {
+            return true;
+        };
+
+        relative
+            .ancestors()
+            .filter(|x| !x.as_os_str().is_empty())
+            .any(|x| self.include.matched(x, x != relative) == Some(true))
+    }
+
+    /// Whether a path of the library is left out of it, along with
+    /// everything under it if it is a directory. Paths outside of the root
+    /// are not.
+    pub fn is_ignored(&self, root: &Path, path: &Path) -> bool {
+        self.is_ignored_with(root, path, &mut IgnoreFileCache::default())
+    }
+
+    /// Like `is_ignored`, taking the ignore files from a cache kept across
+    /// the paths checked.
+    pub fn is_ignored_with(&self, root: &Path, path: &Path, cache: &mut IgnoreFileCache) -> bool {
+        let Ok(relative) = path.strip_prefix(root) else {
+            return false;
+        };
+
+        let depth = relative.components().count();
+        let mut ignore_files = vec![];
+        let mut current = root.to_path_buf();
+        for (index, component) in relative.components().enumerate() {
+            ignore_files.push((current.clone(), cache.get(&current).clone()));
+            current.push(component);
+
+            let is_dir = index + 1 < depth || current.is_dir();
+            if self.is_excluded(root, &current, is_dir, &ignore_files) {
+                return true;
+            }
+        }
+
+        !path.is_dir() && !self.is_included(root, path)
+    }
+}
+
+pub fn scan_audio_files<P: AsRef<Path>>(
+    path: &P,
+    filter: AudioFileFilter,
+) -> impl Iterator<Item = DirEntry> + Send {
+    scan_audio_files_under(path.as_ref(), path.as_ref(), filter)
 }
 
-fn scan_audio_files<P: AsRef<Path>>(path: &P) -> impl Iterator<Item = DirEntry> + Send {
-    WalkDir::new(path)
+/// Walks a directory of the library for audio files, skipping the paths
+/// left out of the library rooted at `root`.
+pub fn scan_audio_files_under(
+    root: &Path,
+    directory: &Path,
+    filter: AudioFileFilter,
+) -> impl Iterator<Item = DirEntry> + Send {
+    let root = root.to_path_buf();
+    let walk_root = root.clone();
+    let walk_filter = filter.clone();
+
+    // Ignore files of the directories above the walked one, the ones of the
+    // directories below are read along the way
+    let mut ignore_files: Vec<(PathBuf, IgnoreRules)> = directory
+        .ancestors()
+        .skip(1)
+        .take_while(|x| x.starts_with(&root))
+        .map(|x| (x.to_path_buf(), IgnoreRules::read(x)))
+        .collect();
+    ignore_files.reverse();
+
+    WalkDir::new(directory)
         .into_iter()
+        .filter_entry(move |entry| {
+            let path = entry.path();
+            // Entries are visited depth first, so the lists of directories
+            // the entry isn't in no longer apply
+            while ignore_files
+                .last()
+                .is_some_and(|(x, _)| !path.starts_with(x))
+            {
+                ignore_files.pop();
+            }
+
+            let is_dir = entry.file_type().is_dir();
+            if entry.depth() > 0 && walk_filter.is_excluded(&walk_root, path, is_dir, &ignore_files)
+            {
+                return false;
+            }
+            if is_dir {
+                ignore_files.push((path.to_path_buf(), IgnoreRules::read(path)));
+            }
+
+            true
+        })
         .filter_map(Result::ok)
-        .filter(|entry| entry.file_type().is_file() && is_audio_file(entry))
+        .filter(move |entry| {
+            entry.file_type().is_file()
+                && filter.is_included(&root, entry.path())
+                && filter.is_audio_path(entry.path())
+        })
 }
 
 pub struct AudioScanner<'a> {
@@ -27,9 +194,13 @@ pub struct AudioScanner<'a> {
 
 impl<'a> AudioScanner<'a> {
     pub fn new<P: AsRef<Path> + Send + 'a>(path: &'a P) -> Self {
+        Self::with_filter(path, AudioFileFilter::default())
+    }
+
+    pub fn with_filter<P: AsRef<Path> + Send + 'a>(path: &'a P, filter: AudioFileFilter) -> Self {
         AudioScanner {
             root_path: path.as_ref().to_path_buf(),
-            iterator: Box::new(scan_audio_files(path)),
+            iterator: Box::new(scan_audio_files(path, filter)),
             ended: false,
         }
     }
@@ -55,3 +226,166 @@ impl<'a> AudioScanner<'a> {
         &self.root_path
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::config::ScanConfig;
+    use crate::ignore::IGNORE_FILE;
+    use crate::test_utils::temp_library;
+
+    use super::*;
+
+    /// A library with audio files in system directories, and in directories
+    /// ignore files are written to.
+    fn library(name: &str) -> PathBuf {
+        temp_library(
+            &format!("scanner-{}", name),
+            &[
+                ".rune/cached.mp3",
+                ".hidden/01.mp3",
+                "@eaDir/thumb.mp3",
+                "$RECYCLE.BIN/deleted.mp3",
+                "System Volume Information/01.mp3",
+                "album/01.mp3",
+                "album/02.flac",
+                "album/live/01.mp3",
+                "album/disc1/01.mp3",
+                "album/disc1/keep.flac",
+                "singles/01.mp3",
+            ],
+        )
+    }
+
+    fn scanned(root: &Path, filter: &AudioFileFilter) -> Vec<String> {
+        let mut files: Vec<String> = scan_audio_files(&root, filter.clone())
+            .map(|x| to_slashes(x.path().strip_prefix(root).unwrap()))
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn to_slashes(path: &Path) -> String {
+        path.components()
+            .map(|x| x.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    #[test]
+    fn test_ignored_paths() {
+        let root = library("ignored");
+        let filter = AudioFileFilter::default();
+        assert_eq!(
+            scanned(&root, &filter),
+            vec![
+                "album/01.mp3",
+                "album/02.flac",
+                "album/disc1/01.mp3",
+                "album/disc1/keep.flac",
+                "album/live/01.mp3",
+                "singles/01.mp3"
+            ]
+        );
+
+        // Ignore files apply below their directory, deeper ones taking
+        // precedence
+        fs::write(root.join(IGNORE_FILE), "*.flac\n").unwrap();
+        fs::write(root.join("album").join(IGNORE_FILE), "live/\n").unwrap();
+        fs::write(
+            root.join("album").join("disc1").join(IGNORE_FILE),
+            "!keep.flac\n",
+        )
+        .unwrap();
+        assert_eq!(
+            scanned(&root, &filter),
+            vec![
+                "album/01.mp3",
+                "album/disc1/01.mp3",
+                "album/disc1/keep.flac",
+                "singles/01.mp3"
+            ]
+        );
+
+        // Single paths are left out the same way as walked ones
+        assert!(filter.is_ignored(&root, &root.join("@eaDir").join("thumb.mp3")));
+        assert!(filter.is_ignored(&root, &root.join(".hidden").join("01.mp3")));
+        assert!(filter.is_ignored(&root, &root.join("$RECYCLE.BIN").join("deleted.mp3")));
+        assert!(filter.is_ignored(&root, &root.join("album").join("live").join("01.mp3")));
+        assert!(filter.is_ignored(&root, &root.join("album").join("02.flac")));
+        assert!(!filter.is_ignored(&root, &root.join("album").join("disc1").join("keep.flac")));
+        assert!(!filter.is_ignored(&root, &root.join("album").join("01.mp3")));
+
+        // Walks of a directory apply the ignore files above it
+        let files: Vec<PathBuf> =
+            scan_audio_files_under(&root, &root.join("album"), filter.clone())
+                .map(|x| x.into_path())
+                .collect();
+        assert!(!files
+            .iter()
+            .any(|x| x.ends_with("02.flac") || x.ends_with("live/01.mp3")));
+
+        // Include patterns take files by themselves or by their directories
+        let filter = AudioFileFilter::new(&ScanConfig {
+            include: vec!["album/".to_string(), "/singles/*.flac".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(
+            scanned(&root, &filter),
+            vec![
+                "album/01.mp3",
+                "album/disc1/01.mp3",
+                "album/disc1/keep.flac"
+            ]
+        );
+        assert!(filter.is_ignored(&root, &root.join("singles").join("01.mp3")));
+        assert!(!filter.is_ignored(&root, &root.join("singles")));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_custom_exclude() {
+        let root = library("custom-exclude");
+
+        // The built-in patterns still apply
+        let filter = AudioFileFilter::new(&ScanConfig {
+            exclude: vec!["live/".to_string(), "!@eaDir/".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(
+            scanned(&root, &filter),
+            vec![
+                "@eaDir/thumb.mp3",
+                "album/01.mp3",
+                "album/02.flac",
+                "album/disc1/01.mp3",
+                "album/disc1/keep.flac",
+                "singles/01.mp3"
+            ]
+        );
+
+        let filter = AudioFileFilter::new(&ScanConfig {
+            exclude: vec![".rune/".to_string(), "live/".to_string()],
+            default_exclude: false,
+            ..Default::default()
+        });
+        assert_eq!(
+            scanned(&root, &filter),
+            vec![
+                "$RECYCLE.BIN/deleted.mp3",
+                ".hidden/01.mp3",
+                "@eaDir/thumb.mp3",
+                "System Volume Information/01.mp3",
+                "album/01.mp3",
+                "album/02.flac",
+                "album/disc1/01.mp3",
+                "album/disc1/keep.flac",
+                "singles/01.mp3"
+            ]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::cue::{cue_sheet_audio_files, is_cue_path};
+use crate::ignore::is_ignore_path;
+use crate::scanner::{scan_audio_files_under, AudioFileFilter};
+
+/// Directory of the library database, whose writes are not library changes.
+const DATABASE_DIRECTORY: &str = ".rune";
+
+/// What changed under a library root during one batch of events, as
+/// absolute paths.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LibraryChanges {
+    /// Audio files that were created, modified, or moved to their path.
+    pub changed: Vec<PathBuf>,
+    /// Paths that no longer exist, either files or whole directories.
+    pub removed: Vec<PathBuf>,
+    /// Whether events were lost, so that only a full scan can catch up.
+    pub rescan: bool,
+}
+
+impl LibraryChanges {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty() && !self.rescan
+    }
+}
+
+/// Watches a library root recursively and reports its changes in batches,
+/// once no event arrived for the debounce duration. Watching stops when the
+/// watcher is dropped.
+pub struct LibraryWatcher {
+    watcher: Option<RecommendedWatcher>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LibraryWatcher {
+    pub fn new<F>(
+        root: &Path,
+        debounce: Duration,
+        filter: AudioFileFilter,
+        on_changes: F,
+    ) -> Result<Self>
+    where
+        F: FnMut(LibraryChanges) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(tx).with_context(|| "Failed to create library watcher")?;
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch library: {}", root.display()))?;
+
+        let root = root.to_path_buf();
+        let thread = thread::Builder::new()
+            .name("library-watcher".to_string())
+            .spawn(move || debounce_events(&root, debounce, &filter, rx, on_changes))
+            .with_context(|| "Failed to start library watcher thread")?;
+
+        Ok(LibraryWatcher {
+            watcher: Some(watcher),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for LibraryWatcher {
+    fn drop(&mut self) {
+        // Dropping the watcher closes the event channel, which ends the thread
+        // and discards the pending events
+        drop(self.watcher.take());
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("Library watcher thread panicked");
+            }
+        }
+    }
+}
+
+fn is_database_path(root: &Path, path: &Path) -> bool {
+    path.strip_prefix(root)
+        .is_ok_and(|x| x.starts_with(DATABASE_DIRECTORY))
+}
+
+/// Whether an event may have added audio files under a directory. Other
+/// directory events, like changed permissions, don't need a walk.
+fn adds_directory(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_)) | EventKind::Any
+    )
+}
+
+fn debounce_events<F>(
+    root: &Path,
+    debounce: Duration,
+    filter: &AudioFileFilter,
+    rx: Receiver<notify::Result<Event>>,
+    mut on_changes: F,
+) where
+    F: FnMut(LibraryChanges),
+{
+    let mut pending: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut rescan = false;
+
+    loop {
+        let received = if pending.is_empty() && !rescan {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            rx.recv_timeout(debounce)
+        };
+
+        match received {
+            Ok(Ok(event)) => {
+                if event.need_rescan() {
+                    rescan = true;
+                }
+                if matches!(event.kind, EventKind::Access(_)) {
+                    continue;
+                }
+
+                let adds_directory = adds_directory(&event.kind);
+                pending.extend(
+                    event
+                        .paths
+                        .into_iter()
+                        .filter(|x| !is_database_path(root, x) && (adds_directory || !x.is_dir())),
+                );
+            }
+            Ok(Err(e)) => warn!("Library watcher error: {:?}", e),
+            Err(RecvTimeoutError::Timeout) => {
+                let changes = collect_changes(root, std::mem::take(&mut pending), filter, rescan);
+                rescan = false;
+
+                if !changes.is_empty() {
+                    debug!("Library changed: {:?}", changes);
+                    on_changes(changes);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+/// The audio files directly in a directory, not in its subdirectories.
+fn audio_files_in(directory: &Path, filter: &AudioFileFilter) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|x| x.path())
+        .filter(|x| x.is_file() && filter.is_audio_path(x))
+        .collect()
+}
+
+/// Sorts the paths of a batch by what they are once the events settled, so
+/// that a file created and deleted again within a batch is only removed.
+fn collect_changes(
+    root: &Path,
+    paths: BTreeSet<PathBuf>,
+    filter: &AudioFileFilter,
+    rescan: bool,
+) -> LibraryChanges {
+    let mut changes = LibraryChanges {
+        rescan,
+        ..Default::default()
+    };
+
+    for path in paths {
+        if is_ignore_path(&path) {
+            // Files may have left the library or come back anywhere below
+            changes.rescan = true;
+        } else if path.is_dir() {
+            if !filter.is_ignored(root, &path) {
+                changes.changed.extend(
+                    scan_audio_files_under(root, &path, filter.clone()).map(|x| x.into_path()),
+                );
+            }
+        } else if path.is_file() {
+            if filter.is_ignored(root, &path) {
+                continue;
+            }
+
+            if is_cue_path(&path) {
+                // The tracks of the files it describes changed
+                changes.changed.extend(
+                    cue_sheet_audio_files(&path)
+                        .into_iter()
+                        .filter(|x| filter.is_audio_path(x) && !filter.is_ignored(root, x)),
+                );
+            } else if filter.is_audio_path(&path) {
+                changes.changed.push(path);
+            }
+        } else if is_cue_path(&path) {
+            // Which files it described is gone with it, so the files next to
+            // it are played as a whole again unless they have another sheet
+            if let Some(directory) = path.parent() {
+                changes.changed.extend(
+                    audio_files_in(directory, filter)
+                        .into_iter()
+                        .filter(|x| !filter.is_ignored(root, x)),
+                );
+            }
+        } else {
+            changes.removed.push(path);
+        }
+    }
+
+    changes.changed.sort();
+    changes.changed.dedup();
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use crate::config::ScanConfig;
+    use crate::ignore::IGNORE_FILE;
+    use crate::test_utils::temp_library;
+
+    use super::*;
+
+    const DEBOUNCE: Duration = Duration::from_millis(200);
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn test_batches_changes() {
+        let root = temp_library("watcher-batches", &[]);
+        let (tx, rx) = mpsc::channel();
+        let watcher = LibraryWatcher::new(&root, DEBOUNCE, AudioFileFilter::default(), move |x| {
+            tx.send(x).unwrap()
+        })
+        .unwrap();
+
+        // Writes in quick succession end up in one batch
+        let track = root.join("track.flac");
+        fs::write(&track, b"first").unwrap();
+        fs::write(&track, b"second").unwrap();
+        fs::write(root.join("cover.jpg"), b"image").unwrap();
+        fs::create_dir_all(root.join(DATABASE_DIRECTORY)).unwrap();
+        fs::write(root.join(DATABASE_DIRECTORY).join("main.db"), b"data").unwrap();
+
+        let changes = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(changes.changed, vec![track.clone()]);
+        assert!(changes.removed.is_empty(), "{:?}", changes);
+
+        let moved = root.join("album").join("track.flac");
+        fs::create_dir_all(moved.parent().unwrap()).unwrap();
+        fs::rename(&track, &moved).unwrap();
+
+        let changes = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(changes.changed, vec![moved.clone()]);
+        assert_eq!(changes.removed, vec![track]);
+
+        fs::remove_dir_all(root.join("album")).unwrap();
+
+        let changes = rx.recv_timeout(TIMEOUT).unwrap();
+        assert!(changes.changed.is_empty(), "{:?}", changes);
+        assert!(changes.removed.contains(&moved), "{:?}", changes);
+
+        // Nothing is reported once the watcher is dropped
+        drop(watcher);
+        fs::write(root.join("late.flac"), b"late").unwrap();
+        assert!(rx.recv_timeout(DEBOUNCE * 3).is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_directory_moved_in() {
+        let root = temp_library("watcher-directory", &[]);
+        let outside = temp_library("watcher-outside", &[]);
+        fs::create_dir_all(outside.join("disc1")).unwrap();
+        fs::write(outside.join("disc1").join("01.mp3"), b"one").unwrap();
+        fs::write(outside.join("02.ogg"), b"two").unwrap();
+        fs::write(outside.join("notes.txt"), b"notes").unwrap();
+        // Recognized by its content
+        fs::write(outside.join("03.bin"), b"fLaC\0\0\0\x22").unwrap();
+
+        let filter = AudioFileFilter::new(&ScanConfig {
+            sniff: true,
+            ..Default::default()
+        });
+        let (tx, rx) = mpsc::channel();
+        let _watcher =
+            LibraryWatcher::new(&root, DEBOUNCE, filter, move |x| tx.send(x).unwrap()).unwrap();
+
+        // Moving a directory in only reports the directory itself
+        let album = root.join("album");
+        fs::rename(&outside, &album).unwrap();
+
+        let changes = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(
+            changes.changed,
+            vec![
+                album.join("02.ogg"),
+                album.join("03.bin"),
+                album.join("disc1").join("01.mp3")
+            ]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_cue_sheet_changes() {
+        let root = temp_library("watcher-cue", &[]);
+        let album = root.join("album.flac");
+        fs::write(&album, b"album").unwrap();
+        fs::write(root.join("other.flac"), b"other").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let _watcher = LibraryWatcher::new(&root, DEBOUNCE, AudioFileFilter::default(), move |x| {
+            tx.send(x).unwrap()
+        })
+        .unwrap();
+
+        // A sheet changes the files it describes
+        let sheet = root.join("album.cue");
+        fs::write(&sheet, "FILE \"album.wav\" WAVE\n").unwrap();
+
+        let changes = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(changes.changed, vec![album.clone()]);
+        assert!(changes.removed.is_empty(), "{:?}", changes);
+
+        // A removed sheet changes every file next to it
+        fs::remove_file(&sheet).unwrap();
+
+        let changes = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(changes.changed, vec![album, root.join("other.flac")]);
+        assert!(changes.removed.is_empty(), "{:?}", changes);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_ignored_changes() {
+        let root = temp_library("watcher-ignored", &[]);
+        let (tx, rx) = mpsc::channel();
+        let _watcher = LibraryWatcher::new(&root, DEBOUNCE, AudioFileFilter::default(), move |x| {
+            tx.send(x).unwrap()
+        })
+        .unwrap();
+
+        // System directories are skipped by default
+        fs::create_dir_all(root.join("@eaDir")).unwrap();
+        fs::write(root.join("@eaDir").join("thumb.mp3"), b"thumb").unwrap();
+        let track = root.join("track.mp3");
+        fs::write(&track, b"track").unwrap();
+
+        let changes = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(changes.changed, vec![track]);
+
+        // Changed rules may apply to any file, which only a scan finds
+        fs::write(root.join(IGNORE_FILE), "live/\n").unwrap();
+
+        let changes = rx.recv_timeout(TIMEOUT).unwrap();
+        assert!(changes.rescan, "{:?}", changes);
+
+        fs::create_dir_all(root.join("live")).unwrap();
+        fs::write(root.join("live").join("01.mp3"), b"live").unwrap();
+        let other = root.join("other.mp3");
+        fs::write(&other, b"other").unwrap();
+
+        let changes = rx.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(changes.changed, vec![other]);
+        assert!(!changes.rescan, "{:?}", changes);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
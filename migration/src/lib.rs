@@ -18,6 +18,18 @@ mod m20231029_000017_create_search_index;
 mod m20231107_000018_add_column_primary_color;
 mod m20231110_000019_create_playback_queue_table;
 mod m20231117_000020_create_log_table;
+mod m20241201_000021_create_media_metadata_suggestions_table;
+mod m20241208_000022_add_loudness_columns;
+mod m20241215_000023_add_tempo_columns;
+mod m20241222_000024_add_key_columns;
+mod m20241229_000025_create_media_analysis_failures_table;
+mod m20250105_000026_create_media_analysis_features_table;
+mod m20250105_000027_create_recommendation_layout_table;
+mod m20250112_000028_add_analysis_parameter_columns;
+mod m20250112_000029_create_recommendation_scaling_table;
+mod m20250119_000030_add_media_file_size_column;
+mod m20250126_000031_add_media_file_range_columns;
+mod m20250202_000032_create_analysis_settings_table;
 
 pub struct Migrator;
 
@@ -43,6 +55,18 @@ impl MigratorTrait for Migrator {
             Box::new(m20231107_000018_add_column_primary_color::Migration),
             Box::new(m20231110_000019_create_playback_queue_table::Migration),
             Box::new(m20231117_000020_create_log_table::Migration),
+            Box::new(m20241201_000021_create_media_metadata_suggestions_table::Migration),
+            Box::new(m20241208_000022_add_loudness_columns::Migration),
+            Box::new(m20241215_000023_add_tempo_columns::Migration),
+            Box::new(m20241222_000024_add_key_columns::Migration),
+            Box::new(m20241229_000025_create_media_analysis_failures_table::Migration),
+            Box::new(m20250105_000026_create_media_analysis_features_table::Migration),
+            Box::new(m20250105_000027_create_recommendation_layout_table::Migration),
+            Box::new(m20250112_000028_add_analysis_parameter_columns::Migration),
+            Box::new(m20250112_000029_create_recommendation_scaling_table::Migration),
+            Box::new(m20250119_000030_add_media_file_size_column::Migration),
+            Box::new(m20250126_000031_add_media_file_range_columns::Migration),
+            Box::new(m20250202_000032_create_analysis_settings_table::Migration),
         ]
     }
 }
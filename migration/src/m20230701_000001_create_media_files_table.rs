@@ -68,4 +68,8 @@ pub enum MediaFiles {
     CoverArtId,
     SampleRate,
     Duration,
+    FileSize,
+    CueTrack,
+    StartTime,
+    EndTime,
 }
@@ -172,4 +172,16 @@ pub enum MediaAnalysis {
     Mfcc10,
     Mfcc11,
     Mfcc12,
+    IntegratedLoudness,
+    LoudnessRange,
+    TruePeak,
+    Bpm,
+    BpmConfidence,
+    BeatOffset,
+    MusicalKey,
+    KeyConfidence,
+    AnalyzerVersion,
+    WindowSize,
+    OverlapSize,
+    ComputingDevice,
 }
@@ -0,0 +1,124 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230701_000001_create_media_files_table::MediaFiles;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20241201_000021_create_media_metadata_suggestions_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MediaMetadataSuggestions::Table)
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::FileId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::AcoustidId)
+                            .string()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::RecordingId)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::ReleaseId)
+                            .string()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::TrackId)
+                            .string()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::ArtistIds)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::Title)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::Artist)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::Album)
+                            .text()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::Score)
+                            .double()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaMetadataSuggestions::CreatedAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-media_metadata_suggestions-file_id")
+                            .from(
+                                MediaMetadataSuggestions::Table,
+                                MediaMetadataSuggestions::FileId,
+                            )
+                            .to(MediaFiles::Table, MediaFiles::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(MediaMetadataSuggestions::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum MediaMetadataSuggestions {
+    Table,
+    Id,
+    FileId,
+    AcoustidId,
+    RecordingId,
+    ReleaseId,
+    TrackId,
+    ArtistIds,
+    Title,
+    Artist,
+    Album,
+    Score,
+    CreatedAt,
+}
@@ -0,0 +1,82 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230701_000001_create_media_files_table::MediaFiles;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20241229_000025_create_media_analysis_failures_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MediaAnalysisFailures::Table)
+                    .col(
+                        ColumnDef::new(MediaAnalysisFailures::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaAnalysisFailures::FileId)
+                            .integer()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaAnalysisFailures::Kind)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaAnalysisFailures::Detail)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaAnalysisFailures::Attempts)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaAnalysisFailures::UpdatedAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-media_analysis_failures-file_id")
+                            .from(MediaAnalysisFailures::Table, MediaAnalysisFailures::FileId)
+                            .to(MediaFiles::Table, MediaFiles::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MediaAnalysisFailures::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum MediaAnalysisFailures {
+    Table,
+    Id,
+    FileId,
+    Kind,
+    Detail,
+    Attempts,
+    UpdatedAt,
+}
@@ -0,0 +1,75 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230701_000001_create_media_files_table::MediaFiles;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20250105_000026_create_media_analysis_features_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MediaAnalysisFeatures::Table)
+                    .col(
+                        ColumnDef::new(MediaAnalysisFeatures::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaAnalysisFeatures::FileId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaAnalysisFeatures::Extractor)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaAnalysisFeatures::Version)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MediaAnalysisFeatures::Data)
+                            .var_binary(16777216)
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-media_analysis_features-file_id")
+                            .from(MediaAnalysisFeatures::Table, MediaAnalysisFeatures::FileId)
+                            .to(MediaFiles::Table, MediaFiles::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MediaAnalysisFeatures::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum MediaAnalysisFeatures {
+    Table,
+    Id,
+    FileId,
+    Extractor,
+    Version,
+    Data,
+}
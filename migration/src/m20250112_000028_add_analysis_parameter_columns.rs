@@ -0,0 +1,56 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20230701_000003_create_media_analysis_table::MediaAnalysis;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20250112_000028_add_analysis_parameter_columns"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Results stored before these columns existed have unknown
+        // parameters, and are left null
+        for column in [
+            MediaAnalysis::AnalyzerVersion,
+            MediaAnalysis::WindowSize,
+            MediaAnalysis::OverlapSize,
+            MediaAnalysis::ComputingDevice,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(MediaAnalysis::Table)
+                        .add_column(ColumnDef::new(column).integer().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            MediaAnalysis::AnalyzerVersion,
+            MediaAnalysis::WindowSize,
+            MediaAnalysis::OverlapSize,
+            MediaAnalysis::ComputingDevice,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(MediaAnalysis::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
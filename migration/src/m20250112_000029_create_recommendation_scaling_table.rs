@@ -0,0 +1,65 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20250112_000029_create_recommendation_scaling_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RecommendationScaling::Table)
+                    .col(
+                        ColumnDef::new(RecommendationScaling::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RecommendationScaling::Dimension)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RecommendationScaling::Center)
+                            .double()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RecommendationScaling::Scale)
+                            .double()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RecommendationScaling::Weight)
+                            .double()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RecommendationScaling::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum RecommendationScaling {
+    Table,
+    Id,
+    Dimension,
+    Center,
+    Scale,
+    Weight,
+}
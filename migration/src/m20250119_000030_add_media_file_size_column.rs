@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20230701_000001_create_media_files_table::MediaFiles;
+
+const FILE_HASH_INDEX: &str = "idx_media_files_file_hash_file_size";
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20250119_000030_add_media_file_size_column"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Files scanned before this column existed have an unknown size until
+        // they are scanned again
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MediaFiles::Table)
+                    .add_column(ColumnDef::new(MediaFiles::FileSize).big_integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Moved files are looked up by their content
+        manager
+            .create_index(
+                Index::create()
+                    .name(FILE_HASH_INDEX)
+                    .table(MediaFiles::Table)
+                    .col(MediaFiles::FileHash)
+                    .col(MediaFiles::FileSize)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(FILE_HASH_INDEX)
+                    .table(MediaFiles::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MediaFiles::Table)
+                    .drop_column(MediaFiles::FileSize)
+                    .to_owned(),
+            )
+            .await
+    }
+}
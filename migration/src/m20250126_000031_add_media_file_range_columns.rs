@@ -0,0 +1,60 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20230701_000001_create_media_files_table::MediaFiles;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20250126_000031_add_media_file_range_columns"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Tracks of a CUE sheet are played from a range of their file, the
+        // whole file is played when these are null
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MediaFiles::Table)
+                    .add_column(ColumnDef::new(MediaFiles::CueTrack).integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // SQLite only adds one column per statement
+        for column in [MediaFiles::StartTime, MediaFiles::EndTime] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(MediaFiles::Table)
+                        .add_column(ColumnDef::new(column).double().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            MediaFiles::CueTrack,
+            MediaFiles::StartTime,
+            MediaFiles::EndTime,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(MediaFiles::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m20250202_000032_create_analysis_settings_table"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AnalysisSettings::Table)
+                    .col(
+                        ColumnDef::new(AnalysisSettings::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(AnalysisSettings::ComputingDevice)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(AnalysisSettings::SegmentDuration)
+                            .double()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(AnalysisSettings::Extractors)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(AnalysisSettings::ScalingMethod)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(AnalysisSettings::FeatureWeights)
+                            .string()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AnalysisSettings::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum AnalysisSettings {
+    Table,
+    Id,
+    ComputingDevice,
+    SegmentDuration,
+    Extractors,
+    ScalingMethod,
+    FeatureWeights,
+}
@@ -7,10 +7,14 @@ use tokio::sync::Mutex;
 use tokio::task;
 use tokio_util::sync::CancellationToken;
 
-use database::actions::analysis::analysis_audio_library;
+use analysis::extractor::ExtractorSet;
+
+use database::actions::analysis::{
+    analysis_audio_library, set_analysis_settings, AnalysisOptions, AnalysisSettings,
+};
 use database::actions::cover_art::scan_cover_arts;
 use database::actions::metadata::scan_audio_library;
-use database::actions::recommendation::sync_recommendation;
+use database::actions::recommendation::{sync_recommendation, IndexScaling};
 use database::connection::MainDbConnection;
 use database::connection::RecommendationDbConnection;
 
@@ -55,6 +59,9 @@ impl Signal for CloseLibraryRequest {
         if let Some(token) = tokens.analyze_token.take() {
             token.cancel();
         }
+        if let Some(token) = tokens.watch_token.take() {
+            token.cancel();
+        }
 
         main_token.cancel();
 
@@ -68,6 +75,7 @@ impl ParamsExtractor for ScanAudioLibraryRequest {
     type Params = (
         Arc<MainDbConnection>,
         Arc<Mutex<TaskTokens>>,
+        Arc<Mutex<()>>,
         Arc<dyn Broadcaster>,
     );
 
@@ -75,6 +83,7 @@ impl ParamsExtractor for ScanAudioLibraryRequest {
         (
             Arc::clone(&all_params.main_db),
             Arc::clone(&all_params.task_tokens),
+            Arc::clone(&all_params.library_lock),
             Arc::clone(&all_params.broadcaster),
         )
     }
@@ -84,13 +93,14 @@ impl Signal for ScanAudioLibraryRequest {
     type Params = (
         Arc<MainDbConnection>,
         Arc<Mutex<TaskTokens>>,
+        Arc<Mutex<()>>,
         Arc<dyn Broadcaster>,
     );
     type Response = ();
 
     async fn handle(
         &self,
-        (main_db, task_tokens, broadcaster): Self::Params,
+        (main_db, task_tokens, library_lock, broadcaster): Self::Params,
         dart_signal: &Self,
     ) -> Result<Option<()>> {
         let mut tokens = task_tokens.lock().await;
@@ -110,6 +120,9 @@ impl Signal for ScanAudioLibraryRequest {
         task::spawn_blocking(move || {
             let runtime = tokio::runtime::Runtime::new().unwrap();
             runtime.block_on(async move {
+                // Wait for the watcher, or the task this one replaced
+                let _guard = library_lock.lock().await;
+
                 let path = request.path.clone();
                 let result: Result<()> = async {
                     let file_processed = scan_audio_library(
@@ -182,6 +195,7 @@ impl ParamsExtractor for AnalyzeAudioLibraryRequest {
         Arc<MainDbConnection>,
         Arc<RecommendationDbConnection>,
         Arc<Mutex<TaskTokens>>,
+        Arc<Mutex<()>>,
         Arc<dyn Broadcaster>,
     );
 
@@ -190,6 +204,7 @@ impl ParamsExtractor for AnalyzeAudioLibraryRequest {
             Arc::clone(&all_params.main_db),
             Arc::clone(&all_params.recommend_db),
             Arc::clone(&all_params.task_tokens),
+            Arc::clone(&all_params.library_lock),
             Arc::clone(&all_params.broadcaster),
         )
     }
@@ -200,13 +215,14 @@ impl Signal for AnalyzeAudioLibraryRequest {
         Arc<MainDbConnection>,
         Arc<RecommendationDbConnection>,
         Arc<Mutex<TaskTokens>>,
+        Arc<Mutex<()>>,
         Arc<dyn Broadcaster>,
     );
     type Response = ();
 
     async fn handle(
         &self,
-        (main_db, recommend_db, task_tokens, broadcaster): Self::Params,
+        (main_db, recommend_db, task_tokens, library_lock, broadcaster): Self::Params,
         dart_signal: &Self,
     ) -> Result<Option<Self::Response>> {
         let mut tokens = task_tokens.lock().await;
@@ -228,13 +244,42 @@ impl Signal for AnalyzeAudioLibraryRequest {
         task::spawn_blocking(move || {
             let runtime = tokio::runtime::Runtime::new().unwrap();
             runtime.block_on(async move {
+                let _guard = library_lock.lock().await;
+
                 let cloned_broadcaster = Arc::clone(&broadcaster);
                 let result = async {
+                    let extractors = if request.extractors.is_empty() {
+                        ExtractorSet::default()
+                    } else {
+                        ExtractorSet::from_names(&request.extractors)?
+                    };
+                    let scaling = IndexScaling {
+                        method: request.feature_scaling.into(),
+                        weights: request
+                            .feature_weights
+                            .iter()
+                            .map(|(name, weight)| (name.clone(), *weight))
+                            .collect(),
+                    };
+
+                    let settings = AnalysisSettings {
+                        computing_device: request.computing_device.into(),
+                        segment_duration: Some(request.segment_duration as f64)
+                            .filter(|x| *x > 0.0),
+                        extractors,
+                        scaling,
+                    };
+
                     let total_files = analysis_audio_library(
                         &main_db,
                         Path::new(&request_path),
                         batch_size,
-                        request.computing_device.into(),
+                        settings.computing_device,
+                        AnalysisOptions {
+                            segment_duration: settings.segment_duration,
+                            extractors,
+                            retry_failed: request.retry_failed,
+                        },
                         move |progress, total| {
                             cloned_broadcaster.broadcast(&AnalyzeAudioLibraryProgress {
                                 path: closure_request_path.clone(),
@@ -247,10 +292,15 @@ impl Signal for AnalyzeAudioLibraryRequest {
                     .await
                     .with_context(|| "Audio analysis failed")?;
 
-                    sync_recommendation(&main_db, &recommend_db)
+                    sync_recommendation(&main_db, &recommend_db, extractors, &settings.scaling)
                         .await
                         .with_context(|| "Recommendation synchronization failed")?;
 
+                    // Files changed later are analyzed the same way
+                    set_analysis_settings(&main_db, &settings)
+                        .await
+                        .with_context(|| "Failed to record analysis settings")?;
+
                     broadcaster.broadcast(&AnalyzeAudioLibraryResponse {
                         path: request_path.clone(),
                         total: total_files as i32,
@@ -5,7 +5,10 @@ use anyhow::Result;
 use ::database::{
     connection::MainDbConnection, playing_item::dispatcher::PlayingItemActionDispatcher,
 };
-use ::lyric::parser::parse_audio_lyrics;
+use ::lyric::{
+    parser::parse_audio_lyrics_with_source,
+    types::{LyricLine, LyricSource as LyricFileSource, LyricTrackKind as LyricFileTrackKind},
+};
 use ::playback::player::PlayingItem;
 
 use crate::{
@@ -14,6 +17,25 @@ use crate::{
     Signal,
 };
 
+fn to_content_lines(lines: Vec<LyricLine>) -> Vec<LyricContentLine> {
+    lines
+        .into_iter()
+        .map(|x| LyricContentLine {
+            start_time: x.start_time.into(),
+            end_time: x.end_time.into(),
+            sections: x
+                .word_time_tags
+                .into_iter()
+                .map(|tag| LyricContentLineSection {
+                    start_time: tag.0.into(),
+                    end_time: tag.1.into(),
+                    content: tag.2,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
 impl ParamsExtractor for GetLyricByTrackIdRequest {
     type Params = (Arc<String>, Arc<MainDbConnection>);
 
@@ -52,30 +74,55 @@ impl Signal for GetLyricByTrackIdRequest {
 
             match path {
                 Some(path) => {
-                    let lyric = parse_audio_lyrics(path.to_path_buf());
+                    let lyric = parse_audio_lyrics_with_source(path.to_path_buf());
 
                     match lyric {
-                        Some(lyric) => match lyric {
-                            Ok(lyric) => Ok(Some(GetLyricByTrackIdResponse {
-                                item: Some(item.clone()),
-                                lines: lyric
-                                    .lyrics
-                                    .into_iter()
-                                    .map(|x| LyricContentLine {
-                                        start_time: x.start_time.into(),
-                                        end_time: x.end_time.into(),
-                                        sections: x
-                                            .word_time_tags
-                                            .into_iter()
-                                            .map(|tag| LyricContentLineSection {
-                                                start_time: tag.0.into(),
-                                                end_time: tag.1.into(),
-                                                content: tag.2,
-                                            })
-                                            .collect(),
+                        Some((source, lyric)) => match lyric {
+                            Ok(lyric) => {
+                                let tracks = lyric
+                                    .tracks
+                                    .iter()
+                                    .enumerate()
+                                    .map(|(index, track)| LyricTrackSummary {
+                                        index: index as i32 + 1,
+                                        kind: match track.kind {
+                                            LyricFileTrackKind::Translation => {
+                                                LyricTrackKind::Translation
+                                            }
+                                            LyricFileTrackKind::Romanization => {
+                                                LyricTrackKind::Romanization
+                                            }
+                                        } as i32,
+                                        language: track.language.clone().unwrap_or_default(),
                                     })
-                                    .collect(),
-                            })),
+                                    .collect();
+
+                                let selected = if dart_signal.track > 0 {
+                                    lyric.tracks.get(dart_signal.track as usize - 1)
+                                } else {
+                                    None
+                                };
+
+                                // Fall back to the original lyrics for unknown tracks
+                                let (track, lines) = match selected {
+                                    Some(selected) => (dart_signal.track, selected.lyrics.clone()),
+                                    None => (0, lyric.lyrics),
+                                };
+
+                                Ok(Some(GetLyricByTrackIdResponse {
+                                    item: Some(item.clone()),
+                                    source: match source {
+                                        LyricFileSource::Sidecar => LyricSource::Sidecar,
+                                        LyricFileSource::Embedded => LyricSource::Embedded,
+                                        LyricFileSource::EmbeddedSynchronized => {
+                                            LyricSource::EmbeddedSynchronized
+                                        }
+                                    } as i32,
+                                    lines: to_content_lines(lines),
+                                    track,
+                                    tracks,
+                                }))
+                            }
                             Err(err) => {
                                 Err(err.context(format!("Unable to parse lyric: item={:#?}", item)))
                             }
@@ -83,12 +130,18 @@ impl Signal for GetLyricByTrackIdRequest {
                         None => Ok(Some(GetLyricByTrackIdResponse {
                             item: Some(item.clone()),
                             lines: [].to_vec(),
+                            source: LyricSource::NoLyric as i32,
+                            track: 0,
+                            tracks: [].to_vec(),
                         })),
                     }
                 }
                 None => Ok(Some(GetLyricByTrackIdResponse {
                     item: Some(item.clone()),
                     lines: [].to_vec(),
+                    source: LyricSource::NoLyric as i32,
+                    track: 0,
+                    tracks: [].to_vec(),
                 })),
             }
         } else {
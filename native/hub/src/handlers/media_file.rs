@@ -4,6 +4,7 @@ use std::sync::Arc;
 use anyhow::{anyhow, Context, Result};
 use sea_orm::DatabaseConnection;
 
+use ::database::actions::analysis::get_analysis_key;
 use ::database::actions::cover_art::{bake_cover_art_by_file_ids, bake_cover_art_by_media_files};
 use ::database::actions::file::{get_files_by_ids, get_media_files, list_files};
 use ::database::actions::metadata::{get_metadata_summary_by_files, get_parsed_file_by_id};
@@ -148,6 +149,10 @@ impl Signal for FetchParsedMediaFileRequest {
             .ok_or(anyhow!("Parsed album not found for file_id: {}", file_id))
             .with_context(|| "Failed to query album")?;
 
+        let key = get_analysis_key(&db, file_id)
+            .await
+            .with_context(|| "Failed to query musical key")?;
+
         Ok(Some(FetchParsedMediaFileResponse {
             file: Some(media_file.clone()),
             artists: artists
@@ -161,6 +166,12 @@ impl Signal for FetchParsedMediaFileRequest {
                 id: album.id,
                 name: album.name,
             }),
+            key: key.map(|x| MusicalKey {
+                name: x.key.to_string(),
+                camelot: x.key.camelot(),
+                open_key: x.key.open_key(),
+                confidence: x.confidence,
+            }),
         }))
     }
 }
@@ -1,16 +1,20 @@
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use ::playback::player::Playable;
 use anyhow::{Context, Result};
+use log::error;
 use tokio::sync::Mutex;
 
+use ::database::actions::analysis::get_analysis_loudness;
 use ::database::actions::mixes::query_mix_media_files;
 use ::database::actions::stats::increase_skipped;
 use ::database::connection::MainDbConnection;
 use ::database::connection::RecommendationDbConnection;
 use ::database::playing_item::dispatcher::PlayingItemActionDispatcher;
 use ::playback::player::PlayingItem;
+use ::playback::replaygain::{FallbackLoudness, ReplayGainSettings};
 use ::playback::strategies::AddMode;
 
 use crate::utils::files_to_playback_request;
@@ -386,6 +390,55 @@ impl Signal for SetAdaptiveSwitchingEnabledRequest {
     }
 }
 
+impl ParamsExtractor for SetCrossfadeRequest {
+    type Params = (Arc<Mutex<dyn Playable>>,);
+
+    fn extract_params(&self, all_params: &GlobalParams) -> Self::Params {
+        (Arc::clone(&all_params.player),)
+    }
+}
+
+impl Signal for SetCrossfadeRequest {
+    type Params = (Arc<Mutex<dyn Playable>>,);
+    type Response = ();
+
+    async fn handle(
+        &self,
+        (player,): Self::Params,
+        dart_signal: &Self,
+    ) -> Result<Option<Self::Response>> {
+        let duration = Duration::from_secs_f64(dart_signal.seconds.max(0.0));
+        player.lock().await.set_crossfade(duration);
+        Ok(Some(()))
+    }
+}
+
+impl ParamsExtractor for SetReplayGainRequest {
+    type Params = (Arc<Mutex<dyn Playable>>,);
+
+    fn extract_params(&self, all_params: &GlobalParams) -> Self::Params {
+        (Arc::clone(&all_params.player),)
+    }
+}
+
+impl Signal for SetReplayGainRequest {
+    type Params = (Arc<Mutex<dyn Playable>>,);
+    type Response = ();
+
+    async fn handle(
+        &self,
+        (player,): Self::Params,
+        dart_signal: &Self,
+    ) -> Result<Option<Self::Response>> {
+        player.lock().await.set_replay_gain(ReplayGainSettings {
+            mode: dart_signal.mode.into(),
+            preamp: dart_signal.preamp,
+            prevent_clipping: dart_signal.prevent_clipping,
+        });
+        Ok(Some(()))
+    }
+}
+
 impl ParamsExtractor for OperatePlaybackWithMixQueryRequest {
     type Params = (
         Arc<MainDbConnection>,
@@ -451,6 +504,35 @@ impl Signal for OperatePlaybackWithMixQueryRequest {
             .collect()
         };
 
+        // Loudness from the analysis, for files without ReplayGain tags
+        let file_ids = tracks
+            .iter()
+            .filter_map(|x| match x.item {
+                PlayingItem::InLibrary(id) => Some(id),
+                _ => None,
+            })
+            .collect();
+        // Tracks without it play at their own level, which is no reason to
+        // fail the whole request
+        let loudness = match get_analysis_loudness(&main_db, file_ids).await {
+            Ok(x) => x
+                .into_iter()
+                .map(|(id, loudness, true_peak)| {
+                    (
+                        PlayingItem::InLibrary(id),
+                        FallbackLoudness {
+                            loudness,
+                            true_peak,
+                        },
+                    )
+                })
+                .collect(),
+            Err(e) => {
+                error!("Failed to get the loudness of tracks: {:?}", e);
+                vec![]
+            }
+        };
+
         let mut player = player.lock().await;
 
         let operate_mode = PlaylistOperateMode::try_from(request.operate_mode)?;
@@ -459,6 +541,9 @@ impl Signal for OperatePlaybackWithMixQueryRequest {
             player.clear_playlist();
         }
 
+        // After clearing, which forgets the loudness of the old playlist
+        player.set_fallback_loudness(loudness);
+
         let add_mode = if operate_mode == PlaylistOperateMode::PlayNext {
             AddMode::PlayNext
         } else {
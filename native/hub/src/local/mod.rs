@@ -21,6 +21,7 @@ use crate::listen_local_gui_event;
 use crate::messages::*;
 use crate::server::ServerManager;
 use crate::utils::device_scanner::DeviceScanner;
+use crate::utils::library_watcher::initialize_library_watcher;
 use crate::utils::player::initialize_local_player;
 use crate::utils::Broadcaster;
 use crate::utils::DatabaseConnections;
@@ -49,7 +50,12 @@ pub async fn local_player_loop(
         let config_path: Arc<String> = Arc::new(config_path);
 
         let main_cancel_token = CancellationToken::new();
-        let task_tokens: Arc<Mutex<TaskTokens>> = Arc::new(Mutex::new(TaskTokens::default()));
+        let watch_token = main_cancel_token.child_token();
+        let task_tokens: Arc<Mutex<TaskTokens>> = Arc::new(Mutex::new(TaskTokens {
+            watch_token: Some(watch_token.clone()),
+            ..Default::default()
+        }));
+        let library_lock: Arc<Mutex<()>> = Arc::new(Mutex::new(()));
 
         info!("Initializing player");
         let player = Player::new(Some(main_cancel_token.clone()));
@@ -80,6 +86,16 @@ pub async fn local_player_loop(
             cert_validator.clone(),
         ));
 
+        info!("Initializing library watcher");
+        tokio::spawn(initialize_library_watcher(
+            lib_path.clone(),
+            main_db.clone(),
+            recommend_db.clone(),
+            broadcaster.clone(),
+            library_lock.clone(),
+            watch_token,
+        ));
+
         info!("Initializing UI events");
         let global_params = GlobalParams {
             lib_path,
@@ -88,6 +104,7 @@ pub async fn local_player_loop(
             recommend_db,
             main_token: Arc::clone(&main_cancel_token),
             task_tokens,
+            library_lock,
             player,
             sfx_player,
             scrobbler,
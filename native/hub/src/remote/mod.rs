@@ -269,6 +269,7 @@ pub async fn server_player_loop(url: &String) {
             bridge,
             ScanAudioLibraryProgress,
             ScanAudioLibraryResponse,
+            LibraryWatchUpdate,
             SetMediaLibraryPathResponse,
             AnalyzeAudioLibraryProgress,
             AnalyzeAudioLibraryResponse,
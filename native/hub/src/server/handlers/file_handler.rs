@@ -7,9 +7,13 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use dunce::canonicalize;
+use log::warn;
 use tower::ServiceExt;
 use tower_http::services::ServeDir;
 
+use ::metadata::config::LibraryConfig;
+use ::metadata::scanner::AudioFileFilter;
+
 use crate::server::ServerState;
 
 pub async fn file_handler(
@@ -46,6 +50,17 @@ pub async fn file_handler(
         return StatusCode::FORBIDDEN.into_response();
     }
 
+    // Paths left out of the library are not served, like the database
+    if prefix == "library" {
+        let config = LibraryConfig::load(root_dir).unwrap_or_else(|e| {
+            warn!("Using the default library config: {:?}", e);
+            LibraryConfig::default()
+        });
+        if AudioFileFilter::new(&config.scan).is_ignored(root_dir, &canonical_path) {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    }
+
     // Get the relative path
     let relative_path = match canonical_path.strip_prefix(root_dir) {
         Ok(path) => path,
@@ -22,8 +22,9 @@ use hub::{
         ServerManager, WebSocketService,
     },
     utils::{
-        device_scanner::DeviceScanner, initialize_databases, player::initialize_local_player,
-        GlobalParams, TaskTokens,
+        device_scanner::DeviceScanner, initialize_databases,
+        library_watcher::initialize_library_watcher, player::initialize_local_player, GlobalParams,
+        TaskTokens,
     },
 };
 
@@ -197,7 +198,12 @@ async fn initialize_global_params(lib_path: &str, config_path: &str) -> Result<A
     let config_path: Arc<String> = Arc::new(config_path.to_string());
 
     let main_cancel_token = CancellationToken::new();
-    let task_tokens: Arc<Mutex<TaskTokens>> = Arc::new(Mutex::new(TaskTokens::default()));
+    let watch_token = main_cancel_token.child_token();
+    let task_tokens: Arc<Mutex<TaskTokens>> = Arc::new(Mutex::new(TaskTokens {
+        watch_token: Some(watch_token.clone()),
+        ..Default::default()
+    }));
+    let library_lock: Arc<Mutex<()>> = Arc::new(Mutex::new(()));
 
     info!("Initializing player");
     let player = Player::new(Some(main_cancel_token.clone()));
@@ -227,6 +233,16 @@ async fn initialize_global_params(lib_path: &str, config_path: &str) -> Result<A
         cert_validator.clone(),
     ));
 
+    info!("Initializing library watcher");
+    tokio::spawn(initialize_library_watcher(
+        lib_path.clone(),
+        main_db.clone(),
+        recommend_db.clone(),
+        broadcaster.clone(),
+        library_lock.clone(),
+        watch_token,
+    ));
+
     let global_params = Arc::new(GlobalParams {
         lib_path,
         config_path,
@@ -234,6 +250,7 @@ async fn initialize_global_params(lib_path: &str, config_path: &str) -> Result<A
         recommend_db,
         main_token: main_cancel_token,
         task_tokens,
+        library_lock,
         player,
         sfx_player,
         scrobbler,
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use dunce::canonicalize;
+use log::{error, info, warn};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task;
+use tokio_util::sync::CancellationToken;
+
+use ::database::actions::watcher::{sync_library_changes, WatchOptions};
+use ::database::connection::{MainDbConnection, RecommendationDbConnection};
+use ::metadata::config::LibraryConfig;
+use ::metadata::scanner::AudioFileFilter;
+use ::metadata::watcher::LibraryWatcher;
+
+use crate::messages::*;
+use crate::utils::{determine_batch_size, Broadcaster};
+
+/// How long the library has to stay quiet before its changes are processed,
+/// so that files being copied are only read once they are complete.
+const DEBOUNCE: Duration = Duration::from_millis(1500);
+
+/// Keeps the database in sync with the files of the library until the token
+/// is cancelled, and tells the clients which files changed. Changes are only
+/// processed while holding `library_lock`, after any running scan or analysis.
+pub async fn initialize_library_watcher(
+    lib_path: Arc<String>,
+    main_db: Arc<MainDbConnection>,
+    recommend_db: Arc<RecommendationDbConnection>,
+    broadcaster: Arc<dyn Broadcaster>,
+    library_lock: Arc<Mutex<()>>,
+    cancel_token: CancellationToken,
+) -> Result<()> {
+    // Events are reported under the canonical path of the root
+    let root = canonicalize(lib_path.as_str())
+        .with_context(|| format!("Failed to resolve library path: {}", lib_path))?;
+
+    let config = LibraryConfig::load(&root).unwrap_or_else(|e| {
+        warn!("Using the default library config: {:?}", e);
+        LibraryConfig::default()
+    });
+    let filter = AudioFileFilter::new(&config.scan);
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let watcher = match LibraryWatcher::new(&root, DEBOUNCE, filter, move |changes| {
+        // The receiver is only gone once the watcher is stopping
+        let _ = tx.send(changes);
+    }) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Failed to watch library: {:?}", e);
+            return Err(e);
+        }
+    };
+    info!("Watching library: {}", root.display());
+
+    let options = WatchOptions {
+        batch_size: determine_batch_size(0.5),
+    };
+
+    loop {
+        let changes = tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            changes = rx.recv() => match changes {
+                Some(x) => x,
+                None => break,
+            },
+        };
+
+        let guard = tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            guard = library_lock.lock() => guard,
+        };
+
+        let result = sync_library_changes(
+            &main_db,
+            &recommend_db,
+            &root,
+            &changes,
+            options,
+            Some(cancel_token.clone()),
+        )
+        .await;
+        drop(guard);
+
+        match result {
+            Ok(update) => {
+                if !update.is_empty() {
+                    info!(
+                        "Library changed: {} files changed, {} files moved, {} files removed",
+                        update.changed_ids.len(),
+                        update.moved_ids.len(),
+                        update.removed_ids.len()
+                    );
+                    broadcaster.broadcast(&LibraryWatchUpdate {
+                        path: lib_path.to_string(),
+                        changed_ids: update.changed_ids,
+                        removed_ids: update.removed_ids,
+                        rescanned: update.rescanned,
+                        moved_ids: update.moved_ids,
+                    });
+                }
+            }
+            Err(e) => error!("Failed to sync library changes: {:?}", e),
+        }
+    }
+
+    // Stopping the watcher joins its thread
+    if let Err(e) = task::spawn_blocking(move || drop(watcher)).await {
+        error!("Failed to stop the library watcher: {:?}", e);
+    }
+    info!("Stopped watching library: {}", root.display());
+
+    Ok(())
+}
@@ -1,9 +1,10 @@
 pub mod broadcastable;
 pub mod device_scanner;
+pub mod library_watcher;
 pub mod player;
 
 use std::fmt::Debug;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::sync::{Arc, OnceLock};
 
 use anyhow::{Context, Result};
@@ -28,6 +29,7 @@ use ::database::entities::media_files;
 use ::database::playing_item::MediaFileHandle;
 use ::playback::player::{Playable, PlayingItem};
 use ::playback::sfx_player::SfxPlayer;
+use ::playback::PlaylistItem;
 use ::scrobbling::manager::ScrobblingManager;
 
 use crate::local::local_player_loop;
@@ -69,6 +71,7 @@ pub async fn initialize_databases(
 pub struct TaskTokens {
     pub scan_token: Option<CancellationToken>,
     pub analyze_token: Option<CancellationToken>,
+    pub watch_token: Option<CancellationToken>,
 }
 
 pub struct GlobalParams {
@@ -78,6 +81,9 @@ pub struct GlobalParams {
     pub recommend_db: Arc<RecommendationDbConnection>,
     pub main_token: Arc<CancellationToken>,
     pub task_tokens: Arc<Mutex<TaskTokens>>,
+    /// Held while the library is scanned, analyzed or synced with the
+    /// changes the watcher found, so that only one of them writes at a time.
+    pub library_lock: Arc<Mutex<()>>,
     pub player: Arc<Mutex<dyn Playable>>,
     pub sfx_player: Arc<Mutex<SfxPlayer>>,
     pub scrobbler: Arc<Mutex<ScrobblingManager>>,
@@ -366,7 +372,7 @@ pub async fn parse_media_files(
 pub fn files_to_playback_request(
     lib_path: &String,
     files: &[MediaFileHandle],
-) -> Vec<(PlayingItem, PathBuf)> {
+) -> Vec<PlaylistItem> {
     files
         .iter()
         .filter_map(|file| {
@@ -379,7 +385,11 @@ pub fn files_to_playback_request(
             };
 
             match canonicalize(&file_path) {
-                Ok(canonical_path) => Some((file.item.clone(), canonical_path)),
+                Ok(canonical_path) => Some(PlaylistItem {
+                    item: file.item.clone(),
+                    path: canonical_path,
+                    range: file.range,
+                }),
                 Err(_) => None,
             }
         })
@@ -102,6 +102,16 @@ pub fn define_request_types(_input: TokenStream) -> TokenStream {
             response: None,
             local_only: false,
         },
+        RequestResponse {
+            request: "SetCrossfadeRequest".to_string(),
+            response: None,
+            local_only: false,
+        },
+        RequestResponse {
+            request: "SetReplayGainRequest".to_string(),
+            response: None,
+            local_only: false,
+        },
         // SFX
         RequestResponse {
             request: "SfxPlayRequest".to_string(),
@@ -0,0 +1,572 @@
+use std::f32::consts::FRAC_PI_2;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::error;
+use rodio::source::SeekError;
+use rodio::{Sample, Source};
+
+/// Gain shared by every track of a sink. The audio thread ramps towards the
+/// target, which is how the player fades out before pausing and back in
+/// when resuming.
+#[derive(Clone)]
+pub struct FadeHandle {
+    target: Arc<AtomicU32>,
+    // Microseconds
+    duration: Arc<AtomicU32>,
+}
+
+impl Default for FadeHandle {
+    fn default() -> Self {
+        FadeHandle {
+            target: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            duration: Arc::new(AtomicU32::new(0)),
+        }
+    }
+}
+
+impl FadeHandle {
+    pub fn fade_to(&self, gain: f32, duration: Duration) {
+        self.duration
+            .store(duration.as_micros() as u32, Ordering::SeqCst);
+        self.target.store(gain.to_bits(), Ordering::SeqCst);
+    }
+
+    fn target(&self) -> f32 {
+        f32::from_bits(self.target.load(Ordering::SeqCst))
+    }
+
+    fn duration(&self) -> Duration {
+        Duration::from_micros(self.duration.load(Ordering::SeqCst) as u64)
+    }
+}
+
+pub type NextSource<S> = Box<dyn Source<Item = S> + Send>;
+
+struct PendingCrossfade<S> {
+    next: NextSource<S>,
+    frames: u64,
+    mixed: Arc<AtomicU64>,
+}
+
+/// The track to fade in over the end of the current one. The current track
+/// takes it out of the slot when the crossfade starts, so the audio thread
+/// does not lock for every frame.
+pub struct CrossfadeSlot<S> {
+    inner: Arc<Mutex<Option<PendingCrossfade<S>>>>,
+    // Length of the attached crossfade, 0 if there is none. Stays set once
+    // the current track took the crossfade out.
+    frames: Arc<AtomicU64>,
+}
+
+impl<S> Clone for CrossfadeSlot<S> {
+    fn clone(&self) -> Self {
+        CrossfadeSlot {
+            inner: Arc::clone(&self.inner),
+            frames: Arc::clone(&self.frames),
+        }
+    }
+}
+
+impl<S> Default for CrossfadeSlot<S> {
+    fn default() -> Self {
+        CrossfadeSlot {
+            inner: Arc::new(Mutex::new(None)),
+            frames: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl<S> CrossfadeSlot<S> {
+    /// Mix the first `frames` frames of `next` into the end of the track.
+    /// `mixed` counts the frames of `next` played that way, so the next
+    /// track knows where it picks up.
+    pub fn attach(&self, next: NextSource<S>, frames: u64, mixed: Arc<AtomicU64>) {
+        if let Ok(mut inner) = self.inner.lock() {
+            *inner = Some(PendingCrossfade {
+                next,
+                frames,
+                mixed,
+            });
+            self.frames.store(frames, Ordering::SeqCst);
+        }
+    }
+
+    /// Drop the pending crossfade. Returns `false` if it already started.
+    pub fn detach(&self) -> bool {
+        let Ok(mut inner) = self.inner.lock() else {
+            return false;
+        };
+
+        // Taken out by the current track
+        if inner.is_none() && self.frames.load(Ordering::SeqCst) > 0 {
+            return false;
+        }
+
+        *inner = None;
+        self.frames.store(0, Ordering::SeqCst);
+        true
+    }
+
+    fn take(&self) -> Option<PendingCrossfade<S>> {
+        self.inner.lock().ok()?.take()
+    }
+
+    fn restore(&self, crossfade: PendingCrossfade<S>) {
+        if let Ok(mut inner) = self.inner.lock() {
+            *inner = Some(crossfade);
+        }
+    }
+}
+
+/// Gains of the outgoing and incoming tracks at `progress` through a
+/// crossfade. The curves keep the perceived loudness constant.
+pub fn equal_power(progress: f32) -> (f32, f32) {
+    let angle = progress.clamp(0.0, 1.0) * FRAC_PI_2;
+    (angle.cos(), angle.sin())
+}
+
+/// Wraps a track to apply the pause/resume fade, fade around seeks, and mix
+/// the next track in over its end.
+pub struct Transition<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    source: I,
+    channels: u16,
+    sample_rate: u32,
+    total_frames: Option<u64>,
+    position: u64,
+    channel: u16,
+    started: bool,
+
+    fade: FadeHandle,
+    gain: f32,
+
+    seek: Option<Duration>,
+    // Length of the fade around seeks, and how far up that fade we are
+    seek_frames: u64,
+    seek_level: u64,
+
+    // Frames of this track already played by the crossfade of the previous
+    incoming: Arc<AtomicU64>,
+    outgoing: CrossfadeSlot<I::Item>,
+    // The crossfade once taken out of the slot
+    crossfade: Option<PendingCrossfade<I::Item>>,
+    next_frame: Vec<I::Item>,
+    out_gain: f32,
+    in_gain: f32,
+}
+
+impl<I> Transition<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    pub fn new(
+        source: I,
+        fade: FadeHandle,
+        seek_fade: Duration,
+        incoming: Arc<AtomicU64>,
+        outgoing: CrossfadeSlot<I::Item>,
+    ) -> Self {
+        let channels = source.channels().max(1);
+        let sample_rate = source.sample_rate();
+        let total_frames = source
+            .total_duration()
+            .map(|x| (x.as_secs_f64() * sample_rate as f64).round() as u64);
+        let seek_frames = (seek_fade.as_secs_f64() * sample_rate as f64).round() as u64;
+
+        Transition {
+            source,
+            channels,
+            sample_rate,
+            total_frames,
+            position: 0,
+            channel: 0,
+            started: false,
+            gain: fade.target(),
+            fade,
+            seek: None,
+            seek_frames,
+            seek_level: seek_frames,
+            incoming,
+            outgoing,
+            crossfade: None,
+            next_frame: Vec::with_capacity(channels as usize),
+            out_gain: 1.0,
+            in_gain: 0.0,
+        }
+    }
+
+    /// Gain change per frame for a linear ramp over `duration`.
+    fn step(&self, duration: Duration) -> f32 {
+        let frames = duration.as_secs_f64() * self.sample_rate as f64;
+        if frames < 1.0 {
+            1.0
+        } else {
+            (1.0 / frames) as f32
+        }
+    }
+
+    fn seek_now(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.position = (pos.as_secs_f64() * self.sample_rate as f64).round() as u64;
+
+        // Leaving the crossfade, the next track has to start over and can be
+        // withdrawn again
+        if let Some(mut crossfade) = self.crossfade.take() {
+            if crossfade.mixed.load(Ordering::SeqCst) > 0 {
+                crossfade.mixed.store(0, Ordering::SeqCst);
+                if let Err(e) = crossfade.next.try_seek(Duration::ZERO) {
+                    error!("Failed to rewind the next track: {:?}", e);
+                }
+            }
+            self.outgoing.restore(crossfade);
+        }
+
+        Ok(())
+    }
+
+    fn begin_frame(&mut self) {
+        if !self.started {
+            self.started = true;
+            self.position += self.incoming.load(Ordering::SeqCst);
+        }
+
+        let target = self.fade.target();
+        if self.gain != target {
+            let step = self.step(self.fade.duration());
+            self.gain = if self.gain < target {
+                (self.gain + step).min(target)
+            } else {
+                (self.gain - step).max(target)
+            };
+        }
+
+        if let Some(pos) = self.seek {
+            self.seek_level = self.seek_level.saturating_sub(1);
+            if self.seek_level == 0 {
+                self.seek = None;
+                if let Err(e) = self.seek_now(pos) {
+                    error!("Failed to seek: {:?}", e);
+                }
+            }
+        } else if self.seek_level < self.seek_frames {
+            self.seek_level += 1;
+        }
+    }
+
+    /// Pull the frame of the next track to mix with the current frame.
+    fn mix_next_frame(&mut self) {
+        self.next_frame.clear();
+        self.out_gain = 1.0;
+        self.in_gain = 0.0;
+
+        let Some(total_frames) = self.total_frames else {
+            return;
+        };
+        let remaining = total_frames.saturating_sub(self.position);
+
+        if self.crossfade.is_none() {
+            let frames = self.outgoing.frames.load(Ordering::Relaxed);
+            if frames == 0 || remaining > frames {
+                return;
+            }
+            self.crossfade = self.outgoing.take();
+        }
+
+        let Some(crossfade) = self.crossfade.as_mut() else {
+            return;
+        };
+        if remaining > crossfade.frames {
+            return;
+        }
+
+        // Mixing needs both tracks in the same format, fall back to gapless
+        if crossfade.next.channels() != self.channels
+            || crossfade.next.sample_rate() != self.sample_rate
+        {
+            return;
+        }
+
+        for _ in 0..self.channels {
+            match crossfade.next.next() {
+                Some(sample) => self.next_frame.push(sample),
+                None => break,
+            }
+        }
+
+        if self.next_frame.len() == self.channels as usize {
+            crossfade.mixed.fetch_add(1, Ordering::SeqCst);
+            (self.out_gain, self.in_gain) =
+                equal_power(1.0 - remaining as f32 / crossfade.frames as f32);
+        } else {
+            self.next_frame.clear();
+        }
+    }
+}
+
+impl<I> Iterator for Transition<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    type Item = I::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<I::Item> {
+        if self.channel == 0 {
+            self.begin_frame();
+        }
+
+        let sample = self.source.next()?;
+        if self.channel == 0 {
+            self.mix_next_frame();
+        }
+
+        let mut gain = self.gain;
+        if self.seek_level < self.seek_frames {
+            gain *= self.seek_level as f32 / self.seek_frames as f32;
+        }
+
+        let mut output = sample.amplify(gain * self.out_gain);
+        if let Some(next) = self.next_frame.get(self.channel as usize) {
+            output = output.saturating_add(next.amplify(gain * self.in_gain));
+        }
+
+        self.channel += 1;
+        if self.channel >= self.channels {
+            self.channel = 0;
+            self.position += 1;
+        }
+
+        Some(output)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl<I> Source for Transition<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        // Nothing is audible while paused, so there is nothing to fade
+        let silent = self.gain == 0.0 && self.fade.target() == 0.0;
+
+        if silent || self.seek_frames == 0 || !self.started {
+            self.seek = None;
+            return self.seek_now(pos);
+        }
+
+        // The seek happens once the fade out is over
+        self.seek = Some(pos);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const RATE: u32 = 1000;
+
+    fn constant(value: f32, frames: usize) -> SamplesBuffer<f32> {
+        SamplesBuffer::new(1, RATE, vec![value; frames])
+    }
+
+    fn transition(source: SamplesBuffer<f32>, fade: FadeHandle) -> Transition<SamplesBuffer<f32>> {
+        Transition::new(
+            source,
+            fade,
+            Duration::from_millis(10),
+            Arc::new(AtomicU64::new(0)),
+            CrossfadeSlot::default(),
+        )
+    }
+
+    fn assert_close(actual: f32, expected: f32, index: usize) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "sample {}: {} != {}",
+            index,
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_fade_out_and_in() {
+        let fade = FadeHandle::default();
+        let mut source = transition(constant(1.0, 100), fade.clone());
+
+        assert_eq!(source.next(), Some(1.0));
+
+        // 10 frames at 1000 Hz
+        fade.fade_to(0.0, Duration::from_millis(10));
+        let faded: Vec<f32> = (&mut source).take(15).collect();
+        for (i, sample) in faded.iter().enumerate() {
+            assert_close(*sample, (1.0 - (i + 1) as f32 / 10.0).max(0.0), i);
+        }
+
+        fade.fade_to(1.0, Duration::from_millis(20));
+        let faded: Vec<f32> = (&mut source).take(25).collect();
+        for (i, sample) in faded.iter().enumerate() {
+            assert_close(*sample, ((i + 1) as f32 / 20.0).min(1.0), i);
+        }
+    }
+
+    #[test]
+    fn test_seek_fade() {
+        let ramp: Vec<f32> = (0..1000).map(|x| x as f32).collect();
+        let mut source = transition(SamplesBuffer::new(1, RATE, ramp), FadeHandle::default());
+
+        assert_eq!((&mut source).take(100).last(), Some(99.0));
+
+        source.try_seek(Duration::from_millis(500)).unwrap();
+        let output: Vec<f32> = (&mut source).take(30).collect();
+
+        // Fading out the old position
+        for (i, sample) in output.iter().take(9).enumerate() {
+            let gain = 1.0 - (i + 1) as f32 / 10.0;
+            assert_close(*sample, (100 + i) as f32 * gain, i);
+        }
+        // Silence on the seek, then the new position fades in
+        assert_close(output[9], 0.0, 9);
+        for (i, sample) in output.iter().enumerate().skip(10) {
+            let gain = ((i - 9) as f32 / 10.0).min(1.0);
+            assert_close(*sample, (500 + i - 9) as f32 * gain, i);
+        }
+    }
+
+    #[test]
+    fn test_crossfade_envelope() {
+        let slot = CrossfadeSlot::default();
+        let mixed = Arc::new(AtomicU64::new(0));
+        slot.attach(Box::new(constant(1.0, 1000)), 100, mixed.clone());
+
+        // The current track is silent, so the output is the fade in alone
+        let source = Transition::new(
+            constant(0.0, 1000),
+            FadeHandle::default(),
+            Duration::from_millis(10),
+            Arc::new(AtomicU64::new(0)),
+            slot.clone(),
+        );
+        let output: Vec<f32> = source.collect();
+
+        assert_eq!(output.len(), 1000);
+        assert!(output[..900].iter().all(|x| *x == 0.0));
+        for (i, sample) in output.iter().enumerate().skip(900) {
+            let remaining = 1000 - i;
+            let (_, fade_in) = equal_power(1.0 - remaining as f32 / 100.0);
+            assert_close(*sample, fade_in, i);
+        }
+        assert_eq!(mixed.load(Ordering::SeqCst), 100);
+        assert!(!slot.detach());
+
+        // And the other way around
+        let slot = CrossfadeSlot::default();
+        slot.attach(
+            Box::new(constant(0.0, 1000)),
+            100,
+            Arc::new(AtomicU64::new(0)),
+        );
+        let source = Transition::new(
+            constant(1.0, 1000),
+            FadeHandle::default(),
+            Duration::from_millis(10),
+            Arc::new(AtomicU64::new(0)),
+            slot,
+        );
+        let output: Vec<f32> = source.collect();
+
+        assert!(output[..900].iter().all(|x| *x == 1.0));
+        for (i, sample) in output.iter().enumerate().skip(900) {
+            let remaining = 1000 - i;
+            let (fade_out, _) = equal_power(1.0 - remaining as f32 / 100.0);
+            assert_close(*sample, fade_out, i);
+        }
+    }
+
+    #[test]
+    fn test_crossfade_picks_up() {
+        let mixed = Arc::new(AtomicU64::new(40));
+        let mut source = Transition::new(
+            constant(1.0, 1000),
+            FadeHandle::default(),
+            Duration::from_millis(10),
+            mixed,
+            CrossfadeSlot::default(),
+        );
+
+        // The previous track already played the first 40 frames, so the
+        // position is ahead of what this source produced
+        source.next();
+        assert_eq!(source.position, 41);
+    }
+
+    #[test]
+    fn test_seek_out_of_crossfade() {
+        let slot = CrossfadeSlot::default();
+        let mixed = Arc::new(AtomicU64::new(0));
+        slot.attach(Box::new(constant(1.0, 1000)), 100, mixed.clone());
+
+        let mut source = Transition::new(
+            constant(0.0, 1000),
+            FadeHandle::default(),
+            Duration::ZERO,
+            Arc::new(AtomicU64::new(0)),
+            slot.clone(),
+        );
+
+        assert_eq!((&mut source).take(950).count(), 950);
+        assert_eq!(mixed.load(Ordering::SeqCst), 50);
+        assert!(slot.inner.lock().unwrap().is_none());
+        assert!(!slot.detach());
+
+        // Back before the crossfade, the next track is in the slot again
+        source.try_seek(Duration::from_millis(100)).unwrap();
+        assert_eq!(mixed.load(Ordering::SeqCst), 0);
+        assert!(slot.detach());
+        assert!(source.all(|x| x == 0.0));
+    }
+
+    #[test]
+    fn test_detach_before_crossfade() {
+        let slot = CrossfadeSlot::<f32>::default();
+        slot.attach(Box::new(constant(1.0, 10)), 5, Arc::new(AtomicU64::new(0)));
+
+        assert!(slot.detach());
+        assert!(slot.inner.lock().unwrap().is_none());
+    }
+}
@@ -0,0 +1,493 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::source::SeekError;
+use rodio::{Sample, Source};
+
+/// The largest `moov` box we are willing to load while looking for gapless
+/// information.
+const MAX_MOOV_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Encoder delay and padding, in frames.
+///
+/// Symphonia already trims LAME/Xing and Ogg priming samples on its own, but
+/// ignores the iTunes `iTunSMPB` tag written by most AAC encoders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GaplessInfo {
+    pub delay: u64,
+    pub padding: u64,
+    // Number of frames of the original audio, 0 if unknown
+    pub frames: u64,
+}
+
+/// Parse the value of an `iTunSMPB` tag, eg.
+/// ` 00000000 00000840 000001CA 00000000000F4BF6 00000000 ...`.
+pub fn parse_itunsmpb(value: &str) -> Option<GaplessInfo> {
+    let fields = value
+        .split_whitespace()
+        .map(|x| u64::from_str_radix(x, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    if fields.len() < 4 {
+        return None;
+    }
+
+    Some(GaplessInfo {
+        delay: fields[1],
+        padding: fields[2],
+        frames: fields[3],
+    })
+}
+
+fn read_box_header<R: Read + Seek>(reader: &mut R) -> Option<([u8; 4], u64)> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header).ok()?;
+
+    let size = u32::from_be_bytes(header[0..4].try_into().ok()?) as u64;
+    let name: [u8; 4] = header[4..8].try_into().ok()?;
+
+    let payload = match size {
+        0 => {
+            let position = reader.stream_position().ok()?;
+            let end = reader.seek(SeekFrom::End(0)).ok()?;
+            reader.seek(SeekFrom::Start(position)).ok()?;
+            end - position
+        }
+        1 => {
+            let mut large_size = [0u8; 8];
+            reader.read_exact(&mut large_size).ok()?;
+            u64::from_be_bytes(large_size).checked_sub(16)?
+        }
+        _ => size.checked_sub(8)?,
+    };
+
+    Some((name, payload))
+}
+
+/// Iterate over the boxes of an in-memory MP4 payload.
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+
+        let size = u32::from_be_bytes(data[0..4].try_into().ok()?) as usize;
+        let size = if size == 0 { data.len() } else { size };
+        if size < 8 || size > data.len() {
+            return None;
+        }
+
+        let (current, rest) = data.split_at(size);
+        data = rest;
+
+        Some((&current[4..8], &current[8..]))
+    })
+}
+
+fn child<'a>(data: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    boxes(data).find(|(x, _)| *x == name).map(|(_, x)| x)
+}
+
+fn find_itunsmpb(moov: &[u8]) -> Option<String> {
+    let udta = child(moov, b"udta")?;
+    // `meta` is a full box, the children start after the version and flags
+    let meta = child(udta, b"meta")?.get(4..)?;
+    let ilst = child(meta, b"ilst")?;
+
+    for (name, freeform) in boxes(ilst) {
+        if name != b"----" {
+            continue;
+        }
+
+        let is_smpb = child(freeform, b"name")
+            .and_then(|x| x.get(4..))
+            .is_some_and(|x| x == b"iTunSMPB");
+
+        if is_smpb {
+            // Type and locale come before the value
+            let value = child(freeform, b"data")?.get(8..)?;
+            return Some(String::from_utf8_lossy(value).into_owned());
+        }
+    }
+
+    None
+}
+
+/// Read the `iTunSMPB` gapless information of an MP4 file.
+///
+/// Returns `None` for any other container, or if the tag is absent.
+pub fn read_gapless_info(path: &Path) -> Option<GaplessInfo> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+
+    let (name, size) = read_box_header(&mut reader)?;
+    if &name != b"ftyp" {
+        return None;
+    }
+    reader.seek(SeekFrom::Current(size as i64)).ok()?;
+
+    loop {
+        let (name, size) = read_box_header(&mut reader)?;
+
+        if &name == b"moov" {
+            if size > MAX_MOOV_SIZE {
+                return None;
+            }
+
+            let mut moov = vec![0u8; size as usize];
+            reader.read_exact(&mut moov).ok()?;
+
+            return find_itunsmpb(&moov).and_then(|x| parse_itunsmpb(&x));
+        }
+
+        reader.seek(SeekFrom::Current(size as i64)).ok()?;
+    }
+}
+
+/// Drops the encoder delay at the start of a source and the padding at its
+/// end.
+pub struct EncoderTrim<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    source: I,
+    info: GaplessInfo,
+    // Samples left before the padding starts, across all channels
+    remaining: Option<u64>,
+}
+
+impl<I> EncoderTrim<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    pub fn new(source: I, info: GaplessInfo) -> Self {
+        let mut trim = EncoderTrim {
+            source,
+            info,
+            remaining: None,
+        };
+
+        let delay = info.delay * trim.source.channels() as u64;
+        for _ in 0..delay {
+            if trim.source.next().is_none() {
+                break;
+            }
+        }
+        trim.reset_remaining(0);
+
+        trim
+    }
+
+    /// Reset the count of playable samples once the source is at `frame`,
+    /// counted from the end of the encoder delay.
+    fn reset_remaining(&mut self, frame: u64) {
+        self.remaining = if self.info.frames > 0 {
+            Some(self.info.frames.saturating_sub(frame) * self.source.channels() as u64)
+        } else {
+            None
+        };
+    }
+}
+
+impl<I> Iterator for EncoderTrim<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    type Item = I::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<I::Item> {
+        match self.remaining {
+            Some(0) => None,
+            Some(ref mut remaining) => {
+                *remaining -= 1;
+                self.source.next()
+            }
+            None => self.source.next(),
+        }
+    }
+}
+
+impl<I> Source for EncoderTrim<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        match (self.source.current_frame_len(), self.remaining) {
+            (Some(len), Some(remaining)) => Some(len.min(remaining as usize)),
+            (None, Some(remaining)) => Some(remaining as usize),
+            (len, None) => len,
+        }
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        if self.info.frames > 0 {
+            Some(Duration::from_secs_f64(
+                self.info.frames as f64 / self.sample_rate() as f64,
+            ))
+        } else {
+            self.source.total_duration()
+        }
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let rate = self.sample_rate() as f64;
+        let delay = Duration::from_secs_f64(self.info.delay as f64 / rate);
+
+        self.source.try_seek(pos + delay)?;
+        self.reset_remaining((pos.as_secs_f64() * rate).round() as u64);
+
+        Ok(())
+    }
+}
+
+const QUEUED_PENDING: u8 = 0;
+const QUEUED_STARTED: u8 = 1;
+const QUEUED_CANCELLED: u8 = 2;
+
+/// Shared state of a track appended to the sink ahead of time.
+#[derive(Clone, Default)]
+pub struct QueuedHandle {
+    state: Arc<AtomicU8>,
+}
+
+impl QueuedHandle {
+    /// Prevent the track from playing. Returns `false` if the audio thread
+    /// already started it, in which case it can no longer be withdrawn.
+    pub fn cancel(&self) -> bool {
+        self.state
+            .compare_exchange(
+                QUEUED_PENDING,
+                QUEUED_CANCELLED,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_ok()
+            || self.state.load(Ordering::SeqCst) == QUEUED_CANCELLED
+    }
+
+    fn start(&self) -> bool {
+        self.state
+            .compare_exchange(
+                QUEUED_PENDING,
+                QUEUED_STARTED,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_ok()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == QUEUED_CANCELLED
+    }
+}
+
+/// A source waiting in the sink queue behind the current track. The callback
+/// runs when the audio thread pulls its first sample, which is exactly when
+/// the previous source ran out. Once cancelled, the source is empty and the
+/// queue moves past it.
+pub struct QueuedSource<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    source: I,
+    handle: QueuedHandle,
+    on_start: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl<I> QueuedSource<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    pub fn new(source: I, handle: QueuedHandle, on_start: Box<dyn FnOnce() + Send>) -> Self {
+        QueuedSource {
+            source,
+            handle,
+            on_start: Some(on_start),
+        }
+    }
+}
+
+impl<I> Iterator for QueuedSource<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    type Item = I::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<I::Item> {
+        if let Some(on_start) = self.on_start.take() {
+            if !self.handle.start() {
+                return None;
+            }
+            on_start();
+        }
+
+        if self.handle.is_cancelled() {
+            return None;
+        }
+
+        self.source.next()
+    }
+}
+
+impl<I> Source for QueuedSource<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        if self.handle.is_cancelled() {
+            return Some(0);
+        }
+        self.source.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_itunsmpb() {
+        let info = parse_itunsmpb(
+            " 00000000 00000840 000001CA 00000000000F4BF6 00000000 00000000 00000000 00000000",
+        );
+
+        assert_eq!(
+            info,
+            Some(GaplessInfo {
+                delay: 2112,
+                padding: 458,
+                frames: 1002486,
+            })
+        );
+        assert_eq!(parse_itunsmpb("garbage"), None);
+    }
+
+    #[test]
+    fn test_encoder_trim() {
+        let samples: Vec<i16> = (0..20).collect();
+        let source = SamplesBuffer::new(2, 44100, samples);
+
+        let trimmed: Vec<i16> = EncoderTrim::new(
+            source,
+            GaplessInfo {
+                delay: 2,
+                padding: 3,
+                frames: 5,
+            },
+        )
+        .collect();
+
+        assert_eq!(trimmed, (4..14).collect::<Vec<i16>>());
+    }
+
+    #[test]
+    fn test_find_itunsmpb() {
+        fn atom(name: &[u8], payload: &[u8]) -> Vec<u8> {
+            let mut result = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+            result.extend_from_slice(name);
+            result.extend_from_slice(payload);
+            result
+        }
+
+        let value = b" 00000000 00000840 000001CA 00000000000F4BF6";
+        let freeform = [
+            atom(b"mean", b"\0\0\0\0com.apple.iTunes"),
+            atom(b"name", b"\0\0\0\0iTunSMPB"),
+            atom(
+                b"data",
+                &[&[0, 0, 0, 1, 0, 0, 0, 0][..], &value[..]].concat(),
+            ),
+        ]
+        .concat();
+        let ilst = atom(b"----", &freeform);
+        let meta = atom(
+            b"meta",
+            &[&[0, 0, 0, 0][..], &atom(b"ilst", &ilst)].concat(),
+        );
+        let moov = atom(b"udta", &meta);
+
+        let found = find_itunsmpb(&moov).and_then(|x| parse_itunsmpb(&x));
+        assert_eq!(found.map(|x| x.delay), Some(2112));
+    }
+
+    #[test]
+    fn test_queued_source() {
+        let started = Arc::new(AtomicUsize::new(0));
+        let handle = QueuedHandle::default();
+
+        let queued = QueuedSource::new(
+            SamplesBuffer::new(1, 44100, vec![1i16, 2, 3]),
+            handle.clone(),
+            Box::new({
+                let started = started.clone();
+                move || {
+                    started.fetch_add(1, Ordering::SeqCst);
+                }
+            }),
+        );
+
+        assert_eq!(queued.collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+        assert!(!handle.cancel());
+
+        let handle = QueuedHandle::default();
+        let queued = QueuedSource::new(
+            SamplesBuffer::new(1, 44100, vec![1i16, 2, 3]),
+            handle.clone(),
+            Box::new(|| panic!("Cancelled source started")),
+        );
+
+        assert!(handle.cancel());
+        assert_eq!(queued.count(), 0);
+    }
+}
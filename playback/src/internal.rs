@@ -1,6 +1,8 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -12,14 +14,25 @@ use tokio::time::{interval, sleep_until, Duration, Instant};
 use tokio_util::sync::CancellationToken;
 
 use crate::buffered::rune_buffered;
+use crate::fade::{CrossfadeSlot, FadeHandle, Transition};
+use crate::gapless::{read_gapless_info, EncoderTrim, GaplessInfo, QueuedHandle, QueuedSource};
 use crate::output_stream::{RuneOutputStream, RuneOutputStreamHandle};
 use crate::player::PlayingItem;
+use crate::range::{Ranged, TrackRange};
 use crate::realtime_fft::RealTimeFFT;
+use crate::replaygain::{FallbackLoudness, Gain, GainHandle, ReplayGainMode, ReplayGainSettings};
 use crate::shared_source::SharedSource;
 use crate::strategies::{
     AddMode, PlaybackStrategy, RepeatAllStrategy, RepeatOneStrategy, SequentialStrategy,
     ShuffleStrategy, UpdateReason,
 };
+use crate::track_info::{read_track_info, TrackInfo};
+
+/// Fade applied when pausing and resuming.
+const PAUSE_FADE: Duration = Duration::from_millis(100);
+
+/// Fade out before and fade in after a seek.
+const SEEK_FADE: Duration = Duration::from_millis(20);
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PlaybackMode {
@@ -65,7 +78,7 @@ pub enum PlayerCommand {
     Switch(usize),
     Seek(f64),
     AddToPlaylist {
-        tracks: Vec<(PlayingItem, std::path::PathBuf)>,
+        tracks: Vec<PlaylistItem>,
         mode: AddMode,
     },
     RemoveFromPlaylist {
@@ -80,6 +93,14 @@ pub enum PlayerCommand {
     SetVolume(f32),
     SetRealtimeFFTEnabled(bool),
     SetAdaptiveSwitchingEnabled(bool),
+    /// Length of the crossfade between tracks, zero to play them gapless.
+    /// Only automatic track changes crossfade. Stopping, skipping and
+    /// switching tracks cut right away.
+    SetCrossfade(Duration),
+    SetReplayGain(ReplayGainSettings),
+    /// Loudness of tracks measured while analyzing the library. Kept while
+    /// the tracks are in the playlist.
+    SetFallbackLoudness(Vec<(PlayingItem, FallbackLoudness)>),
 }
 
 #[derive(Debug, Clone)]
@@ -137,6 +158,48 @@ pub enum PlayerEvent {
 pub struct PlaylistItem {
     pub item: PlayingItem,
     pub path: PathBuf,
+    /// The part of the file the item plays, the whole file when unset.
+    pub range: Option<TrackRange>,
+}
+
+/// What the player needs from a file before it can play it.
+type OpenedTrack = (Decoder<BufReader<File>>, TrackInfo, GaplessInfo);
+
+fn open_track(path: &Path) -> Result<OpenedTrack> {
+    let file = File::open(path).with_context(|| format!("Failed to open file: {:?}", path))?;
+    let decoder = Decoder::new(BufReader::new(file))
+        .with_context(|| format!("Failed to decode file: {:?}", path))?;
+
+    Ok((
+        decoder,
+        read_track_info(path),
+        read_gapless_info(path).unwrap_or_default(),
+    ))
+}
+
+/// The track after the current one, opened off the player loop.
+struct PreparedTrack {
+    generation: u64,
+    index: usize,
+    item: PlaylistItem,
+    opened: Result<OpenedTrack>,
+}
+
+/// The track appended to the sink behind the current one.
+struct QueuedTrack {
+    index: usize,
+    item: PlayingItem,
+    path: PathBuf,
+    info: TrackInfo,
+    gain: GainHandle,
+    sample_rate: u32,
+    duration: Option<Duration>,
+    generation: u64,
+    handle: QueuedHandle,
+    // Outgoing crossfade of the queued track
+    slot: CrossfadeSlot<i16>,
+    // Frames played by the crossfade into the queued track
+    mixed: Arc<AtomicU64>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -189,9 +252,28 @@ pub(crate) struct PlayerInternal {
     current_item: Option<PlayingItem>,
     current_track_index: Option<usize>,
     current_track_path: Option<PathBuf>,
+    current_info: TrackInfo,
+    // Unknown for streams the decoder can not measure
+    current_duration: Option<Duration>,
+    current_slot: CrossfadeSlot<i16>,
+    current_gain: GainHandle,
+    position_offset: Duration,
+    queued_track: Option<QueuedTrack>,
+    queue_generation: u64,
+    // Generation of the next track being opened
+    pending_queue: Option<u64>,
+    track_prepared_sender: mpsc::UnboundedSender<PreparedTrack>,
+    track_prepared_receiver: mpsc::UnboundedReceiver<PreparedTrack>,
+    track_started_sender: mpsc::UnboundedSender<u64>,
+    track_started_receiver: mpsc::UnboundedReceiver<u64>,
     sink: Option<Sink>,
     _stream: Option<RuneOutputStream>,
     state: InternalPlaybackState,
+    fade: FadeHandle,
+    pending_pause: Option<Instant>,
+    crossfade: Duration,
+    replaygain: ReplayGainSettings,
+    fallback_loudness: HashMap<PlayingItem, FallbackLoudness>,
     debounce_timer: Option<Instant>,
     cancellation_token: CancellationToken,
     playback_mode: PlaybackMode,
@@ -210,6 +292,8 @@ impl PlayerInternal {
         cancellation_token: CancellationToken,
     ) -> Self {
         let (stream_error_sender, stream_error_receiver) = mpsc::unbounded_channel();
+        let (track_prepared_sender, track_prepared_receiver) = mpsc::unbounded_channel();
+        let (track_started_sender, track_started_receiver) = mpsc::unbounded_channel();
         Self {
             commands,
             event_sender,
@@ -217,10 +301,27 @@ impl PlayerInternal {
             current_item: None,
             current_track_index: None,
             current_track_path: None,
+            current_info: TrackInfo::default(),
+            current_duration: None,
+            current_slot: CrossfadeSlot::default(),
+            current_gain: GainHandle::new(1.0),
+            position_offset: Duration::ZERO,
+            queued_track: None,
+            queue_generation: 0,
+            pending_queue: None,
+            track_prepared_sender,
+            track_prepared_receiver,
+            track_started_sender,
+            track_started_receiver,
             sink: None,
             _stream: None,
             realtime_fft: Arc::new(Mutex::new(RealTimeFFT::new(512))),
             state: InternalPlaybackState::Stopped,
+            fade: FadeHandle::default(),
+            pending_pause: None,
+            crossfade: Duration::ZERO,
+            replaygain: ReplayGainSettings::default(),
+            fallback_loudness: HashMap::new(),
             debounce_timer: None,
             cancellation_token,
             playback_mode: PlaybackMode::Sequential,
@@ -277,6 +378,9 @@ impl PlayerInternal {
                         PlayerCommand::SetVolume(volume) => self.set_volume(volume),
                         PlayerCommand::SetRealtimeFFTEnabled(enabled) => self.set_realtime_fft_enabled(enabled),
                         PlayerCommand::SetAdaptiveSwitchingEnabled(enabled) => self.set_adaptive_switching(enabled),
+                        PlayerCommand::SetCrossfade(duration) => self.set_crossfade(duration),
+                        PlayerCommand::SetReplayGain(settings) => self.set_replay_gain(settings),
+                        PlayerCommand::SetFallbackLoudness(loudness) => self.set_fallback_loudness(loudness),
                     }?;
                 },
                 Ok(fft_data) = fft_receiver.recv() => {
@@ -300,6 +404,22 @@ impl PlayerInternal {
                     self.debounce_timer = None;
                     self.send_playlist_updated()?;
                 },
+                _ = async {
+                    if let Some(timer) = self.pending_pause {
+                        sleep_until(timer).await;
+                    }
+                }, if self.pending_pause.is_some() => {
+                    self.pending_pause = None;
+                    if let Some(sink) = &self.sink {
+                        sink.pause();
+                    }
+                },
+                Some(prepared) = self.track_prepared_receiver.recv() => {
+                    self.queue_prepared(prepared);
+                },
+                Some(generation) = self.track_started_receiver.recv() => {
+                    self.advance_to_queued(generation)?;
+                },
                 Some(error_message) = self.stream_error_receiver.recv() => {
                     self.stop()?;
                     error!("Received error message: {}", error_message);
@@ -363,8 +483,23 @@ impl PlayerInternal {
                 return Ok(());
             }
 
-            let source = SharedSource::new(rune_buffered(source.unwrap()));
-            let source_for_fft = Arc::clone(&source.inner);
+            // Start silent when loading paused, so the first play fades in
+            self.fade = FadeHandle::default();
+            if !play {
+                self.fade.fade_to(0.0, Duration::ZERO);
+            }
+
+            let item = item.clone();
+            let info = read_track_info(&item.path);
+            let gain = GainHandle::new(self.gain_factor(index, &item.item, &info, None));
+
+            let (source, shared, slot) = self.build_source(
+                source.unwrap(),
+                &item,
+                read_gapless_info(&item.path).unwrap_or_default(),
+                gain.clone(),
+                Arc::new(AtomicU64::new(0)),
+            );
 
             let (stream, stream_handle) = RuneOutputStream::try_default_with_callback({
                 let error_sender = self.stream_error_sender.clone();
@@ -375,38 +510,8 @@ impl PlayerInternal {
             .context("Failed to create output stream")?;
             let sink = try_new_sink(&stream_handle).context("Failed to create sink")?;
 
-            // Create a channel to transfer FFT data
-            let (fft_tx, mut fft_rx) = mpsc::unbounded_channel();
-
-            // Create a new thread for calculating realtime FFT
-            let realtime_fft = Arc::clone(&self.realtime_fft);
-            let fft_enabled = Arc::clone(&self.fft_enabled);
-            tokio::spawn(async move {
-                while let Some(data) = fft_rx.recv().await {
-                    if let Ok(enabled) = fft_enabled.lock() {
-                        if *enabled {
-                            if let Ok(fft) = realtime_fft.lock() {
-                                fft.add_data(data);
-                            }
-                        }
-                    }
-                }
-            });
-
             sink.set_volume(self.volume);
-            sink.append(source.periodic_access(
-                Duration::from_millis(12),
-                move |_sample: &mut SharedSource| {
-                    if let Ok(guard) = source_for_fft.lock() {
-                        let data: Option<Vec<i16>> = guard.current_samples();
-                        if let Some(data) = data {
-                            if fft_tx.send(data).is_err() {
-                                error!("Failed to send FFT data");
-                            }
-                        }
-                    }
-                },
-            ));
+            sink.append(source);
 
             if !play {
                 sink.pause();
@@ -414,8 +519,16 @@ impl PlayerInternal {
 
             self.sink = Some(sink);
             self._stream = Some(stream);
+            self.pending_pause = None;
+            self.queued_track = None;
+            self.pending_queue = None;
+            self.current_slot = slot;
+            self.current_duration = shared.total_duration();
+            self.current_info = info;
+            self.current_gain = gain;
+            self.position_offset = Duration::ZERO;
             self.current_track_index = Some(index);
-            self.current_item = Some(item.item.clone());
+            self.current_item = Some(item.item);
             self.current_track_path = Some(item.path.clone());
             info!("Track loaded: {:?}", item.path);
 
@@ -454,15 +567,301 @@ impl PlayerInternal {
                     .context("Failed to send Playing event")?;
                 self.state = InternalPlaybackState::Stopped;
             }
+
+            self.queue_next();
         } else {
             error!("Load command received without index");
         }
         Ok(())
     }
 
+    /// Wrap a decoded track into the source appended to the sink, trimming
+    /// encoder delay and padding and limiting it to the range of the item,
+    /// applying ReplayGain and fades and feeding the realtime FFT.
+    ///
+    /// Also returns a handle to the decoded track, which the previous track
+    /// reads from while crossfading, and the slot for the outgoing crossfade.
+    fn build_source(
+        &self,
+        decoder: Decoder<BufReader<File>>,
+        item: &PlaylistItem,
+        gapless_info: GaplessInfo,
+        gain: GainHandle,
+        incoming: Arc<AtomicU64>,
+    ) -> (
+        impl Source<Item = i16> + Send + 'static,
+        SharedSource,
+        CrossfadeSlot<i16>,
+    ) {
+        let source = SharedSource::new(rune_buffered(Gain::new(
+            Ranged::new(
+                EncoderTrim::new(decoder, gapless_info),
+                item.range.unwrap_or_default(),
+            ),
+            gain,
+        )));
+        let shared = source.clone();
+        let source_for_fft = Arc::clone(&source.inner);
+        let slot = CrossfadeSlot::default();
+
+        // Create a channel to transfer FFT data
+        let (fft_tx, mut fft_rx) = mpsc::unbounded_channel();
+
+        // Create a new thread for calculating realtime FFT
+        let realtime_fft = Arc::clone(&self.realtime_fft);
+        let fft_enabled = Arc::clone(&self.fft_enabled);
+        tokio::spawn(async move {
+            while let Some(data) = fft_rx.recv().await {
+                if let Ok(enabled) = fft_enabled.lock() {
+                    if *enabled {
+                        if let Ok(fft) = realtime_fft.lock() {
+                            fft.add_data(data);
+                        }
+                    }
+                }
+            }
+        });
+
+        let source = source.periodic_access(
+            Duration::from_millis(12),
+            move |_sample: &mut SharedSource| {
+                if let Ok(guard) = source_for_fft.lock() {
+                    let data: Option<Vec<i16>> = guard.current_samples();
+                    if let Some(data) = data {
+                        if fft_tx.send(data).is_err() {
+                            error!("Failed to send FFT data");
+                        }
+                    }
+                }
+            },
+        );
+
+        let source = Transition::new(source, self.fade.clone(), SEEK_FADE, incoming, slot.clone());
+
+        (source, shared, slot)
+    }
+
+    /// Open the track the playback strategy picks after the current one. It is
+    /// appended to the sink once ready, so it starts on the very sample the
+    /// current track ends. Opening and probing the file happens off the
+    /// player loop, which keeps answering commands meanwhile.
+    fn queue_next(&mut self) {
+        if self.queued_track.is_some() || self.pending_queue.is_some() {
+            return;
+        }
+
+        let Some(index) = self.current_track_index else {
+            return;
+        };
+
+        if self.sink.is_none() || self.playlist.is_empty() {
+            return;
+        }
+
+        let Some(next_index) = self.playback_strategy.next(index, self.playlist.len()) else {
+            return;
+        };
+
+        if next_index >= self.playlist.len() {
+            return;
+        }
+
+        self.queue_generation += 1;
+        let generation = self.queue_generation;
+        self.pending_queue = Some(generation);
+
+        let item = self.playlist[self.get_mapped_track_index(next_index)].clone();
+        let track_prepared_sender = self.track_prepared_sender.clone();
+        tokio::task::spawn_blocking(move || {
+            let opened = open_track(&item.path);
+            let _ = track_prepared_sender.send(PreparedTrack {
+                generation,
+                index: next_index,
+                item,
+                opened,
+            });
+        });
+    }
+
+    /// Append the next track to the sink, unless the playlist, the playback
+    /// mode or the current track changed while it was being opened.
+    fn queue_prepared(&mut self, prepared: PreparedTrack) {
+        if self.pending_queue != Some(prepared.generation) {
+            return;
+        }
+        self.pending_queue = None;
+
+        let Some(index) = self.current_track_index else {
+            return;
+        };
+
+        let PreparedTrack {
+            generation,
+            index: next_index,
+            item,
+            opened,
+        } = prepared;
+
+        // Leave it to `next()` to skip the track once the current one ends
+        let (source, info, gapless_info) = match opened {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to open the next track: {:#?}", e);
+                return;
+            }
+        };
+
+        let handle = QueuedHandle::default();
+        let track_started_sender = self.track_started_sender.clone();
+
+        let gain = GainHandle::new(self.gain_factor(
+            next_index,
+            &item.item,
+            &info,
+            Some(&self.current_info),
+        ));
+        let mixed = Arc::new(AtomicU64::new(0));
+        let (source, shared, slot) =
+            self.build_source(source, &item, gapless_info, gain.clone(), mixed.clone());
+        let sample_rate = shared.sample_rate();
+        let duration = shared.total_duration();
+
+        // Consecutive tracks of an album are meant to be heard back to back,
+        // as are consecutive ranges of a file
+        let current = &self.playlist[self.get_mapped_track_index(index)];
+        let follows_in_file = current.path == item.path
+            && matches!(
+                (current.range, item.range),
+                (Some(current), Some(next)) if current.is_followed_by(&next)
+            );
+        if !self.crossfade.is_zero()
+            && !follows_in_file
+            && !self.current_info.is_followed_on_album_by(&info)
+        {
+            // Without its length, there is no telling where the current track
+            // ends, and so where to start fading
+            if self.current_duration.is_none() {
+                info!(
+                    "Crossfade skipped, the length of the current track is unknown: {:?}",
+                    self.current_track_path
+                );
+            } else {
+                let frames = (self.crossfade.as_secs_f64() * sample_rate as f64).round() as u64;
+                self.current_slot
+                    .attach(Box::new(shared), frames, mixed.clone());
+            }
+        }
+
+        let source = QueuedSource::new(
+            source,
+            handle.clone(),
+            Box::new(move || {
+                let _ = track_started_sender.send(generation);
+            }),
+        );
+
+        if let Some(sink) = &self.sink {
+            sink.append(source);
+            debug!("Queued next track: {:?}", item.path);
+
+            self.queued_track = Some(QueuedTrack {
+                index: next_index,
+                item: item.item,
+                path: item.path,
+                info,
+                gain,
+                sample_rate,
+                duration,
+                generation,
+                handle,
+                slot,
+                mixed,
+            });
+        }
+    }
+
+    /// Withdraw the queued track after the playlist or the playback mode
+    /// changed, and queue whatever comes next now.
+    fn requeue_next(&mut self) {
+        if let Some(queued) = &self.queued_track {
+            // Already audible, the pending transition queues the next one
+            if !self.current_slot.detach() || !queued.handle.cancel() {
+                return;
+            }
+            self.queued_track = None;
+        }
+
+        // The track being opened may no longer be the next one
+        self.pending_queue = None;
+        self.queue_next();
+    }
+
+    /// The audio thread moved on to the queued track.
+    fn advance_to_queued(&mut self, generation: u64) -> Result<()> {
+        let queued = match self.queued_track.take() {
+            Some(queued) if queued.generation == generation => queued,
+            other => {
+                // A cancelled track, or one from a sink that no longer exists
+                self.queued_track = other;
+                return Ok(());
+            }
+        };
+
+        if let (Some(item), Some(index), Some(path)) = (
+            self.current_item.clone(),
+            self.current_track_index,
+            self.current_track_path.clone(),
+        ) {
+            self.event_sender
+                .send(PlayerEvent::EndOfTrack {
+                    item,
+                    index: self.get_mapped_track_index(index),
+                    path,
+                    playback_mode: self.playback_mode,
+                })
+                .with_context(|| "Failed to send EndOfTrack event")?;
+        }
+
+        // The crossfade already played the beginning of the track
+        let mixed = queued.mixed.load(Ordering::SeqCst);
+        self.position_offset = Duration::from_secs_f64(mixed as f64 / queued.sample_rate as f64);
+
+        self.current_track_index = Some(queued.index);
+        self.current_item = Some(queued.item.clone());
+        self.current_track_path = Some(queued.path.clone());
+        self.current_info = queued.info;
+        self.current_duration = queued.duration;
+        self.current_gain = queued.gain;
+        self.current_slot = queued.slot;
+        info!("Track started: {:?}", queued.path);
+
+        self.event_sender
+            .send(PlayerEvent::Playing {
+                item: queued.item,
+                index: self.get_mapped_track_index(queued.index),
+                path: queued.path,
+                playback_mode: self.playback_mode,
+                position: self.position_offset,
+            })
+            .with_context(|| "Failed to send Playing event")?;
+        self.state = InternalPlaybackState::Playing;
+
+        self.queue_next();
+
+        Ok(())
+    }
+
+    /// Position in the current track, which the sink only knows from the
+    /// moment the track became the current source.
+    fn get_position(&self, sink: &Sink) -> Duration {
+        sink.get_pos() + self.position_offset
+    }
+
     fn play(&mut self) -> Result<()> {
         if let Some(sink) = &self.sink {
+            self.pending_pause = None;
             sink.play();
+            self.fade.fade_to(1.0, PAUSE_FADE);
             info!("Playback started");
 
             if let Some(track_index) = self.current_track_index {
@@ -494,10 +893,12 @@ impl PlayerInternal {
 
     fn pause(&mut self) -> Result<()> {
         if let Some(sink) = &self.sink {
-            sink.pause();
+            // The sink is paused once the fade is over
+            self.fade.fade_to(0.0, PAUSE_FADE);
+            self.pending_pause = Some(Instant::now() + PAUSE_FADE);
             info!("Playback paused");
 
-            let position = sink.get_pos();
+            let position = self.get_position(sink);
             if let Some(track_index) = self.current_track_index {
                 let track_index = self.get_mapped_track_index(track_index);
                 self.event_sender.send(PlayerEvent::Paused {
@@ -517,6 +918,9 @@ impl PlayerInternal {
     fn stop(&mut self) -> Result<()> {
         if let Some(sink) = self.sink.take() {
             sink.stop();
+            self.pending_pause = None;
+            self.queued_track = None;
+            self.pending_queue = None;
             info!("Playback stopped");
             self.event_sender
                 .send(PlayerEvent::Stopped)
@@ -569,7 +973,7 @@ impl PlayerInternal {
         if let Some(index) = self.current_track_index {
             match &self.sink {
                 Some(sink) => {
-                    let need_adaptive = sink.get_pos() > Duration::from_secs(3);
+                    let need_adaptive = self.get_position(sink) > Duration::from_secs(3);
 
                     if self.adaptive_switching && need_adaptive {
                         self.load(Some(index), true, true)
@@ -607,7 +1011,8 @@ impl PlayerInternal {
                 Ok(_) => {
                     info!("Seeking to position: {} s", position);
 
-                    let position = sink.get_pos();
+                    self.position_offset = Duration::ZERO;
+                    let position = self.get_position(sink);
                     if let Some(track_index) = self.current_track_index {
                         let track_index = self.get_mapped_track_index(track_index);
 
@@ -657,7 +1062,7 @@ impl PlayerInternal {
         Ok(())
     }
 
-    fn add_to_playlist(&mut self, tracks: Vec<(PlayingItem, std::path::PathBuf)>, mode: AddMode) {
+    fn add_to_playlist(&mut self, tracks: Vec<PlaylistItem>, mode: AddMode) {
         debug!("Adding tracks to playlist with mode: {:?}", mode);
         let insert_index = match mode {
             AddMode::PlayNext => {
@@ -672,20 +1077,10 @@ impl PlayerInternal {
 
         if let Some(index) = insert_index {
             for (i, track) in tracks.into_iter().enumerate() {
-                self.playlist.insert(
-                    index + i,
-                    PlaylistItem {
-                        item: track.0,
-                        path: track.1,
-                    },
-                );
+                self.playlist.insert(index + i, track);
             }
         } else {
-            self.playlist
-                .extend(tracks.into_iter().map(|track| PlaylistItem {
-                    item: track.0,
-                    path: track.1,
-                }));
+            self.playlist.extend(tracks);
         }
 
         self.playback_strategy.on_playlist_updated(
@@ -696,17 +1091,20 @@ impl PlayerInternal {
             },
         );
         self.schedule_playlist_update();
+        self.requeue_next();
     }
 
     fn remove_from_playlist(&mut self, index: usize) -> Result<()> {
         if index < self.playlist.len() {
             debug!("Removing from playlist at index: {}", index);
             self.playlist.remove(index);
+            self.prune_fallback_loudness();
             self.playback_strategy.on_playlist_updated(
                 self.playlist.len(),
                 UpdateReason::RemoveFromPlaylist { index },
             );
             self.schedule_playlist_update();
+            self.requeue_next();
         } else {
             bail!(
                 "Remove command received but index {} is out of bounds",
@@ -719,9 +1117,12 @@ impl PlayerInternal {
 
     fn clear_playlist(&mut self) -> Result<()> {
         self.playlist.clear();
+        self.fallback_loudness.clear();
         self.playback_strategy
             .on_playlist_updated(0, UpdateReason::ClearPlaylist);
         self.current_track_index = None;
+        self.queued_track = None;
+        self.pending_queue = None;
         self.sink = None;
         self._stream = None;
         info!("Playlist cleared");
@@ -742,6 +1143,7 @@ impl PlayerInternal {
             PlaybackMode::RepeatAll => Box::new(RepeatAllStrategy),
             PlaybackMode::Shuffle => Box::new(ShuffleStrategy::new(self.playlist.len())),
         };
+        self.requeue_next();
         self.send_progress()?;
         info!("Playback mode set to {:?}", mode);
 
@@ -761,7 +1163,7 @@ impl PlayerInternal {
         let playback_mode = self.playback_mode;
 
         if let Some(sink) = &self.sink {
-            let position = sink.get_pos();
+            let position = self.get_position(sink);
 
             if sink.empty() {
                 self.event_sender
@@ -847,6 +1249,7 @@ impl PlayerInternal {
         }
 
         self.schedule_playlist_update();
+        self.requeue_next();
     }
 
     fn schedule_playlist_update(&mut self) {
@@ -883,6 +1286,100 @@ impl PlayerInternal {
         Ok(())
     }
 
+    fn set_crossfade(&mut self, duration: Duration) -> Result<()> {
+        self.crossfade = duration;
+        self.requeue_next();
+
+        info!("Crossfade duration changed: {:?}", duration);
+
+        Ok(())
+    }
+
+    /// ReplayGain factor of the track at `index`. `previous` is the track
+    /// played before it, when already known.
+    fn gain_factor(
+        &self,
+        index: usize,
+        item: &PlayingItem,
+        info: &TrackInfo,
+        previous: Option<&TrackInfo>,
+    ) -> f32 {
+        let fallback_loudness = self.fallback_loudness.get(item).copied();
+        let in_album = self.replaygain.mode == ReplayGainMode::Auto
+            && self.plays_in_album_order(index, info, previous);
+
+        self.replaygain.factor(info, in_album, fallback_loudness)
+    }
+
+    /// Whether the track at `index` sits between tracks of its own album, in
+    /// the order of the record.
+    fn plays_in_album_order(
+        &self,
+        index: usize,
+        info: &TrackInfo,
+        previous: Option<&TrackInfo>,
+    ) -> bool {
+        let len = self.playlist.len();
+        let neighbour_info = |index: Option<usize>| {
+            index
+                .filter(|x| *x < len)
+                .map(|x| read_track_info(&self.playlist[self.get_mapped_track_index(x)].path))
+        };
+
+        let follows_previous = match previous {
+            Some(previous) => previous.is_followed_on_album_by(info),
+            None => neighbour_info(self.playback_strategy.previous(index, len))
+                .is_some_and(|previous| previous.is_followed_on_album_by(info)),
+        };
+
+        follows_previous
+            || neighbour_info(self.playback_strategy.next(index, len))
+                .is_some_and(|next| info.is_followed_on_album_by(&next))
+    }
+
+    /// Recompute the gain of the current and the queued track.
+    fn update_gains(&mut self) {
+        if let (Some(index), Some(item)) = (self.current_track_index, &self.current_item) {
+            self.current_gain
+                .set(self.gain_factor(index, item, &self.current_info, None));
+        }
+
+        if let Some(queued) = &self.queued_track {
+            queued.gain.set(self.gain_factor(
+                queued.index,
+                &queued.item,
+                &queued.info,
+                Some(&self.current_info),
+            ));
+        }
+    }
+
+    fn set_replay_gain(&mut self, settings: ReplayGainSettings) -> Result<()> {
+        self.replaygain = settings;
+        self.update_gains();
+
+        info!("ReplayGain settings changed: {:?}", settings);
+
+        Ok(())
+    }
+
+    /// Forget the loudness of tracks no longer in the playlist.
+    fn prune_fallback_loudness(&mut self) {
+        let items: HashSet<&PlayingItem> = self.playlist.iter().map(|x| &x.item).collect();
+        self.fallback_loudness
+            .retain(|item, _| items.contains(item));
+    }
+
+    fn set_fallback_loudness(
+        &mut self,
+        loudness: Vec<(PlayingItem, FallbackLoudness)>,
+    ) -> Result<()> {
+        self.fallback_loudness.extend(loudness);
+        self.update_gains();
+
+        Ok(())
+    }
+
     fn set_adaptive_switching(&mut self, x: bool) -> Result<()> {
         self.adaptive_switching = x;
 
@@ -891,3 +1388,160 @@ impl PlayerInternal {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const RATE: u32 = 8000;
+
+    /// Write a mono 16 bit WAV file of `frames` frames.
+    fn write_wav(name: &str, frames: usize) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rune-{}-{}.wav", name, std::process::id()));
+        let data_size = (frames * 2) as u32;
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&RATE.to_le_bytes());
+        bytes.extend_from_slice(&(RATE * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_size.to_le_bytes());
+        for _ in 0..frames {
+            bytes.extend_from_slice(&1000i16.to_le_bytes());
+        }
+
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_prune_fallback_loudness() {
+        let (_command_sender, command_receiver) = mpsc::unbounded_channel();
+        let (event_sender, _event_receiver) = mpsc::unbounded_channel();
+        let mut internal =
+            PlayerInternal::new(command_receiver, event_sender, CancellationToken::new());
+
+        let items: Vec<PlaylistItem> = (1..=3)
+            .map(|id| PlaylistItem {
+                item: PlayingItem::InLibrary(id),
+                path: PathBuf::from(format!("{}.flac", id)),
+                range: None,
+            })
+            .collect();
+        internal.add_to_playlist(items.clone(), AddMode::AppendToEnd);
+
+        let loudness = FallbackLoudness {
+            loudness: -10.0,
+            true_peak: Some(0.9),
+        };
+        internal
+            .set_fallback_loudness(items.iter().map(|x| (x.item.clone(), loudness)).collect())
+            .unwrap();
+        assert_eq!(internal.fallback_loudness.len(), 3);
+
+        internal.remove_from_playlist(1).unwrap();
+        assert_eq!(internal.fallback_loudness.len(), 2);
+        assert!(!internal
+            .fallback_loudness
+            .contains_key(&PlayingItem::InLibrary(2)));
+
+        internal.clear_playlist().unwrap();
+        assert!(internal.fallback_loudness.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_gapless_transition_events() {
+        let first = write_wav("gapless-first", 800);
+        let second = write_wav("gapless-second", 800);
+
+        let (_command_sender, command_receiver) = mpsc::unbounded_channel();
+        let (event_sender, mut event_receiver) = mpsc::unbounded_channel();
+        let mut internal =
+            PlayerInternal::new(command_receiver, event_sender, CancellationToken::new());
+
+        internal.playlist = vec![
+            PlaylistItem {
+                item: PlayingItem::InLibrary(1),
+                path: first.clone(),
+                range: None,
+            },
+            PlaylistItem {
+                item: PlayingItem::InLibrary(2),
+                path: second.clone(),
+                range: None,
+            },
+        ];
+
+        // Play the first track into a sink without an output device, pulling
+        // the samples the audio thread would
+        let (sink, mut output) = Sink::new_idle();
+        let (decoder, info, gapless_info) = open_track(&first).unwrap();
+        let gain = GainHandle::new(1.0);
+        let (source, _, slot) = internal.build_source(
+            decoder,
+            &internal.playlist[0].clone(),
+            gapless_info,
+            gain.clone(),
+            Arc::new(AtomicU64::new(0)),
+        );
+        sink.append(source);
+
+        internal.sink = Some(sink);
+        internal.current_track_index = Some(0);
+        internal.current_item = Some(PlayingItem::InLibrary(1));
+        internal.current_track_path = Some(first.clone());
+        internal.current_info = info;
+        internal.current_gain = gain;
+        internal.current_slot = slot;
+
+        // The next track is opened off the loop, then appended
+        internal.queue_next();
+        assert!(internal.queued_track.is_none());
+        let prepared = internal.track_prepared_receiver.recv().await.unwrap();
+        internal.queue_prepared(prepared);
+        assert!(internal.queued_track.is_some());
+
+        // Nothing happens until the last sample of the first track is played
+        assert_eq!((&mut output).take(800).count(), 800);
+        assert!(internal.track_started_receiver.try_recv().is_err());
+        assert!(event_receiver.try_recv().is_err());
+
+        // The very next sample belongs to the second track
+        output.next();
+        let generation = internal.track_started_receiver.try_recv().unwrap();
+        internal.advance_to_queued(generation).unwrap();
+
+        match event_receiver.try_recv().unwrap() {
+            PlayerEvent::EndOfTrack { item, index, .. } => {
+                assert_eq!(item, PlayingItem::InLibrary(1));
+                assert_eq!(index, 0);
+            }
+            other => panic!("Expected EndOfTrack, got {:?}", other),
+        }
+        match event_receiver.try_recv().unwrap() {
+            PlayerEvent::Playing {
+                item,
+                index,
+                position,
+                ..
+            } => {
+                assert_eq!(item, PlayingItem::InLibrary(2));
+                assert_eq!(index, 1);
+                assert_eq!(position, Duration::ZERO);
+            }
+            other => panic!("Expected Playing, got {:?}", other),
+        }
+
+        fs::remove_file(first).unwrap();
+        fs::remove_file(second).unwrap();
+    }
+}
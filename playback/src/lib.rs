@@ -1,12 +1,17 @@
+mod fade;
+mod gapless;
 mod internal;
 mod realtime_fft;
 mod sfx_internal;
 mod shared_source;
+mod track_info;
 
 pub mod buffered;
 pub mod controller;
 pub mod output_stream;
 pub mod player;
+pub mod range;
+pub mod replaygain;
 pub mod sfx_player;
 pub mod strategies;
 
@@ -19,7 +24,7 @@ pub use dummy_souvlaki::{MediaMetadata, MediaPlayback, MediaPosition};
 #[cfg(not(target_os = "android"))]
 pub use souvlaki::{MediaMetadata, MediaPlayback, MediaPosition};
 
-pub use internal::{PlayerCommand, PlayerEvent};
+pub use internal::{PlayerCommand, PlayerEvent, PlaylistItem};
 
 #[cfg(target_os = "android")]
 pub mod android_utils;
\ No newline at end of file
@@ -8,7 +8,10 @@ use simple_channel::{SimpleChannel, SimpleReceiver, SimpleSender};
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
-use crate::internal::{InternalLog, PlaybackMode, PlayerCommand, PlayerEvent, PlayerInternal};
+use crate::internal::{
+    InternalLog, PlaybackMode, PlayerCommand, PlayerEvent, PlayerInternal, PlaylistItem,
+};
+use crate::replaygain::{FallbackLoudness, ReplayGainSettings};
 use crate::strategies::AddMode;
 
 #[derive(Debug, Clone)]
@@ -85,7 +88,7 @@ pub trait Playable: Send {
     fn previous(&self);
     fn switch(&self, index: usize);
     fn seek(&self, position_ms: f64);
-    fn add_to_playlist(&self, tracks: Vec<(PlayingItem, PathBuf)>, mode: AddMode);
+    fn add_to_playlist(&self, tracks: Vec<PlaylistItem>, mode: AddMode);
     fn remove_from_playlist(&self, index: usize);
     fn clear_playlist(&self);
     fn move_playlist_item(&self, old_index: usize, new_index: usize);
@@ -93,6 +96,9 @@ pub trait Playable: Send {
     fn set_volume(&mut self, volume: f32);
     fn set_realtime_fft_enabled(&mut self, enabled: bool);
     fn set_adaptive_switching_enabled(&mut self, enabled: bool);
+    fn set_crossfade(&mut self, duration: Duration);
+    fn set_replay_gain(&mut self, settings: ReplayGainSettings);
+    fn set_fallback_loudness(&self, loudness: Vec<(PlayingItem, FallbackLoudness)>);
     fn terminate(&self);
     fn get_status(&self) -> PlayerStatus;
     fn get_playlist(&self) -> Vec<PlayingItem>;
@@ -361,7 +367,7 @@ impl Playable for Player {
         self.command(PlayerCommand::Seek(position_ms));
     }
 
-    fn add_to_playlist(&self, tracks: Vec<(PlayingItem, PathBuf)>, mode: AddMode) {
+    fn add_to_playlist(&self, tracks: Vec<PlaylistItem>, mode: AddMode) {
         self.command(PlayerCommand::AddToPlaylist { tracks, mode });
     }
 
@@ -396,6 +402,18 @@ impl Playable for Player {
         self.command(PlayerCommand::SetAdaptiveSwitchingEnabled(enabled));
     }
 
+    fn set_crossfade(&mut self, duration: Duration) {
+        self.command(PlayerCommand::SetCrossfade(duration));
+    }
+
+    fn set_replay_gain(&mut self, settings: ReplayGainSettings) {
+        self.command(PlayerCommand::SetReplayGain(settings));
+    }
+
+    fn set_fallback_loudness(&self, loudness: Vec<(PlayingItem, FallbackLoudness)>) {
+        self.command(PlayerCommand::SetFallbackLoudness(loudness));
+    }
+
     fn terminate(&self) {
         self.cancellation_token.cancel();
     }
@@ -444,7 +462,7 @@ impl Playable for MockPlayer {
     fn previous(&self) {}
     fn switch(&self, _index: usize) {}
     fn seek(&self, _position_ms: f64) {}
-    fn add_to_playlist(&self, _tracks: Vec<(PlayingItem, PathBuf)>, _mode: AddMode) {}
+    fn add_to_playlist(&self, _tracks: Vec<PlaylistItem>, _mode: AddMode) {}
     fn remove_from_playlist(&self, _index: usize) {}
     fn clear_playlist(&self) {}
     fn move_playlist_item(&self, _old_index: usize, _new_index: usize) {}
@@ -452,6 +470,9 @@ impl Playable for MockPlayer {
     fn set_volume(&mut self, _volume: f32) {}
     fn set_realtime_fft_enabled(&mut self, _enabled: bool) {}
     fn set_adaptive_switching_enabled(&mut self, _enabled: bool) {}
+    fn set_crossfade(&mut self, _duration: Duration) {}
+    fn set_replay_gain(&mut self, _settings: ReplayGainSettings) {}
+    fn set_fallback_loudness(&self, _loudness: Vec<(PlayingItem, FallbackLoudness)>) {}
     fn terminate(&self) {}
     fn get_status(&self) -> PlayerStatus {
         PlayerStatus {
@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use rodio::source::SeekError;
+use rodio::{Sample, Source};
+
+/// The part of a file a track is played from, like a track of a CUE sheet.
+/// The whole file by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrackRange {
+    pub start: Duration,
+    /// The end of the file when unset.
+    pub end: Option<Duration>,
+}
+
+impl TrackRange {
+    /// Whether the other range starts right where this one ends, so that
+    /// both are heard back to back when they are in the same file.
+    pub fn is_followed_by(&self, next: &TrackRange) -> bool {
+        self.end == Some(next.start)
+    }
+}
+
+/// Plays a range of a source as if it were the whole source: it starts at
+/// the start of the range, ends at its end, and seeks and reports its
+/// duration relative to it.
+pub struct Ranged<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    source: I,
+    range: TrackRange,
+    // Samples left before the end of the range, across all channels
+    remaining: Option<u64>,
+}
+
+impl<I> Ranged<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    pub fn new(source: I, range: TrackRange) -> Self {
+        let mut ranged = Ranged {
+            source,
+            range,
+            remaining: None,
+        };
+
+        // Sources that can't seek get there by decoding
+        if !range.start.is_zero() && ranged.source.try_seek(range.start).is_err() {
+            let skipped = ranged.samples(range.start);
+            for _ in 0..skipped {
+                if ranged.source.next().is_none() {
+                    break;
+                }
+            }
+        }
+        ranged.reset_remaining(range.start);
+
+        ranged
+    }
+
+    fn samples(&self, duration: Duration) -> u64 {
+        (duration.as_secs_f64() * self.source.sample_rate() as f64).round() as u64
+            * self.source.channels() as u64
+    }
+
+    /// Reset the count of playable samples once the source is at `position`,
+    /// counted from the start of the file.
+    fn reset_remaining(&mut self, position: Duration) {
+        self.remaining = self
+            .range
+            .end
+            .map(|end| self.samples(end.saturating_sub(position)));
+    }
+}
+
+impl<I> Iterator for Ranged<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    type Item = I::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<I::Item> {
+        match self.remaining {
+            Some(0) => None,
+            Some(ref mut remaining) => {
+                *remaining -= 1;
+                self.source.next()
+            }
+            None => self.source.next(),
+        }
+    }
+}
+
+impl<I> Source for Ranged<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        match (self.source.current_frame_len(), self.remaining) {
+            (Some(len), Some(remaining)) => Some(len.min(remaining as usize)),
+            (None, Some(remaining)) => Some(remaining as usize),
+            (len, None) => len,
+        }
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.range
+            .end
+            .or_else(|| self.source.total_duration())
+            .map(|end| end.saturating_sub(self.range.start))
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let position = self.range.start + pos;
+
+        self.source.try_seek(position)?;
+        self.reset_remaining(position);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    fn source() -> SamplesBuffer<i16> {
+        // Ten frames of two channels, ten frames per second
+        SamplesBuffer::new(2, 10, (0..20).collect::<Vec<i16>>())
+    }
+
+    fn range(start: u64, end: Option<u64>) -> TrackRange {
+        TrackRange {
+            start: Duration::from_millis(start),
+            end: end.map(Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn test_ranged() {
+        let ranged = Ranged::new(source(), range(200, Some(500)));
+        assert_eq!(ranged.total_duration(), Some(Duration::from_millis(300)));
+        assert_eq!(ranged.collect::<Vec<i16>>(), (4..10).collect::<Vec<i16>>());
+
+        // The last track runs to the end of the file
+        let ranged = Ranged::new(source(), range(700, None));
+        assert_eq!(ranged.total_duration(), Some(Duration::from_millis(300)));
+        assert_eq!(ranged.collect::<Vec<i16>>(), (14..20).collect::<Vec<i16>>());
+    }
+
+    #[test]
+    fn test_ranged_seek() {
+        let mut ranged = Ranged::new(source(), range(200, Some(600)));
+        ranged.try_seek(Duration::from_millis(300)).unwrap();
+        assert_eq!(ranged.collect::<Vec<i16>>(), vec![10, 11]);
+    }
+
+    #[test]
+    fn test_followed_by() {
+        assert!(range(0, Some(300)).is_followed_by(&range(300, None)));
+        assert!(!range(0, Some(300)).is_followed_by(&range(400, None)));
+        assert!(!range(300, None).is_followed_by(&range(300, None)));
+    }
+}
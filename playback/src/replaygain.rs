@@ -0,0 +1,273 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::source::SeekError;
+use rodio::{Sample, Source};
+
+use crate::track_info::TrackInfo;
+
+/// Loudness ReplayGain 2.0 normalizes to, in LUFS.
+pub const REFERENCE_LOUDNESS: f32 = -18.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+    /// Album gain while an album plays in order, track gain otherwise.
+    Auto,
+}
+
+impl From<i32> for ReplayGainMode {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => ReplayGainMode::Off,
+            1 => ReplayGainMode::Track,
+            2 => ReplayGainMode::Album,
+            3 => ReplayGainMode::Auto,
+            _ => ReplayGainMode::Off,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayGainSettings {
+    pub mode: ReplayGainMode,
+    /// Added to every gain, in dB.
+    pub preamp: f32,
+    /// Lower the gain so the peak of the track stays below full scale.
+    pub prevent_clipping: bool,
+}
+
+impl Default for ReplayGainSettings {
+    fn default() -> Self {
+        ReplayGainSettings {
+            mode: ReplayGainMode::Off,
+            preamp: 0.0,
+            prevent_clipping: true,
+        }
+    }
+}
+
+/// Loudness of a track measured while analyzing the library, used when the
+/// file carries no ReplayGain tags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FallbackLoudness {
+    /// In LUFS, or an estimate of it.
+    pub loudness: f32,
+    /// Linear, with full scale at 1.0.
+    pub true_peak: Option<f32>,
+}
+
+impl ReplayGainSettings {
+    /// Linear factor applied to a track.
+    ///
+    /// `in_album` tells whether the track plays as part of its album, and
+    /// `fallback_loudness` is used when the file carries no ReplayGain tags.
+    pub fn factor(
+        &self,
+        info: &TrackInfo,
+        in_album: bool,
+        fallback_loudness: Option<FallbackLoudness>,
+    ) -> f32 {
+        let use_album = match self.mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => false,
+            ReplayGainMode::Album => true,
+            ReplayGainMode::Auto => in_album,
+        };
+
+        let (gain, peak) = if use_album && info.album_gain.is_some() {
+            (info.album_gain, info.album_peak)
+        } else if info.track_gain.is_some() {
+            (info.track_gain, info.track_peak)
+        } else {
+            // Album tags alone are still better than nothing in track mode
+            (info.album_gain, info.album_peak)
+        };
+
+        let (gain, peak) = match (gain, fallback_loudness) {
+            (Some(gain), _) => (gain, peak),
+            (None, Some(x)) => (REFERENCE_LOUDNESS - x.loudness, x.true_peak),
+            (None, None) => return 1.0,
+        };
+
+        let factor = 10f32.powf((gain + self.preamp) / 20.0);
+        if !self.prevent_clipping {
+            return factor;
+        }
+
+        // Without a known peak, never amplify
+        match peak {
+            Some(peak) if peak > 0.0 => factor.min(1.0 / peak),
+            _ => factor.min(1.0),
+        }
+    }
+}
+
+/// Shared gain of a playing track, so a change of settings is heard right away.
+#[derive(Debug, Clone)]
+pub struct GainHandle(Arc<AtomicU32>);
+
+impl GainHandle {
+    pub fn new(factor: f32) -> Self {
+        GainHandle(Arc::new(AtomicU32::new(factor.to_bits())))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, factor: f32) {
+        self.0.store(factor.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Scale the samples of a source by the factor of a [`GainHandle`].
+pub struct Gain<I> {
+    input: I,
+    handle: GainHandle,
+}
+
+impl<I> Gain<I> {
+    pub fn new(input: I, handle: GainHandle) -> Self {
+        Gain { input, handle }
+    }
+}
+
+impl<I> Iterator for Gain<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.input
+            .next()
+            .map(|sample| sample.amplify(self.handle.get()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+impl<I> Source for Gain<I>
+where
+    I: Source,
+    I::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged() -> TrackInfo {
+        TrackInfo {
+            track_gain: Some(-6.0),
+            track_peak: Some(0.5),
+            album_gain: Some(-8.0),
+            album_peak: Some(0.9),
+            ..Default::default()
+        }
+    }
+
+    fn settings(mode: ReplayGainMode, preamp: f32, prevent_clipping: bool) -> ReplayGainSettings {
+        ReplayGainSettings {
+            mode,
+            preamp,
+            prevent_clipping,
+        }
+    }
+
+    fn db(factor: f32) -> f32 {
+        20.0 * factor.log10()
+    }
+
+    #[test]
+    fn test_modes() {
+        let info = tagged();
+
+        assert_eq!(
+            settings(ReplayGainMode::Off, 6.0, true).factor(&info, true, None),
+            1.0
+        );
+        assert!(
+            (db(settings(ReplayGainMode::Track, 0.0, true).factor(&info, true, None)) + 6.0).abs()
+                < 1e-3
+        );
+        assert!(
+            (db(settings(ReplayGainMode::Album, 0.0, true).factor(&info, false, None)) + 8.0).abs()
+                < 1e-3
+        );
+        assert!(
+            (db(settings(ReplayGainMode::Auto, 0.0, true).factor(&info, true, None)) + 8.0).abs()
+                < 1e-3
+        );
+        assert!(
+            (db(settings(ReplayGainMode::Auto, 0.0, true).factor(&info, false, None)) + 6.0).abs()
+                < 1e-3
+        );
+    }
+
+    #[test]
+    fn test_clipping_prevention() {
+        let info = tagged();
+
+        // +18 dB of preamp would push the 0.5 peak past full scale
+        let limited = settings(ReplayGainMode::Track, 18.0, true).factor(&info, false, None);
+        assert!((limited - 2.0).abs() < 1e-4);
+
+        let unlimited = settings(ReplayGainMode::Track, 18.0, false).factor(&info, false, None);
+        assert!((db(unlimited) - 12.0).abs() < 1e-3);
+    }
+
+    fn measured(loudness: f32, true_peak: Option<f32>) -> Option<FallbackLoudness> {
+        Some(FallbackLoudness {
+            loudness,
+            true_peak,
+        })
+    }
+
+    #[test]
+    fn test_fallback_loudness() {
+        let untagged = TrackInfo::default();
+        let track = settings(ReplayGainMode::Track, 0.0, false);
+
+        assert!((db(track.factor(&untagged, false, measured(-10.0, None))) + 8.0).abs() < 1e-3);
+        assert_eq!(track.factor(&untagged, false, None), 1.0);
+        // Tags win over the analysis
+        assert!((db(track.factor(&tagged(), false, measured(-10.0, None))) + 6.0).abs() < 1e-3);
+
+        let limited = settings(ReplayGainMode::Track, 0.0, true);
+        // The peak is unknown, so quiet tracks are not amplified
+        assert_eq!(limited.factor(&untagged, false, measured(-30.0, None)), 1.0);
+        // With a known peak, they are, up to full scale
+        assert!(
+            (db(limited.factor(&untagged, false, measured(-30.0, Some(0.1)))) - 12.0).abs() < 1e-3
+        );
+        assert!((limited.factor(&untagged, false, measured(-30.0, Some(0.5))) - 2.0).abs() < 1e-4);
+    }
+}
@@ -5,13 +5,19 @@ use std::sync::{Arc, Mutex};
 use rodio::Decoder;
 
 use crate::buffered::RuneBuffered;
+use crate::gapless::EncoderTrim;
+use crate::range::Ranged;
+use crate::replaygain::Gain;
 
+type DecodedSource = RuneBuffered<Gain<Ranged<EncoderTrim<Decoder<BufReader<File>>>>>>;
+
+#[derive(Clone)]
 pub struct SharedSource {
-    pub inner: Arc<Mutex<RuneBuffered<Decoder<BufReader<File>>>>>,
+    pub inner: Arc<Mutex<DecodedSource>>,
 }
 
 impl SharedSource {
-    pub fn new(source: RuneBuffered<Decoder<BufReader<File>>>) -> Self {
+    pub fn new(source: DecodedSource) -> Self {
         Self {
             inner: Arc::new(Mutex::new(source)),
         }
@@ -19,7 +25,7 @@ impl SharedSource {
 }
 
 impl Iterator for SharedSource {
-    type Item = <RuneBuffered<Decoder<BufReader<File>>> as Iterator>::Item;
+    type Item = <DecodedSource as Iterator>::Item;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.lock().unwrap().next()
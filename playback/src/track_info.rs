@@ -0,0 +1,170 @@
+use std::fs::File;
+use std::path::Path;
+
+use audio_formats::hint_extension;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+/// Tags the player needs to decide how to move from one track to the next.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackInfo {
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub disc_number: Option<u32>,
+    pub track_number: Option<u32>,
+    // ReplayGain, in dB
+    pub track_gain: Option<f32>,
+    pub album_gain: Option<f32>,
+    // ReplayGain peaks, linear with full scale at 1.0
+    pub track_peak: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+/// Parse numbers like `3` or `3/12`.
+fn parse_number(value: &str) -> Option<u32> {
+    value.split('/').next()?.trim().parse().ok()
+}
+
+/// Parse ReplayGain values like `-6.50 dB` or `0.988312`.
+fn parse_gain(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("DB"))
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    value.trim().parse().ok().filter(|x: &f32| x.is_finite())
+}
+
+impl TrackInfo {
+    fn apply(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let value = tag.value.to_string();
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+
+            match tag.std_key {
+                Some(StandardTagKey::Album) => self.album = Some(value.to_string()),
+                Some(StandardTagKey::AlbumArtist) => self.album_artist = Some(value.to_string()),
+                Some(StandardTagKey::DiscNumber) => self.disc_number = parse_number(value),
+                Some(StandardTagKey::TrackNumber) => self.track_number = parse_number(value),
+                Some(StandardTagKey::ReplayGainTrackGain) => self.track_gain = parse_gain(value),
+                Some(StandardTagKey::ReplayGainTrackPeak) => self.track_peak = parse_gain(value),
+                Some(StandardTagKey::ReplayGainAlbumGain) => self.album_gain = parse_gain(value),
+                Some(StandardTagKey::ReplayGainAlbumPeak) => self.album_peak = parse_gain(value),
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether `next` directly follows this track on the same album, in which
+    /// case the transition is part of the record and must stay gapless.
+    pub fn is_followed_on_album_by(&self, next: &TrackInfo) -> bool {
+        let same_album = match (&self.album, &next.album) {
+            (Some(a), Some(b)) => a == b && self.album_artist == next.album_artist,
+            _ => false,
+        };
+
+        if !same_album {
+            return false;
+        }
+
+        match (self.track_number, next.track_number) {
+            (Some(current), Some(next_number)) => {
+                if self.disc_number.unwrap_or(1) == next.disc_number.unwrap_or(1) {
+                    next_number == current + 1
+                } else {
+                    next.disc_number.unwrap_or(1) == self.disc_number.unwrap_or(1) + 1
+                        && next_number == 1
+                }
+            }
+            // Without track numbers, consecutive tracks of an album are
+            // assumed to be in order
+            _ => true,
+        }
+    }
+}
+
+/// Read the tags of an audio file. Missing or unreadable tags are left empty.
+pub fn read_track_info(path: &Path) -> TrackInfo {
+    let mut info = TrackInfo::default();
+
+    let Ok(file) = File::open(path) else {
+        return info;
+    };
+
+    let mut hint = Hint::new();
+    if let Some(extension) = hint_extension(path) {
+        hint.with_extension(&extension);
+    }
+
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let Ok(mut probed) = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) else {
+        return info;
+    };
+
+    // Tags in front of the container, like ID3v2, come first
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.current() {
+            info.apply(revision);
+        }
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        info.apply(revision);
+    }
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(album: &str, disc: Option<u32>, track: Option<u32>) -> TrackInfo {
+        TrackInfo {
+            album: Some(album.to_string()),
+            album_artist: None,
+            disc_number: disc,
+            track_number: track,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_followed_on_album() {
+        let third = track("Live", Some(1), Some(3));
+        let last = track("Live", Some(1), Some(12));
+
+        assert!(third.is_followed_on_album_by(&track("Live", Some(1), Some(4))));
+        assert!(last.is_followed_on_album_by(&track("Live", Some(2), Some(1))));
+        assert!(track("Live", None, None).is_followed_on_album_by(&track("Live", None, None)));
+
+        assert!(!third.is_followed_on_album_by(&track("Live", Some(1), Some(7))));
+        assert!(!third.is_followed_on_album_by(&track("Studio", Some(1), Some(4))));
+        assert!(!TrackInfo::default().is_followed_on_album_by(&TrackInfo::default()));
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("3/12"), Some(3));
+        assert_eq!(parse_number(" 7 "), Some(7));
+        assert_eq!(parse_number("A1"), None);
+    }
+
+    #[test]
+    fn test_parse_gain() {
+        assert_eq!(parse_gain("-6.50 dB"), Some(-6.5));
+        assert_eq!(parse_gain("+2.1dB"), Some(2.1));
+        assert_eq!(parse_gain("0.988312"), Some(0.988312));
+        assert_eq!(parse_gain("loud"), None);
+    }
+}
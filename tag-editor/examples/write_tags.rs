@@ -0,0 +1,44 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use clap::{Arg, ArgAction, Command};
+
+use tag_editor::tag_writer::writer::{write_tags, TagEdit};
+
+fn main() -> Result<()> {
+    // Set up CLI arguments
+    let matches = Command::new("Tag Writer")
+        .version("1.0")
+        .author("Rune Developers")
+        .about("Writes tags to an audio file, use `key=` to remove a key")
+        .arg(
+            Arg::new("FILE_PATH")
+                .help("Sets the input audio file path")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::new("EDITS")
+                .help("Tag edits in `key=value` form, using standard key names")
+                .required(true)
+                .action(ArgAction::Append)
+                .index(2),
+        )
+        .get_matches();
+
+    let file_path = matches.get_one::<String>("FILE_PATH").unwrap();
+
+    let mut edits = Vec::new();
+    for edit in matches.get_many::<String>("EDITS").unwrap() {
+        match edit.split_once('=') {
+            Some((key, "")) => edits.push(TagEdit::remove(key)),
+            Some((key, value)) => edits.push(TagEdit::set(key, value)),
+            None => bail!("Invalid edit: {}", edit),
+        }
+    }
+
+    write_tags(Path::new(file_path), &edits)?;
+    println!("Tags written: {}", file_path);
+
+    Ok(())
+}
@@ -43,6 +43,7 @@ pub struct AcoustIdResult {
 pub struct Recording {
     pub id: String,
     pub title: String,
+    pub duration: Option<f64>,
     pub artists: Vec<Artist>,
     pub releases: Option<Vec<Release>>,
 }
@@ -51,14 +52,24 @@ pub struct Recording {
 pub struct Artist {
     pub id: String,
     pub name: String,
+    pub joinphrase: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Release {
     pub id: String,
+    pub title: Option<String>,
+    pub date: Option<ReleaseDate>,
     pub mediums: Option<Vec<Medium>>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ReleaseDate {
+    pub year: Option<i32>,
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Medium {
     pub format: String,
@@ -73,6 +84,12 @@ pub struct Track {
     pub position: u32,
     pub title: String,
     pub artists: Vec<Artist>,
+    pub recording: Option<TrackRecording>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TrackRecording {
+    pub id: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -81,7 +98,13 @@ pub struct ErrorDetail {
     pub message: String,
 }
 
+pub const ACOUSTID_LOOKUP_ENDPOINT: &str = "https://api.acoustid.org/v2/lookup";
+
+/// How long to wait before retrying a failed lookup.
+pub const ACOUSTID_RETRY_DELAY: Duration = Duration::from_secs(1);
+
 async fn identify_implementation(
+    endpoint: &str,
     api_key: &str,
     fingerprint: Vec<u32>,
     config: &Configuration,
@@ -99,11 +122,7 @@ async fn identify_implementation(
     form_data.insert("fingerprint", &encoded_fingerprint);
     form_data.insert("meta", "recordings releases tracks");
 
-    let response = client
-        .post("https://api.acoustid.org/v2/lookup")
-        .form(&form_data)
-        .send()
-        .await?;
+    let response = client.post(endpoint).form(&form_data).send().await?;
 
     let response_text = response.text().await?;
 
@@ -120,14 +139,35 @@ pub async fn identify(
     fingerprint: Vec<u32>,
     config: &Configuration,
     duration: u32,
+) -> Result<Vec<AcoustIdResult>> {
+    identify_with_endpoint(
+        ACOUSTID_LOOKUP_ENDPOINT,
+        api_key,
+        fingerprint,
+        config,
+        duration,
+        ACOUSTID_RETRY_DELAY,
+    )
+    .await
+}
+
+pub async fn identify_with_endpoint(
+    endpoint: &str,
+    api_key: &str,
+    fingerprint: Vec<u32>,
+    config: &Configuration,
+    duration: u32,
+    retry_delay: Duration,
 ) -> Result<Vec<AcoustIdResult>> {
     let mut attempts = 0;
     loop {
         if attempts > 0 {
-            sleep(Duration::from_secs(1)).await;
+            sleep(retry_delay).await;
         }
 
-        match identify_implementation(api_key, fingerprint.clone(), config, duration).await {
+        match identify_implementation(endpoint, api_key, fingerprint.clone(), config, duration)
+            .await
+        {
             Ok(results) => return Ok(results),
             Err(e) if attempts < 3 => {
                 attempts += 1;
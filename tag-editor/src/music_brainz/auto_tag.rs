@@ -0,0 +1,525 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusty_chromaprint::Configuration;
+use tokio_util::sync::CancellationToken;
+
+use database::actions::file::get_file_by_id;
+use database::actions::metadata::get_metadata_by_file_id;
+use database::actions::suggestions::{
+    apply_metadata_candidate, replace_metadata_suggestions, MetadataCandidate,
+};
+use database::connection::MainDbConnection;
+
+use crate::shazam::rate_limiter::RateLimiter;
+
+use super::api::{identify_with_endpoint, AcoustIdResult, Recording, Release};
+use super::fingerprint::calc_fingerprint;
+
+/// AcoustID allows three requests per second for each client key.
+const ACOUSTID_REQUEST_INTERVAL: Duration = Duration::from_millis(334);
+
+/// Duration difference, in seconds, at which a recording no longer counts as
+/// a duration match.
+const DURATION_TOLERANCE: f64 = 10.0;
+
+/// How many candidates to keep as suggestions for an ambiguous file.
+const MAX_SUGGESTIONS: usize = 5;
+
+static YEAR_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d{4}").unwrap());
+
+#[derive(Debug, Clone)]
+pub struct AutoTagOptions {
+    pub api_key: String,
+    pub endpoint: String,
+    /// Minimum score for a candidate to be applied without review.
+    pub min_score: f64,
+    /// Minimum lead of the best candidate over the runner-up to be applied
+    /// without review.
+    pub min_margin: f64,
+    /// How long to wait before retrying a failed lookup.
+    pub retry_delay: Duration,
+}
+
+impl AutoTagOptions {
+    pub fn new(api_key: &str) -> Self {
+        AutoTagOptions {
+            api_key: api_key.to_string(),
+            endpoint: super::api::ACOUSTID_LOOKUP_ENDPOINT.to_string(),
+            min_score: 0.8,
+            min_margin: 0.1,
+            retry_delay: super::api::ACOUSTID_RETRY_DELAY,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AutoTagSummary {
+    pub applied: usize,
+    pub pending: usize,
+    pub unmatched: usize,
+    pub failed: usize,
+}
+
+/// What the library already knows about a file, used to break ties between
+/// releases of the same recording.
+#[derive(Debug, Default, Clone)]
+pub struct ExistingTags {
+    pub year: Option<i32>,
+    pub album: Option<String>,
+}
+
+impl ExistingTags {
+    pub fn from_metadata(metadata: &[(String, String)]) -> Self {
+        let find = |key: &str| {
+            metadata
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        };
+
+        let year = find("original_date")
+            .or_else(|| find("date"))
+            .or_else(|| find("release_date"))
+            .and_then(|x| {
+                YEAR_RE
+                    .find(&x)
+                    .and_then(|mat| mat.as_str().parse::<i32>().ok())
+            });
+
+        ExistingTags {
+            year,
+            album: find("album"),
+        }
+    }
+}
+
+fn artist_credit(recording: &Recording) -> String {
+    recording
+        .artists
+        .iter()
+        .enumerate()
+        .map(|(index, artist)| {
+            let is_last = index + 1 == recording.artists.len();
+            match (&artist.joinphrase, is_last) {
+                (Some(joinphrase), false) => format!("{}{}", artist.name, joinphrase),
+                (None, false) => format!("{}, ", artist.name),
+                _ => artist.name.clone(),
+            }
+        })
+        .collect()
+}
+
+fn release_year(release: &Release) -> Option<i32> {
+    release.date.as_ref().and_then(|date| date.year)
+}
+
+fn duration_score(recording: &Recording, file_duration: f64) -> f64 {
+    match recording.duration {
+        Some(duration) => {
+            (1.0 - (duration - file_duration).abs() / DURATION_TOLERANCE).clamp(0.0, 1.0)
+        }
+        None => 0.5,
+    }
+}
+
+fn release_score(release: &Release, existing: &ExistingTags) -> f64 {
+    let date_score = match (release_year(release), existing.year) {
+        (Some(year), Some(existing_year)) if year == existing_year => 1.0,
+        (Some(year), Some(existing_year)) if (year - existing_year).abs() <= 1 => 0.75,
+        (Some(_), Some(_)) => 0.0,
+        (Some(_), None) => 0.5,
+        (None, _) => 0.25,
+    };
+
+    let album_score = match (&release.title, &existing.album) {
+        (Some(title), Some(album)) if title.to_lowercase() == album.to_lowercase() => 1.0,
+        _ => 0.0,
+    };
+
+    date_score + album_score
+}
+
+/// Pick the release that fits the existing tags best, preferring the earliest
+/// release when the tags do not tell them apart.
+fn choose_release<'a>(releases: &'a [Release], existing: &ExistingTags) -> Option<&'a Release> {
+    releases.iter().max_by(|a, b| {
+        release_score(a, existing)
+            .partial_cmp(&release_score(b, existing))
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| {
+                let a_year = release_year(a).unwrap_or(i32::MAX);
+                let b_year = release_year(b).unwrap_or(i32::MAX);
+                b_year.cmp(&a_year)
+            })
+    })
+}
+
+/// Find the track of the recording on a release. Tracks that name their
+/// recording are matched by its ID, the others by the recording title, so a
+/// release never hands out the track of another recording.
+fn find_track_id(release: &Release, recording: &Recording) -> Option<String> {
+    let tracks: Vec<_> = release
+        .mediums
+        .as_ref()?
+        .iter()
+        .filter_map(|medium| medium.tracks.as_ref())
+        .flatten()
+        .collect();
+
+    tracks
+        .iter()
+        .find(|track| {
+            track
+                .recording
+                .as_ref()
+                .is_some_and(|x| x.id == recording.id)
+        })
+        .or_else(|| {
+            tracks.iter().find(|track| {
+                track.recording.is_none()
+                    && track.title.to_lowercase() == recording.title.to_lowercase()
+            })
+        })
+        .map(|track| track.id.clone())
+}
+
+/// Rank every recording returned by AcoustID. Candidates are scored by the
+/// fingerprint match, how close the recording duration is to the file, and
+/// whether the chosen release agrees with the dates already in the tags.
+/// Each recording appears once, best candidate first.
+pub fn rank_candidates(
+    results: &[AcoustIdResult],
+    file_duration: f64,
+    existing: &ExistingTags,
+) -> Vec<MetadataCandidate> {
+    let mut candidates: Vec<MetadataCandidate> = Vec::new();
+
+    for result in results {
+        let recordings = match &result.recordings {
+            Some(recordings) => recordings,
+            None => continue,
+        };
+
+        for recording in recordings {
+            let release = recording
+                .releases
+                .as_ref()
+                .and_then(|releases| choose_release(releases, existing));
+
+            let date_consistency = match release {
+                Some(release) => release_score(release, existing).min(1.0),
+                None => 0.25,
+            };
+
+            let score = 0.5 * result.score
+                + 0.3 * duration_score(recording, file_duration)
+                + 0.2 * date_consistency;
+
+            candidates.push(MetadataCandidate {
+                acoustid_id: Some(result.id.clone()),
+                recording_id: recording.id.clone(),
+                release_id: release.map(|x| x.id.clone()),
+                track_id: release.and_then(|x| find_track_id(x, recording)),
+                artist_ids: recording.artists.iter().map(|x| x.id.clone()).collect(),
+                title: recording.title.clone(),
+                artist: artist_credit(recording),
+                album: release.and_then(|x| x.title.clone()),
+                score,
+            });
+        }
+    }
+
+    candidates.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let mut seen = HashSet::new();
+    candidates.retain(|x| seen.insert(x.recording_id.clone()));
+
+    candidates
+}
+
+/// Decide whether the best candidate is unambiguous enough to be applied
+/// without asking the user.
+pub fn is_confident_match(candidates: &[MetadataCandidate], options: &AutoTagOptions) -> bool {
+    match candidates {
+        [] => false,
+        [best] => best.score >= options.min_score,
+        [best, runner_up, ..] => {
+            best.score >= options.min_score && best.score - runner_up.score >= options.min_margin
+        }
+    }
+}
+
+async fn auto_tag_file(
+    main_db: &MainDbConnection,
+    lib_path: &Path,
+    file_id: i32,
+    options: &AutoTagOptions,
+    rate_limiter: &RateLimiter,
+) -> Result<AutoTagSummary> {
+    let file = get_file_by_id(main_db, file_id)
+        .await?
+        .with_context(|| format!("File not found: {}", file_id))?;
+    let full_path = lib_path.join(&file.directory).join(&file.file_name);
+
+    let (fingerprint, duration) = tokio::task::spawn_blocking(move || {
+        calc_fingerprint(&full_path, &Configuration::default())
+    })
+    .await??;
+
+    rate_limiter.acquire().await;
+    let results = identify_with_endpoint(
+        &options.endpoint,
+        &options.api_key,
+        fingerprint,
+        &Configuration::default(),
+        duration.as_secs().try_into()?,
+        options.retry_delay,
+    )
+    .await?;
+
+    let existing = ExistingTags::from_metadata(&get_metadata_by_file_id(main_db, file_id).await?);
+    let candidates = rank_candidates(&results, duration.as_secs_f64(), &existing);
+
+    let mut summary = AutoTagSummary::default();
+    if candidates.is_empty() {
+        summary.unmatched += 1;
+    } else if is_confident_match(&candidates, options) {
+        apply_metadata_candidate(main_db, file_id, &candidates[0]).await?;
+        summary.applied += 1;
+    } else {
+        let kept = candidates.len().min(MAX_SUGGESTIONS);
+        replace_metadata_suggestions(main_db, file_id, &candidates[..kept]).await?;
+        summary.pending += 1;
+    }
+
+    Ok(summary)
+}
+
+/// Identify a set of library files through AcoustID and record the matched
+/// MusicBrainz metadata. Confident matches are written to `media_metadata`
+/// directly, ambiguous ones are kept as pending suggestions.
+pub async fn auto_tag_files<F>(
+    main_db: &MainDbConnection,
+    lib_path: &Path,
+    file_ids: &[i32],
+    options: &AutoTagOptions,
+    progress_callback: F,
+    cancel_token: Option<CancellationToken>,
+) -> Result<AutoTagSummary>
+where
+    F: Fn(usize, usize) + Send + Sync,
+{
+    let rate_limiter = RateLimiter::new(ACOUSTID_REQUEST_INTERVAL);
+    let mut summary = AutoTagSummary::default();
+
+    for (index, file_id) in file_ids.iter().enumerate() {
+        if let Some(ref token) = cancel_token {
+            if token.is_cancelled() {
+                info!("Auto tagging cancelled.");
+                return Ok(summary);
+            }
+        }
+
+        match auto_tag_file(main_db, lib_path, *file_id, options, &rate_limiter).await {
+            Ok(result) => {
+                summary.applied += result.applied;
+                summary.pending += result.pending;
+                summary.unmatched += result.unmatched;
+            }
+            Err(e) => {
+                error!("Failed to auto tag file {}: {:?}", file_id, e);
+                summary.failed += 1;
+            }
+        }
+
+        progress_callback(index + 1, file_ids.len());
+    }
+
+    if summary.failed > 0 {
+        warn!("{} files failed to be auto tagged", summary.failed);
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOOKUP_RESPONSE: &str = r#"{
+        "status": "ok",
+        "results": [{
+            "id": "acoustid-1",
+            "score": 0.95,
+            "recordings": [
+                {
+                    "id": "recording-live",
+                    "title": "Song (Live)",
+                    "duration": 262,
+                    "artists": [{"id": "artist-1", "name": "Band"}],
+                    "releases": [{"id": "release-live", "title": "Live", "date": {"year": 2011}}]
+                },
+                {
+                    "id": "recording-studio",
+                    "title": "Song",
+                    "duration": 201,
+                    "artists": [
+                        {"id": "artist-1", "name": "Band", "joinphrase": " feat. "},
+                        {"id": "artist-2", "name": "Singer"}
+                    ],
+                    "releases": [
+                        {"id": "release-best-of", "title": "Best Of", "date": {"year": 2015}},
+                        {
+                            "id": "release-album",
+                            "title": "Album",
+                            "date": {"year": 2004, "month": 5},
+                            "mediums": [{
+                                "format": "CD",
+                                "position": 1,
+                                "track_count": 10,
+                                "tracks": [
+                                    {
+                                        "id": "track-1",
+                                        "position": 1,
+                                        "title": "Opener",
+                                        "artists": [{"id": "artist-1", "name": "Band"}],
+                                        "recording": {"id": "recording-opener"}
+                                    },
+                                    {
+                                        "id": "track-3",
+                                        "position": 3,
+                                        "title": "Song (Album Version)",
+                                        "artists": [{"id": "artist-1", "name": "Band"}],
+                                        "recording": {"id": "recording-studio"}
+                                    }
+                                ]
+                            }]
+                        }
+                    ]
+                }
+            ]
+        }]
+    }"#;
+
+    async fn lookup_from_mock_server(body: &str) -> Result<Vec<AcoustIdResult>> {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v2/lookup")
+            .match_body(mockito::Matcher::UrlEncoded(
+                "client".into(),
+                "test-key".into(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(body)
+            .create_async()
+            .await;
+
+        let results = identify_with_endpoint(
+            &format!("{}/v2/lookup", server.url()),
+            "test-key",
+            vec![1, 2, 3, 4],
+            &Configuration::default(),
+            200,
+            Duration::ZERO,
+        )
+        .await;
+
+        mock.assert_async().await;
+        results
+    }
+
+    #[tokio::test]
+    async fn test_rank_prefers_duration_and_release_date() -> Result<()> {
+        let results = lookup_from_mock_server(LOOKUP_RESPONSE).await?;
+
+        let existing = ExistingTags {
+            year: Some(2004),
+            album: None,
+        };
+        let candidates = rank_candidates(&results, 200.5, &existing);
+
+        assert_eq!(candidates.len(), 2);
+
+        let best = &candidates[0];
+        assert_eq!(best.recording_id, "recording-studio");
+        assert_eq!(best.release_id.as_deref(), Some("release-album"));
+        assert_eq!(best.track_id.as_deref(), Some("track-3"));
+        assert_eq!(best.album.as_deref(), Some("Album"));
+        assert_eq!(best.artist, "Band feat. Singer");
+        assert_eq!(best.artist_ids, vec!["artist-1", "artist-2"]);
+        assert_eq!(best.acoustid_id.as_deref(), Some("acoustid-1"));
+
+        assert!(is_confident_match(
+            &candidates,
+            &AutoTagOptions::new("test-key")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ambiguous_match_is_not_applied() -> Result<()> {
+        let results = lookup_from_mock_server(LOOKUP_RESPONSE).await?;
+
+        // Halfway between both recordings and without any date hint
+        let candidates = rank_candidates(&results, 231.5, &ExistingTags::default());
+
+        assert_eq!(candidates.len(), 2);
+        assert!(!is_confident_match(
+            &candidates,
+            &AutoTagOptions::new("test-key")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lookup_error_is_reported() {
+        let body = r#"{"status": "error", "error": {"code": 4, "message": "invalid API key"}}"#;
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v2/lookup")
+            .with_status(200)
+            .with_body(body)
+            .expect(4)
+            .create_async()
+            .await;
+
+        let results = identify_with_endpoint(
+            &format!("{}/v2/lookup", server.url()),
+            "bad-key",
+            vec![1, 2, 3, 4],
+            &Configuration::default(),
+            200,
+            Duration::ZERO,
+        )
+        .await;
+
+        // Every retry reaches the server, without waiting in between
+        mock.assert_async().await;
+        assert!(results.is_err());
+    }
+
+    #[test]
+    fn test_existing_tags_from_metadata() {
+        let metadata = vec![
+            ("album".to_string(), "Album".to_string()),
+            ("date".to_string(), "2004-05-01".to_string()),
+        ];
+
+        let existing = ExistingTags::from_metadata(&metadata);
+        assert_eq!(existing.year, Some(2004));
+        assert_eq!(existing.album.as_deref(), Some("Album"));
+    }
+}
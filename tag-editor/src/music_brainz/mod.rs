@@ -1,2 +1,3 @@
+pub mod api;
+pub mod auto_tag;
 pub mod fingerprint;
-pub mod api;
\ No newline at end of file
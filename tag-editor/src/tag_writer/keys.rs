@@ -0,0 +1,79 @@
+use lofty::tag::ItemKey;
+
+/// Maps a standard key name produced by `metadata::reader` to the lofty item
+/// key used when writing it back. Keys without a portable representation
+/// across ID3v2, Vorbis comments and MP4 atoms return `None`.
+pub fn standard_key_to_item_key(key: &str) -> Option<ItemKey> {
+    let item_key = match key {
+        "album" => ItemKey::AlbumTitle,
+        "album_artist" => ItemKey::AlbumArtist,
+        "arranger" => ItemKey::Arranger,
+        "artist" => ItemKey::TrackArtist,
+        "bpm" => ItemKey::IntegerBpm,
+        "comment" => ItemKey::Comment,
+        "compilation" => ItemKey::FlagCompilation,
+        "composer" => ItemKey::Composer,
+        "conductor" => ItemKey::Conductor,
+        "content_group" => ItemKey::ContentGroup,
+        "copyright" => ItemKey::CopyrightMessage,
+        "date" => ItemKey::RecordingDate,
+        "description" => ItemKey::Description,
+        "disc_number" => ItemKey::DiscNumber,
+        "disc_subtitle" => ItemKey::SetSubtitle,
+        "disc_total" => ItemKey::DiscTotal,
+        "encoded_by" => ItemKey::EncodedBy,
+        "encoder" => ItemKey::EncoderSoftware,
+        "encoder_settings" => ItemKey::EncoderSettings,
+        "engineer" => ItemKey::Engineer,
+        "genre" => ItemKey::Genre,
+        "ident_barcode" => ItemKey::Barcode,
+        "ident_catalog_number" => ItemKey::CatalogNumber,
+        "ident_isrc" => ItemKey::Isrc,
+        "label" => ItemKey::Label,
+        "language" => ItemKey::Language,
+        "license" => ItemKey::License,
+        "lyricist" => ItemKey::Lyricist,
+        "lyrics" => ItemKey::Lyrics,
+        "mix_dj" => ItemKey::MixDj,
+        "mix_engineer" => ItemKey::MixEngineer,
+        "mood" => ItemKey::Mood,
+        "movement_name" => ItemKey::Movement,
+        "movement_number" => ItemKey::MovementNumber,
+        "musicbrainz_album_artist_id" => ItemKey::MusicBrainzReleaseArtistId,
+        "musicbrainz_album_id" => ItemKey::MusicBrainzReleaseId,
+        "musicbrainz_artist_id" => ItemKey::MusicBrainzArtistId,
+        "musicbrainz_recording_id" => ItemKey::MusicBrainzRecordingId,
+        "musicbrainz_release_group_id" => ItemKey::MusicBrainzReleaseGroupId,
+        "musicbrainz_release_track_id" => ItemKey::MusicBrainzTrackId,
+        // Symphonia reports `MUSICBRAINZ_TRACKID` under this key, which
+        // Picard uses for the recording MBID.
+        "musicbrainz_track_id" => ItemKey::MusicBrainzRecordingId,
+        "musicbrainz_work_id" => ItemKey::MusicBrainzWorkId,
+        "original_album" => ItemKey::OriginalAlbumTitle,
+        "original_artist" => ItemKey::OriginalArtist,
+        "original_date" => ItemKey::OriginalReleaseDate,
+        "original_writer" => ItemKey::OriginalLyricist,
+        "performer" => ItemKey::Performer,
+        "producer" => ItemKey::Producer,
+        "release_date" => ItemKey::ReleaseDate,
+        "remixer" => ItemKey::Remixer,
+        "replaygain_album_gain" => ItemKey::ReplayGainAlbumGain,
+        "replaygain_album_peak" => ItemKey::ReplayGainAlbumPeak,
+        "replaygain_track_gain" => ItemKey::ReplayGainTrackGain,
+        "replaygain_track_peak" => ItemKey::ReplayGainTrackPeak,
+        "script" => ItemKey::Script,
+        "sort_album" => ItemKey::AlbumTitleSortOrder,
+        "sort_album_artist" => ItemKey::AlbumArtistSortOrder,
+        "sort_artist" => ItemKey::TrackArtistSortOrder,
+        "sort_composer" => ItemKey::ComposerSortOrder,
+        "sort_track_title" => ItemKey::TrackTitleSortOrder,
+        "track_number" => ItemKey::TrackNumber,
+        "track_subtitle" => ItemKey::TrackSubtitle,
+        "track_title" => ItemKey::TrackTitle,
+        "track_total" => ItemKey::TrackTotal,
+        "writer" => ItemKey::Writer,
+        _ => return None,
+    };
+
+    Some(item_key)
+}
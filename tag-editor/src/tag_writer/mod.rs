@@ -0,0 +1,4 @@
+pub mod keys;
+pub mod replaygain;
+pub mod sync;
+pub mod writer;
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Result;
+use log::{error, info, warn};
+
+use database::actions::replaygain::ReplayGainValues;
+use database::connection::MainDbConnection;
+
+use super::sync::write_tags_and_sync;
+use super::writer::TagEdit;
+
+fn format_gain(gain: f64) -> String {
+    format!("{:.2} dB", gain)
+}
+
+fn format_peak(peak: f64) -> String {
+    format!("{:.6}", peak)
+}
+
+/// The REPLAYGAIN_* tags of a track. Tags whose value is unknown are left
+/// untouched.
+pub fn replaygain_edits(values: &ReplayGainValues) -> Vec<TagEdit> {
+    let mut edits = vec![TagEdit::set(
+        "replaygain_track_gain",
+        format_gain(values.track_gain),
+    )];
+
+    if let Some(peak) = values.track_peak {
+        edits.push(TagEdit::set("replaygain_track_peak", format_peak(peak)));
+    }
+    if let Some(gain) = values.album_gain {
+        edits.push(TagEdit::set("replaygain_album_gain", format_gain(gain)));
+    }
+    if let Some(peak) = values.album_peak {
+        edits.push(TagEdit::set("replaygain_album_peak", format_peak(peak)));
+    }
+
+    edits
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayGainWriteReport {
+    pub written: usize,
+    /// Tracks of CUE sheets, whose values can't be told apart in their file.
+    pub skipped: usize,
+    pub failed: Vec<(i32, String)>,
+}
+
+/// Writes ReplayGain tags to the library files and syncs them back into the
+/// database. Files are written one album at a time, and a failing album does
+/// not stop the others. Tracks of CUE sheets are skipped, as every track of
+/// a sheet would write its values to the same file.
+pub async fn write_replaygain_tags(
+    main_db: &MainDbConnection,
+    lib_path: &Path,
+    values: &[ReplayGainValues],
+) -> Result<ReplayGainWriteReport> {
+    let mut report = ReplayGainWriteReport::default();
+
+    let mut groups: BTreeMap<Option<i32>, Vec<&ReplayGainValues>> = BTreeMap::new();
+    for x in values {
+        if x.cue_track.is_some() {
+            warn!(
+                "Skipping ReplayGain of track {} of a CUE sheet: {}",
+                x.cue_track.unwrap_or_default(),
+                x.path.display()
+            );
+            report.skipped += 1;
+            continue;
+        }
+        groups.entry(x.album_id).or_default().push(x);
+    }
+
+    for (album_id, tracks) in groups {
+        // Files without an album do not need to be written together
+        let batches: Vec<Vec<&ReplayGainValues>> = match album_id {
+            Some(_) => vec![tracks],
+            None => tracks.into_iter().map(|x| vec![x]).collect(),
+        };
+
+        for batch in batches {
+            let edits: Vec<(i32, Vec<TagEdit>)> = batch
+                .iter()
+                .map(|x| (x.file_id, replaygain_edits(x)))
+                .collect();
+
+            match write_tags_and_sync(main_db, lib_path, &edits).await {
+                Ok(_) => report.written += batch.len(),
+                Err(e) => {
+                    error!("Failed to write ReplayGain tags: {:?}", e);
+                    for x in batch {
+                        report.failed.push((x.file_id, format!("{:#}", e)));
+                    }
+                }
+            }
+        }
+    }
+
+    info!(
+        "ReplayGain tags written to {} files, {} skipped, {} failed",
+        report.written,
+        report.skipped,
+        report.failed.len()
+    );
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use database::actions::file::get_file_by_path;
+    use database::actions::metadata::{empty_progress_callback, scan_audio_library};
+    use database::connection::connect_main_db;
+    use metadata::reader::get_metadata;
+
+    use super::*;
+
+    #[test]
+    fn test_replaygain_edits() {
+        let mut values = ReplayGainValues {
+            file_id: 1,
+            path: PathBuf::from("a.flac"),
+            album_id: Some(2),
+            cue_track: None,
+            track_gain: -6.504,
+            track_peak: Some(0.98831234),
+            album_gain: None,
+            album_peak: None,
+        };
+
+        assert_eq!(
+            replaygain_edits(&values),
+            vec![
+                TagEdit::set("replaygain_track_gain", "-6.50 dB"),
+                TagEdit::set("replaygain_track_peak", "0.988312"),
+            ]
+        );
+
+        values.album_gain = Some(1.0);
+        values.album_peak = Some(1.2);
+        let edits = replaygain_edits(&values);
+        assert_eq!(edits.len(), 4);
+        assert_eq!(edits[2], TagEdit::set("replaygain_album_gain", "1.00 dB"));
+        assert_eq!(edits[3], TagEdit::set("replaygain_album_peak", "1.200000"));
+
+        // Unknown peaks are not written
+        values.track_peak = None;
+        values.album_peak = None;
+        assert_eq!(
+            replaygain_edits(&values),
+            vec![
+                TagEdit::set("replaygain_track_gain", "-6.50 dB"),
+                TagEdit::set("replaygain_album_gain", "1.00 dB"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_skip_cue_tracks() {
+        let lib_path =
+            std::env::temp_dir().join(format!("rune-replaygain-cue-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&lib_path);
+        fs::create_dir_all(&lib_path).unwrap();
+        fs::copy("../assets/startup_0.ogg", lib_path.join("track.ogg")).unwrap();
+
+        let main_db = connect_main_db(lib_path.to_str().unwrap(), None)
+            .await
+            .unwrap();
+        scan_audio_library(
+            &main_db,
+            &lib_path,
+            true,
+            false,
+            empty_progress_callback,
+            None,
+        )
+        .await
+        .unwrap();
+        let file = get_file_by_path(&main_db, Path::new("track.ogg"))
+            .await
+            .unwrap()
+            .expect("The fixture should be scanned");
+
+        let values = |cue_track| ReplayGainValues {
+            file_id: file.id,
+            path: PathBuf::from("track.ogg"),
+            album_id: Some(1),
+            cue_track,
+            track_gain: -3.0,
+            track_peak: Some(0.5),
+            album_gain: Some(-4.0),
+            album_peak: Some(0.9),
+        };
+        let track_gain = || {
+            get_metadata(lib_path.join("track.ogg").to_str().unwrap(), None)
+                .unwrap()
+                .into_iter()
+                .find(|(key, _)| key == "replaygain_track_gain")
+                .map(|(_, value)| value)
+        };
+
+        // Every track of the sheet would write to the same file
+        let report =
+            write_replaygain_tags(&main_db, &lib_path, &[values(Some(1)), values(Some(2))])
+                .await
+                .unwrap();
+        assert_eq!(report.written, 0);
+        assert_eq!(report.skipped, 2);
+        assert!(report.failed.is_empty());
+        assert_eq!(track_gain(), None);
+
+        let report = write_replaygain_tags(&main_db, &lib_path, &[values(None)])
+            .await
+            .unwrap();
+        assert_eq!(report.written, 1);
+        assert_eq!(report.skipped, 0);
+        assert_eq!(track_gain().as_deref(), Some("-3.00 dB"));
+
+        fs::remove_dir_all(&lib_path).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::{error, warn};
+
+use database::actions::file::get_file_by_id;
+use database::actions::index::index_media_files;
+use database::actions::metadata::sync_file_descriptions;
+use database::connection::MainDbConnection;
+use metadata::describe::{describe_file, FileDescription};
+
+use super::writer::{write_tags, TagEdit};
+
+/// Writes tag edits to library files and re-syncs their `media_files` and
+/// `media_metadata` rows, so the library reflects the change without a rescan.
+///
+/// Files are written in order. If a write fails, the files written before it
+/// are still synced and the error is returned. Tracks of CUE sheets are
+/// skipped, their file holds the tags of every track of the sheet.
+pub async fn write_tags_and_sync(
+    main_db: &MainDbConnection,
+    lib_path: &Path,
+    edits: &[(i32, Vec<TagEdit>)],
+) -> Result<()> {
+    let mut descriptions: Vec<Option<FileDescription>> = Vec::new();
+    let mut file_ids: Vec<i32> = Vec::new();
+    let mut failure = None;
+
+    for (file_id, file_edits) in edits {
+        let result: Result<Option<FileDescription>> = async {
+            let file = get_file_by_id(main_db, *file_id)
+                .await?
+                .with_context(|| format!("File not found: {}", file_id))?;
+            if file.cue_track.is_some() {
+                return Ok(None);
+            }
+
+            let full_path = lib_path.join(&file.directory).join(&file.file_name);
+            write_tags(&full_path, file_edits)?;
+
+            describe_file(&full_path, &Some(lib_path.to_path_buf())).map(Some)
+        }
+        .await;
+
+        match result {
+            Ok(Some(description)) => {
+                descriptions.push(Some(description));
+                file_ids.push(*file_id);
+            }
+            Ok(None) => warn!("Skipping tags of a track of a CUE sheet: {}", file_id),
+            Err(e) => {
+                error!("{:?}", e);
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+
+    if !descriptions.is_empty() {
+        // Force the sync, the modification time may not have moved within the same second
+        sync_file_descriptions(main_db, &mut descriptions, true)
+            .await
+            .with_context(|| "Failed to sync written files")?;
+
+        index_media_files(main_db, file_ids, None)
+            .await
+            .with_context(|| "Failed to index written files")?;
+    }
+
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use database::actions::file::get_file_by_path;
+    use database::actions::metadata::{
+        empty_progress_callback, get_metadata_by_file_id, scan_audio_library,
+    };
+    use database::connection::connect_main_db;
+    use metadata::reader::get_metadata;
+
+    use super::*;
+
+    fn temp_library(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rune-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::copy("../assets/startup_0.ogg", root.join("track.ogg")).unwrap();
+
+        root
+    }
+
+    fn value_of<'a>(metadata: &'a [(String, String)], key: &str) -> Option<&'a str> {
+        metadata
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[tokio::test]
+    async fn test_write_tags_and_sync() {
+        let lib_path = temp_library("tag-writer-sync");
+        let main_db = connect_main_db(lib_path.to_str().unwrap(), None)
+            .await
+            .unwrap();
+
+        scan_audio_library(
+            &main_db,
+            &lib_path,
+            true,
+            false,
+            empty_progress_callback,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let file = get_file_by_path(&main_db, Path::new("track.ogg"))
+            .await
+            .unwrap()
+            .expect("The fixture should be scanned");
+
+        write_tags_and_sync(
+            &main_db,
+            &lib_path,
+            &[(
+                file.id,
+                vec![
+                    TagEdit::set("track_title", "Written Title"),
+                    TagEdit::set("artist", "Written Artist"),
+                ],
+            )],
+        )
+        .await
+        .unwrap();
+
+        // The tags are in the file
+        let full_path = lib_path.join("track.ogg");
+        let written = get_metadata(full_path.to_str().unwrap(), None).unwrap();
+        assert_eq!(value_of(&written, "track_title"), Some("Written Title"));
+        assert_eq!(value_of(&written, "artist"), Some("Written Artist"));
+
+        // And in the database, without a rescan
+        let synced = get_metadata_by_file_id(&main_db, file.id).await.unwrap();
+        assert_eq!(value_of(&synced, "track_title"), Some("Written Title"));
+        assert_eq!(value_of(&synced, "artist"), Some("Written Artist"));
+
+        // The stored hash is the one of the written file
+        let file = get_file_by_path(&main_db, Path::new("track.ogg"))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut description = describe_file(&full_path, &Some(lib_path.clone())).unwrap();
+        assert_eq!(file.file_hash, description.get_crc().unwrap());
+
+        fs::remove_dir_all(&lib_path).unwrap();
+    }
+}
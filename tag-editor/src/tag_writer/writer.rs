@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use lofty::config::WriteOptions;
+use lofty::file::{FileType, TaggedFileExt};
+use lofty::tag::{Tag, TagExt};
+
+use super::keys::standard_key_to_item_key;
+
+/// A single change to a tag, keyed by a `metadata::reader` standard key name.
+/// A `None` value removes the key from the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagEdit {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl TagEdit {
+    pub fn set(key: impl Into<String>, value: impl Into<String>) -> Self {
+        TagEdit {
+            key: key.into(),
+            value: Some(value.into()),
+        }
+    }
+
+    pub fn remove(key: impl Into<String>) -> Self {
+        TagEdit {
+            key: key.into(),
+            value: None,
+        }
+    }
+}
+
+pub fn is_writable_file_type(file_type: FileType) -> bool {
+    matches!(
+        file_type,
+        FileType::Mpeg | FileType::Flac | FileType::Vorbis | FileType::Opus | FileType::Mp4
+    )
+}
+
+pub fn write_tags(path: &Path, edits: &[TagEdit]) -> Result<()> {
+    // Resolve every key up front so a bad edit never leaves a half-written file
+    let resolved = edits
+        .iter()
+        .map(|edit| {
+            standard_key_to_item_key(&edit.key)
+                .map(|item_key| (item_key, edit.value.clone()))
+                .with_context(|| format!("Unsupported tag key: {}", edit.key))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut tagged_file = lofty::read_from_path(path)
+        .with_context(|| format!("Failed to read tags: {}", path.display()))?;
+
+    let file_type = tagged_file.file_type();
+    if !is_writable_file_type(file_type) {
+        bail!(
+            "Writing tags is not supported for {:?}: {}",
+            file_type,
+            path.display()
+        );
+    }
+
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+
+    let tag = tagged_file
+        .primary_tag_mut()
+        .with_context(|| format!("No writable tag found: {}", path.display()))?;
+
+    for (item_key, value) in resolved {
+        match value {
+            Some(value) => {
+                if !tag.insert_text(item_key.clone(), value) {
+                    bail!(
+                        "Tag key {:?} can not be stored in {:?}: {}",
+                        item_key,
+                        tag.tag_type(),
+                        path.display()
+                    );
+                }
+            }
+            None => tag.remove_key(&item_key),
+        }
+    }
+
+    tag.save_to_path(path, WriteOptions::default())
+        .with_context(|| format!("Failed to save tags: {}", path.display()))?;
+
+    Ok(())
+}